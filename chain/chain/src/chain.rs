@@ -7,13 +7,16 @@ use chrono::Duration;
 use log::{debug, info};
 
 use near_primitives::hash::CryptoHash;
-use near_primitives::transaction::{ReceiptTransaction, TransactionResult};
+use near_primitives::transaction::{ReceiptTransaction, SignedTransaction, TransactionResult};
 use near_primitives::types::{BlockIndex, MerkleHash, ShardId, ValidatorStake};
-use near_store::Store;
+use near_store::{StatePart, Store, TrieChanges};
 
 use crate::error::{Error, ErrorKind};
+use crate::metrics;
 use crate::store::{ChainStore, ChainStoreAccess, ChainStoreUpdate};
-use crate::types::{Block, BlockHeader, BlockStatus, Provenance, RuntimeAdapter, Tip};
+use crate::types::{
+    Block, BlockHeader, BlockStatus, Provenance, RuntimeAdapter, ShardStateSyncResponseHeader, Tip,
+};
 
 /// Maximum number of orphans chain can store.
 pub const MAX_ORPHAN_SIZE: usize = 1024;
@@ -24,6 +27,9 @@ const MAX_ORPHAN_AGE_SECS: u64 = 300;
 /// Refuse blocks more than this many block intervals in the future (as in bitcoin).
 const ACCEPTABLE_TIME_DIFFERENCE: i64 = 12 * 10;
 
+/// Maximum number of heights to garbage collect at once, to not block processing for too long.
+const MAX_HEIGHTS_TO_CLEAR: BlockIndex = 100;
+
 pub struct Orphan {
     block: Block,
     provenance: Provenance,
@@ -118,6 +124,9 @@ pub struct Chain {
     runtime_adapter: Arc<dyn RuntimeAdapter>,
     orphans: OrphanBlockPool,
     genesis: BlockHeader,
+    /// Keep all the blocks and state, changes to the trie are not recorded for the garbage
+    /// collection.
+    archive: bool,
 }
 
 impl Chain {
@@ -125,6 +134,7 @@ impl Chain {
         store: Arc<Store>,
         runtime_adapter: Arc<dyn RuntimeAdapter>,
         genesis_time: DateTime<Utc>,
+        archive: bool,
     ) -> Result<Chain, Error> {
         let mut store = ChainStore::new(store);
 
@@ -184,6 +194,7 @@ impl Chain {
 
                     head = Tip::from_header(&genesis.header);
                     store_update.save_head(&head)?;
+                    store_update.save_body_tail(&head);
                     store_update.save_sync_head(&head);
 
                     store_update.merge(state_store_update);
@@ -202,6 +213,7 @@ impl Chain {
             runtime_adapter,
             orphans: OrphanBlockPool::new(),
             genesis: genesis.header,
+            archive,
         })
     }
 
@@ -218,8 +230,12 @@ impl Chain {
     /// Process a block header received during "header first" propagation.
     pub fn process_block_header(&mut self, header: &BlockHeader) -> Result<(), Error> {
         // We create new chain update, but it's not going to be committed so it's read only.
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            self.archive,
+        );
         chain_update.process_block_header(header)?;
         Ok(())
     }
@@ -235,20 +251,27 @@ impl Chain {
     where
         F: Copy + FnMut(&Block, BlockStatus, Provenance) -> (),
     {
+        let timer = near_metrics::start_timer(&metrics::BLOCK_PROCESSING_TIME);
         let hash = block.hash();
-        let res = self.process_block_single(block, provenance, block_accepted);
+        let mut res = self.process_block_single(block, provenance, block_accepted);
         if res.is_ok() {
             if let Some(new_res) = self.check_orphans(hash, block_accepted) {
-                return Ok(Some(new_res));
+                res = Ok(Some(new_res));
             }
         }
+        near_metrics::set_gauge(&metrics::NUM_ORPHANS, self.orphans.len() as i64);
+        near_metrics::stop_timer(timer);
         res
     }
 
     /// Processes headers and adds them to store for syncing.
     pub fn sync_block_headers(&mut self, headers: Vec<BlockHeader>) -> Result<(), Error> {
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            self.archive,
+        );
         chain_update.sync_block_headers(headers)?;
         chain_update.commit()
     }
@@ -339,12 +362,17 @@ impl Chain {
         F: FnMut(&Block, BlockStatus, Provenance) -> (),
     {
         let prev_head = self.store.head()?;
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            self.archive,
+        );
         let maybe_new_head = chain_update.process_block(&block, &provenance);
 
         if let Ok(_) = maybe_new_head {
             chain_update.commit()?;
+            near_metrics::inc_counter(&metrics::BLOCK_PROCESSED_TOTAL);
         }
 
         match maybe_new_head {
@@ -386,6 +414,16 @@ impl Chain {
                     );
                     Err(ErrorKind::Unfit(msg.clone()).into())
                 }
+                ErrorKind::InvalidTransactionReference | ErrorKind::ExpiredTransaction => {
+                    debug!(
+                        target: "chain",
+                        "Block {} at {} has transactions with invalid references: {:?}",
+                        block.hash(),
+                        block.header.height,
+                        e.kind()
+                    );
+                    Err(e.kind().into())
+                }
                 _ => Err(ErrorKind::Other(format!("{:?}", e)).into()),
             },
         }
@@ -436,30 +474,227 @@ impl Chain {
         maybe_new_head
     }
 
-    pub fn set_shard_state(
+    /// Returns the header of the shard state at the given block, used to start state sync.
+    pub fn get_state_response_header(
         &mut self,
         shard_id: ShardId,
-        hash: CryptoHash,
-        payload: Vec<u8>,
-        receipts: Vec<ReceiptTransaction>,
-    ) -> Result<(), Error> {
+        sync_hash: CryptoHash,
+    ) -> Result<ShardStateSyncResponseHeader, Error> {
         // TODO(1046): update this with any required changes for chunks support.
-        let header = self.get_block_header(&hash)?;
+        let header = self.get_block_header(&sync_hash)?;
         let (prev_hash, state_root) = (header.prev_hash, header.prev_state_root);
+        let info = self
+            .runtime_adapter
+            .get_state_parts_info(shard_id, state_root)
+            .map_err(|err| ErrorKind::Other(err.to_string()))?;
+        let receipts = self.get_receipts(&prev_hash)?.clone();
+        Ok(ShardStateSyncResponseHeader {
+            part_boundaries: info.boundaries,
+            total_size: info.total_size,
+            receipts,
+        })
+    }
 
-        // Save state in the runtime, will also check it's validity.
+    /// Returns the part of the shard state at the given block.
+    pub fn get_state_response_part(
+        &mut self,
+        shard_id: ShardId,
+        part_id: u64,
+        sync_hash: CryptoHash,
+    ) -> Result<StatePart, Error> {
+        let state_root = self.get_block_header(&sync_hash)?.prev_state_root;
         self.runtime_adapter
-            .set_state(shard_id, state_root, payload)
-            .map_err(|err| ErrorKind::InvalidStatePayload(err.to_string()))?;
+            .obtain_state_part(shard_id, part_id, state_root)
+            .map_err(|err| ErrorKind::Other(err.to_string()).into())
+    }
+
+    /// Remembers the block which state is being downloaded, to resume the download after restart.
+    pub fn set_state_sync_hash(&mut self, sync_hash: CryptoHash) -> Result<(), Error> {
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.save_state_sync_hash(&sync_hash);
+        chain_store_update.commit()
+    }
+
+    /// Saves the header of the shard state received from a peer.
+    pub fn set_state_header(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        header: ShardStateSyncResponseHeader,
+    ) -> Result<(), Error> {
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.save_state_header(shard_id, &sync_hash, header);
+        chain_store_update.commit()
+    }
+
+    /// Checks the part of the shard state against the state root and the saved header of the shard
+    /// state and saves it.
+    pub fn set_state_part(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part: StatePart,
+    ) -> Result<(), Error> {
+        let state_root = self.get_block_header(&sync_hash)?.prev_state_root;
+        let shard_state_header = self.store.get_state_header(shard_id, &sync_hash)?;
+        if !self.runtime_adapter.validate_state_part(
+            shard_id,
+            state_root,
+            &shard_state_header.part_boundaries,
+            &part,
+        ) {
+            return Err(ErrorKind::InvalidStatePayload(format!(
+                "Invalid state part {} for shard {}",
+                part.part_id, shard_id
+            ))
+            .into());
+        }
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.save_state_part(shard_id, &sync_hash, part);
+        chain_store_update.commit()
+    }
+
+    /// Sets the shard state from all downloaded parts, checking that it matches the state root.
+    /// Downloaded parts are removed on success or on invalid state, so the download can start over.
+    pub fn set_state_finalize(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+    ) -> Result<(), Error> {
+        let header = self.get_block_header(&sync_hash)?;
+        let (prev_hash, state_root) = (header.prev_hash, header.prev_state_root);
+        let shard_state_header = self.store.get_state_header(shard_id, &sync_hash)?;
+        let num_parts = shard_state_header.num_parts();
+
+        // Save state in the runtime, will also check it's validity.
+        let store = &self.store;
+        let get_part = |part_id| -> Result<StatePart, Box<dyn std::error::Error>> {
+            store
+                .get_state_part(shard_id, &sync_hash, part_id)
+                .map_err(|err| err.to_string().into())
+        };
+        let result: Result<(), Error> = self
+            .runtime_adapter
+            .confirm_state(shard_id, state_root, num_parts, &get_part)
+            .map_err(|err| ErrorKind::InvalidStatePayload(err.to_string()).into());
 
         // Update pointers to state root and receipts.
         let mut chain_store_update = self.store.store_update();
-        chain_store_update.save_post_state_root(&prev_hash, &state_root);
-        chain_store_update.save_receipt(&prev_hash, receipts);
+        if result.is_ok() {
+            chain_store_update.save_post_state_root(&prev_hash, &state_root);
+            chain_store_update.save_receipt(&prev_hash, shard_state_header.receipts);
+        }
+        chain_store_update.delete_state_parts(shard_id, &sync_hash, num_parts);
         chain_store_update.commit()?;
 
+        result
+    }
+
+    /// Removes the downloaded header and parts of the shard state.
+    pub fn clear_state_parts(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        num_parts: u64,
+    ) -> Result<(), Error> {
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.delete_state_parts(shard_id, &sync_hash, num_parts);
+        chain_store_update.commit()
+    }
+
+    /// Removes data of the blocks that are more than `num_blocks_to_keep` blocks behind the head:
+    /// block bodies, receipts, transaction results, post state roots and the trie nodes that
+    /// are not used by the remaining state. Forks below this height are removed as well.
+    /// Headers and the genesis block are kept. Moves the tail to the oldest remaining block on the
+    /// main chain.
+    pub fn clear_old_data(&mut self, num_blocks_to_keep: BlockIndex) -> Result<(), Error> {
+        let head = self.store.head()?;
+        let mut tail = match self.store.tail() {
+            Ok(tail) => tail,
+            Err(err) => match err.kind() {
+                ErrorKind::DBNotFoundErr(_) => Tip::from_header(&self.genesis),
+                _ => return Err(err),
+            },
+        };
+        let gc_stop_height = std::cmp::min(
+            head.height.saturating_sub(num_blocks_to_keep),
+            tail.height + MAX_HEIGHTS_TO_CLEAR,
+        );
+        if gc_stop_height <= tail.height {
+            return Ok(());
+        }
+        let trie = self.runtime_adapter.get_trie();
+        for height in (tail.height + 1)..=gc_stop_height {
+            let canonical_hash = match self.store.get_block_hash_by_height(height) {
+                Ok(hash) => Some(hash),
+                Err(err) => match err.kind() {
+                    ErrorKind::DBNotFoundErr(_) => None,
+                    _ => return Err(err),
+                },
+            };
+
+            // Remove forks at this height, discarding the state they have created.
+            for hash in self.store.get_all_block_hashes_by_height(height)? {
+                if Some(hash) == canonical_hash {
+                    continue;
+                }
+                let mut chain_store_update = self.store.store_update();
+                if let Some(trie_changes) = get_trie_changes_if_any(&chain_store_update, &hash)? {
+                    let mut store_update = chain_store_update.store().store_update();
+                    trie_changes
+                        .revert_insertions_into(trie.clone(), &mut store_update)
+                        .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                    chain_store_update.merge(store_update);
+                }
+                chain_store_update.clear_block_data(&hash)?;
+                chain_store_update.commit()?;
+            }
+
+            // Move the tail to the block on the main chain, discarding the state of the previous tail.
+            if let Some(hash) = canonical_hash {
+                let mut chain_store_update = self.store.store_update();
+                if let Some(trie_changes) = get_trie_changes_if_any(&chain_store_update, &hash)? {
+                    let mut store_update = chain_store_update.store().store_update();
+                    trie_changes
+                        .deletions_into(trie.clone(), &mut store_update)
+                        .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                    chain_store_update.merge(store_update);
+                }
+                chain_store_update.delete_trie_changes(&hash);
+                // Genesis block is kept to serve the chain from its start.
+                if tail.last_block_hash != self.genesis.hash() {
+                    chain_store_update.clear_block_data(&tail.last_block_hash)?;
+                }
+                for cleared_height in tail.height..height {
+                    chain_store_update.delete_block_hashes_by_height(cleared_height);
+                }
+                let new_tail = Tip::from_header(chain_store_update.get_block_header(&hash)?);
+                chain_store_update.save_body_tail(&new_tail);
+                chain_store_update.commit()?;
+                debug!(target: "chain", "Cleared old data, new tail: {} at {}", new_tail.last_block_hash, new_tail.height);
+                tail = new_tail;
+            }
+        }
+        self.store.clear_block_data_cache();
         Ok(())
     }
+
+    /// Returns height of the block referenced by the transaction if the transaction can be
+    /// included into the next block on top of the current head, error if it has expired
+    /// or refers to a block that is not on the current chain.
+    pub fn get_transaction_reference_index(
+        &mut self,
+        transaction: &SignedTransaction,
+    ) -> Result<BlockIndex, Error> {
+        let head = self.store.head()?;
+        get_transaction_reference_index(
+            &mut self.store,
+            &head.last_block_hash,
+            head.height + 1,
+            &transaction.body.get_block_hash(),
+            self.runtime_adapter.transaction_validity_period(),
+        )
+    }
 }
 
 /// Various chain getters.
@@ -532,6 +767,25 @@ impl Chain {
         self.store.get_post_state_root(hash)
     }
 
+    /// Get state root hash after applying block with given hash, failing with
+    /// `StateGarbageCollected` if the state of this block has been removed by `clear_old_data`.
+    pub fn get_post_state_root_if_kept(&mut self, hash: &CryptoHash) -> Result<MerkleHash, Error> {
+        let height = self.store.get_block_header(hash)?.height;
+        match self.store.tail() {
+            Ok(tail) => {
+                if height < tail.height || (height == tail.height && *hash != tail.last_block_hash)
+                {
+                    return Err(ErrorKind::StateGarbageCollected(height, tail.height).into());
+                }
+            }
+            Err(err) => match err.kind() {
+                ErrorKind::DBNotFoundErr(_) => {}
+                _ => return Err(err),
+            },
+        }
+        self.store.get_post_state_root(hash).map(Clone::clone)
+    }
+
     /// Get receipts stored for the given hash.
     #[inline]
     pub fn get_receipts(&mut self, hash: &CryptoHash) -> Result<&Vec<ReceiptTransaction>, Error> {
@@ -592,6 +846,62 @@ impl Chain {
     }
 }
 
+/// Returns changes to the trie made by given block. Blocks which state was downloaded by the
+/// state sync don't have them.
+fn get_trie_changes_if_any<T: ChainStoreAccess>(
+    chain_store: &T,
+    hash: &CryptoHash,
+) -> Result<Option<TrieChanges>, Error> {
+    match chain_store.get_trie_changes(hash) {
+        Ok(trie_changes) => Ok(Some(trie_changes)),
+        Err(err) => match err.kind() {
+            ErrorKind::DBNotFoundErr(_) => Ok(None),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Returns height of the block with `reference_hash` referenced by a transaction, if the
+/// transaction can be included into a block at `height` on top of `prev_hash`: the referenced
+/// block must be this block's ancestor and at most `validity_period` blocks older than it.
+fn get_transaction_reference_index<T: ChainStoreAccess>(
+    chain_store: &mut T,
+    prev_hash: &CryptoHash,
+    height: BlockIndex,
+    reference_hash: &CryptoHash,
+    validity_period: BlockIndex,
+) -> Result<BlockIndex, Error> {
+    let reference_height = match chain_store.get_block_header(reference_hash) {
+        Ok(header) => header.height,
+        Err(err) => match err.kind() {
+            ErrorKind::DBNotFoundErr(_) => {
+                return Err(ErrorKind::InvalidTransactionReference.into())
+            }
+            _ => return Err(err),
+        },
+    };
+    if height > reference_height + validity_period {
+        return Err(ErrorKind::ExpiredTransaction.into());
+    }
+    let mut header = chain_store.get_block_header(prev_hash)?.clone();
+    if header.height < reference_height {
+        return Err(ErrorKind::InvalidTransactionReference.into());
+    }
+    // Both blocks are on the canonical chain, no need to walk back.
+    if chain_store.get_block_hash_by_height(header.height).ok() == Some(*prev_hash)
+        && chain_store.get_block_hash_by_height(reference_height).ok() == Some(*reference_hash)
+    {
+        return Ok(reference_height);
+    }
+    while header.height > reference_height {
+        header = chain_store.get_previous_header(&header)?.clone();
+    }
+    if header.hash() != *reference_hash {
+        return Err(ErrorKind::InvalidTransactionReference.into());
+    }
+    Ok(reference_height)
+}
+
 /// Chain update helper, contains information that is needed to process block
 /// and decide to accept it or reject it.
 /// If rejected nothing will be updated in underlying storage.
@@ -600,6 +910,7 @@ struct ChainUpdate<'a> {
     runtime_adapter: Arc<dyn RuntimeAdapter>,
     chain_store_update: ChainStoreUpdate<'a, ChainStore>,
     orphans: &'a OrphanBlockPool,
+    archive: bool,
 }
 
 impl<'a> ChainUpdate<'a> {
@@ -607,9 +918,10 @@ impl<'a> ChainUpdate<'a> {
         store: &'a mut ChainStore,
         runtime_adapter: Arc<dyn RuntimeAdapter>,
         orphans: &'a OrphanBlockPool,
+        archive: bool,
     ) -> Self {
         let chain_store_update = store.store_update();
-        ChainUpdate { runtime_adapter, chain_store_update, orphans }
+        ChainUpdate { runtime_adapter, chain_store_update, orphans, archive }
     }
 
     /// Commit changes to the chain into the database.
@@ -676,6 +988,9 @@ impl<'a> ChainUpdate<'a> {
             return Err(ErrorKind::InvalidStateRoot.into());
         }
 
+        // Check that all transactions refer to recent blocks on this chain.
+        self.validate_transaction_references(block)?;
+
         // Retrieve receipts from the previous block.
         let receipts = self.chain_store_update.get_receipts(&prev_hash)?;
         let receipt_hashes = receipts.iter().map(|r| r.get_hash()).collect::<Vec<_>>();
@@ -711,7 +1026,11 @@ impl<'a> ChainUpdate<'a> {
             )
             .map_err(|err| ErrorKind::Other(err.to_string()))?;
 
-        self.chain_store_update.save_trie_changes(trie_changes);
+        if self.archive {
+            self.chain_store_update.save_trie_insertions(trie_changes);
+        } else {
+            self.chain_store_update.save_trie_changes(&block.hash(), trie_changes);
+        }
 
         // Save resulting receipts.
         // TODO: currently only taking into account one shard.
@@ -864,6 +1183,26 @@ impl<'a> ChainUpdate<'a> {
         }
     }
 
+    /// Checks that every transaction in the block refers to one of the block's ancestors
+    /// within the transaction validity period.
+    fn validate_transaction_references(&mut self, block: &Block) -> Result<(), Error> {
+        let validity_period = self.runtime_adapter.transaction_validity_period();
+        let mut checked = HashSet::new();
+        for transaction in block.transactions.iter() {
+            let reference_hash = transaction.body.get_block_hash();
+            if checked.insert(reference_hash) {
+                get_transaction_reference_index(
+                    &mut self.chain_store_update,
+                    &block.header.prev_hash,
+                    block.header.height,
+                    &reference_hash,
+                    validity_period,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     /// Directly updates the head if we've just appended a new block to it or handle
     /// the situation where we've just added enough weight to have a fork with more
     /// work than the head.
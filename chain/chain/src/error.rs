@@ -4,6 +4,8 @@ use std::io;
 use chrono::{DateTime, Utc};
 use failure::{Backtrace, Context, Fail};
 
+use near_primitives::types::BlockIndex;
+
 #[derive(Debug)]
 pub struct Error {
     inner: Context<ErrorKind>,
@@ -50,6 +52,19 @@ pub enum ErrorKind {
     /// Invalid Signature
     #[fail(display = "Invalid Signature")]
     InvalidSignature,
+    /// Transaction refers to a block that is unknown or is not an ancestor of the block.
+    #[fail(display = "Invalid Transaction Block Reference")]
+    InvalidTransactionReference,
+    /// Transaction refers to a block older than the transaction validity period.
+    #[fail(display = "Expired Transaction")]
+    ExpiredTransaction,
+    /// State of the block was removed by the garbage collection. Contains height of the block
+    /// and height of the oldest block which state is kept.
+    #[fail(
+        display = "State at height {} has been garbage collected, oldest available state is at height {}",
+        _0, _1
+    )]
+    StateGarbageCollected(BlockIndex, BlockIndex),
     /// IO Error.
     #[fail(display = "IO Error: {}", _0)]
     IOErr(String),
@@ -95,7 +110,8 @@ impl Error {
             | ErrorKind::Orphan
             | ErrorKind::IOErr(_)
             | ErrorKind::Other(_)
-            | ErrorKind::DBNotFoundErr(_) => false,
+            | ErrorKind::DBNotFoundErr(_)
+            | ErrorKind::StateGarbageCollected(_, _) => false,
             ErrorKind::InvalidBlockPastTime(_, _)
             | ErrorKind::InvalidBlockFutureTime(_)
             | ErrorKind::InvalidBlockHeight
@@ -106,7 +122,9 @@ impl Error {
             | ErrorKind::InvalidStateRoot
             | ErrorKind::InvalidStatePayload(_)
             | ErrorKind::InvalidEpochHash
-            | ErrorKind::InvalidSignature => true,
+            | ErrorKind::InvalidSignature
+            | ErrorKind::InvalidTransactionReference
+            | ErrorKind::ExpiredTransaction => true,
         }
     }
 
@@ -5,12 +5,13 @@ pub use chain::{Chain, MAX_ORPHAN_SIZE};
 pub use error::{Error, ErrorKind};
 pub use store::{ChainStore, ChainStoreAccess};
 pub use types::{
-    Block, BlockApproval, BlockHeader, BlockStatus, Provenance, ReceiptResult, RuntimeAdapter, Tip,
-    ValidTransaction, Weight,
+    Block, BlockApproval, BlockHeader, BlockStatus, Provenance, ReceiptResult, RuntimeAdapter,
+    ShardStateSyncResponse, ShardStateSyncResponseHeader, Tip, ValidTransaction, Weight,
 };
 
 mod chain;
 mod error;
+mod metrics;
 mod store;
 pub mod test_utils;
 mod types;
@@ -0,0 +1,17 @@
+use lazy_static::lazy_static;
+use near_metrics::{Histogram, IntCounter, IntGauge};
+
+lazy_static! {
+    pub static ref BLOCK_PROCESSING_TIME: near_metrics::Result<Histogram> =
+        near_metrics::try_create_histogram(
+            "near_block_processing_time",
+            "Time taken to process a block, including orphans it unblocked, in seconds"
+        );
+    pub static ref BLOCK_PROCESSED_TOTAL: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_block_processed_total",
+            "Total number of blocks processed"
+        );
+    pub static ref NUM_ORPHANS: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge("near_num_orphans", "Number of orphan blocks");
+}
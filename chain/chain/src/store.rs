@@ -7,21 +7,23 @@ use log::debug;
 
 use near_primitives::hash::CryptoHash;
 use near_primitives::transaction::{ReceiptTransaction, TransactionResult};
-use near_primitives::types::{BlockIndex, MerkleHash, ValidatorStake};
+use near_primitives::types::{BlockIndex, MerkleHash, ShardId, ValidatorStake};
 use near_primitives::utils::index_to_bytes;
 use near_store::{
-    read_with_cache, Store, StoreUpdate, WrappedTrieChanges, COL_BLOCK, COL_BLOCK_HEADER,
-    COL_BLOCK_INDEX, COL_BLOCK_MISC, COL_RECEIPTS, COL_STATE_REF, COL_TRANSACTION_RESULT,
+    read_with_cache, StatePart, Store, StoreUpdate, TrieChanges, WrappedTrieChanges, COL_BLOCK,
+    COL_BLOCK_HEADER, COL_BLOCK_INDEX, COL_BLOCK_MISC, COL_BLOCK_PER_HEIGHT, COL_RECEIPTS,
+    COL_STATE_HEADERS, COL_STATE_PARTS, COL_STATE_REF, COL_TRANSACTION_RESULT, COL_TRIE_CHANGES,
     COL_VALIDATOR_PROPOSALS,
 };
 
 use crate::error::{Error, ErrorKind};
-use crate::types::{Block, BlockHeader, Tip};
+use crate::types::{Block, BlockHeader, ShardStateSyncResponseHeader, Tip};
 
 const HEAD_KEY: &[u8; 4] = b"HEAD";
 const TAIL_KEY: &[u8; 4] = b"TAIL";
 const SYNC_HEAD_KEY: &[u8; 9] = b"SYNC_HEAD";
 const HEADER_HEAD_KEY: &[u8; 11] = b"HEADER_HEAD";
+const STATE_SYNC_HASH_KEY: &[u8; 15] = b"STATE_SYNC_HASH";
 
 /// lru cache size
 const CACHE_SIZE: usize = 20;
@@ -57,10 +59,51 @@ pub trait ChainStoreAccess {
     fn get_block_header(&mut self, h: &CryptoHash) -> Result<&BlockHeader, Error>;
     /// Returns hash of the block on the main chain for given height.
     fn get_block_hash_by_height(&mut self, height: BlockIndex) -> Result<CryptoHash, Error>;
+    /// Returns hashes of all known blocks at given height, including forks.
+    fn get_all_block_hashes_by_height(
+        &self,
+        height: BlockIndex,
+    ) -> Result<HashSet<CryptoHash>, Error>;
     /// Returns resulting receipt for given block.
     fn get_receipts(&mut self, hash: &CryptoHash) -> Result<&Vec<ReceiptTransaction>, Error>;
     /// Returns transaction result for given tx hash.
     fn get_transaction_result(&mut self, hash: &CryptoHash) -> Result<&TransactionResult, Error>;
+    /// Returns changes to the trie made by applying given block.
+    fn get_trie_changes(&self, hash: &CryptoHash) -> Result<TrieChanges, Error>;
+    /// Hash of the block which state is being downloaded by the state sync.
+    fn state_sync_hash(&self) -> Result<CryptoHash, Error>;
+    /// Returns header of the shard state downloaded for state sync at given block.
+    fn get_state_header(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+    ) -> Result<ShardStateSyncResponseHeader, Error>;
+    /// Returns part of the shard state downloaded for state sync at given block.
+    fn get_state_part(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<StatePart, Error>;
+    /// Does this part of the shard state exist?
+    fn state_part_exists(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<bool, Error>;
+}
+
+fn get_state_header_key(shard_id: ShardId, sync_hash: &CryptoHash) -> Vec<u8> {
+    let mut key = index_to_bytes(shard_id);
+    key.extend_from_slice(sync_hash.as_ref());
+    key
+}
+
+fn get_state_part_key(shard_id: ShardId, sync_hash: &CryptoHash, part_id: u64) -> Vec<u8> {
+    let mut key = get_state_header_key(shard_id, sync_hash);
+    key.extend_from_slice(&index_to_bytes(part_id));
+    key
 }
 
 /// All chain-related database operations.
@@ -104,6 +147,15 @@ impl ChainStore {
         }
     }
 
+    /// Drops cached blocks, post state roots, receipts and transaction results,
+    /// which may contain data that was garbage collected.
+    pub fn clear_block_data_cache(&mut self) {
+        self.blocks = SizedCache::with_size(CACHE_SIZE);
+        self.post_state_roots = SizedCache::with_size(CACHE_SIZE);
+        self.receipts = SizedCache::with_size(CACHE_SIZE);
+        self.transaction_results = SizedCache::with_size(CACHE_SIZE);
+    }
+
     pub fn store_update(&mut self) -> ChainStoreUpdate<Self> {
         ChainStoreUpdate::new(self)
     }
@@ -223,6 +275,64 @@ impl ChainStoreAccess for ChainStore {
             &format!("TRANSACTION: {}", hash),
         )
     }
+
+    fn get_all_block_hashes_by_height(
+        &self,
+        height: BlockIndex,
+    ) -> Result<HashSet<CryptoHash>, Error> {
+        Ok(self
+            .store
+            .get_ser(COL_BLOCK_PER_HEIGHT, &index_to_bytes(height))?
+            .unwrap_or_else(HashSet::default))
+    }
+
+    fn get_trie_changes(&self, hash: &CryptoHash) -> Result<TrieChanges, Error> {
+        option_to_not_found(
+            self.store.get_ser(COL_TRIE_CHANGES, hash.as_ref()),
+            &format!("TRIE CHANGES: {}", hash),
+        )
+    }
+
+    fn state_sync_hash(&self) -> Result<CryptoHash, Error> {
+        option_to_not_found(
+            self.store.get_ser(COL_BLOCK_MISC, STATE_SYNC_HASH_KEY),
+            "STATE_SYNC_HASH",
+        )
+    }
+
+    fn get_state_header(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+    ) -> Result<ShardStateSyncResponseHeader, Error> {
+        option_to_not_found(
+            self.store.get_ser(COL_STATE_HEADERS, &get_state_header_key(shard_id, sync_hash)),
+            &format!("STATE HEADER: {} {}", shard_id, sync_hash),
+        )
+    }
+
+    fn get_state_part(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<StatePart, Error> {
+        option_to_not_found(
+            self.store.get_ser(COL_STATE_PARTS, &get_state_part_key(shard_id, sync_hash, part_id)),
+            &format!("STATE PART: {} {} {}", shard_id, sync_hash, part_id),
+        )
+    }
+
+    fn state_part_exists(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<bool, Error> {
+        self.store
+            .exists(COL_STATE_PARTS, &get_state_part_key(shard_id, sync_hash, part_id))
+            .map_err(|e| e.into())
+    }
 }
 
 /// Provides layer to update chain without touching underlaying database.
@@ -233,17 +343,30 @@ pub struct ChainStoreUpdate<'a, T> {
     /// Blocks added during this update. Takes ownership (unclear how to not do it because of failure exists).
     blocks: HashMap<CryptoHash, Block>,
     deleted_blocks: HashSet<CryptoHash>,
+    /// Heights for which the list of known blocks is removed.
+    deleted_block_heights: HashSet<BlockIndex>,
     headers: HashMap<CryptoHash, BlockHeader>,
     post_state_roots: HashMap<CryptoHash, MerkleHash>,
+    deleted_post_state_roots: HashSet<CryptoHash>,
     post_validator_proposals: HashMap<CryptoHash, Vec<ValidatorStake>>,
     block_index: HashMap<BlockIndex, Option<CryptoHash>>,
     receipts: HashMap<CryptoHash, Vec<ReceiptTransaction>>,
+    deleted_receipts: HashSet<CryptoHash>,
     transaction_results: HashMap<CryptoHash, TransactionResult>,
+    deleted_transaction_results: HashSet<CryptoHash>,
     head: Option<Tip>,
     tail: Option<Tip>,
     header_head: Option<Tip>,
     sync_head: Option<Tip>,
-    trie_changes: Option<WrappedTrieChanges>,
+    /// Changes to the trie made by applying a block, with the hash of the block if the changes
+    /// are kept for the garbage collection.
+    trie_changes: Option<(Option<CryptoHash>, WrappedTrieChanges)>,
+    deleted_trie_changes: HashSet<CryptoHash>,
+    state_sync_hash: Option<CryptoHash>,
+    state_headers: HashMap<(ShardId, CryptoHash), ShardStateSyncResponseHeader>,
+    state_parts: HashMap<(ShardId, CryptoHash, u64), StatePart>,
+    /// Shard states for state sync to remove: shard id, sync hash and number of parts.
+    deleted_state_parts: Vec<(ShardId, CryptoHash, u64)>,
 }
 
 impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
@@ -253,17 +376,26 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
             store_updates: vec![],
             blocks: HashMap::default(),
             deleted_blocks: HashSet::default(),
+            deleted_block_heights: HashSet::default(),
             headers: HashMap::default(),
             block_index: HashMap::default(),
             post_state_roots: HashMap::default(),
+            deleted_post_state_roots: HashSet::default(),
             post_validator_proposals: HashMap::default(),
             receipts: HashMap::default(),
+            deleted_receipts: HashSet::default(),
             transaction_results: HashMap::default(),
+            deleted_transaction_results: HashSet::default(),
             head: None,
             tail: None,
             header_head: None,
             sync_head: None,
             trie_changes: None,
+            deleted_trie_changes: HashSet::default(),
+            state_sync_hash: None,
+            state_headers: HashMap::default(),
+            state_parts: HashMap::default(),
+            deleted_state_parts: vec![],
         }
     }
 }
@@ -378,6 +510,71 @@ impl<'a, T: ChainStoreAccess> ChainStoreAccess for ChainStoreUpdate<'a, T> {
     fn get_transaction_result(&mut self, hash: &CryptoHash) -> Result<&TransactionResult, Error> {
         self.chain_store.get_transaction_result(hash)
     }
+
+    fn get_all_block_hashes_by_height(
+        &self,
+        height: BlockIndex,
+    ) -> Result<HashSet<CryptoHash>, Error> {
+        let mut hashes = if self.deleted_block_heights.contains(&height) {
+            HashSet::default()
+        } else {
+            self.chain_store.get_all_block_hashes_by_height(height)?
+        };
+        hashes.extend(
+            self.blocks
+                .iter()
+                .filter(|(_, block)| block.header.height == height)
+                .map(|(hash, _)| *hash),
+        );
+        Ok(hashes)
+    }
+
+    fn get_trie_changes(&self, hash: &CryptoHash) -> Result<TrieChanges, Error> {
+        self.chain_store.get_trie_changes(hash)
+    }
+
+    fn state_sync_hash(&self) -> Result<CryptoHash, Error> {
+        if let Some(state_sync_hash) = &self.state_sync_hash {
+            Ok(*state_sync_hash)
+        } else {
+            self.chain_store.state_sync_hash()
+        }
+    }
+
+    fn get_state_header(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+    ) -> Result<ShardStateSyncResponseHeader, Error> {
+        if let Some(header) = self.state_headers.get(&(shard_id, *sync_hash)) {
+            Ok(header.clone())
+        } else {
+            self.chain_store.get_state_header(shard_id, sync_hash)
+        }
+    }
+
+    fn get_state_part(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<StatePart, Error> {
+        if let Some(part) = self.state_parts.get(&(shard_id, *sync_hash, part_id)) {
+            Ok(part.clone())
+        } else {
+            self.chain_store.get_state_part(shard_id, sync_hash, part_id)
+        }
+    }
+
+    fn state_part_exists(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        part_id: u64,
+    ) -> Result<bool, Error> {
+        Ok(self.state_parts.contains_key(&(shard_id, *sync_hash, part_id))
+            || self.chain_store.state_part_exists(shard_id, sync_hash, part_id)?)
+    }
 }
 
 impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
@@ -471,6 +668,34 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         self.transaction_results.insert(*hash, result);
     }
 
+    /// Save hash of the block which state is being downloaded by the state sync.
+    pub fn save_state_sync_hash(&mut self, hash: &CryptoHash) {
+        self.state_sync_hash = Some(*hash);
+    }
+
+    pub fn save_state_header(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        header: ShardStateSyncResponseHeader,
+    ) {
+        self.state_headers.insert((shard_id, *sync_hash), header);
+    }
+
+    pub fn save_state_part(&mut self, shard_id: ShardId, sync_hash: &CryptoHash, part: StatePart) {
+        self.state_parts.insert((shard_id, *sync_hash, part.part_id), part);
+    }
+
+    /// Remove downloaded header and parts of the shard state.
+    pub fn delete_state_parts(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        num_parts: u64,
+    ) {
+        self.deleted_state_parts.push((shard_id, *sync_hash, num_parts));
+    }
+
     /// Starts a sub-ChainUpdate with atomic commit/rollback of all operations done
     /// within this scope.
     /// If the closure returns and error, all changes are canceled.
@@ -497,8 +722,51 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         }
     }
 
-    pub fn save_trie_changes(&mut self, trie_changes: WrappedTrieChanges) {
-        self.trie_changes = Some(trie_changes);
+    /// Save changes to the trie made by applying given block. Insertions are applied to the
+    /// state right away, deletions are kept to be applied by the garbage collection.
+    pub fn save_trie_changes(&mut self, hash: &CryptoHash, trie_changes: WrappedTrieChanges) {
+        self.trie_changes = Some((Some(*hash), trie_changes));
+    }
+
+    /// Apply insertions of the changes to the trie made by applying a block, without keeping the
+    /// changes: archival nodes never garbage collect the state.
+    pub fn save_trie_insertions(&mut self, trie_changes: WrappedTrieChanges) {
+        self.trie_changes = Some((None, trie_changes));
+    }
+
+    pub fn delete_trie_changes(&mut self, hash: &CryptoHash) {
+        self.deleted_trie_changes.insert(*hash);
+    }
+
+    /// Remove the list of known blocks at given height.
+    pub fn delete_block_hashes_by_height(&mut self, height: BlockIndex) {
+        self.deleted_block_heights.insert(height);
+    }
+
+    /// Remove the body of the block together with everything that was produced by applying it:
+    /// receipts, transaction results, post state root and trie changes.
+    /// Header of the block is kept.
+    pub fn clear_block_data(&mut self, hash: &CryptoHash) -> Result<(), Error> {
+        let tx_hashes: Vec<CryptoHash> = match self.get_block(hash) {
+            Ok(block) => block.transactions.iter().map(|tx| tx.get_hash()).collect(),
+            Err(err) => match err.kind() {
+                ErrorKind::DBNotFoundErr(_) => vec![],
+                _ => return Err(err),
+            },
+        };
+        let receipt_hashes: Vec<CryptoHash> = match self.get_receipts(hash) {
+            Ok(receipts) => receipts.iter().map(|receipt| receipt.get_hash()).collect(),
+            Err(err) => match err.kind() {
+                ErrorKind::DBNotFoundErr(_) => vec![],
+                _ => return Err(err),
+            },
+        };
+        self.deleted_transaction_results.extend(tx_hashes.into_iter().chain(receipt_hashes));
+        self.deleted_blocks.insert(*hash);
+        self.deleted_receipts.insert(*hash);
+        self.deleted_post_state_roots.insert(*hash);
+        self.deleted_trie_changes.insert(*hash);
+        Ok(())
     }
 
     /// Merge another StoreUpdate into this one
@@ -508,6 +776,15 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
 
     pub fn finalize(mut self) -> Result<StoreUpdate, Error> {
         let mut store_update = self.store().store_update();
+        for height in self.deleted_block_heights.iter() {
+            store_update.delete(COL_BLOCK_PER_HEIGHT, &index_to_bytes(*height));
+        }
+        let block_heights: HashSet<BlockIndex> =
+            self.blocks.values().map(|block| block.header.height).collect();
+        for height in block_heights {
+            let hashes = self.get_all_block_hashes_by_height(height)?;
+            store_update.set_ser(COL_BLOCK_PER_HEIGHT, &index_to_bytes(height), &hashes)?;
+        }
         if let Some(t) = self.head {
             store_update.set_ser(COL_BLOCK_MISC, HEAD_KEY, &t).map_err::<Error, _>(|e| e.into())?;
         }
@@ -542,6 +819,9 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
                 .set_ser(COL_STATE_REF, hash.as_ref(), &state_root)
                 .map_err::<Error, _>(|e| e.into())?;
         }
+        for hash in self.deleted_post_state_roots.drain() {
+            store_update.delete(COL_STATE_REF, hash.as_ref());
+        }
         for (hash, validator_proposals) in self.post_validator_proposals.drain() {
             store_update
                 .set_ser(COL_VALIDATOR_PROPOSALS, hash.as_ref(), &validator_proposals)
@@ -559,14 +839,53 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         for (hash, receipt) in self.receipts.drain() {
             store_update.set_ser(COL_RECEIPTS, hash.as_ref(), &receipt)?;
         }
+        for hash in self.deleted_receipts.drain() {
+            store_update.delete(COL_RECEIPTS, hash.as_ref());
+        }
         for (hash, tx_result) in self.transaction_results.drain() {
             store_update.set_ser(COL_TRANSACTION_RESULT, hash.as_ref(), &tx_result)?;
         }
-        if let Some(trie_changes) = self.trie_changes {
+        for hash in self.deleted_transaction_results.drain() {
+            store_update.delete(COL_TRANSACTION_RESULT, hash.as_ref());
+        }
+        if let Some(hash) = self.state_sync_hash {
+            store_update.set_ser(COL_BLOCK_MISC, STATE_SYNC_HASH_KEY, &hash)?;
+        }
+        for ((shard_id, sync_hash), header) in self.state_headers.drain() {
+            store_update.set_ser(
+                COL_STATE_HEADERS,
+                &get_state_header_key(shard_id, &sync_hash),
+                &header,
+            )?;
+        }
+        for ((shard_id, sync_hash, part_id), part) in self.state_parts.drain() {
+            store_update.set_ser(
+                COL_STATE_PARTS,
+                &get_state_part_key(shard_id, &sync_hash, part_id),
+                &part,
+            )?;
+        }
+        for (shard_id, sync_hash, num_parts) in self.deleted_state_parts.drain(..) {
+            store_update.delete(COL_STATE_HEADERS, &get_state_header_key(shard_id, &sync_hash));
+            for part_id in 0..num_parts {
+                store_update
+                    .delete(COL_STATE_PARTS, &get_state_part_key(shard_id, &sync_hash, part_id));
+            }
+        }
+        if let Some((hash, trie_changes)) = self.trie_changes {
             trie_changes
                 .insertions_into(&mut store_update)
                 .map_err(|err| ErrorKind::Other(err.to_string()))?;
-            // TODO: save deletions separately for garbage collection.
+            if let Some(hash) = hash {
+                store_update.set_ser(
+                    COL_TRIE_CHANGES,
+                    hash.as_ref(),
+                    trie_changes.trie_changes(),
+                )?;
+            }
+        }
+        for hash in self.deleted_trie_changes.drain() {
+            store_update.delete(COL_TRIE_CHANGES, hash.as_ref());
         }
         for other in self.store_updates {
             store_update.merge(other);
@@ -6,14 +6,17 @@ use chrono::Utc;
 use near_primitives::crypto::signature::{verify, Signature};
 use near_primitives::crypto::signer::InMemorySigner;
 use near_primitives::hash::CryptoHash;
-use near_primitives::rpc::{AccountViewCallResult, QueryResponse};
+use near_primitives::rpc::{AccountViewCallResult, EpochBlockProducers, QueryResponse};
 use near_primitives::test_utils::get_public_key_from_seed;
 use near_primitives::transaction::{
     ReceiptTransaction, SignedTransaction, TransactionResult, TransactionStatus,
 };
 use near_primitives::types::{AccountId, BlockIndex, MerkleHash, ShardId, ValidatorStake};
 use near_store::test_utils::create_test_store;
-use near_store::{Store, StoreUpdate, Trie, TrieChanges, WrappedTrieChanges};
+use near_store::{
+    StatePart, StatePartsInfo, Store, StoreUpdate, Trie, TrieChanges, WrappedTrieChanges,
+    COL_BLOCK_HEADER, STATE_PART_MAX_SIZE,
+};
 
 use crate::error::{Error, ErrorKind};
 use crate::types::{BlockHeader, ReceiptResult, RuntimeAdapter, Weight};
@@ -24,7 +27,10 @@ pub struct KeyValueRuntime {
     store: Arc<Store>,
     trie: Arc<Trie>,
     root: MerkleHash,
-    validators: Vec<ValidatorStake>,
+    /// Validators of each epoch, going over them again once all of them had their epoch.
+    validators: Vec<Vec<ValidatorStake>>,
+    /// Number of heights in each epoch.
+    epoch_length: BlockIndex,
 }
 
 impl KeyValueRuntime {
@@ -33,6 +39,15 @@ impl KeyValueRuntime {
     }
 
     pub fn new_with_validators(store: Arc<Store>, validators: Vec<AccountId>) -> Self {
+        Self::new_with_epochs(store, vec![validators], 1)
+    }
+
+    /// Runtime with validators that rotate every `epoch_length` heights.
+    pub fn new_with_epochs(
+        store: Arc<Store>,
+        validators: Vec<Vec<AccountId>>,
+        epoch_length: BlockIndex,
+    ) -> Self {
         let trie = Arc::new(Trie::new(store.clone()));
         KeyValueRuntime {
             store,
@@ -40,15 +55,36 @@ impl KeyValueRuntime {
             root: MerkleHash::default(),
             validators: validators
                 .iter()
-                .map(|account_id| ValidatorStake {
-                    account_id: account_id.clone(),
-                    public_key: get_public_key_from_seed(account_id),
-                    amount: 1_000_000,
+                .map(|validators| {
+                    validators
+                        .iter()
+                        .map(|account_id| ValidatorStake {
+                            account_id: account_id.clone(),
+                            public_key: get_public_key_from_seed(account_id),
+                            amount: 1_000_000,
+                        })
+                        .collect()
                 })
                 .collect(),
+            epoch_length,
         }
     }
 
+    /// Validators of the epoch of given height.
+    fn validators_at(&self, height: BlockIndex) -> &[ValidatorStake] {
+        &self.validators[((height / self.epoch_length) as usize) % self.validators.len()]
+    }
+
+    /// Validators of the height after the given block, which produce and approve the next block.
+    fn validators_after(&self, block_hash: &CryptoHash) -> &[ValidatorStake] {
+        let height = match self.store.get_ser::<BlockHeader>(COL_BLOCK_HEADER, block_hash.as_ref())
+        {
+            Ok(Some(header)) => header.height + 1,
+            _ => 0,
+        };
+        self.validators_at(height)
+    }
+
     pub fn get_root(&self) -> MerkleHash {
         self.root
     }
@@ -59,12 +95,17 @@ impl RuntimeAdapter for KeyValueRuntime {
         (self.store.store_update(), vec![MerkleHash::default()])
     }
 
+    fn get_trie(&self) -> Arc<Trie> {
+        self.trie.clone()
+    }
+
     fn compute_block_weight(
         &self,
         prev_header: &BlockHeader,
         header: &BlockHeader,
     ) -> Result<Weight, Error> {
-        let validator = &self.validators[(header.height as usize) % self.validators.len()];
+        let validators = self.validators_at(header.height);
+        let validator = &validators[(header.height as usize) % validators.len()];
         if !header.verify_block_producer(&validator.public_key) {
             return Err(ErrorKind::InvalidBlockProposer.into());
         }
@@ -74,9 +115,25 @@ impl RuntimeAdapter for KeyValueRuntime {
     fn get_epoch_block_proposers(
         &self,
         _epoch_hash: &CryptoHash,
-        _block_hash: &CryptoHash,
+        block_hash: &CryptoHash,
     ) -> Result<Vec<(AccountId, bool)>, Box<dyn std::error::Error>> {
-        Ok(self.validators.iter().map(|x| (x.account_id.clone(), false)).collect())
+        Ok(self
+            .validators_after(block_hash)
+            .iter()
+            .map(|x| (x.account_id.clone(), false))
+            .collect())
+    }
+
+    fn get_epoch_block_producers(
+        &self,
+        epoch_hash: &CryptoHash,
+    ) -> Result<EpochBlockProducers, Box<dyn std::error::Error>> {
+        let validators = self.validators_after(epoch_hash);
+        Ok(EpochBlockProducers {
+            epoch_hash: *epoch_hash,
+            validators: validators.to_vec(),
+            seats: (0..validators.len()).collect(),
+        })
     }
 
     fn get_block_proposer(
@@ -84,7 +141,8 @@ impl RuntimeAdapter for KeyValueRuntime {
         _epoch_hash: &CryptoHash,
         height: BlockIndex,
     ) -> Result<AccountId, Box<dyn std::error::Error>> {
-        Ok(self.validators[(height as usize) % self.validators.len()].account_id.clone())
+        let validators = self.validators_at(height);
+        Ok(validators[(height as usize) % validators.len()].account_id.clone())
     }
 
     fn get_chunk_proposer(
@@ -93,7 +151,8 @@ impl RuntimeAdapter for KeyValueRuntime {
         _parent_hash: CryptoHash,
         height: BlockIndex,
     ) -> Result<AccountId, Box<dyn std::error::Error>> {
-        Ok(self.validators[(height as usize) % self.validators.len()].account_id.clone())
+        let validators = self.validators_at(height);
+        Ok(validators[(height as usize) % validators.len()].account_id.clone())
     }
 
     fn check_validator_signature(
@@ -106,6 +165,7 @@ impl RuntimeAdapter for KeyValueRuntime {
         if let Some(validator) = self
             .validators
             .iter()
+            .flatten()
             .find(|&validator_stake| &validator_stake.account_id == account_id)
         {
             verify(data, signature, &validator.public_key)
@@ -122,13 +182,22 @@ impl RuntimeAdapter for KeyValueRuntime {
         0
     }
 
+    fn transaction_validity_period(&self) -> BlockIndex {
+        100
+    }
+
     fn validate_tx(
         &self,
         _shard_id: ShardId,
         _state_root: MerkleHash,
+        _block_index: BlockIndex,
+        reference_block_index: BlockIndex,
         transaction: SignedTransaction,
     ) -> Result<ValidTransaction, String> {
-        Ok(ValidTransaction { transaction })
+        let public_key = transaction.public_key.ok_or_else(|| {
+            "Transaction doesn't specify the public key it's signed with".to_string()
+        })?;
+        Ok(ValidTransaction { transaction, public_key, reference_block_index })
     }
 
     fn add_validator_proposals(
@@ -198,7 +267,6 @@ impl RuntimeAdapter for KeyValueRuntime {
         let path = path.split("/").collect::<Vec<_>>();
         Ok(QueryResponse::ViewAccount(AccountViewCallResult {
             account_id: path[1].to_string(),
-            nonce: 0,
             amount: 1000,
             stake: 0,
             public_keys: vec![],
@@ -206,28 +274,62 @@ impl RuntimeAdapter for KeyValueRuntime {
         }))
     }
 
-    fn dump_state(
+    fn query_proof(
+        &self,
+        state_root: MerkleHash,
+        path: &str,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        self.trie.get_proof(&state_root, path.as_bytes())
+    }
+
+    fn get_state_parts_info(
         &self,
         _shard_id: ShardId,
-        _state_root: MerkleHash,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        Ok(vec![])
+        state_root: MerkleHash,
+    ) -> Result<StatePartsInfo, Box<dyn std::error::Error>> {
+        self.trie.get_state_parts_info(&state_root, STATE_PART_MAX_SIZE)
     }
 
-    fn set_state(
+    fn obtain_state_part(
+        &self,
+        _shard_id: ShardId,
+        part_id: u64,
+        state_root: MerkleHash,
+    ) -> Result<StatePart, Box<dyn std::error::Error>> {
+        let info = self.trie.get_state_parts_info(&state_root, STATE_PART_MAX_SIZE)?;
+        self.trie.get_state_part(&state_root, part_id, &info.boundaries)
+    }
+
+    fn validate_state_part(
+        &self,
+        _shard_id: ShardId,
+        state_root: MerkleHash,
+        part_boundaries: &[Vec<u8>],
+        part: &StatePart,
+    ) -> bool {
+        Trie::validate_state_part(&state_root, part_boundaries, part)
+    }
+
+    fn confirm_state(
         &self,
         _shard_id: ShardId,
         _state_root: MerkleHash,
-        _payload: Vec<u8>,
+        _num_parts: u64,
+        _get_part: &dyn Fn(u64) -> Result<StatePart, Box<dyn std::error::Error>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
 }
 
 pub fn setup() -> (Chain, Arc<KeyValueRuntime>, Arc<InMemorySigner>) {
+    setup_with_archive(false)
+}
+
+/// Sets up chain that keeps all the blocks and state if `archive` is set.
+pub fn setup_with_archive(archive: bool) -> (Chain, Arc<KeyValueRuntime>, Arc<InMemorySigner>) {
     let store = create_test_store();
     let runtime = Arc::new(KeyValueRuntime::new(store.clone()));
-    let chain = Chain::new(store, runtime.clone(), Utc::now()).unwrap();
+    let chain = Chain::new(store, runtime.clone(), Utc::now(), archive).unwrap();
     let signer = Arc::new(InMemorySigner::from_seed("test", "test"));
     (chain, runtime, signer)
 }
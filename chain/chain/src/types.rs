@@ -1,13 +1,14 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub use near_primitives::block::{Block, BlockHeader, Weight};
-use near_primitives::crypto::signature::Signature;
-use near_primitives::crypto::signer::EDSigner;
+use near_primitives::crypto::signature::{PublicKey, Signature};
+use near_primitives::crypto::signer::{EDSigner, SignerError};
 use near_primitives::hash::CryptoHash;
-use near_primitives::rpc::QueryResponse;
+use near_primitives::rpc::{EpochBlockProducers, QueryResponse};
 use near_primitives::transaction::{ReceiptTransaction, SignedTransaction, TransactionResult};
 use near_primitives::types::{AccountId, BlockIndex, MerkleHash, ShardId, ValidatorStake};
-use near_store::{StoreUpdate, WrappedTrieChanges};
+use near_store::{StatePart, StatePartsInfo, StoreUpdate, Trie, WrappedTrieChanges};
 
 use crate::error::Error;
 
@@ -36,6 +37,10 @@ pub enum Provenance {
 /// Information about valid transaction that was processed by chain + runtime.
 pub struct ValidTransaction {
     pub transaction: SignedTransaction,
+    /// Access key that signed the transaction, its nonces are independent of the other keys.
+    pub public_key: PublicKey,
+    /// Height of the block referenced by the transaction.
+    pub reference_block_index: BlockIndex,
 }
 
 /// Map of shard to list of receipts to send to it.
@@ -49,6 +54,9 @@ pub trait RuntimeAdapter: Send + Sync {
     /// StoreUpdate can be discarded if the chain past the genesis.
     fn genesis_state(&self) -> (StoreUpdate, Vec<MerkleHash>);
 
+    /// Returns trie that stores the state. Used to remove old state during garbage collection.
+    fn get_trie(&self) -> Arc<Trie>;
+
     /// Verify block producer validity and return weight of given block for fork choice rule.
     fn compute_block_weight(
         &self,
@@ -64,6 +72,12 @@ pub trait RuntimeAdapter: Send + Sync {
         block_hash: &CryptoHash,
     ) -> Result<Vec<(AccountId, bool)>, Box<dyn std::error::Error>>;
 
+    /// Validators and block producer seats of the epoch, used by light clients to verify headers.
+    fn get_epoch_block_producers(
+        &self,
+        epoch_hash: &CryptoHash,
+    ) -> Result<EpochBlockProducers, Box<dyn std::error::Error>>;
+
     /// Block proposer for given height for the main block. Return error if outside of known boundaries.
     fn get_block_proposer(
         &self,
@@ -94,11 +108,19 @@ pub trait RuntimeAdapter: Send + Sync {
     /// Account Id to Shard Id mapping, given current number of shards.
     fn account_id_to_shard_id(&self, account_id: &AccountId) -> ShardId;
 
+    /// Number of blocks after the block referenced by a transaction during which
+    /// this transaction can be included into the chain.
+    fn transaction_validity_period(&self) -> BlockIndex;
+
     /// Validate transaction and return transaction information relevant to ordering it in the mempool.
+    /// `block_index` is the height of the block the transaction is going to be included into and
+    /// `reference_block_index` is the height of the block referenced by the transaction.
     fn validate_tx(
         &self,
         shard_id: ShardId,
         state_root: MerkleHash,
+        block_index: BlockIndex,
+        reference_block_index: BlockIndex,
         transaction: SignedTransaction,
     ) -> Result<ValidTransaction, String>;
 
@@ -151,20 +173,47 @@ pub trait RuntimeAdapter: Send + Sync {
         data: &[u8],
     ) -> Result<QueryResponse, Box<dyn std::error::Error>>;
 
-    /// Read state as byte array from given state root.
-    fn dump_state(
+    /// Returns encoded trie nodes proving the state read by the query with given `path`.
+    fn query_proof(
+        &self,
+        state_root: MerkleHash,
+        path: &str,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>>;
+
+    /// Returns how the state of given shard at given state root is split into parts for state sync
+    /// and the total size of the state in bytes.
+    fn get_state_parts_info(
         &self,
         shard_id: ShardId,
         state_root: MerkleHash,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+    ) -> Result<StatePartsInfo, Box<dyn std::error::Error>>;
 
-    /// Set state that expected to be given state root with provided payload.
-    /// Returns error if failed to parse or if the resulting tree doesn't match the expected root.
-    fn set_state(
+    /// Returns given part of the state of the shard together with the proof against the state root.
+    fn obtain_state_part(
         &self,
-        _shard_id: ShardId,
+        shard_id: ShardId,
+        part_id: u64,
+        state_root: MerkleHash,
+    ) -> Result<StatePart, Box<dyn std::error::Error>>;
+
+    /// Checks that the state part is proven by the given state root and contains exactly the keys
+    /// of its range in the split by given part boundaries.
+    fn validate_state_part(
+        &self,
+        shard_id: ShardId,
         state_root: MerkleHash,
-        payload: Vec<u8>,
+        part_boundaries: &[Vec<u8>],
+        part: &StatePart,
+    ) -> bool;
+
+    /// Sets state of the shard from all of its parts, loading them with `get_part` a few at a
+    /// time. Returns error if the resulting tree doesn't match the expected root.
+    fn confirm_state(
+        &self,
+        shard_id: ShardId,
+        state_root: MerkleHash,
+        num_parts: u64,
+        get_part: &dyn Fn(u64) -> Result<StatePart, Box<dyn std::error::Error>>,
     ) -> Result<(), Box<dyn std::error::Error>>;
 }
 
@@ -198,6 +247,31 @@ impl Tip {
     }
 }
 
+/// Information about the state of a shard that is required to start downloading its parts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardStateSyncResponseHeader {
+    /// First keys of the state parts after the first one, see `near_store::state_part_range`.
+    pub part_boundaries: Vec<Vec<u8>>,
+    /// Total size of the state in bytes.
+    pub total_size: u64,
+    /// Receipts produced by the previous block that must be applied on top of this state.
+    pub receipts: Vec<ReceiptTransaction>,
+}
+
+impl ShardStateSyncResponseHeader {
+    /// Number of parts the state is split into.
+    pub fn num_parts(&self) -> u64 {
+        self.part_boundaries.len() as u64 + 1
+    }
+}
+
+/// Response to the state request: header of the state and / or requested parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardStateSyncResponse {
+    pub header: Option<ShardStateSyncResponseHeader>,
+    pub parts: Vec<StatePart>,
+}
+
 /// Block approval by other block producers.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockApproval {
@@ -207,9 +281,14 @@ pub struct BlockApproval {
 }
 
 impl BlockApproval {
-    pub fn new(hash: CryptoHash, signer: &dyn EDSigner, target: AccountId) -> Self {
-        let signature = signer.sign(hash.as_ref());
-        BlockApproval { hash, signature, target }
+    pub fn new(
+        hash: CryptoHash,
+        height: BlockIndex,
+        signer: &dyn EDSigner,
+        target: AccountId,
+    ) -> Result<Self, SignerError> {
+        let signature = signer.sign_approval(height, &hash)?;
+        Ok(BlockApproval { hash, signature, target })
     }
 }
 
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use near_chain::test_utils::{setup, setup_with_archive};
+use near_chain::{Block, ChainStoreAccess, ErrorKind, Provenance};
+use near_primitives::hash::CryptoHash;
+use near_primitives::test_utils::init_test_logger;
+use near_primitives::types::MerkleHash;
+
+#[test]
+fn clear_old_data_with_forks() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.genesis().clone();
+    let fork1 = Block::empty(&genesis, signer.clone());
+    let fork2 = Block::empty(&fork1.header, signer.clone());
+    let mut blocks = vec![Block::produce(
+        &genesis,
+        2,
+        MerkleHash::default(),
+        CryptoHash::default(),
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    )];
+    for _ in 0..8 {
+        let block = Block::empty(&blocks[blocks.len() - 1].header, signer.clone());
+        blocks.push(block);
+    }
+    let (fork1_hash, fork2_hash) = (fork1.hash(), fork2.hash());
+    chain.process_block(fork1, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    chain.process_block(fork2, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    for block in blocks.iter() {
+        chain.process_block(block.clone(), Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    assert_eq!(chain.head().unwrap().height, 10);
+    assert_eq!(chain.store().tail().unwrap().height, 0);
+
+    // Nothing is removed while the chain is shorter than the number of blocks to keep.
+    chain.clear_old_data(20).unwrap();
+    assert_eq!(chain.store().tail().unwrap().height, 0);
+    assert!(chain.block_exists(&fork1_hash).unwrap());
+
+    chain.clear_old_data(3).unwrap();
+    let tail = chain.store().tail().unwrap();
+    assert_eq!(tail.height, 7);
+    assert_eq!(tail.last_block_hash, blocks[5].hash());
+    // Genesis block survives, only its state is garbage collected.
+    assert!(chain.block_exists(&genesis.hash()).unwrap());
+    assert!(chain.get_block(&genesis.hash()).is_ok());
+    assert_eq!(
+        chain.get_post_state_root_if_kept(&genesis.hash()).unwrap_err().kind(),
+        ErrorKind::StateGarbageCollected(0, 7)
+    );
+    assert!(!chain.block_exists(&fork1_hash).unwrap());
+    assert!(!chain.block_exists(&fork2_hash).unwrap());
+    for block in blocks[..5].iter() {
+        assert!(!chain.block_exists(&block.hash()).unwrap());
+        assert!(chain.get_receipts(&block.hash()).is_err());
+        // Headers are kept.
+        assert!(chain.get_block_header(&block.hash()).is_ok());
+    }
+    for block in blocks[5..].iter() {
+        assert!(chain.block_exists(&block.hash()).unwrap());
+    }
+    assert!(chain.store().get_all_block_hashes_by_height(2).unwrap().is_empty());
+    assert_eq!(chain.get_block_by_height(10).unwrap().hash(), blocks[8].hash());
+
+    // State of the removed blocks is reported as garbage collected, the tail state is kept.
+    assert_eq!(
+        chain.get_post_state_root_if_kept(&blocks[0].hash()).unwrap_err().kind(),
+        ErrorKind::StateGarbageCollected(2, 7)
+    );
+    assert_eq!(
+        chain.get_post_state_root_if_kept(&fork2_hash).unwrap_err().kind(),
+        ErrorKind::StateGarbageCollected(2, 7)
+    );
+    assert!(chain.get_post_state_root_if_kept(&blocks[5].hash()).is_ok());
+}
+
+/// Archival node keeps no trie changes, as it never garbage collects the state.
+#[test]
+fn archive_keeps_no_trie_changes() {
+    init_test_logger();
+    for archive in vec![false, true] {
+        let (mut chain, _, signer) = setup_with_archive(archive);
+        let block = Block::empty(chain.genesis(), signer);
+        let hash = block.hash();
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+        assert!(chain.get_post_state_root(&hash).is_ok());
+        match chain.store().get_trie_changes(&hash) {
+            Ok(_) => assert!(!archive),
+            Err(err) => {
+                assert!(archive);
+                assert!(match err.kind() {
+                    ErrorKind::DBNotFoundErr(_) => true,
+                    _ => false,
+                });
+            }
+        }
+    }
+}
@@ -4,6 +4,7 @@ use near_chain::test_utils::setup;
 use near_chain::{Block, ErrorKind, Provenance};
 use near_primitives::hash::CryptoHash;
 use near_primitives::test_utils::init_test_logger;
+use near_primitives::transaction::TransactionBody;
 use near_primitives::types::MerkleHash;
 
 #[test]
@@ -109,3 +110,63 @@ fn build_chain_with_skips_and_forks() {
     assert!(chain.get_header_by_height(1).is_err());
     assert_eq!(chain.get_header_by_height(5).unwrap().height, 5);
 }
+
+/// Transactions can only refer to recent blocks on the same chain.
+#[test]
+fn transaction_reference() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let send_money = |block_hash: CryptoHash| {
+        TransactionBody::send_money(1, "test", "other", 1, block_hash).sign(&*signer)
+    };
+    let genesis = chain.genesis().clone();
+    let b1 = Block::empty(&genesis, signer.clone());
+    chain.process_block(b1.clone(), Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    let fork = Block::produce(
+        &genesis,
+        2,
+        genesis.prev_state_root,
+        genesis.epoch_hash,
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    chain.process_block(fork.clone(), Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, b1.hash());
+
+    assert_eq!(chain.get_transaction_reference_index(&send_money(genesis.hash())).unwrap(), 0);
+    assert_eq!(chain.get_transaction_reference_index(&send_money(b1.hash())).unwrap(), 1);
+    for hash in vec![fork.hash(), CryptoHash::default()] {
+        assert_eq!(
+            chain.get_transaction_reference_index(&send_money(hash)).unwrap_err().kind(),
+            ErrorKind::InvalidTransactionReference
+        );
+    }
+    let block = Block::produce(
+        &b1.header,
+        2,
+        b1.header.prev_state_root,
+        b1.header.epoch_hash,
+        vec![send_money(fork.hash())],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    assert_eq!(
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap_err().kind(),
+        ErrorKind::InvalidTransactionReference
+    );
+
+    // Transaction validity period of the test runtime is 100 blocks.
+    while chain.head().unwrap().height < 100 {
+        let prev = chain.head_header().unwrap().clone();
+        let block = Block::empty(&prev, signer.clone());
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    assert_eq!(
+        chain.get_transaction_reference_index(&send_money(genesis.hash())).unwrap_err().kind(),
+        ErrorKind::ExpiredTransaction
+    );
+    assert_eq!(chain.get_transaction_reference_index(&send_money(b1.hash())).unwrap(), 1);
+}
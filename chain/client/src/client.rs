@@ -1,36 +1,39 @@
 //! Client is responsible for tracking the chain and related pieces of infrastructure.
 //! Block production is done in done in this actor as well (at the moment).
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ops::Sub;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use actix::prelude::SendError;
 use actix::{
     Actor, ActorFuture, Addr, AsyncContext, Context, ContextFutureSpawner, Handler, Recipient,
-    WrapFuture,
+    ResponseFuture, WrapFuture,
 };
 use chrono::{DateTime, Utc};
 use futures::Future;
 use log::{debug, error, info, warn};
 
 use near_chain::{
-    Block, BlockApproval, BlockHeader, BlockStatus, Chain, ErrorKind, Provenance, RuntimeAdapter,
-    ValidTransaction,
+    Block, BlockApproval, BlockHeader, BlockStatus, Chain, Provenance, RuntimeAdapter,
+    ShardStateSyncResponse, ValidTransaction,
 };
 use near_network::types::{
-    AnnounceAccount, AnnounceAccountRoute, NetworkInfo, PeerId, ReasonForBan,
+    AnnounceAccount, AnnounceAccountRoute, NetworkGraphView, NetworkInfo, PeerBehaviour, PeerId,
+    ReasonForBan, StateResponseInfo,
 };
 use near_network::{
     NetworkClientMessages, NetworkClientResponses, NetworkRequests, NetworkResponses,
 };
 use near_pool::TransactionPool;
 use near_primitives::crypto::signature::{verify, Signature};
+use near_primitives::crypto::signer::SignerError;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::rpc::ValidatorInfo;
-use near_primitives::transaction::{ReceiptTransaction, SignedTransaction};
-use near_primitives::types::{AccountId, BlockIndex, ShardId};
+use near_primitives::transaction::SignedTransaction;
+use near_primitives::types::{AccountId, BlockIndex};
 use near_primitives::unwrap_or_return;
 use near_store::Store;
 use near_telemetry::TelemetryActor;
@@ -38,11 +41,18 @@ use near_telemetry::TelemetryActor;
 use crate::info::InfoHelper;
 use crate::sync::{most_weight_peer, BlockSync, HeaderSync, StateSync};
 use crate::types::{
-    BlockProducer, ClientConfig, Error, ShardSyncStatus, Status, StatusSyncInfo, SyncStatus,
+    BlockNotification, BlockProducer, ClientConfig, Clock, Error, GetNetworkGraph, RunTimers,
+    Status, StatusSyncInfo, SubscribeBlocks, SyncStatus,
 };
 use crate::{sync, StatusResponse};
 use std::cmp::max;
 
+/// Number of recent transaction hashes remembered to forward each transaction only once.
+const SEEN_TXS_CACHE_SIZE: usize = 10_000;
+
+/// Callback scheduled to run on the client after a delay.
+type Timer = Box<dyn FnOnce(&mut ClientActor, &mut Context<ClientActor>) + Send>;
+
 pub struct ClientActor {
     config: ClientConfig,
     sync_status: SyncStatus,
@@ -69,6 +79,16 @@ pub struct ClientActor {
     last_val_announce_height: Option<BlockIndex>,
     /// Info helper.
     info_helper: InfoHelper,
+    /// Recipients of notifications about accepted blocks.
+    block_subscribers: Vec<Recipient<BlockNotification>>,
+    /// Hashes of transactions already forwarded to the block producers.
+    seen_txs: HashSet<CryptoHash>,
+    /// Hashes in `seen_txs` from the oldest, to forget them when there are too many.
+    seen_txs_order: VecDeque<CryptoHash>,
+    /// Timers scheduled with virtual clock, by deadline and order of scheduling.
+    timers: BTreeMap<(Instant, u64), Timer>,
+    /// Number of timers scheduled so far, to order timers with the same deadline.
+    num_timers: u64,
 }
 
 fn wait_until_genesis(genesis_time: &DateTime<Utc>) {
@@ -95,16 +115,20 @@ impl ClientActor {
         telemetry_actor: Addr<TelemetryActor>,
     ) -> Result<Self, Error> {
         wait_until_genesis(&genesis_time);
-        let chain = Chain::new(store, runtime_adapter.clone(), genesis_time)?;
-        let tx_pool = TransactionPool::new();
+        let chain = Chain::new(store, runtime_adapter.clone(), genesis_time, config.archive)?;
+        let tx_pool =
+            TransactionPool::new(config.tx_pool_max_size, config.tx_pool_max_size_per_account);
         let sync_status = SyncStatus::AwaitingPeers;
-        let header_sync = HeaderSync::new(network_actor.clone());
-        let block_sync = BlockSync::new(network_actor.clone(), config.block_fetch_horizon);
-        let state_sync = StateSync::new(network_actor.clone(), config.state_fetch_horizon);
+        let header_sync = HeaderSync::new(network_actor.clone(), config.clock.clone());
+        let block_sync =
+            BlockSync::new(network_actor.clone(), config.block_fetch_horizon, config.clock.clone());
+        let state_sync =
+            StateSync::new(network_actor.clone(), config.state_fetch_horizon, config.clock.clone());
         if let Some(bp) = &block_producer {
             info!(target: "client", "Starting validator node: {}", bp.account_id);
         }
         let info_helper = InfoHelper::new(telemetry_actor, block_producer.clone());
+        let last_block_processed = config.clock.now();
         Ok(ClientActor {
             config,
             sync_status,
@@ -121,14 +145,20 @@ impl ClientActor {
                 received_bytes_per_sec: 0,
                 sent_bytes_per_sec: 0,
                 routes: None,
+                graph: None,
             },
             approvals: HashMap::default(),
-            last_block_processed: Instant::now(),
+            last_block_processed,
             header_sync,
             block_sync,
             state_sync,
             last_val_announce_height: None,
             info_helper,
+            block_subscribers: vec![],
+            seen_txs: HashSet::default(),
+            seen_txs_order: VecDeque::default(),
+            timers: BTreeMap::default(),
+            num_timers: 0,
         })
     }
 
@@ -207,13 +237,7 @@ impl Handler<NetworkClientMessages> for ClientActor {
 
     fn handle(&mut self, msg: NetworkClientMessages, ctx: &mut Context<Self>) -> Self::Result {
         match msg {
-            NetworkClientMessages::Transaction(tx) => match self.validate_tx(tx) {
-                Ok(valid_transaction) => {
-                    self.tx_pool.insert_transaction(valid_transaction);
-                    NetworkClientResponses::ValidTx
-                }
-                Err(err) => NetworkClientResponses::InvalidTx(err),
-            },
+            NetworkClientMessages::Transaction(tx) => self.process_tx(tx),
             NetworkClientMessages::BlockHeader(header, peer_id) => {
                 self.receive_header(header, peer_id)
             }
@@ -261,42 +285,45 @@ impl Handler<NetworkClientMessages> for ClientActor {
                     NetworkClientResponses::Ban { ban_reason: ReasonForBan::BadBlockApproval }
                 }
             }
-            NetworkClientMessages::StateRequest(shard_id, hash) => {
-                if let Ok((payload, receipts)) = self.state_request(shard_id, hash) {
-                    return NetworkClientResponses::StateResponse {
-                        shard_id,
-                        hash,
-                        payload,
-                        receipts,
-                    };
+            NetworkClientMessages::StateRequest(shard_id, hash, need_header, parts_ids) => {
+                let header = if need_header {
+                    match self.chain.get_state_response_header(shard_id, hash) {
+                        Ok(header) => Some(header),
+                        Err(err) => {
+                            error!(target: "client", "Failed to get state header for shard {} @ {}: {}", shard_id, hash, err);
+                            return NetworkClientResponses::NoResponse;
+                        }
+                    }
+                } else {
+                    None
+                };
+                let mut parts = vec![];
+                for part_id in parts_ids.into_iter().take(sync::MAX_STATE_PARTS_PER_RESPONSE) {
+                    match self.chain.get_state_response_part(shard_id, part_id, hash) {
+                        Ok(part) => parts.push(part),
+                        Err(err) => {
+                            error!(target: "client", "Failed to get state part {} for shard {} @ {}: {}", part_id, shard_id, hash, err);
+                            return NetworkClientResponses::NoResponse;
+                        }
+                    }
                 }
-                NetworkClientResponses::NoResponse
+                NetworkClientResponses::StateResponse(StateResponseInfo {
+                    shard_id,
+                    hash,
+                    shard_state: ShardStateSyncResponse { header, parts },
+                })
             }
-            NetworkClientMessages::StateResponse(shard_id, hash, payload, receipts) => {
-                if let SyncStatus::StateSync(sync_hash, sharded_statuses) = &mut self.sync_status {
-                    if hash != *sync_hash {
-                        sharded_statuses.insert(
-                            shard_id,
-                            ShardSyncStatus::Error(format!(
-                                "Incorrect hash of the state response, expected: {}, got: {}",
-                                sync_hash, hash
-                            )),
-                        );
-                    } else {
-                        match self.chain.set_shard_state(shard_id, hash, payload, receipts) {
-                            Ok(()) => {
-                                sharded_statuses.insert(shard_id, ShardSyncStatus::StateDone);
-                            }
-                            Err(err) => {
-                                sharded_statuses.insert(
-                                    shard_id,
-                                    ShardSyncStatus::Error(format!(
-                                        "Failed to set state for {} @ {}: {}",
-                                        shard_id, hash, err
-                                    )),
-                                );
-                            }
-                        }
+            NetworkClientMessages::StateResponse(response, peer_id) => {
+                if let SyncStatus::StateSync(sync_hash, _) = &self.sync_status {
+                    if response.hash != *sync_hash {
+                        debug!(target: "client", "Ignoring state response for {}, syncing state at {}", response.hash, sync_hash);
+                    } else if let Err(err) = self.state_sync.process_state_response(
+                        &mut self.chain,
+                        *sync_hash,
+                        response,
+                        &peer_id,
+                    ) {
+                        error!(target: "client", "Failed to process state response from {}: {}", peer_id, err);
                     }
                 }
                 NetworkClientResponses::NoResponse
@@ -352,7 +379,64 @@ impl Handler<Status> for ClientActor {
     }
 }
 
+impl Handler<GetNetworkGraph> for ClientActor {
+    type Result = ResponseFuture<NetworkGraphView, String>;
+
+    fn handle(&mut self, _: GetNetworkGraph, _: &mut Context<Self>) -> Self::Result {
+        Box::new(
+            self.network_actor
+                .send(NetworkRequests::FetchInfo { level: 1 })
+                .map_err(|err| err.to_string())
+                .and_then(|res| match res {
+                    NetworkResponses::Info(NetworkInfo { graph: Some(graph), .. }) => Ok(graph),
+                    _ => Err("Network graph is not available".to_string()),
+                }),
+        )
+    }
+}
+
+impl Handler<SubscribeBlocks> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeBlocks, _: &mut Context<Self>) {
+        self.block_subscribers.push(msg.recipient);
+    }
+}
+
+impl Handler<RunTimers> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, _: RunTimers, ctx: &mut Context<Self>) {
+        let now = self.config.clock.now();
+        // Timers can schedule new timers that are already due, so take them one at a time.
+        loop {
+            let key = match self.timers.keys().next() {
+                Some(key) if key.0 <= now => *key,
+                _ => break,
+            };
+            let timer = self.timers.remove(&key).expect("Timer was just found");
+            timer(self, ctx);
+        }
+    }
+}
+
 impl ClientActor {
+    /// Runs `f` after `delay`, measured by the configured clock.
+    fn run_later<F>(&mut self, ctx: &mut Context<ClientActor>, delay: Duration, f: F)
+    where
+        F: FnOnce(&mut ClientActor, &mut Context<ClientActor>) + Send + 'static,
+    {
+        match self.config.clock {
+            Clock::Real => {
+                ctx.run_later(delay, f);
+            }
+            Clock::Virtual { .. } => {
+                self.timers.insert((self.config.clock.now() + delay, self.num_timers), Box::new(f));
+                self.num_timers += 1;
+            }
+        }
+    }
+
     /// Gets called when block got accepted.
     /// Send updates over network, update tx pool and notify ourselves if it's time to produce next block.
     fn on_block_accepted(
@@ -371,7 +455,7 @@ impl ClientActor {
         };
 
         // Update when last block was processed.
-        self.last_block_processed = Instant::now();
+        self.last_block_processed = self.config.clock.now();
 
         // Count blocks and transactions processed both in SYNC and regular modes.
         self.info_helper.block_processed(block.transactions.len() as u64);
@@ -403,9 +487,36 @@ impl ClientActor {
         // We only want to reconcile the txpool against the new block *if* total weight has increased.
         if status == BlockStatus::Next || status == BlockStatus::Reorg {
             self.tx_pool.reconcile_block(&block);
+            // Transactions that can't be included into the next block have expired.
+            let validity_period = self.runtime_adapter.transaction_validity_period();
+            self.tx_pool.remove_expired((block.header.height + 1).saturating_sub(validity_period));
         }
 
         self.check_send_announce_account(&block.hash(), block.header.height);
+
+        // Push the block to subscribers, forgetting the ones that have stopped.
+        if !self.block_subscribers.is_empty() {
+            let notification = BlockNotification {
+                block: block.clone(),
+                is_new_head: status == BlockStatus::Next || status == BlockStatus::Reorg,
+            };
+            self.block_subscribers.retain(|recipient| {
+                match recipient.do_send(notification.clone()) {
+                    Err(SendError::Closed(_)) => false,
+                    _ => true,
+                }
+            });
+        }
+
+        // Remove data of old blocks, unless this is an archival node.
+        if !self.config.archive && (status == BlockStatus::Next || status == BlockStatus::Reorg) {
+            if let Err(err) = self
+                .chain
+                .clear_old_data(self.config.gc_num_epochs_to_keep * self.config.epoch_length)
+            {
+                error!(target: "client", "Failed to clear old data: {}", err);
+            }
+        }
     }
 
     /// Check if client Account Id should be sent and send it.
@@ -449,8 +560,14 @@ impl ClientActor {
                     .iter()
                     .any(|account_id| (&(account_id.0) == &block_producer.account_id))
                 {
+                    let (hash, signature) = match self.sign_announce_account(epoch_hash) {
+                        Ok(signed) => signed,
+                        Err(err) => {
+                            error!(target: "client", "Failed to sign account announcement: {}", err);
+                            return;
+                        }
+                    };
                     self.last_val_announce_height = Some(epoch_height);
-                    let (hash, signature) = self.sign_announce_account(epoch_hash).unwrap();
 
                     actix::spawn(
                         self.network_actor
@@ -469,17 +586,24 @@ impl ClientActor {
         }
     }
 
-    fn sign_announce_account(&self, epoch: CryptoHash) -> Result<(CryptoHash, Signature), ()> {
+    fn sign_announce_account(
+        &self,
+        epoch: CryptoHash,
+    ) -> Result<(CryptoHash, Signature), SignerError> {
         if let Some(block_producer) = self.block_producer.as_ref() {
             let hash = AnnounceAccount::build_header_hash(
                 &block_producer.account_id,
                 &self.node_id,
                 epoch,
             );
-            let signature = block_producer.signer.sign(hash.as_ref());
+            let signature = block_producer.signer.sign_announce_account(
+                &block_producer.account_id,
+                self.node_id.as_ref(),
+                &epoch,
+            )?;
             Ok((hash, signature))
         } else {
-            Err(())
+            Err(SignerError::Other("Not a block producer".to_string()))
         }
     }
 
@@ -530,11 +654,18 @@ impl ClientActor {
                         validators.into_iter().find(|v| v.0 == block_producer.account_id)
                     {
                         if !is_slashed {
-                            return Some(BlockApproval::new(
+                            return match BlockApproval::new(
                                 block.hash(),
+                                block.header.height,
                                 &*block_producer.signer,
                                 next_block_producer_account.clone(),
-                            ));
+                            ) {
+                                Ok(approval) => Some(approval),
+                                Err(err) => {
+                                    error!(target: "client", "Failed to approve block {}: {}", block.hash(), err);
+                                    None
+                                }
+                            };
                         }
                     }
                 }
@@ -560,12 +691,12 @@ impl ClientActor {
             unwrap_or_return!(self.get_block_proposer(&epoch_hash, check_height + 1), ());
         if let Some(block_producer) = &self.block_producer {
             if block_producer.account_id.clone() == next_block_producer_account {
-                ctx.run_later(self.config.min_block_production_delay, move |act, ctx| {
+                self.run_later(ctx, self.config.min_block_production_delay, move |act, ctx| {
                     act.produce_block(ctx, block_hash, last_height, check_height + 1);
                 });
             } else {
                 // Otherwise, schedule timeout to check if the next block was produced.
-                ctx.run_later(self.config.max_block_production_delay, move |act, ctx| {
+                self.run_later(ctx, self.config.max_block_production_delay, move |act, ctx| {
                     act.check_block_timeout(ctx, last_height, check_height);
                 });
             }
@@ -589,7 +720,7 @@ impl ClientActor {
         }
         debug!(target: "client", "Timeout for {}, current head {}, suggesting to skip", last_height, head.height);
         // Update how long ago last block arrived to reset block production timer.
-        self.last_block_processed = Instant::now();
+        self.last_block_processed = self.config.clock.now();
         self.handle_scheduling_block_production(
             ctx,
             head.last_block_hash,
@@ -652,12 +783,14 @@ impl ClientActor {
         // If epoch changed, and before there was 2 validators and now there is 1 - prev_same_bp is false, but total validators right now is 1.
         let total_approvals =
             total_validators - max(if prev_same_bp { 1 } else { 2 }, total_validators);
+        let elapsed = self.config.clock.now() - self.last_block_processed;
         if self.approvals.len() < total_approvals
-            && self.last_block_processed.elapsed() < self.config.max_block_production_delay
+            && elapsed < self.config.max_block_production_delay
         {
             // Schedule itself for (max BP delay - how much time passed).
-            ctx.run_later(
-                self.config.max_block_production_delay.sub(self.last_block_processed.elapsed()),
+            self.run_later(
+                ctx,
+                self.config.max_block_production_delay.sub(elapsed),
                 move |act, ctx| {
                     act.produce_block(ctx, head.last_block_hash, last_height, next_height);
                 },
@@ -680,10 +813,13 @@ impl ClientActor {
         let validator_proposals =
             self.chain.get_post_validator_proposals(&head.last_block_hash)?.clone();
 
-        let prev_header = self.chain.get_block_header(&head.last_block_hash)?;
+        // Take transactions from the pool, skipping the ones that refer to blocks not on this chain.
+        let mut transactions =
+            self.tx_pool.prepare_transactions(self.config.block_expected_weight)?;
+        let chain = &mut self.chain;
+        transactions.retain(|tx| chain.get_transaction_reference_index(tx).is_ok());
 
-        // Take transactions from the pool.
-        let transactions = self.tx_pool.prepare_transactions(self.config.block_expected_weight)?;
+        let prev_header = self.chain.get_block_header(&head.last_block_hash)?;
 
         // At this point, the previous epoch hash must be available
         let (epoch_hash, _) = self
@@ -691,7 +827,7 @@ impl ClientActor {
             .get_epoch_offset(head.last_block_hash, next_height)
             .expect("Epoch hash should exist at this point");
 
-        let block = Block::produce(
+        let block = Block::try_produce(
             &prev_header,
             next_height,
             state_root,
@@ -700,7 +836,8 @@ impl ClientActor {
             self.approvals.drain().collect(),
             validator_proposals,
             block_producer.signer.clone(),
-        );
+        )
+        .map_err(|err| Error::BlockProducer(err.to_string()))?;
 
         self.process_block(ctx, block, Provenance::PRODUCED).map(|_| ()).map_err(|err| err.into())
     }
@@ -741,7 +878,7 @@ impl ClientActor {
         let provenance =
             if was_requested { near_chain::Provenance::SYNC } else { near_chain::Provenance::NONE };
         match self.process_block(ctx, block, provenance) {
-            Ok(_) => NetworkClientResponses::NoResponse,
+            Ok(_) => NetworkClientResponses::Report { behaviour: PeerBehaviour::UsefulBlock },
             Err(ref err) if err.is_bad_data() => {
                 NetworkClientResponses::Ban { ban_reason: ReasonForBan::BadBlock }
             }
@@ -762,6 +899,10 @@ impl ClientActor {
                     }
                     NetworkClientResponses::NoResponse
                 }
+                near_chain::ErrorKind::Unfit(_) if !was_requested => {
+                    debug!("Process block: block {} refused by chain: {}", hash, e.kind());
+                    NetworkClientResponses::Report { behaviour: PeerBehaviour::UselessMessage }
+                }
                 _ => {
                     debug!("Process block: block {} refused by chain: {}", hash, e.kind());
                     NetworkClientResponses::NoResponse
@@ -862,7 +1003,71 @@ impl ClientActor {
             .get_post_state_root(&head.last_block_hash)
             .map_err(|err| err.to_string())?
             .clone();
-        self.runtime_adapter.validate_tx(0, state_root, tx)
+        let reference_block_index =
+            self.chain.get_transaction_reference_index(&tx).map_err(|err| err.to_string())?;
+        self.runtime_adapter.validate_tx(0, state_root, head.height + 1, reference_block_index, tx)
+    }
+
+    /// Inserts valid transaction into the mempool if this node is one of the upcoming block
+    /// producers. Otherwise forwards it to them, once per transaction, since the same transaction
+    /// comes back when it's gossiped.
+    fn process_tx(&mut self, tx: SignedTransaction) -> NetworkClientResponses {
+        let tx_hash = tx.get_hash();
+        // Transaction was already forwarded, it is on its way to the block producers.
+        if self.seen_txs.contains(&tx_hash) {
+            return NetworkClientResponses::RequestRouted;
+        }
+        let valid_transaction = match self.validate_tx(tx) {
+            Ok(valid_transaction) => valid_transaction,
+            Err(err) => return NetworkClientResponses::InvalidTx(err),
+        };
+        let account_ids = match self.upcoming_block_producers() {
+            Ok(account_ids) => account_ids,
+            Err(err) => return NetworkClientResponses::InvalidTx(err.to_string()),
+        };
+        let is_upcoming_block_producer = match &self.block_producer {
+            Some(block_producer) => account_ids.contains(&block_producer.account_id),
+            None => false,
+        };
+        if is_upcoming_block_producer {
+            return match self.tx_pool.insert_transaction(valid_transaction) {
+                Ok(()) => NetworkClientResponses::ValidTx,
+                Err(err) => NetworkClientResponses::InvalidTx(err.to_string()),
+            };
+        }
+        if self.network_info.num_active_peers == 0 {
+            debug!(target: "client", "No peers to forward transaction {} to", tx_hash);
+            return NetworkClientResponses::NoResponse;
+        }
+        self.seen_txs.insert(tx_hash);
+        self.seen_txs_order.push_back(tx_hash);
+        if self.seen_txs_order.len() > SEEN_TXS_CACHE_SIZE {
+            if let Some(oldest) = self.seen_txs_order.pop_front() {
+                self.seen_txs.remove(&oldest);
+            }
+        }
+        let _ = self.network_actor.do_send(NetworkRequests::ForwardTx {
+            account_ids,
+            transaction: valid_transaction.transaction,
+        });
+        NetworkClientResponses::RequestRouted
+    }
+
+    /// Accounts of the block producers of the next `tx_routing_height_horizon` heights.
+    fn upcoming_block_producers(&self) -> Result<Vec<AccountId>, Error> {
+        let head = self.chain.head()?;
+        let mut account_ids = vec![];
+        for height in head.height + 1..=head.height + self.config.tx_routing_height_horizon {
+            let (epoch_hash, _) = self
+                .runtime_adapter
+                .get_epoch_offset(head.last_block_hash, height)
+                .map_err(|err| Error::Other(err.to_string()))?;
+            let account_id = self.get_block_proposer(&epoch_hash, height)?;
+            if !account_ids.contains(&account_id) {
+                account_ids.push(account_id);
+            }
+        }
+        Ok(account_ids)
     }
 
     /// Check whether need to (continue) sync.
@@ -911,7 +1116,7 @@ impl ClientActor {
         if self.network_info.num_active_peers < self.config.min_num_peers
             && !self.config.skip_sync_wait
         {
-            ctx.run_later(self.config.sync_step_period, move |act, ctx| {
+            self.run_later(ctx, self.config.sync_step_period, move |act, ctx| {
                 act.start_sync(ctx);
             });
             return;
@@ -927,7 +1132,7 @@ impl ClientActor {
             Ok(v) => v,
             Err(err) => {
                 error!(target: "sync", "Sync: Unexpected error: {}", err);
-                ctx.run_later(self.config.sync_step_period, move |act, ctx| {
+                self.run_later(ctx, self.config.sync_step_period, move |act, ctx| {
                     act.sync(ctx);
                 });
                 return;
@@ -941,7 +1146,7 @@ impl ClientActor {
 
         if !needs_syncing {
             if currently_syncing {
-                self.last_block_processed = Instant::now();
+                self.last_block_processed = self.config.clock.now();
                 self.sync_status = SyncStatus::NoSync;
 
                 // Initial transition out of "syncing" state.
@@ -992,7 +1197,7 @@ impl ClientActor {
             }
         }
 
-        ctx.run_later(wait_period, move |act, ctx| {
+        self.run_later(ctx, wait_period, move |act, ctx| {
             act.sync(ctx);
         });
     }
@@ -1016,14 +1221,14 @@ impl ClientActor {
             })
             .wait(ctx);
 
-        ctx.run_later(self.config.fetch_info_period, move |act, ctx| {
+        self.run_later(ctx, self.config.fetch_info_period, move |act, ctx| {
             act.fetch_network_info(ctx);
         });
     }
 
     /// Periodically log summary.
-    fn log_summary(&self, ctx: &mut Context<Self>) {
-        ctx.run_later(self.config.log_summary_period, move |act, ctx| {
+    fn log_summary(&mut self, ctx: &mut Context<Self>) {
+        self.run_later(ctx, self.config.log_summary_period, move |act, ctx| {
             let head = unwrap_or_return!(act.chain.head(), ());
             let validators = unwrap_or_return!(
                 act.get_epoch_block_proposers(&head.epoch_hash, &head.last_block_hash),
@@ -1100,19 +1305,4 @@ impl ClientActor {
         self.approvals.insert(position, signature.clone());
         true
     }
-
-    fn state_request(
-        &mut self,
-        shard_id: ShardId,
-        hash: CryptoHash,
-    ) -> Result<(Vec<u8>, Vec<ReceiptTransaction>), near_chain::Error> {
-        let header = self.chain.get_block_header(&hash)?;
-        let prev_hash = header.prev_hash;
-        let payload = self
-            .runtime_adapter
-            .dump_state(shard_id, header.prev_state_root)
-            .map_err(|err| ErrorKind::Other(err.to_string()))?;
-        let receipts = self.chain.get_receipts(&prev_hash)?.clone();
-        Ok((payload, receipts))
-    }
 }
@@ -11,6 +11,7 @@ use near_network::types::{NetworkInfo, PeerId};
 use near_primitives::serialize::to_base;
 use near_telemetry::{telemetry, TelemetryActor};
 
+use crate::metrics;
 use crate::types::{BlockProducer, ShardSyncStatus, SyncStatus};
 use std::cmp::min;
 
@@ -51,6 +52,7 @@ impl InfoHelper {
     pub fn block_processed(&mut self, num_transactions: u64) {
         self.num_blocks_processed += 1;
         self.num_tx_processed += num_transactions;
+        near_metrics::inc_counter_by(&metrics::TRANSACTION_PROCESSED_TOTAL, num_transactions);
     }
 
     pub fn info(
@@ -62,6 +64,9 @@ impl InfoHelper {
         is_validator: bool,
         num_validators: usize,
     ) {
+        near_metrics::set_gauge(&metrics::BLOCK_HEIGHT_HEAD, head.height as i64);
+        near_metrics::set_gauge(&metrics::SYNC_STATUS, sync_status.as_metric());
+
         let (cpu_usage, memory) = if let Some(pid) = self.pid {
             if self.sys.refresh_process(pid) {
                 let proc = self
@@ -123,7 +128,9 @@ fn try_sign_json(
     let mut signature = "".to_string();
     if let Some(bp) = block_producer {
         if let Ok(s) = serde_json::to_string(&value) {
-            signature = to_base(&bp.signer.sign(s.as_bytes()));
+            if let Ok(s) = bp.signer.try_sign(s.as_bytes()) {
+                signature = to_base(&s);
+            }
         }
     }
     value["signature"] = signature.into();
@@ -159,9 +166,15 @@ fn display_sync_status(sync_status: &SyncStatus, head: &Tip) -> String {
                                 start_time: _,
                                 prev_update_time: _,
                                 prev_downloaded_size: _,
-                                downloaded_size: _,
-                                total_size: _,
-                            } => format!("download"),
+                                downloaded_size,
+                                total_size,
+                            } => {
+                                if *total_size > 0 {
+                                    format!("download {}%", downloaded_size * 100 / total_size)
+                                } else {
+                                    format!("download")
+                                }
+                            }
                             ShardSyncStatus::StateValidation => format!("validation"),
                             ShardSyncStatus::StateDone => format!("done"),
                             ShardSyncStatus::Error(error) => format!("error {}", error),
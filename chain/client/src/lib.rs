@@ -1,12 +1,14 @@
 pub use crate::client::ClientActor;
 pub use crate::types::{
-    BlockProducer, ClientConfig, Error, GetBlock, Query, Status, StatusResponse,
-    SyncStatus, TxDetails, TxStatus,
+    BlockNotification, BlockProducer, ClientConfig, Clock, Error, GetBlock, GetBlockHeader,
+    GetLightClientProof, GetNetworkGraph, Query, RunTimers, Status, StatusResponse,
+    SubscribeBlocks, SyncStatus, TxDetails, TxStatus,
 };
 pub use crate::view_client::ViewClientActor;
 
 mod client;
 mod info;
+mod metrics;
 mod sync;
 pub mod test_utils;
 mod types;
@@ -0,0 +1,20 @@
+use lazy_static::lazy_static;
+use near_metrics::{IntCounter, IntGauge};
+
+lazy_static! {
+    pub static ref BLOCK_HEIGHT_HEAD: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge(
+            "near_block_height_head",
+            "Height of the current head of the chain"
+        );
+    pub static ref SYNC_STATUS: near_metrics::Result<IntGauge> = near_metrics::try_create_int_gauge(
+        "near_sync_status",
+        "Sync status: 0 - awaiting peers, 1 - no sync, 2 - header sync, 3 - state sync, \
+         4 - state sync done, 5 - body sync"
+    );
+    pub static ref TRANSACTION_PROCESSED_TOTAL: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_transaction_processed_total",
+            "Total number of transactions in the processed blocks"
+        );
+}
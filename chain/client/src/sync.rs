@@ -6,14 +6,15 @@ use chrono::{DateTime, Duration, Utc};
 use log::{debug, error, info};
 use rand::{thread_rng, Rng};
 
-use near_chain::{Chain, Tip};
-use near_network::types::ReasonForBan;
+use near_chain::{Chain, ChainStoreAccess, ErrorKind, ShardStateSyncResponseHeader, Tip};
+use near_network::types::{PeerBehaviour, PeerId, ReasonForBan, StateResponseInfo};
 use near_network::{FullPeerInfo, NetworkRequests};
 use near_primitives::hash::CryptoHash;
 use near_primitives::types::{BlockIndex, ShardId};
 use near_primitives::unwrap_or_return;
+use near_store::STATE_PART_MAX_SIZE;
 
-use crate::types::{ShardSyncStatus, SyncStatus};
+use crate::types::{Clock, ShardSyncStatus, SyncStatus};
 
 /// Maximum number of block headers send over the network.
 pub const MAX_BLOCK_HEADERS: u64 = 512;
@@ -33,8 +34,25 @@ const BLOCK_REQUEST_TIMEOUT: i64 = 6;
 const BLOCK_SOME_RECEIVED_TIMEOUT: i64 = 1;
 const BLOCK_REQUEST_BROADCAST_OFFSET: u64 = 2;
 
-/// Sync state download timeout in minutes.
-const STATE_SYNC_TIMEOUT: i64 = 10;
+/// Timeout for the state header or part request in seconds, after which it's sent to another peer.
+const STATE_REQUEST_TIMEOUT: i64 = 30;
+
+/// Maximum number of state parts requested from a single peer at the same time.
+const MAX_STATE_PARTS_PER_PEER: usize = 2;
+
+/// Maximum number of parts of the shard state, which allows up to 16 GiB of state.
+const MAX_STATE_PARTS: u64 = 1 << 16;
+
+/// Maximum number of state parts sent in a single state response.
+pub const MAX_STATE_PARTS_PER_RESPONSE: usize = 4;
+
+/// Number of different peers that have to send the same state header before it's accepted, or
+/// all the peers if there are fewer of them.
+const STATE_HEADER_CONFIRMATIONS: usize = 2;
+
+/// Number of different peers whose state parts have to fail against the state header before the
+/// header is dropped and the peers that sent it are banned.
+const MAX_STATE_PART_FAILURES: usize = 3;
 
 /// Get random peer from the most weighted peers.
 pub fn most_weight_peer(most_weight_peers: &Vec<FullPeerInfo>) -> Option<FullPeerInfo> {
@@ -45,6 +63,22 @@ pub fn most_weight_peer(most_weight_peers: &Vec<FullPeerInfo>) -> Option<FullPee
     Some(most_weight_peers[index].clone())
 }
 
+/// Reports peer that didn't respond to the request in time, which lowers its score.
+fn report_timeout(network_recipient: &Recipient<NetworkRequests>, peer_id: PeerId) {
+    report_peer(network_recipient, peer_id, PeerBehaviour::Timeout);
+}
+
+/// Reports behaviour of the peer to the network, which changes its score.
+fn report_peer(
+    network_recipient: &Recipient<NetworkRequests>,
+    peer_id: PeerId,
+    behaviour: PeerBehaviour,
+) {
+    if network_recipient.do_send(NetworkRequests::ReportPeer { peer_id, behaviour }).is_err() {
+        error!(target: "sync", "Failed to send message to network agent");
+    }
+}
+
 /// Helper to keep track of sync headers.
 /// Handles major re-orgs by finding closest header that matches and re-downloading headers from that point.
 pub struct HeaderSync {
@@ -53,16 +87,18 @@ pub struct HeaderSync {
     prev_header_sync: (DateTime<Utc>, BlockIndex, BlockIndex),
     syncing_peer: Option<FullPeerInfo>,
     stalling_ts: Option<DateTime<Utc>>,
+    clock: Clock,
 }
 
 impl HeaderSync {
-    pub fn new(network_recipient: Recipient<NetworkRequests>) -> Self {
+    pub fn new(network_recipient: Recipient<NetworkRequests>, clock: Clock) -> Self {
         HeaderSync {
             network_recipient,
             history_locator: vec![],
-            prev_header_sync: (Utc::now(), 0, 0),
+            prev_header_sync: (clock.utc_now(), 0, 0),
             syncing_peer: None,
             stalling_ts: None,
+            clock,
         }
     }
 
@@ -111,7 +147,7 @@ impl HeaderSync {
     }
 
     fn header_sync_due(&mut self, sync_status: &SyncStatus, header_head: &Tip) -> bool {
-        let now = Utc::now();
+        let now = self.clock.utc_now();
         let (timeout, latest_height, prev_height) = self.prev_header_sync;
 
         // Received all necessary header, can request more.
@@ -130,6 +166,10 @@ impl HeaderSync {
                 (now + Duration::seconds(10), header_head.height, header_head.height);
 
             if stalling {
+                if let Some(ref peer) = self.syncing_peer {
+                    debug!(target: "sync", "Sync: no headers from {} for a while", peer.peer_info);
+                    report_timeout(&self.network_recipient, peer.peer_info.id.clone());
+                }
                 if self.stalling_ts.is_none() {
                     self.stalling_ts = Some(now);
                 } else {
@@ -179,7 +219,7 @@ impl HeaderSync {
     }
 
     /// Request headers from a given peer to advance the chain.
-    fn request_headers(&mut self, chain: &Chain, peer: FullPeerInfo) -> Option<FullPeerInfo> {
+    fn request_headers(&mut self, chain: &mut Chain, peer: FullPeerInfo) -> Option<FullPeerInfo> {
         if let Ok(locator) = self.get_locator(chain) {
             debug!(target: "sync", "Sync: request headers: asking {} for headers, {:?}", peer.peer_info.id, locator);
             let _ = self.network_recipient.do_send(NetworkRequests::BlockHeadersRequest {
@@ -191,7 +231,7 @@ impl HeaderSync {
         None
     }
 
-    fn get_locator(&mut self, chain: &Chain) -> Result<Vec<CryptoHash>, near_chain::Error> {
+    fn get_locator(&mut self, chain: &mut Chain) -> Result<Vec<CryptoHash>, near_chain::Error> {
         let tip = chain.sync_head()?;
         let heights = get_locator_heights(tip.height);
 
@@ -207,6 +247,8 @@ impl HeaderSync {
         for h in heights {
             if let Some(x) = close_enough(&self.history_locator, h) {
                 locator.push(x);
+            } else if let Ok(header) = chain.get_header_by_height(h) {
+                locator.push((h, header.hash()));
             }
         }
         locator.dedup_by(|a, b| a.0 == b.0);
@@ -265,19 +307,22 @@ pub struct BlockSync {
     prev_blocks_recevied: BlockIndex,
     /// How far to fetch blocks vs fetch state.
     block_fetch_horizon: BlockIndex,
+    clock: Clock,
 }
 
 impl BlockSync {
     pub fn new(
         network_recipient: Recipient<NetworkRequests>,
         block_fetch_horizon: BlockIndex,
+        clock: Clock,
     ) -> Self {
         BlockSync {
             network_recipient,
             blocks_requested: 0,
-            receive_timeout: Utc::now(),
+            receive_timeout: clock.utc_now(),
             prev_blocks_recevied: 0,
             block_fetch_horizon,
+            clock,
         }
     }
 
@@ -332,7 +377,7 @@ impl BlockSync {
             debug!(target: "sync", "Block sync: {}/{} requesting blocks {:?} from {} peers", head.height, header_head.height, hashes_to_request, most_weight_peers.len());
 
             self.blocks_requested = 0;
-            self.receive_timeout = Utc::now() + Duration::seconds(BLOCK_REQUEST_TIMEOUT);
+            self.receive_timeout = self.clock.utc_now() + Duration::seconds(BLOCK_REQUEST_TIMEOUT);
 
             let mut peers_iter = most_weight_peers.iter().cycle();
             for hash in hashes_to_request.drain(..) {
@@ -361,7 +406,7 @@ impl BlockSync {
 
         // Some blocks have been requested.
         if self.blocks_requested > 0 {
-            let timeout = Utc::now() > self.receive_timeout;
+            let timeout = self.clock.utc_now() > self.receive_timeout;
             if timeout && blocks_received <= self.prev_blocks_recevied {
                 debug!(target: "sync", "Block sync: expecting {} more blocks and none received for a while", self.blocks_requested);
                 return Ok(true);
@@ -370,7 +415,8 @@ impl BlockSync {
 
         if blocks_received > self.prev_blocks_recevied {
             // Some blocks received, update for next check.
-            self.receive_timeout = Utc::now() + Duration::seconds(BLOCK_SOME_RECEIVED_TIMEOUT);
+            self.receive_timeout =
+                self.clock.utc_now() + Duration::seconds(BLOCK_SOME_RECEIVED_TIMEOUT);
             self.blocks_requested =
                 self.blocks_requested.saturating_sub(blocks_received - self.prev_blocks_recevied);
             self.prev_blocks_recevied = blocks_received;
@@ -391,29 +437,154 @@ impl BlockSync {
     }
 }
 
+/// Download status of the header or a part of the shard state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum DownloadStatus {
+    /// Not requested yet or previous request failed.
+    Pending,
+    /// Requested from given peer at given time.
+    Requested(PeerId, DateTime<Utc>),
+    /// Received and saved.
+    Done,
+}
+
+impl DownloadStatus {
+    /// Returns true if this item needs to be requested: it was never requested, request timed out
+    /// or the peer it was requested from is gone.
+    fn needs_request(&self, now: DateTime<Utc>, most_weight_peers: &[FullPeerInfo]) -> bool {
+        match self {
+            DownloadStatus::Pending => true,
+            DownloadStatus::Requested(peer_id, requested_at) => {
+                now - *requested_at > Duration::seconds(STATE_REQUEST_TIMEOUT)
+                    || !most_weight_peers.iter().any(|peer| peer.peer_info.id == *peer_id)
+            }
+            DownloadStatus::Done => false,
+        }
+    }
+
+    /// Returns the peer this item was requested from, if the request timed out.
+    fn timed_out_peer(&self, now: DateTime<Utc>) -> Option<PeerId> {
+        match self {
+            DownloadStatus::Requested(peer_id, requested_at)
+                if now - *requested_at > Duration::seconds(STATE_REQUEST_TIMEOUT) =>
+            {
+                Some(peer_id.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Progress of downloading the state of a single shard.
+struct ShardStateDownload {
+    /// Status of the state header download.
+    header: DownloadStatus,
+    /// Headers received from peers and not confirmed by enough other peers yet.
+    header_candidates: Vec<(PeerId, ShardStateSyncResponseHeader)>,
+    /// Number of peers that have to send the same header, set when the header is requested.
+    header_confirmations: usize,
+    /// Peers that sent the accepted header, unknown if the download was resumed.
+    header_peers: Vec<PeerId>,
+    /// Peers that sent parts which don't match the accepted header.
+    invalid_part_peers: HashSet<PeerId>,
+    /// Statuses of the state parts, known after header is received.
+    parts: Vec<DownloadStatus>,
+    /// Total size of the state, known after header is received.
+    total_size: u64,
+    /// Total size of the received parts.
+    downloaded_size: u64,
+}
+
+impl ShardStateDownload {
+    /// Restores the progress from the parts previously saved in the chain.
+    fn new(
+        chain: &Chain,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+    ) -> Result<Self, near_chain::Error> {
+        let mut download = ShardStateDownload {
+            header: DownloadStatus::Pending,
+            header_candidates: vec![],
+            header_confirmations: STATE_HEADER_CONFIRMATIONS,
+            header_peers: vec![],
+            invalid_part_peers: HashSet::new(),
+            parts: vec![],
+            total_size: 0,
+            downloaded_size: 0,
+        };
+        if let Ok(header) = chain.store().get_state_header(shard_id, sync_hash) {
+            download.set_header(header.num_parts(), header.total_size, vec![]);
+            for part_id in 0..header.num_parts() {
+                if chain.store().state_part_exists(shard_id, sync_hash, part_id)? {
+                    let part = chain.store().get_state_part(shard_id, sync_hash, part_id)?;
+                    download.parts[part_id as usize] = DownloadStatus::Done;
+                    download.downloaded_size += part.size();
+                }
+            }
+            info!(target: "sync", "State sync: resuming download of shard {} @ {}: {} / {} bytes", shard_id, sync_hash, download.downloaded_size, download.total_size);
+        }
+        Ok(download)
+    }
+
+    fn set_header(&mut self, num_parts: u64, total_size: u64, header_peers: Vec<PeerId>) {
+        self.header = DownloadStatus::Done;
+        self.header_candidates.clear();
+        self.header_peers = header_peers;
+        self.parts = vec![DownloadStatus::Pending; num_parts as usize];
+        self.total_size = total_size;
+    }
+
+    fn is_done(&self) -> bool {
+        self.header == DownloadStatus::Done
+            && self.parts.iter().all(|part| *part == DownloadStatus::Done)
+    }
+}
+
+/// Checks the state header received from a peer before allocating anything for its parts.
+/// Parts are packed greedily up to `STATE_PART_MAX_SIZE`, so every two consecutive parts are
+/// larger than that and the number of parts follows from the total size of the state.
+fn check_state_header(header: &ShardStateSyncResponseHeader) -> Result<(), String> {
+    let num_parts = header.num_parts();
+    let max_parts = 2 * (header.total_size / STATE_PART_MAX_SIZE + 1) + 1;
+    if num_parts > cmp::min(max_parts, MAX_STATE_PARTS) {
+        return Err(format!("{} parts for {} bytes of state", num_parts, header.total_size));
+    }
+    if header.part_boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("part boundaries are not increasing".to_string());
+    }
+    Ok(())
+}
+
 /// Helper to track state sync.
+/// State of each shard is downloaded in parts, which are requested from different peers in
+/// parallel, validated against the state root and saved in the store as they arrive.
 pub struct StateSync {
     network_recipient: Recipient<NetworkRequests>,
     state_fetch_horizon: BlockIndex,
+    clock: Clock,
 
-    syncing_peers: HashMap<ShardId, FullPeerInfo>,
-    prev_state_sync: HashMap<ShardId, DateTime<Utc>>,
+    downloads: HashMap<ShardId, ShardStateDownload>,
 }
 
 impl StateSync {
     pub fn new(
         network_recipient: Recipient<NetworkRequests>,
         state_fetch_horizon: BlockIndex,
+        clock: Clock,
     ) -> Self {
-        StateSync {
-            network_recipient,
-            state_fetch_horizon,
-            syncing_peers: Default::default(),
-            prev_state_sync: Default::default(),
-        }
+        StateSync { network_recipient, state_fetch_horizon, clock, downloads: Default::default() }
     }
 
     fn find_sync_hash(&self, chain: &mut Chain) -> Result<CryptoHash, near_chain::Error> {
+        // Continue download of the state that was started before, if it's still ahead of the chain.
+        if let Ok(sync_hash) = chain.store().state_sync_hash() {
+            let head = chain.head()?;
+            if let Ok(header) = chain.get_block_header(&sync_hash) {
+                if header.height > head.height {
+                    return Ok(sync_hash);
+                }
+            }
+        }
         let header_head = chain.header_head()?;
         let mut sync_hash = header_head.prev_block_hash;
         for _ in 0..self.state_fetch_horizon {
@@ -430,34 +601,22 @@ impl StateSync {
         most_weight_peers: &Vec<FullPeerInfo>,
         tracking_shards: Vec<ShardId>,
     ) -> Result<(), near_chain::Error> {
-        let header_head = chain.header_head()?;
-        let mut sync_need_restart = HashSet::new();
-
         let (sync_hash, mut new_shard_sync) = match &sync_status {
             SyncStatus::StateSync(sync_hash, shard_sync) => (sync_hash.clone(), shard_sync.clone()),
-            _ => (self.find_sync_hash(chain)?, HashMap::default()),
-        };
-
-        // Check syncing peer connection status.
-        let mut all_done = false;
-        if let SyncStatus::StateSync(_, shard_statuses) = sync_status {
-            all_done = true;
-            for (shard_id, shard_status) in shard_statuses.iter() {
-                all_done = all_done && ShardSyncStatus::StateDone == *shard_status;
-                if let ShardSyncStatus::Error(error) = shard_status {
-                    error!(target: "sync", "State sync: shard {} sync failed: {}", shard_id, error);
-                    sync_need_restart.insert(shard_id);
-                } else if let Some(ref peer) = self.syncing_peers.get(shard_id) {
-                    if let ShardSyncStatus::StateDownload { .. } = shard_status {
-                        if !most_weight_peers.contains(peer) {
-                            sync_need_restart.insert(shard_id);
-                            info!(target: "sync", "State sync: peer connection lost: {:?}, restart shard {}", peer.peer_info.id, shard_id);
-                        }
-                    }
+            _ => {
+                if chain.header_head()?.height != highest_height {
+                    return Ok(());
                 }
+                let sync_hash = self.find_sync_hash(chain)?;
+                chain.set_state_sync_hash(sync_hash)?;
+                self.downloads.clear();
+                (sync_hash, HashMap::default())
             }
-        }
+        };
 
+        let all_done = tracking_shards
+            .iter()
+            .all(|shard_id| new_shard_sync.get(shard_id) == Some(&ShardSyncStatus::StateDone));
         if all_done {
             info!(target: "sync", "State sync: all shards are done");
 
@@ -478,88 +637,533 @@ impl StateSync {
             chain.check_orphans(hash, |_, _, _| {});
 
             *sync_status = SyncStatus::BodySync { current_height: 0, highest_height: 0 };
-            self.prev_state_sync.clear();
-            self.syncing_peers.clear();
+            self.downloads.clear();
             return Ok(());
         }
 
-        let now = Utc::now();
-        let mut update_sync_status = false;
+        let now = self.clock.utc_now();
         for shard_id in tracking_shards {
-            if sync_need_restart.contains(&shard_id) || header_head.height == highest_height {
-                let (go, download_timeout) = match self.prev_state_sync.get(&shard_id) {
-                    None => {
-                        self.prev_state_sync.insert(shard_id, now);
-                        (true, false)
+            let prev_status = new_shard_sync.get(&shard_id).cloned();
+            match &prev_status {
+                Some(ShardSyncStatus::StateDone) => continue,
+                Some(ShardSyncStatus::Error(error)) => {
+                    error!(target: "sync", "State sync: shard {} sync failed: {}, restarting", shard_id, error);
+                    if let Some(download) = self.downloads.remove(&shard_id) {
+                        chain.clear_state_parts(
+                            shard_id,
+                            sync_hash,
+                            download.parts.len() as u64,
+                        )?;
                     }
-                    Some(prev) => (false, now - *prev > Duration::minutes(STATE_SYNC_TIMEOUT)),
-                };
-
-                if download_timeout {
-                    error!(target: "sync", "State sync: state download for shard {} timed out in {} minutes", shard_id, STATE_SYNC_TIMEOUT);
                 }
+                _ => {}
+            }
 
-                if go || download_timeout {
-                    match self.request_state(shard_id, chain, sync_hash, most_weight_peers) {
-                        Some(peer) => {
-                            self.syncing_peers.insert(shard_id, peer);
-                            new_shard_sync.insert(
-                                shard_id,
-                                ShardSyncStatus::StateDownload {
-                                    start_time: now,
-                                    prev_update_time: now,
-                                    prev_downloaded_size: 0,
-                                    downloaded_size: 0,
-                                    total_size: 0,
-                                },
-                            );
-                        }
-                        None => {
-                            new_shard_sync.insert(
-                                shard_id,
-                                ShardSyncStatus::Error(format!(
-                                    "Failed to find peer with state for shard {}",
-                                    shard_id
-                                )),
+            if !self.downloads.contains_key(&shard_id) {
+                self.downloads
+                    .insert(shard_id, ShardStateDownload::new(chain, shard_id, &sync_hash)?);
+            }
+            let download = self.downloads.get_mut(&shard_id).expect("Inserted above");
+
+            if download.is_done() {
+                let status = match chain.set_state_finalize(shard_id, sync_hash) {
+                    Ok(()) => {
+                        info!(target: "sync", "State sync: shard {} @ {} is done, size = {}", shard_id, sync_hash, download.total_size);
+                        // The header turned out to be right, so the invalid parts were not.
+                        for peer_id in download.invalid_part_peers.drain() {
+                            report_peer(
+                                &self.network_recipient,
+                                peer_id,
+                                PeerBehaviour::Misbehaviour(ReasonForBan::Abusive),
                             );
                         }
+                        ShardSyncStatus::StateDone
                     }
-                    update_sync_status = true;
-                }
+                    Err(err) => ShardSyncStatus::Error(format!(
+                        "Failed to set state for {} @ {}: {}",
+                        shard_id, sync_hash, err
+                    )),
+                };
+                // Downloaded parts were removed by the chain in any case.
+                self.downloads.remove(&shard_id);
+                new_shard_sync.insert(shard_id, status);
+                continue;
             }
+
+            if most_weight_peers.is_empty() {
+                new_shard_sync.insert(
+                    shard_id,
+                    ShardSyncStatus::Error(format!(
+                        "Failed to find peer with state for shard {}",
+                        shard_id
+                    )),
+                );
+                continue;
+            }
+            self.request_state(shard_id, sync_hash, most_weight_peers, now);
+
+            let download = &self.downloads[&shard_id];
+            let (start_time, prev_downloaded_size) = match prev_status {
+                Some(ShardSyncStatus::StateDownload { start_time, downloaded_size, .. }) => {
+                    (start_time, downloaded_size)
+                }
+                _ => (now, 0),
+            };
+            new_shard_sync.insert(
+                shard_id,
+                ShardSyncStatus::StateDownload {
+                    start_time,
+                    prev_update_time: now,
+                    prev_downloaded_size,
+                    downloaded_size: download.downloaded_size,
+                    total_size: download.total_size,
+                },
+            );
         }
-        if update_sync_status {
-            *sync_status = SyncStatus::StateSync(sync_hash, new_shard_sync);
-        }
+        *sync_status = SyncStatus::StateSync(sync_hash, new_shard_sync);
         Ok(())
     }
 
+    /// Requests header or pending parts of the shard state, spreading requests over given peers.
     fn request_state(
         &mut self,
         shard_id: ShardId,
-        _chain: &Chain,
         hash: CryptoHash,
         most_weight_peers: &Vec<FullPeerInfo>,
-    ) -> Option<FullPeerInfo> {
-        if let Some(peer) = most_weight_peer(most_weight_peers) {
+        now: DateTime<Utc>,
+    ) {
+        let download = match self.downloads.get_mut(&shard_id) {
+            Some(download) => download,
+            None => return,
+        };
+
+        if download.header != DownloadStatus::Done {
+            if let Some(peer_id) = download.header.timed_out_peer(now) {
+                report_timeout(&self.network_recipient, peer_id);
+                download.header = DownloadStatus::Pending;
+            }
+            if download.header.needs_request(now, most_weight_peers) {
+                download.header_confirmations =
+                    cmp::min(STATE_HEADER_CONFIRMATIONS, most_weight_peers.len());
+                // Ask the peers that haven't sent their header yet, start over if all of them did
+                // and still don't agree.
+                let mut peers: Vec<FullPeerInfo> = most_weight_peers
+                    .iter()
+                    .filter(|peer| {
+                        download.header_candidates.iter().all(|(id, _)| *id != peer.peer_info.id)
+                    })
+                    .cloned()
+                    .collect();
+                if peers.is_empty() {
+                    download.header_candidates.clear();
+                    peers = most_weight_peers.clone();
+                }
+                if let Some(peer) = most_weight_peer(&peers) {
+                    debug!(target: "sync", "State sync: request header of shard {} @ {} from {}", shard_id, hash, peer.peer_info.id);
+                    let peer_id = peer.peer_info.id;
+                    unwrap_or_return!(
+                        self.network_recipient.do_send(NetworkRequests::StateRequest {
+                            shard_id,
+                            hash,
+                            need_header: true,
+                            parts_ids: vec![],
+                            peer_id: peer_id.clone(),
+                        }),
+                        ()
+                    );
+                    download.header = DownloadStatus::Requested(peer_id, now);
+                }
+            }
+            return;
+        }
+
+        // Number of parts currently requested from each peer.
+        let mut peer_requests: HashMap<PeerId, usize> =
+            most_weight_peers.iter().map(|peer| (peer.peer_info.id.clone(), 0)).collect();
+        for part in download.parts.iter() {
+            if let DownloadStatus::Requested(peer_id, _) = part {
+                if !part.needs_request(now, most_weight_peers) {
+                    peer_requests.entry(peer_id.clone()).and_modify(|count| *count += 1);
+                }
+            }
+        }
+
+        let mut peers_iter = most_weight_peers.iter().cycle();
+        for (part_id, part) in download.parts.iter_mut().enumerate() {
+            if let Some(peer_id) = part.timed_out_peer(now) {
+                report_timeout(&self.network_recipient, peer_id);
+                *part = DownloadStatus::Pending;
+            }
+            if !part.needs_request(now, most_weight_peers) {
+                continue;
+            }
+            // Find next peer in round robin order which is not busy.
+            let peer = peers_iter
+                .by_ref()
+                .take(most_weight_peers.len())
+                .find(|peer| peer_requests[&peer.peer_info.id] < MAX_STATE_PARTS_PER_PEER);
+            let peer_id = match peer {
+                Some(peer) => peer.peer_info.id.clone(),
+                None => break,
+            };
             unwrap_or_return!(
                 self.network_recipient.do_send(NetworkRequests::StateRequest {
                     shard_id,
                     hash,
-                    peer_id: peer.peer_info.id,
+                    need_header: false,
+                    parts_ids: vec![part_id as u64],
+                    peer_id: peer_id.clone(),
                 }),
-                None
+                ()
             );
-            return Some(peer);
+            *peer_requests.get_mut(&peer_id).expect("All peers are in the map") += 1;
+            *part = DownloadStatus::Requested(peer_id, now);
         }
-        None
+    }
+
+    /// Saves header and parts of the state received from the network.
+    /// Header is saved once enough peers sent the same one. Invalid parts are dropped and will be
+    /// requested again from another peer. If parts from several peers don't match the header, the
+    /// header is dropped and the peers that sent it are banned.
+    pub fn process_state_response(
+        &mut self,
+        chain: &mut Chain,
+        sync_hash: CryptoHash,
+        response: StateResponseInfo,
+        peer_id: &PeerId,
+    ) -> Result<(), near_chain::Error> {
+        let shard_id = response.shard_id;
+        let download = match self.downloads.get_mut(&shard_id) {
+            Some(download) => download,
+            None => return Ok(()),
+        };
+        if let Some(header) = response.shard_state.header {
+            match &download.header {
+                DownloadStatus::Requested(requested_from, _) if requested_from == peer_id => {
+                    if let Err(err) = check_state_header(&header) {
+                        error!(target: "sync", "State sync: invalid header of shard {} @ {} from {}: {}", shard_id, sync_hash, peer_id, err);
+                        download.header = DownloadStatus::Pending;
+                        report_peer(
+                            &self.network_recipient,
+                            peer_id.clone(),
+                            PeerBehaviour::Misbehaviour(ReasonForBan::Abusive),
+                        );
+                    } else {
+                        debug!(target: "sync", "State sync: received header of shard {} @ {} from {}: {} parts, {} bytes", shard_id, sync_hash, peer_id, header.num_parts(), header.total_size);
+                        download.header = DownloadStatus::Pending;
+                        let header_peers: Vec<PeerId> = download
+                            .header_candidates
+                            .iter()
+                            .filter(|(_, other)| *other == header)
+                            .map(|(id, _)| id.clone())
+                            .chain(std::iter::once(peer_id.clone()))
+                            .collect();
+                        if header_peers.len() >= download.header_confirmations {
+                            download.set_header(
+                                header.num_parts(),
+                                header.total_size,
+                                header_peers,
+                            );
+                            chain.set_state_header(shard_id, sync_hash, header)?;
+                        } else {
+                            download.header_candidates.push((peer_id.clone(), header));
+                        }
+                    }
+                }
+                _ => {
+                    debug!(target: "sync", "State sync: ignoring header of shard {} @ {} from {}", shard_id, sync_hash, peer_id)
+                }
+            }
+        }
+        for part in response.shard_state.parts {
+            let part_id = part.part_id as usize;
+            match download.parts.get(part_id) {
+                None | Some(DownloadStatus::Done) => continue,
+                _ => {}
+            }
+            let size = part.size();
+            match chain.set_state_part(shard_id, sync_hash, part) {
+                Ok(()) => {
+                    download.parts[part_id] = DownloadStatus::Done;
+                    download.downloaded_size += size;
+                }
+                Err(err) => {
+                    error!(target: "sync", "State sync: invalid part {} of shard {} from {}: {}", part_id, shard_id, peer_id, err);
+                    download.parts[part_id] = DownloadStatus::Pending;
+                    if let ErrorKind::InvalidStatePayload(_) = err.kind() {
+                        // Peer is reported once the state is set, unless the header is wrong.
+                        download.invalid_part_peers.insert(peer_id.clone());
+                    }
+                    if download.invalid_part_peers.len() >= MAX_STATE_PART_FAILURES {
+                        error!(target: "sync", "State sync: parts from {} peers don't match the header of shard {} @ {}, dropping it", download.invalid_part_peers.len(), shard_id, sync_hash);
+                        for header_peer in download.header_peers.drain(..) {
+                            report_peer(
+                                &self.network_recipient,
+                                header_peer,
+                                PeerBehaviour::Misbehaviour(ReasonForBan::Abusive),
+                            );
+                        }
+                        chain.clear_state_parts(
+                            shard_id,
+                            sync_hash,
+                            download.parts.len() as u64,
+                        )?;
+                        self.downloads.insert(
+                            shard_id,
+                            ShardStateDownload::new(chain, shard_id, &sync_hash)?,
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use actix::{Actor, System};
+
+    use near_chain::test_utils::setup;
+    use near_chain::{Block, RuntimeAdapter, ShardStateSyncResponse};
+    use near_network::types::PeerChainInfo;
+    use near_network::{NetworkResponses, PeerInfo};
+    use near_primitives::types::MerkleHash;
+    use near_store::StatePart;
+
+    use crate::test_utils::NetworkMock;
+
     use super::*;
 
+    /// Sets up the chain with a block which state is split into a few parts.
+    /// Returns the chain and the hash of the block to sync the state at.
+    fn setup_state() -> (Chain, CryptoHash) {
+        let (mut chain, runtime, signer) = setup();
+        let trie = runtime.get_trie();
+        let changes = (0..100u8).map(|i| (vec![i], Some(vec![i; 10_000])));
+        let (store_update, root) =
+            trie.update(&MerkleHash::default(), changes).unwrap().into(trie.clone()).unwrap();
+        store_update.commit().unwrap();
+        let genesis = chain.genesis().clone();
+        let block = Block::produce(
+            &genesis,
+            1,
+            root,
+            genesis.epoch_hash,
+            vec![],
+            HashMap::default(),
+            vec![],
+            signer,
+        );
+        let mut chain_store_update = chain.mut_store().store_update();
+        chain_store_update.save_block_header(block.header.clone());
+        chain_store_update.commit().unwrap();
+        (chain, block.hash())
+    }
+
+    fn setup_state_sync() -> StateSync {
+        let network =
+            NetworkMock::mock(Box::new(|_, _| Box::new(Some(NetworkResponses::NoResponse))));
+        StateSync::new(network.start().recipient(), 5, Clock::Real)
+    }
+
+    fn setup_peer() -> FullPeerInfo {
+        FullPeerInfo {
+            peer_info: PeerInfo::random(),
+            chain_info: PeerChainInfo {
+                genesis: Default::default(),
+                height: 1,
+                total_weight: 1.into(),
+            },
+        }
+    }
+
+    fn state_response(
+        sync_hash: CryptoHash,
+        header: Option<ShardStateSyncResponseHeader>,
+        parts: Vec<StatePart>,
+    ) -> StateResponseInfo {
+        StateResponseInfo {
+            shard_id: 0,
+            hash: sync_hash,
+            shard_state: ShardStateSyncResponse { header, parts },
+        }
+    }
+
+    fn requested_from(status: &DownloadStatus) -> Option<PeerId> {
+        match status {
+            DownloadStatus::Requested(peer_id, _) => Some(peer_id.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_state_sync_download_and_resume() {
+        let _system = System::new("test");
+        let (mut chain, sync_hash) = setup_state();
+        let peers = vec![setup_peer()];
+        let peer_id = peers[0].peer_info.id.clone();
+        let mut state_sync = setup_state_sync();
+        let mut sync_status = SyncStatus::StateSync(sync_hash, HashMap::default());
+
+        // Header is requested first.
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        assert_eq!(requested_from(&state_sync.downloads[&0].header), Some(peer_id.clone()));
+        assert!(state_sync.downloads[&0].parts.is_empty());
+
+        // Header is only accepted from the peer it was requested from.
+        let header = chain.get_state_response_header(0, sync_hash).unwrap();
+        let num_parts = header.num_parts();
+        assert!(num_parts > 2);
+        let other_peer_id = setup_peer().peer_info.id;
+        let response = state_response(sync_hash, Some(header.clone()), vec![]);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &other_peer_id).unwrap();
+        assert_ne!(state_sync.downloads[&0].header, DownloadStatus::Done);
+        let response = state_response(sync_hash, Some(header.clone()), vec![]);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Done);
+        assert_eq!(state_sync.downloads[&0].parts.len() as u64, num_parts);
+
+        // Parts are requested, but no more than allowed from a single peer.
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        let requested = state_sync.downloads[&0]
+            .parts
+            .iter()
+            .filter(|part| requested_from(part) == Some(peer_id.clone()))
+            .count();
+        assert_eq!(requested, MAX_STATE_PARTS_PER_PEER);
+        match &sync_status {
+            SyncStatus::StateSync(_, shard_sync) => match shard_sync[&0] {
+                ShardSyncStatus::StateDownload { total_size, .. } => {
+                    assert_eq!(total_size, header.total_size)
+                }
+                ref status => panic!("Unexpected shard sync status {:?}", status),
+            },
+            status => panic!("Unexpected sync status {:?}", status),
+        }
+
+        // Valid part sent under the index of another part is rejected.
+        let mut wrong_part = chain.get_state_response_part(0, 1, sync_hash).unwrap();
+        wrong_part.part_id = 0;
+        let response = state_response(sync_hash, None, vec![wrong_part]);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].parts[0], DownloadStatus::Pending);
+        assert_eq!(state_sync.downloads[&0].downloaded_size, 0);
+
+        let parts =
+            (0..2).map(|part_id| chain.get_state_response_part(0, part_id, sync_hash).unwrap());
+        let parts: Vec<_> = parts.collect();
+        let parts_size: u64 = parts.iter().map(StatePart::size).sum();
+        let response = state_response(sync_hash, None, parts);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].downloaded_size, parts_size);
+
+        // Download is resumed from the saved header and parts after restart.
+        let mut state_sync = setup_state_sync();
+        let mut sync_status = SyncStatus::StateSync(sync_hash, HashMap::default());
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        let download = &state_sync.downloads[&0];
+        assert_eq!(download.header, DownloadStatus::Done);
+        assert_eq!(download.downloaded_size, parts_size);
+        assert_eq!(download.parts[..2], [DownloadStatus::Done, DownloadStatus::Done]);
+        assert_eq!(requested_from(&download.parts[2]), Some(peer_id.clone()));
+
+        let parts = (2..num_parts)
+            .map(|part_id| chain.get_state_response_part(0, part_id, sync_hash).unwrap());
+        let response = state_response(sync_hash, None, parts.collect());
+        state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].downloaded_size, header.total_size);
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        match &sync_status {
+            SyncStatus::StateSync(_, shard_sync) => {
+                assert_eq!(shard_sync[&0], ShardSyncStatus::StateDone)
+            }
+            status => panic!("Unexpected sync status {:?}", status),
+        }
+        assert!(!chain.store().state_part_exists(0, &sync_hash, 0).unwrap());
+    }
+
+    #[test]
+    fn test_state_sync_header_confirmation() {
+        let _system = System::new("test");
+        let (mut chain, sync_hash) = setup_state();
+        let peers = vec![setup_peer(), setup_peer()];
+        let mut state_sync = setup_state_sync();
+        let mut sync_status = SyncStatus::StateSync(sync_hash, HashMap::default());
+
+        // Header from one peer is not enough, it's requested from the other peer as well.
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        let first_peer_id = requested_from(&state_sync.downloads[&0].header).unwrap();
+        let header = chain.get_state_response_header(0, sync_hash).unwrap();
+        let response = state_response(sync_hash, Some(header.clone()), vec![]);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &first_peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Pending);
+        assert!(chain.store().get_state_header(0, &sync_hash).is_err());
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+        let second_peer_id = requested_from(&state_sync.downloads[&0].header).unwrap();
+        assert_ne!(second_peer_id, first_peer_id);
+        let response = state_response(sync_hash, Some(header.clone()), vec![]);
+        state_sync
+            .process_state_response(&mut chain, sync_hash, response, &second_peer_id)
+            .unwrap();
+        assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Done);
+        assert_eq!(state_sync.downloads[&0].header_peers, vec![first_peer_id, second_peer_id]);
+
+        // Header is dropped once parts from enough different peers don't match it.
+        let mut wrong_part = chain.get_state_response_part(0, 1, sync_hash).unwrap();
+        wrong_part.part_id = 0;
+        for i in 0..MAX_STATE_PART_FAILURES {
+            assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Done);
+            let response = state_response(sync_hash, None, vec![wrong_part.clone()]);
+            let peer_id = setup_peer().peer_info.id;
+            state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+            assert_eq!(
+                state_sync.downloads[&0].invalid_part_peers.len(),
+                (i + 1) % MAX_STATE_PART_FAILURES
+            );
+        }
+        assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Pending);
+        assert!(state_sync.downloads[&0].parts.is_empty());
+        assert!(chain.store().get_state_header(0, &sync_hash).is_err());
+    }
+
+    #[test]
+    fn test_state_sync_invalid_header() {
+        let _system = System::new("test");
+        let (mut chain, sync_hash) = setup_state();
+        let peers = vec![setup_peer()];
+        let peer_id = peers[0].peer_info.id.clone();
+        let mut state_sync = setup_state_sync();
+        let mut sync_status = SyncStatus::StateSync(sync_hash, HashMap::default());
+        state_sync.run(&mut sync_status, &mut chain, 1, &peers, vec![0]).unwrap();
+
+        // Too many parts for the size of the state.
+        let mut header = chain.get_state_response_header(0, sync_hash).unwrap();
+        header.part_boundaries = (0..1000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let response = state_response(sync_hash, Some(header), vec![]);
+        state_sync.process_state_response(&mut chain, sync_hash, response, &peer_id).unwrap();
+        assert_eq!(state_sync.downloads[&0].header, DownloadStatus::Pending);
+        assert!(state_sync.downloads[&0].parts.is_empty());
+        assert!(chain.store().get_state_header(0, &sync_hash).is_err());
+    }
+
+    #[test]
+    fn test_check_state_header() {
+        let header = |part_boundaries: Vec<Vec<u8>>, total_size| ShardStateSyncResponseHeader {
+            part_boundaries,
+            total_size,
+            receipts: vec![],
+        };
+        assert!(check_state_header(&header(vec![], 0)).is_ok());
+        assert!(check_state_header(&header(vec![vec![1], vec![2]], 0)).is_ok());
+        assert!(check_state_header(&header(vec![vec![1], vec![2], vec![3]], 0)).is_err());
+        assert!(check_state_header(&header(vec![vec![2], vec![1]], 0)).is_err());
+        assert!(check_state_header(&header(vec![vec![1], vec![1]], 0)).is_err());
+        let boundaries: Vec<_> = (0..MAX_STATE_PARTS).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert!(check_state_header(&header(boundaries, std::u64::MAX)).is_err());
+    }
+
     #[test]
     fn test_get_locator_heights() {
         assert_eq!(get_locator_heights(0), vec![0]);
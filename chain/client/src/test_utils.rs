@@ -1,18 +1,23 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use actix::actors::mocker::Mocker;
 use actix::{Actor, Addr, AsyncContext, Context, Recipient};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 use near_chain::test_utils::KeyValueRuntime;
-use near_network::types::NetworkInfo;
-use near_network::{NetworkRequests, NetworkResponses, PeerManagerActor};
+use near_network::test_utils::{NetworkSimulator, SimulatorConfig};
+use near_network::types::{NetworkInfo, PeerId};
+use near_network::{NetworkRequests, NetworkResponses, PeerInfo, PeerManagerActor};
 use near_primitives::crypto::signature::PublicKey;
 use near_primitives::crypto::signer::InMemorySigner;
+use near_primitives::test_utils::get_key_pair_from_seed;
+use near_primitives::types::BlockIndex;
 use near_store::test_utils::create_test_store;
+use near_store::Store;
 use near_telemetry::TelemetryActor;
 
-use crate::{BlockProducer, ClientActor, ClientConfig, ViewClientActor};
+use crate::{BlockProducer, ClientActor, ClientConfig, Clock, RunTimers, ViewClientActor};
 
 pub type NetworkMock = Mocker<PeerManagerActor>;
 
@@ -22,19 +27,38 @@ pub fn setup(
     account_id: &str,
     skip_sync_wait: bool,
     recipient: Recipient<NetworkRequests>,
+    genesis_time: DateTime<Utc>,
 ) -> (ClientActor, ViewClientActor) {
     let store = create_test_store();
     let runtime = Arc::new(KeyValueRuntime::new_with_validators(
         store.clone(),
         validators.into_iter().map(Into::into).collect(),
     ));
+    setup_with_runtime(
+        store,
+        runtime,
+        ClientConfig::test(skip_sync_wait),
+        account_id,
+        recipient,
+        genesis_time,
+    )
+}
+
+/// Sets up ClientActor with given config and ViewClientActor, both on top of given runtime.
+fn setup_with_runtime(
+    store: Arc<Store>,
+    runtime: Arc<KeyValueRuntime>,
+    config: ClientConfig,
+    account_id: &str,
+    recipient: Recipient<NetworkRequests>,
+    genesis_time: DateTime<Utc>,
+) -> (ClientActor, ViewClientActor) {
     let signer = Arc::new(InMemorySigner::from_seed(account_id, account_id));
-    let genesis_time = Utc::now();
     let telemetry = TelemetryActor::default().start();
     let view_client =
         ViewClientActor::new(store.clone(), genesis_time.clone(), runtime.clone()).unwrap();
     let client = ClientActor::new(
-        ClientConfig::test(skip_sync_wait),
+        config,
         store,
         genesis_time,
         runtime,
@@ -70,7 +94,8 @@ pub fn setup_mock(
             Box::new(Some(resp))
         }))
         .start();
-        let (client, view_client) = setup(validators, account_id, skip_sync_wait, pm.recipient());
+        let (client, view_client) =
+            setup(validators, account_id, skip_sync_wait, pm.recipient(), Utc::now());
         *view_client_addr1.write().unwrap() = Some(view_client.start());
         client
     });
@@ -95,12 +120,80 @@ pub fn setup_no_network(
                 received_bytes_per_sec: 0,
                 sent_bytes_per_sec: 0,
                 routes: None,
+                graph: None,
             }),
             _ => NetworkResponses::NoResponse,
         }),
     )
 }
 
+/// Sets up ClientActor and ViewClientActor of each of the validators, connected by the simulated
+/// network with given config. Validators of each epoch are given in order, epochs rotate every
+/// `epoch_length` heights. Node indices in the simulator follow the order in which the validators
+/// first appear. Timers of the clients run by the virtual clock of the simulator.
+pub fn setup_simulated(
+    validators: Vec<Vec<&'static str>>,
+    epoch_length: BlockIndex,
+    config: SimulatorConfig,
+) -> (NetworkSimulator, Vec<(Addr<ClientActor>, Addr<ViewClientActor>)>) {
+    let mut accounts: Vec<&'static str> = vec![];
+    for account_id in validators.iter().flatten() {
+        if !accounts.contains(account_id) {
+            accounts.push(*account_id);
+        }
+    }
+    let peers = accounts
+        .iter()
+        .map(|account_id| PeerInfo {
+            id: PeerId::from(get_key_pair_from_seed(account_id).0),
+            addr: None,
+            account_id: Some(account_id.to_string()),
+        })
+        .collect();
+    let simulator = NetworkSimulator::new(config, peers);
+    let genesis_time = Utc::now();
+    let now_ms = Arc::new(AtomicU64::new(0));
+    let clients = accounts
+        .iter()
+        .enumerate()
+        .map(|(index, account_id)| {
+            let store = create_test_store();
+            let runtime = Arc::new(KeyValueRuntime::new_with_epochs(
+                store.clone(),
+                validators
+                    .iter()
+                    .map(|epoch| epoch.iter().map(|account_id| account_id.to_string()).collect())
+                    .collect(),
+                epoch_length,
+            ));
+            let mut client_config = ClientConfig::test(true);
+            client_config.epoch_length = epoch_length;
+            client_config.clock = Clock::new_virtual(genesis_time, now_ms.clone());
+            let peer_manager = simulator.peer_manager(index).start();
+            let (client, view_client) = setup_with_runtime(
+                store,
+                runtime,
+                client_config,
+                account_id,
+                peer_manager.recipient(),
+                genesis_time,
+            );
+            let client = client.start();
+            simulator.set_client(index, client.clone().recipient());
+            (client, view_client.start())
+        })
+        .collect::<Vec<_>>();
+    let client_addrs = clients.iter().map(|(client, _)| client.clone()).collect::<Vec<_>>();
+    simulator.on_tick(move |now| {
+        now_ms.store(now, Ordering::SeqCst);
+        for client in client_addrs.iter() {
+            client.do_send(RunTimers);
+        }
+    });
+    simulator.start();
+    (simulator, clients)
+}
+
 impl BlockProducer {
     pub fn test(seed: &str) -> Self {
         Arc::new(InMemorySigner::from_seed(seed, seed)).into()
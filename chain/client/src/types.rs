@@ -1,14 +1,16 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use actix::Message;
+use actix::{Message, Recipient};
 use chrono::{DateTime, Utc};
 
-use near_chain::Block;
+use near_chain::{Block, BlockHeader};
+use near_network::types::NetworkGraphView;
 use near_primitives::crypto::signer::{AccountSigner, EDSigner, InMemorySigner};
 use near_primitives::hash::CryptoHash;
-use near_primitives::rpc::QueryResponse;
+use near_primitives::rpc::{BlockId, Finality, LightClientProof, QueryResponse};
 pub use near_primitives::rpc::{StatusResponse, StatusSyncInfo};
 use near_primitives::transaction::{FinalTransactionResult, TransactionResult};
 use near_primitives::types::{AccountId, BlockIndex, ShardId, Version};
@@ -102,6 +104,18 @@ pub struct ClientConfig {
     pub state_fetch_horizon: BlockIndex,
     /// Behind this horizon header fetch kicks in.
     pub block_header_fetch_horizon: BlockIndex,
+    /// Keep all the blocks and state, don't garbage collect.
+    pub archive: bool,
+    /// Number of epochs of blocks and state to keep when garbage collecting.
+    pub gc_num_epochs_to_keep: BlockIndex,
+    /// Maximum number of transactions in the transaction pool.
+    pub tx_pool_max_size: usize,
+    /// Maximum number of transactions from a single account in the transaction pool.
+    pub tx_pool_max_size_per_account: usize,
+    /// Number of upcoming heights, block producers of which get the transactions we receive.
+    pub tx_routing_height_horizon: BlockIndex,
+    /// Source of time for block production, sync and other timers.
+    pub clock: Clock,
 }
 
 impl ClientConfig {
@@ -127,6 +141,12 @@ impl ClientConfig {
             block_fetch_horizon: 50,
             state_fetch_horizon: 5,
             block_header_fetch_horizon: 50,
+            archive: false,
+            gc_num_epochs_to_keep: 5,
+            tx_pool_max_size: 10_000,
+            tx_pool_max_size_per_account: 100,
+            tx_routing_height_horizon: 4,
+            clock: Clock::Real,
         }
     }
 }
@@ -154,6 +174,12 @@ impl ClientConfig {
             block_fetch_horizon: 50,
             state_fetch_horizon: 5,
             block_header_fetch_horizon: 50,
+            archive: false,
+            gc_num_epochs_to_keep: 5,
+            tx_pool_max_size: 10_000,
+            tx_pool_max_size_per_account: 100,
+            tx_routing_height_horizon: 4,
+            clock: Clock::Real,
         }
     }
 }
@@ -177,6 +203,47 @@ impl From<Arc<InMemorySigner>> for BlockProducer {
     }
 }
 
+/// Source of time for the client.
+/// With `Virtual` clock, time only moves when the owner of `now_ms` advances it, and timers fire
+/// when the client receives `RunTimers` (used by the network simulator).
+#[derive(Clone)]
+pub enum Clock {
+    Real,
+    Virtual { start: Instant, start_utc: DateTime<Utc>, now_ms: Arc<AtomicU64> },
+}
+
+impl Clock {
+    /// Virtual clock starting at `start_utc`, that is `now_ms` milliseconds later.
+    pub fn new_virtual(start_utc: DateTime<Utc>, now_ms: Arc<AtomicU64>) -> Self {
+        Clock::Virtual { start: Instant::now(), start_utc, now_ms }
+    }
+
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::Real => Instant::now(),
+            Clock::Virtual { start, now_ms, .. } => {
+                *start + Duration::from_millis(now_ms.load(Ordering::SeqCst))
+            }
+        }
+    }
+
+    pub fn utc_now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::Real => Utc::now(),
+            Clock::Virtual { start_utc, now_ms, .. } => {
+                *start_utc + chrono::Duration::milliseconds(now_ms.load(Ordering::SeqCst) as i64)
+            }
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        match self {
+            Clock::Real => false,
+            Clock::Virtual { .. } => true,
+        }
+    }
+}
+
 /// Various status of syncing a specific shard.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ShardSyncStatus {
@@ -218,6 +285,18 @@ impl SyncStatus {
     pub fn is_syncing(&self) -> bool {
         self != &SyncStatus::NoSync
     }
+
+    /// Numeric code of the status, in the order of variants, to report as a metric.
+    pub fn as_metric(&self) -> i64 {
+        match self {
+            SyncStatus::AwaitingPeers => 0,
+            SyncStatus::NoSync => 1,
+            SyncStatus::HeaderSync { .. } => 2,
+            SyncStatus::StateSync(_, _) => 3,
+            SyncStatus::StateSyncDone => 4,
+            SyncStatus::BodySync { .. } => 5,
+        }
+    }
 }
 
 /// Actor message requesting block by id or hash.
@@ -231,22 +310,75 @@ impl Message for GetBlock {
     type Result = Result<Block, String>;
 }
 
+impl From<BlockId> for GetBlock {
+    fn from(block_id: BlockId) -> Self {
+        match block_id {
+            BlockId::Height(height) => GetBlock::Height(height),
+            BlockId::Hash(hash) => GetBlock::Hash(hash),
+            BlockId::Finality(Finality::Latest) => GetBlock::Best,
+        }
+    }
+}
+
+/// Actor message requesting only the header of the block by id or hash.
+pub enum GetBlockHeader {
+    Best,
+    Height(BlockIndex),
+    Hash(CryptoHash),
+}
+
+impl Message for GetBlockHeader {
+    type Result = Result<BlockHeader, String>;
+}
+
+impl From<BlockId> for GetBlockHeader {
+    fn from(block_id: BlockId) -> Self {
+        match block_id {
+            BlockId::Height(height) => GetBlockHeader::Height(height),
+            BlockId::Hash(hash) => GetBlockHeader::Hash(hash),
+            BlockId::Finality(Finality::Latest) => GetBlockHeader::Best,
+        }
+    }
+}
+
 /// Queries client for given path / data.
 pub struct Query {
     pub path: String,
     pub data: Vec<u8>,
+    /// Block after which the state is queried, the head of the chain if not given.
+    pub block_id: Option<BlockId>,
+    /// Attach the proof of the state that was read to the response.
+    pub prove: bool,
 }
 
 impl Message for Query {
     type Result = Result<QueryResponse, String>;
 }
 
+/// Headers and block producers for a light client to move from the trusted header to the target
+/// block, the head of the chain if not given.
+pub struct GetLightClientProof {
+    pub trusted_hash: CryptoHash,
+    pub target: Option<BlockId>,
+}
+
+impl Message for GetLightClientProof {
+    type Result = Result<LightClientProof, String>;
+}
+
 pub struct Status {}
 
 impl Message for Status {
     type Result = Result<StatusResponse, String>;
 }
 
+/// Graph of the network as seen by this node, for debugging.
+pub struct GetNetworkGraph {}
+
+impl Message for GetNetworkGraph {
+    type Result = Result<NetworkGraphView, String>;
+}
+
 /// Status of given transaction including all the subsequent receipts.
 pub struct TxStatus {
     pub tx_hash: CryptoHash,
@@ -264,3 +396,32 @@ pub struct TxDetails {
 impl Message for TxDetails {
     type Result = Result<TransactionResult, String>;
 }
+
+/// Block accepted by the client, pushed to the recipients registered with `SubscribeBlocks`.
+#[derive(Clone, Debug)]
+pub struct BlockNotification {
+    pub block: Block,
+    /// Whether this block became the new head of the chain.
+    pub is_new_head: bool,
+}
+
+impl Message for BlockNotification {
+    type Result = ();
+}
+
+/// Registers recipient for notifications about every block accepted from now on.
+/// Recipient is dropped once its actor stops.
+pub struct SubscribeBlocks {
+    pub recipient: Recipient<BlockNotification>,
+}
+
+impl Message for SubscribeBlocks {
+    type Result = ();
+}
+
+/// Fires the client timers that are due by the virtual clock.
+pub struct RunTimers;
+
+impl Message for RunTimers {
+    type Result = ();
+}
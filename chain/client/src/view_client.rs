@@ -6,18 +6,24 @@ use std::sync::Arc;
 use actix::{Actor, Context, Handler};
 use chrono::{DateTime, Utc};
 
-use near_chain::{Block, Chain, ErrorKind, RuntimeAdapter};
+use near_chain::{Block, BlockHeader, Chain, ErrorKind, RuntimeAdapter};
 use near_primitives::hash::CryptoHash;
-use near_primitives::rpc::QueryResponse;
+use near_primitives::rpc::{
+    BlockId, EpochBlockProducers, LightClientProof, ProvenQueryResponse, QueryResponse, StateProof,
+};
 use near_primitives::transaction::{
     FinalTransactionResult, FinalTransactionStatus, TransactionLogs, TransactionResult,
     TransactionStatus,
 };
 use near_store::Store;
 
-use crate::types::{Error, GetBlock, Query, TxStatus};
+use crate::types::{Error, GetBlock, GetBlockHeader, GetLightClientProof, Query, TxStatus};
 use crate::TxDetails;
 
+/// Maximum number of blocks between the trusted and the target block of a light client proof,
+/// longer distances are to be covered by several proofs.
+const MAX_LIGHT_CLIENT_PROOF_BLOCKS: u64 = 10_000;
+
 /// View client provides currently committed (to the storage) view of the current chain and state.
 pub struct ViewClientActor {
     chain: Chain,
@@ -31,7 +37,7 @@ impl ViewClientActor {
         runtime_adapter: Arc<dyn RuntimeAdapter>,
     ) -> Result<Self, Error> {
         // TODO: should we create shared ChainStore that is passed to both Client and ViewClient?
-        let chain = Chain::new(store, runtime_adapter.clone(), genesis_time)?;
+        let chain = Chain::new(store, runtime_adapter.clone(), genesis_time, false)?;
         Ok(ViewClientActor { chain, runtime_adapter })
     }
 
@@ -88,12 +94,35 @@ impl Handler<Query> for ViewClientActor {
     type Result = Result<QueryResponse, String>;
 
     fn handle(&mut self, msg: Query, _: &mut Context<Self>) -> Self::Result {
-        let head = self.chain.head().map_err(|err| err.to_string())?;
-        let state_root =
-            self.chain.get_post_state_root(&head.last_block_hash).map_err(|err| err.to_string())?;
-        self.runtime_adapter
-            .query(*state_root, head.height, &msg.path, &msg.data)
-            .map_err(|err| err.to_string())
+        let (block_hash, height) = match msg.block_id {
+            None | Some(BlockId::Finality(_)) => self.chain.head_header(),
+            Some(BlockId::Height(height)) => self.chain.get_header_by_height(height),
+            Some(BlockId::Hash(hash)) => self.chain.get_block_header(&hash),
+        }
+        .map(|header| (header.hash(), header.height))
+        .map_err(|err| err.to_string())?;
+        // State that was garbage collected is reported without the backtrace, as a plain message.
+        let state_root = self.chain.get_post_state_root_if_kept(&block_hash).map_err(|err| {
+            match err.kind() {
+                ErrorKind::StateGarbageCollected(_, _) => err.kind().to_string(),
+                _ => err.to_string(),
+            }
+        })?;
+        let response = self
+            .runtime_adapter
+            .query(state_root, height, &msg.path, &msg.data)
+            .map_err(|err| err.to_string())?;
+        if !msg.prove {
+            return Ok(response);
+        }
+        let nodes = self
+            .runtime_adapter
+            .query_proof(state_root, &msg.path)
+            .map_err(|err| err.to_string())?;
+        Ok(QueryResponse::Proven(ProvenQueryResponse {
+            result: Box::new(response),
+            proof: StateProof { block_hash, state_root, nodes },
+        }))
     }
 }
 
@@ -114,6 +143,107 @@ impl Handler<GetBlock> for ViewClientActor {
     }
 }
 
+impl Handler<GetBlockHeader> for ViewClientActor {
+    type Result = Result<BlockHeader, String>;
+
+    fn handle(&mut self, msg: GetBlockHeader, _: &mut Context<Self>) -> Self::Result {
+        match msg {
+            GetBlockHeader::Best => match self.chain.head() {
+                Ok(head) => self.chain.get_block_header(&head.last_block_hash).map(Clone::clone),
+                Err(err) => Err(err),
+            },
+            GetBlockHeader::Height(height) => {
+                self.chain.get_header_by_height(height).map(Clone::clone)
+            }
+            GetBlockHeader::Hash(hash) => self.chain.get_block_header(&hash).map(Clone::clone),
+        }
+        .map_err(|err| err.to_string())
+    }
+}
+
+/// Collects headers of the canonical chain that a light client needs to get from the trusted
+/// header to the target one: headers with validator proposals, both headers around every change
+/// of the epoch and the target header itself. Targets more than `MAX_LIGHT_CLIENT_PROOF_BLOCKS`
+/// after the trusted header are rejected to bound the walk back through the chain.
+impl Handler<GetLightClientProof> for ViewClientActor {
+    type Result = Result<LightClientProof, String>;
+
+    fn handle(&mut self, msg: GetLightClientProof, _: &mut Context<Self>) -> Self::Result {
+        let trusted =
+            self.chain.get_block_header(&msg.trusted_hash).map_err(|err| err.to_string())?.clone();
+        let target = match msg.target {
+            None | Some(BlockId::Finality(_)) => self.chain.head_header().map(Clone::clone),
+            Some(BlockId::Height(height)) => {
+                self.chain.get_header_by_height(height).map(Clone::clone)
+            }
+            Some(BlockId::Hash(hash)) => self.chain.get_block_header(&hash).map(Clone::clone),
+        }
+        .map_err(|err| err.to_string())?;
+        if target.height <= trusted.height {
+            return Err(format!(
+                "Target block at height {} is not after the trusted block at height {}",
+                target.height, trusted.height
+            ));
+        }
+        if target.height - trusted.height > MAX_LIGHT_CLIENT_PROOF_BLOCKS {
+            return Err(format!(
+                "Target block at height {} is more than {} blocks after the trusted block",
+                target.height, MAX_LIGHT_CLIENT_PROOF_BLOCKS
+            ));
+        }
+
+        // Walk back from the target to the trusted header, which has to be its ancestor.
+        let target_hash = target.hash();
+        let mut chain_headers = vec![target];
+        loop {
+            let prev_header = self
+                .chain
+                .get_previous_header(chain_headers.last().unwrap())
+                .map_err(|err| err.to_string())?
+                .clone();
+            if prev_header.height <= trusted.height {
+                if prev_header.hash() != trusted.hash() {
+                    return Err(format!(
+                        "Block {} is not an ancestor of {}",
+                        trusted.hash(),
+                        target_hash
+                    ));
+                }
+                break;
+            }
+            chain_headers.push(prev_header);
+        }
+        chain_headers.reverse();
+
+        let mut headers = vec![];
+        let mut prev_epoch_hash = trusted.epoch_hash;
+        for (i, header) in chain_headers.iter().enumerate() {
+            let next_epoch_hash =
+                chain_headers.get(i + 1).map_or(header.epoch_hash, |next| next.epoch_hash);
+            if !header.validator_proposal.is_empty()
+                || header.epoch_hash != prev_epoch_hash
+                || header.epoch_hash != next_epoch_hash
+                || i == chain_headers.len() - 1
+            {
+                headers.push(header.clone());
+            }
+            prev_epoch_hash = header.epoch_hash;
+        }
+
+        let mut block_producers: Vec<EpochBlockProducers> = vec![];
+        for header in headers.iter() {
+            if block_producers.iter().all(|epoch| epoch.epoch_hash != header.epoch_hash) {
+                block_producers.push(
+                    self.runtime_adapter
+                        .get_epoch_block_producers(&header.epoch_hash)
+                        .map_err(|err| err.to_string())?,
+                );
+            }
+        }
+        Ok(LightClientProof { headers, block_producers })
+    }
+}
+
 impl Handler<TxStatus> for ViewClientActor {
     type Result = Result<FinalTransactionResult, String>;
 
@@ -2,19 +2,21 @@ use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
-use actix::System;
+use actix::{Actor, System};
 use futures::{future, Future};
 
 use near_chain::{Block, BlockApproval};
 use near_client::test_utils::setup_mock;
 use near_client::GetBlock;
-use near_network::test_utils::wait_or_panic;
+use near_network::test_utils::{wait_or_panic, WaitOrTimeout};
 use near_network::types::{FullPeerInfo, NetworkInfo, PeerChainInfo};
-use near_network::{NetworkClientMessages, NetworkRequests, NetworkResponses, PeerInfo};
+use near_network::{
+    NetworkClientMessages, NetworkClientResponses, NetworkRequests, NetworkResponses, PeerInfo,
+};
 use near_primitives::crypto::signer::InMemorySigner;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::test_utils::init_test_logger;
-use near_primitives::transaction::SignedTransaction;
+use near_primitives::transaction::TransactionBody;
 use near_primitives::types::MerkleHash;
 
 /// Runs block producing client and stops after network mock received two blocks.
@@ -47,7 +49,7 @@ fn produce_blocks_with_tx() {
     let count = Arc::new(AtomicUsize::new(0));
     init_test_logger();
     System::run(|| {
-        let (client, _) = setup_mock(
+        let (client, view_client) = setup_mock(
             vec!["test"],
             "test",
             true,
@@ -61,7 +63,13 @@ fn produce_blocks_with_tx() {
                 NetworkResponses::NoResponse
             }),
         );
-        client.do_send(NetworkClientMessages::Transaction(SignedTransaction::empty()));
+        actix::spawn(view_client.send(GetBlock::Best).then(move |res| {
+            let block_hash = res.unwrap().unwrap().hash();
+            let signer = InMemorySigner::from_seed("test", "test");
+            let tx = TransactionBody::send_money(1, "test", "other", 1, block_hash).sign(&signer);
+            client.do_send(NetworkClientMessages::Transaction(tx));
+            future::result(Ok(()))
+        }));
     })
     .unwrap();
 }
@@ -188,7 +196,13 @@ fn produce_block_with_approvals() {
                 vec![],
                 signer1,
             );
-            let block_approval = BlockApproval::new(block.hash(), &*signer3, "test2".to_string());
+            let block_approval = BlockApproval::new(
+                block.hash(),
+                block.header.height,
+                &*signer3,
+                "test2".to_string(),
+            )
+            .unwrap();
             client.do_send(NetworkClientMessages::Block(block, PeerInfo::random().id, false));
             client.do_send(NetworkClientMessages::BlockApproval(
                 "test3".to_string(),
@@ -325,6 +339,7 @@ fn client_sync_headers() {
                     sent_bytes_per_sec: 0,
                     received_bytes_per_sec: 0,
                     routes: None,
+                    graph: None,
                 }),
                 NetworkRequests::BlockHeadersRequest { hashes, peer_id } => {
                     assert_eq!(*peer_id, peer_info1.id);
@@ -340,3 +355,111 @@ fn client_sync_headers() {
     })
     .unwrap();
 }
+
+/// Runs client that is not a block producer and forwards transaction to the block producer.
+#[test]
+fn forward_tx_to_block_producer() {
+    init_test_logger();
+    System::run(|| {
+        let (client, view_client) = setup_mock(
+            vec!["test"],
+            "other",
+            true,
+            Box::new(move |msg, _ctx, _client_actor| match msg {
+                NetworkRequests::FetchInfo { level: _ } => NetworkResponses::Info(NetworkInfo {
+                    num_active_peers: 1,
+                    peer_max_count: 1,
+                    most_weight_peers: vec![],
+                    sent_bytes_per_sec: 0,
+                    received_bytes_per_sec: 0,
+                    routes: None,
+                    graph: None,
+                }),
+                NetworkRequests::ForwardTx { account_ids, .. } => {
+                    assert_eq!(*account_ids, vec!["test".to_string()]);
+                    System::current().stop();
+                    NetworkResponses::NoResponse
+                }
+                _ => NetworkResponses::NoResponse,
+            }),
+        );
+        actix::spawn(view_client.send(GetBlock::Best).then(move |res| {
+            let block_hash = res.unwrap().unwrap().hash();
+            let signer = InMemorySigner::from_seed("test", "test");
+            let tx = TransactionBody::send_money(1, "test", "other", 1, block_hash).sign(&signer);
+            // Keep sending until the client learns it has peers to forward the transaction to.
+            WaitOrTimeout::new(
+                Box::new(move |_| {
+                    client.do_send(NetworkClientMessages::Transaction(tx.clone()));
+                }),
+                100,
+                5000,
+            )
+            .start();
+            future::result(Ok(()))
+        }));
+    })
+    .unwrap();
+}
+
+/// Transaction sent again after it was forwarded is reported as routed without forwarding it
+/// again, so the RPC keeps waiting for its result.
+#[test]
+fn forward_tx_already_seen() {
+    init_test_logger();
+    System::run(|| {
+        let forwarded = Arc::new(AtomicUsize::new(0));
+        let (client, view_client) = setup_mock(
+            vec!["test"],
+            "other",
+            true,
+            Box::new(move |msg, _ctx, _client_actor| match msg {
+                NetworkRequests::FetchInfo { level: _ } => NetworkResponses::Info(NetworkInfo {
+                    num_active_peers: 1,
+                    peer_max_count: 1,
+                    most_weight_peers: vec![],
+                    sent_bytes_per_sec: 0,
+                    received_bytes_per_sec: 0,
+                    routes: None,
+                    graph: None,
+                }),
+                NetworkRequests::ForwardTx { .. } => {
+                    assert_eq!(forwarded.fetch_add(1, Ordering::Relaxed), 0);
+                    NetworkResponses::NoResponse
+                }
+                _ => NetworkResponses::NoResponse,
+            }),
+        );
+        actix::spawn(view_client.send(GetBlock::Best).then(move |res| {
+            let block_hash = res.unwrap().unwrap().hash();
+            let signer = InMemorySigner::from_seed("test", "test");
+            let tx = TransactionBody::send_money(1, "test", "other", 1, block_hash).sign(&signer);
+            let routed = Arc::new(AtomicUsize::new(0));
+            WaitOrTimeout::new(
+                Box::new(move |_| {
+                    let routed = routed.clone();
+                    actix::spawn(client.send(NetworkClientMessages::Transaction(tx.clone())).then(
+                        move |res| {
+                            match res.unwrap() {
+                                NetworkClientResponses::RequestRouted => {
+                                    if routed.fetch_add(1, Ordering::Relaxed) >= 2 {
+                                        System::current().stop();
+                                    }
+                                }
+                                // Client doesn't know about its peers yet.
+                                NetworkClientResponses::NoResponse => {}
+                                _ => panic!("Transaction should be routed"),
+                            }
+                            future::result(Ok(()))
+                        },
+                    ));
+                }),
+                100,
+                5000,
+            )
+            .start();
+            future::result(Ok(()))
+        }));
+    })
+    .unwrap();
+}
@@ -4,7 +4,7 @@ use futures::future::Future;
 
 use near_client::test_utils::setup_no_network;
 use near_client::Query;
-use near_primitives::rpc::QueryResponse;
+use near_primitives::rpc::{BlockId, QueryResponse};
 use near_primitives::test_utils::init_test_logger;
 
 /// Query account from view client
@@ -14,16 +14,57 @@ fn query_client() {
     System::run(|| {
         let (_, view_client) = setup_no_network(vec!["test"], "other", true);
         actix::spawn(
-            view_client.send(Query { path: "account/test".to_string(), data: vec![] }).then(
-                |res| {
+            view_client
+                .send(Query {
+                    path: "account/test".to_string(),
+                    data: vec![],
+                    block_id: None,
+                    prove: false,
+                })
+                .then(|res| {
                     match res {
                         Ok(Ok(QueryResponse::ViewAccount(_))) => (),
                         _ => panic!("Invalid response"),
                     }
                     System::current().stop();
                     future::result(Ok(()))
-                },
-            ),
+                }),
+        );
+    })
+    .unwrap();
+}
+
+/// Query account as of the genesis block and as of a block that doesn't exist.
+#[test]
+fn query_client_at_block() {
+    init_test_logger();
+    System::run(|| {
+        let (_, view_client) = setup_no_network(vec!["test"], "other", true);
+        actix::spawn(
+            view_client
+                .send(Query {
+                    path: "account/test".to_string(),
+                    data: vec![],
+                    block_id: Some(BlockId::Height(0)),
+                    prove: false,
+                })
+                .then(move |res| {
+                    match res {
+                        Ok(Ok(QueryResponse::ViewAccount(_))) => (),
+                        _ => panic!("Invalid response"),
+                    }
+                    view_client.send(Query {
+                        path: "account/test".to_string(),
+                        data: vec![],
+                        block_id: Some(BlockId::Height(100)),
+                        prove: false,
+                    })
+                })
+                .then(|res| {
+                    assert!(res.unwrap().is_err());
+                    System::current().stop();
+                    future::result(Ok(()))
+                }),
         );
     })
     .unwrap();
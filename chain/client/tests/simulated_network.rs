@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::rc::Rc;
+
+use actix::{Actor, Addr, System};
+use futures::future;
+use futures::future::Future;
+
+use near_chain::Block;
+use near_client::test_utils::setup_simulated;
+use near_client::{GetBlock, ViewClientActor};
+use near_network::test_utils::{SimulatorConfig, WaitOrTimeout};
+use near_primitives::hash::CryptoHash;
+use near_primitives::test_utils::{get_public_key_from_seed, init_test_logger};
+use near_primitives::types::BlockIndex;
+
+/// Calls `f` with the head once all the view clients have the same head at least at given height.
+fn on_same_head<F>(view_clients: &[Addr<ViewClientActor>], min_height: BlockIndex, f: F)
+where
+    F: FnOnce(Block) + 'static,
+{
+    let num_clients = view_clients.len();
+    let heads: Vec<_> =
+        view_clients.iter().map(|view_client| view_client.send(GetBlock::Best)).collect();
+    actix::spawn(future::join_all(heads).then(move |res| {
+        if let Ok(heads) = res {
+            let heads = heads.into_iter().filter_map(Result::ok).collect::<Vec<_>>();
+            if heads.len() == num_clients
+                && heads[0].header.height >= min_height
+                && heads.iter().all(|block| block.hash() == heads[0].hash())
+            {
+                f(heads[0].clone());
+            }
+        }
+        future::result(Ok(()))
+    }));
+}
+
+/// Stops the system once all the view clients have the same head at least at given height.
+fn stop_on_same_head(view_clients: &[Addr<ViewClientActor>], min_height: BlockIndex) {
+    on_same_head(view_clients, min_height, |_| System::current().stop());
+}
+
+/// Validators produce the same chain over the network with random latencies and dropped messages.
+#[test]
+fn simulated_block_production() {
+    init_test_logger();
+    System::run(|| {
+        let config = SimulatorConfig {
+            seed: 1,
+            max_latency_ms: 100,
+            drop_probability: 0.05,
+            ..Default::default()
+        };
+        let (_simulator, clients) =
+            setup_simulated(vec![vec!["test1", "test2", "test3", "test4"]], 10, config);
+        let view_clients =
+            clients.into_iter().map(|(_, view_client)| view_client).collect::<Vec<_>>();
+        WaitOrTimeout::new(Box::new(move |_| stop_on_same_head(&view_clients, 10)), 100, 60000)
+            .start();
+    })
+    .unwrap();
+}
+
+/// Progress of the partition scenario.
+enum PartitionPhase {
+    Partitioned,
+    /// Waiting for the heads of the partitions.
+    Querying,
+    /// Partition healed after the partitions forked at the given heads.
+    Healed {
+        forks: Vec<(CryptoHash, BlockIndex)>,
+    },
+}
+
+/// Validator cut off from the others forks the chain and switches to the heavier fork of the
+/// majority once the partition heals.
+#[test]
+fn simulated_partition() {
+    init_test_logger();
+    System::run(|| {
+        let config = SimulatorConfig { seed: 2, ..Default::default() };
+        // Long epochs keep the forked blocks from being garbage collected before they're checked.
+        let (simulator, clients) =
+            setup_simulated(vec![vec!["test1", "test2", "test3", "test4"]], 1000, config);
+        let view_clients =
+            clients.into_iter().map(|(_, view_client)| view_client).collect::<Vec<_>>();
+        simulator.partition(&[&[0, 1, 2], &[3]]);
+        let phase = Rc::new(RefCell::new(PartitionPhase::Partitioned));
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                let forks = match &*phase.borrow() {
+                    PartitionPhase::Partitioned if simulator.now() >= 3000 => None,
+                    PartitionPhase::Healed { forks } => Some(forks.clone()),
+                    _ => return,
+                };
+                match forks {
+                    None => {
+                        *phase.borrow_mut() = PartitionPhase::Querying;
+                        let (phase, simulator) = (phase.clone(), simulator.clone());
+                        let heads = view_clients[0]
+                            .send(GetBlock::Best)
+                            .join(view_clients[3].send(GetBlock::Best));
+                        actix::spawn(heads.then(move |res| {
+                            let (head1, head2) = res.unwrap();
+                            let (head1, head2) = (head1.unwrap(), head2.unwrap());
+                            assert_ne!(head1.hash(), head2.hash(), "Partitions didn't fork");
+                            simulator.heal();
+                            *phase.borrow_mut() = PartitionPhase::Healed {
+                                forks: vec![
+                                    (head1.hash(), head1.header.height),
+                                    (head2.hash(), head2.header.height),
+                                ],
+                            };
+                            future::result(Ok(()))
+                        }));
+                    }
+                    Some(forks) => {
+                        let min_height = cmp::max(forks[0].1, forks[1].1) + 5;
+                        let view_client = view_clients[0].clone();
+                        on_same_head(&view_clients, min_height, move |_| {
+                            // Fork of the majority must end up in the canonical chain.
+                            let canonical: Vec<_> = forks
+                                .iter()
+                                .map(move |(hash, height)| {
+                                    let hash = *hash;
+                                    view_client.send(GetBlock::Height(*height)).map(move |block| {
+                                        block.map(|block| block.hash() == hash).unwrap_or(false)
+                                    })
+                                })
+                                .collect();
+                            actix::spawn(future::join_all(canonical).then(|res| {
+                                let canonical = res.unwrap();
+                                assert_eq!(canonical, vec![true, false]);
+                                System::current().stop();
+                                future::result(Ok(()))
+                            }));
+                        });
+                    }
+                }
+            }),
+            100,
+            60000,
+        )
+        .start();
+    })
+    .unwrap();
+}
+
+/// Chain keeps growing while block production moves between disjoint sets of validators every
+/// epoch, and blocks of each height are produced by the validators of its epoch.
+#[test]
+fn simulated_validator_rotation() {
+    init_test_logger();
+    System::run(|| {
+        let epochs = vec![vec!["test1", "test2"], vec!["test3", "test4"]];
+        let epoch_length = 5;
+        let config = SimulatorConfig { seed: 3, ..Default::default() };
+        let (_simulator, clients) = setup_simulated(epochs.clone(), epoch_length, config);
+        let view_clients =
+            clients.into_iter().map(|(_, view_client)| view_client).collect::<Vec<_>>();
+        let min_height = 4 * epoch_length;
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                let view_client = view_clients[0].clone();
+                let epochs = epochs.clone();
+                on_same_head(&view_clients, min_height, move |_| {
+                    let blocks = (1..=min_height)
+                        .map(|height| view_client.send(GetBlock::Height(height)))
+                        .collect::<Vec<_>>();
+                    actix::spawn(future::join_all(blocks).then(move |res| {
+                        let blocks =
+                            res.unwrap().into_iter().filter_map(Result::ok).collect::<Vec<_>>();
+                        for block in blocks.iter() {
+                            let height = block.header.height;
+                            let epoch = &epochs[((height / epoch_length) as usize) % epochs.len()];
+                            assert!(
+                                epoch.iter().any(|account_id| block
+                                    .header
+                                    .verify_block_producer(&get_public_key_from_seed(account_id))),
+                                "Block at {} is not produced by validators of its epoch",
+                                height
+                            );
+                        }
+                        // Validators of each of the epochs produced blocks.
+                        for epoch in 0..(min_height / epoch_length) {
+                            assert!(blocks
+                                .iter()
+                                .any(|block| { block.header.height / epoch_length == epoch }));
+                        }
+                        System::current().stop();
+                        future::result(Ok(()))
+                    }));
+                });
+            }),
+            100,
+            60000,
+        )
+        .start();
+    })
+    .unwrap();
+}
@@ -4,10 +4,9 @@ use actix_web::client::Client;
 use futures::Future;
 use serde::Serialize;
 
-use near_primitives::block::Block;
-use near_primitives::rpc::{QueryResponse, StatusResponse};
+use near_primitives::block::{Block, BlockHeader};
+use near_primitives::rpc::{BlockId, LightClientProof, QueryResponse, StatusResponse};
 use near_primitives::transaction::{FinalTransactionResult, TransactionResult};
-use near_primitives::types::BlockIndex;
 
 pub mod message;
 use crate::message::{from_slice, Message};
@@ -169,9 +168,43 @@ jsonrpc_client!(pub struct JsonRpcClient {
     pub fn health(&mut self) -> RpcRequest<()>;
     pub fn tx(&mut self, hash: String) -> RpcRequest<FinalTransactionResult>;
     pub fn tx_details(&mut self, hash: String) -> RpcRequest<TransactionResult>;
-    pub fn block(&mut self, height: BlockIndex) -> RpcRequest<Block>;
+    pub fn block(&mut self, block_id: BlockId) -> RpcRequest<Block>;
+    pub fn header(&mut self, block_id: BlockId) -> RpcRequest<BlockHeader>;
 });
 
+impl JsonRpcClient {
+    /// Queries the state as of the given block instead of the head of the chain.
+    pub fn query_at(
+        &mut self,
+        path: String,
+        data: String,
+        block_id: BlockId,
+    ) -> RpcRequest<QueryResponse> {
+        call_method(&self.client, &self.server_addr, "query", (path, data, block_id))
+    }
+
+    /// Queries the state as of the given block, or the head of the chain, together with
+    /// the proof of the state that was read.
+    pub fn query_with_proof(
+        &mut self,
+        path: String,
+        data: String,
+        block_id: Option<BlockId>,
+    ) -> RpcRequest<QueryResponse> {
+        call_method(&self.client, &self.server_addr, "query", (path, data, block_id, true))
+    }
+
+    /// Headers and block producers to verify the given block, or the head of the chain, starting
+    /// from the trusted block.
+    pub fn light_client_proof(
+        &mut self,
+        trusted_hash: String,
+        block_id: Option<BlockId>,
+    ) -> RpcRequest<LightClientProof> {
+        call_method(&self.client, &self.server_addr, "light_client_proof", (trusted_hash, block_id))
+    }
+}
+
 /// Create new JSON RPC client that connects to the given address.
 pub fn new_client(server_addr: &str) -> JsonRpcClient {
     let client = Client::build().timeout(CONNECT_TIMEOUT).finish();
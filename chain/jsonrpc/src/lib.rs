@@ -17,17 +17,22 @@ use serde_json::Value;
 use async_utils::{delay, timeout};
 use message::{Request, RpcError};
 use message::Message;
-use near_client::{ClientActor, GetBlock, Query, Status, TxDetails, TxStatus, ViewClientActor};
+use near_client::{
+    ClientActor, GetBlock, GetBlockHeader, GetLightClientProof, GetNetworkGraph, Query, Status,
+    TxDetails, TxStatus, ViewClientActor,
+};
 pub use near_jsonrpc_client as client;
+pub use crate::ws::MAX_SUBSCRIPTIONS_PER_CONNECTION;
 use near_jsonrpc_client::message as message;
 use near_network::{NetworkClientMessages, NetworkClientResponses};
 use near_primitives::hash::CryptoHash;
+use near_primitives::rpc::BlockId;
 use near_primitives::serialize::{BaseEncode, from_base, from_base64};
 use near_primitives::transaction::{FinalTransactionStatus, SignedTransaction};
-use near_primitives::types::BlockIndex;
 use near_protos::signed_transaction as transaction_proto;
 
 pub mod test_utils;
+mod ws;
 
 /// Maximum byte size of the json payload.
 const JSON_PAYLOAD_MAX_SIZE: usize = 2 * 1024 * 1024;
@@ -109,13 +114,50 @@ fn parse_tx(params: Option<Value>) -> Result<SignedTransaction, RpcError> {
     })?)
 }
 
-fn parse_hash(params: Option<Value>) -> Result<CryptoHash, RpcError> {
-    let (encoded,) = parse_params::<(String,)>(params)?;
+fn hash_from_base(encoded: String) -> Result<CryptoHash, RpcError> {
     from_base_or_parse_err(encoded).and_then(|bytes| {
         CryptoHash::try_from(bytes).map_err(|err| RpcError::parse_error(err.to_string()))
     })
 }
 
+/// Parses path and data of the query, optionally followed by the block to query the state at
+/// (head of the chain by default) and the flag to attach the proof of the state.
+fn parse_query(params: Option<Value>) -> Result<Query, RpcError> {
+    let mut params = parse_params::<Vec<Value>>(params)?;
+    if params.len() < 2 || params.len() > 4 {
+        return Err(RpcError::invalid_params(Some(
+            "Expected path, data, optional block id and optional prove flag".to_owned(),
+        )));
+    }
+    params.resize(4, Value::Null);
+    let (path, data, block_id, prove) =
+        parse_params::<(String, String, Option<BlockId>, Option<bool>)>(Some(Value::Array(
+            params,
+        )))?;
+    let data = from_base_or_parse_err(data)?;
+    Ok(Query { path, data, block_id, prove: prove.unwrap_or(false) })
+}
+
+/// Parses the trusted block hash, optionally followed by the target block (head of the chain by
+/// default).
+fn parse_light_client_proof(params: Option<Value>) -> Result<GetLightClientProof, RpcError> {
+    let mut params = parse_params::<Vec<Value>>(params)?;
+    if params.is_empty() || params.len() > 2 {
+        return Err(RpcError::invalid_params(Some(
+            "Expected trusted block hash and optional target block id".to_owned(),
+        )));
+    }
+    params.resize(2, Value::Null);
+    let (trusted_hash, target) =
+        parse_params::<(String, Option<BlockId>)>(Some(Value::Array(params)))?;
+    Ok(GetLightClientProof { trusted_hash: hash_from_base(trusted_hash)?, target })
+}
+
+fn parse_hash(params: Option<Value>) -> Result<CryptoHash, RpcError> {
+    let (encoded,) = parse_params::<(String,)>(params)?;
+    hash_from_base(encoded)
+}
+
 struct JsonRpcHandler {
     client_addr: Addr<ClientActor>,
     view_client_addr: Addr<ViewClientActor>,
@@ -143,6 +185,9 @@ impl JsonRpcHandler {
             "tx" => self.tx_status(request.params).await,
             "tx_details" => self.tx_details(request.params).await,
             "block" => self.block(request.params).await,
+            "header" => self.header(request.params).await,
+            "light_client_proof" => self.light_client_proof(request.params).await,
+            "network_graph" => self.network_graph().await,
             _ => Err(RpcError::method_not_found(request.method)),
         }
     }
@@ -150,13 +195,20 @@ impl JsonRpcHandler {
     async fn send_tx_async(&self, params: Option<Value>) -> Result<Value, RpcError> {
         let tx = parse_tx(params)?;
         let hash = (&tx.get_hash()).to_base();
-        actix::spawn(
-            self.client_addr
-                .send(NetworkClientMessages::Transaction(tx))
-                .map(|_| ())
-                .map_err(|_| ()),
-        );
-        Ok(Value::String(hash))
+        let result = self.client_addr
+            .send(NetworkClientMessages::Transaction(tx))
+            .map_err(|err| RpcError::server_error(Some(err.to_string())))
+            .compat()
+            .await?;
+        match result {
+            NetworkClientResponses::ValidTx | NetworkClientResponses::RequestRouted => {
+                Ok(Value::String(hash))
+            }
+            NetworkClientResponses::InvalidTx(err) => Err(RpcError::server_error(Some(err))),
+            _ => Err(RpcError::server_error(Some(
+                "Transaction was not forwarded, there are no peers".to_owned(),
+            ))),
+        }
     }
 
     async fn send_tx_commit(&self, params: Option<Value>) -> Result<Value, RpcError> {
@@ -168,7 +220,7 @@ impl JsonRpcHandler {
             .compat()
             .await?;
         match result {
-            NetworkClientResponses::ValidTx => {
+            NetworkClientResponses::ValidTx | NetworkClientResponses::RequestRouted => {
                 timeout(self.polling_config.polling_timeout, async {
                     loop {
                         let final_tx = self.view_client_addr.send(TxStatus { tx_hash }).compat().await;
@@ -189,7 +241,9 @@ impl JsonRpcHandler {
             NetworkClientResponses::InvalidTx(err) => {
                 Err(RpcError::server_error(Some(err)))
             }
-            _ => unreachable!(),
+            _ => Err(RpcError::server_error(Some(
+                "Transaction was not forwarded, there are no peers".to_owned(),
+            ))),
         }
     }
 
@@ -202,9 +256,8 @@ impl JsonRpcHandler {
     }
 
     async fn query(&self, params: Option<Value>) -> Result<Value, RpcError> {
-        let (path, data) = parse_params::<(String, String)>(params)?;
-        let data = from_base_or_parse_err(data)?;
-        jsonify(self.view_client_addr.send(Query { path, data }).compat().await)
+        let query = parse_query(params)?;
+        jsonify(self.view_client_addr.send(query).compat().await)
     }
 
     async fn tx_status(&self, params: Option<Value>) -> Result<Value, RpcError> {
@@ -218,8 +271,22 @@ impl JsonRpcHandler {
     }
 
     async fn block(&self, params: Option<Value>) -> Result<Value, RpcError> {
-        let (height,) = parse_params::<(BlockIndex,)>(params)?;
-        jsonify(self.view_client_addr.send(GetBlock::Height(height)).compat().await)
+        let (block_id,) = parse_params::<(BlockId,)>(params)?;
+        jsonify(self.view_client_addr.send(GetBlock::from(block_id)).compat().await)
+    }
+
+    async fn header(&self, params: Option<Value>) -> Result<Value, RpcError> {
+        let (block_id,) = parse_params::<(BlockId,)>(params)?;
+        jsonify(self.view_client_addr.send(GetBlockHeader::from(block_id)).compat().await)
+    }
+
+    async fn light_client_proof(&self, params: Option<Value>) -> Result<Value, RpcError> {
+        let msg = parse_light_client_proof(params)?;
+        jsonify(self.view_client_addr.send(msg).compat().await)
+    }
+
+    async fn network_graph(&self) -> Result<Value, RpcError> {
+        jsonify(self.client_addr.send(GetNetworkGraph {}).compat().await)
     }
 }
 
@@ -234,6 +301,14 @@ fn rpc_handler(
     response.boxed().compat()
 }
 
+/// Exposes metrics of the node in the Prometheus text format.
+fn metrics_handler() -> HttpResponse {
+    match near_metrics::encode() {
+        Ok((buffer, content_type)) => HttpResponse::Ok().content_type(content_type).body(buffer),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 fn status_handler(handler: web::Data<JsonRpcHandler>) -> impl Future<Item = HttpResponse, Error = HttpError> {
     let response = async move {
         match handler.status().await {
@@ -275,6 +350,8 @@ pub fn start_http(
             .wrap(middleware::Logger::default())
             .service(web::resource("/").route(web::post().to_async(rpc_handler)))
             .service(web::resource("/status").route(web::get().to_async(status_handler)))
+            .service(web::resource("/metrics").route(web::get().to(metrics_handler)))
+            .service(web::resource("/ws").route(web::get().to(ws::ws_handler)))
     })
     .bind(addr)
     .unwrap()
@@ -7,8 +7,14 @@ use near_network::test_utils::open_port;
 use crate::{start_http, RpcConfig};
 
 pub fn start_all(validator: bool) -> (Addr<ViewClientActor>, String) {
-    let (client_addr, view_client_addr) =
-        setup_no_network(vec!["test1", "test2"], if validator { "test1" } else { "other" }, true);
+    start_all_with_validators(vec!["test1", "test2"], if validator { "test1" } else { "other" })
+}
+
+pub fn start_all_with_validators(
+    validators: Vec<&'static str>,
+    account_id: &'static str,
+) -> (Addr<ViewClientActor>, String) {
+    let (client_addr, view_client_addr) = setup_no_network(validators, account_id, true);
 
     let addr = format!("127.0.0.1:{}", open_port());
     start_http(RpcConfig::new(&addr), client_addr.clone(), view_client_addr.clone());
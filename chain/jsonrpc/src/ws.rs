@@ -0,0 +1,299 @@
+//! WebSocket endpoint that pushes chain updates to subscribers.
+//!
+//! Client sends JSON RPC requests `subscribe` and `unsubscribe` over the socket and receives
+//! `subscription` notifications with `{"subscription": id, "result": ...}` params, where the
+//! result depends on the kind of the subscription:
+//!  * `["newHeads"]` - header of every new head of the chain;
+//!  * `["newBlocks"]` - every block that became the new head of the chain;
+//!  * `["tx", <base58 hash>]` - final result of the transaction, sent once;
+//!  * `["account", <account id>]` - account view every time it changes.
+//! Updates are pushed by the client when a block is accepted, instead of being polled.
+//! On every new head, view client is queried once for each distinct transaction and account
+//! subscribed to, and each connection can have at most `MAX_SUBSCRIPTIONS_PER_CONNECTION`
+//! subscriptions.
+
+use std::collections::{HashMap, HashSet};
+
+use actix::{
+    Actor, ActorContext, ActorFuture, Addr, AsyncContext, ContextFutureSpawner, Handler,
+    StreamHandler, WrapFuture,
+};
+use actix_web::{web, Error as HttpError, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use log::debug;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use near_client::{
+    BlockNotification, ClientActor, Query, SubscribeBlocks, TxStatus, ViewClientActor,
+};
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::FinalTransactionStatus;
+use near_primitives::types::AccountId;
+
+use crate::message::{self, Message, Request, RpcError};
+use crate::{hash_from_base, parse_params, JsonRpcHandler};
+
+/// Method name of the notifications pushed to subscribers.
+const SUBSCRIPTION_METHOD: &str = "subscription";
+
+/// Maximum number of subscriptions of a single WebSocket connection.
+pub const MAX_SUBSCRIPTIONS_PER_CONNECTION: usize = 100;
+
+enum Subscription {
+    NewHeads,
+    NewBlocks,
+    /// Final result of the transaction. Removed once the result is sent.
+    Transaction(CryptoHash),
+    /// Account view, with the last value sent to the subscriber.
+    Account {
+        account_id: AccountId,
+        last_value: Option<Value>,
+    },
+}
+
+/// WebSocket connection with its subscriptions.
+pub struct WsSession {
+    client_addr: Addr<ClientActor>,
+    view_client_addr: Addr<ViewClientActor>,
+    next_subscription_id: u64,
+    subscriptions: HashMap<u64, Subscription>,
+}
+
+impl WsSession {
+    fn new(client_addr: Addr<ClientActor>, view_client_addr: Addr<ViewClientActor>) -> Self {
+        WsSession {
+            client_addr,
+            view_client_addr,
+            next_subscription_id: 0,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self>, message: &Message) {
+        match serde_json::to_string(message) {
+            Ok(text) => ctx.text(text),
+            Err(err) => debug!(target: "jsonrpc", "Failed to serialize message: {}", err),
+        }
+    }
+
+    fn notify<T: Serialize>(&self, ctx: &mut ws::WebsocketContext<Self>, id: u64, result: &T) {
+        let notification = Message::notification(
+            SUBSCRIPTION_METHOD.to_string(),
+            Some(json!({ "subscription": id, "result": result })),
+        );
+        self.send(ctx, &notification);
+    }
+
+    fn process_text(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let response = match message::from_str(text) {
+            Ok(Message::Request(request)) => {
+                let id = request.id.clone();
+                Message::response(id, self.process_request(request, ctx))
+            }
+            Ok(_) => Message::error(RpcError::invalid_request()),
+            Err(broken) => broken.reply(),
+        };
+        self.send(ctx, &response);
+    }
+
+    fn process_request(
+        &mut self,
+        request: Request,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<Value, RpcError> {
+        match request.method.as_ref() {
+            "subscribe" => self.subscribe(request.params, ctx),
+            "unsubscribe" => self.unsubscribe(request.params),
+            _ => Err(RpcError::method_not_found(request.method)),
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        params: Option<Value>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<Value, RpcError> {
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS_PER_CONNECTION {
+            return Err(RpcError::server_error(Some(format!(
+                "Too many subscriptions, at most {} per connection",
+                MAX_SUBSCRIPTIONS_PER_CONNECTION
+            ))));
+        }
+        let params = parse_params::<Vec<String>>(params)?;
+        let subscription = match params.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["newHeads"] => Subscription::NewHeads,
+            ["newBlocks"] => Subscription::NewBlocks,
+            ["tx", hash] => Subscription::Transaction(hash_from_base(hash.to_string())?),
+            ["account", account_id] => {
+                Subscription::Account { account_id: account_id.to_string(), last_value: None }
+            }
+            _ => {
+                return Err(RpcError::invalid_params(Some(format!(
+                    "Unknown subscription {:?}",
+                    params
+                ))))
+            }
+        };
+        let id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        self.subscriptions.insert(id, subscription);
+        // Transaction might be already final and account has to be sent in its current state.
+        self.check_subscription(id, ctx);
+        Ok(json!(id))
+    }
+
+    fn unsubscribe(&mut self, params: Option<Value>) -> Result<Value, RpcError> {
+        let (id,) = parse_params::<(u64,)>(params)?;
+        Ok(Value::Bool(self.subscriptions.remove(&id).is_some()))
+    }
+
+    /// Queries view client for the current value of the transaction or account subscription and
+    /// pushes it to the subscriber if there is something new.
+    fn check_subscription(&mut self, id: u64, ctx: &mut ws::WebsocketContext<Self>) {
+        match self.subscriptions.get(&id) {
+            Some(Subscription::Transaction(tx_hash)) => {
+                let tx_hash = *tx_hash;
+                self.check_transaction(tx_hash, ctx)
+            }
+            Some(Subscription::Account { account_id, .. }) => {
+                let account_id = account_id.clone();
+                self.check_account(account_id, ctx)
+            }
+            _ => {}
+        }
+    }
+
+    /// Queries view client for the transaction once and pushes its final result to all the
+    /// subscriptions of this transaction, removing them.
+    fn check_transaction(&mut self, tx_hash: CryptoHash, ctx: &mut ws::WebsocketContext<Self>) {
+        self.view_client_addr
+            .send(TxStatus { tx_hash })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                if let Ok(Ok(result)) = res {
+                    match result.status {
+                        FinalTransactionStatus::Unknown | FinalTransactionStatus::Started => {}
+                        _ => {
+                            let ids: Vec<u64> = act
+                                .subscriptions
+                                .iter()
+                                .filter_map(|(id, subscription)| match subscription {
+                                    Subscription::Transaction(hash) if *hash == tx_hash => {
+                                        Some(*id)
+                                    }
+                                    _ => None,
+                                })
+                                .collect();
+                            for id in ids {
+                                act.subscriptions.remove(&id);
+                                act.notify(ctx, id, &result);
+                            }
+                        }
+                    }
+                }
+                actix::fut::ok(())
+            })
+            .spawn(ctx)
+    }
+
+    /// Queries view client for the account once and pushes its view to all the subscriptions of
+    /// this account that haven't seen it yet.
+    fn check_account(&mut self, account_id: AccountId, ctx: &mut ws::WebsocketContext<Self>) {
+        self.view_client_addr
+            .send(Query {
+                path: format!("account/{}", account_id),
+                data: vec![],
+                block_id: None,
+                prove: false,
+            })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                let value = match res {
+                    Ok(Ok(response)) => serde_json::to_value(response).ok(),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    let mut ids = vec![];
+                    for (id, subscription) in act.subscriptions.iter_mut() {
+                        if let Subscription::Account { account_id: subscribed, last_value } =
+                            subscription
+                        {
+                            if *subscribed == account_id && last_value.as_ref() != Some(&value) {
+                                *last_value = Some(value.clone());
+                                ids.push(*id);
+                            }
+                        }
+                    }
+                    for id in ids {
+                        act.notify(ctx, id, &value);
+                    }
+                }
+                actix::fut::ok(())
+            })
+            .spawn(ctx)
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.client_addr.do_send(SubscribeBlocks { recipient: ctx.address().recipient() });
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for WsSession {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Text(text) => self.process_text(&text, ctx),
+            ws::Message::Close(_) => ctx.stop(),
+            _ => {}
+        }
+    }
+}
+
+impl Handler<BlockNotification> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: BlockNotification, ctx: &mut Self::Context) {
+        // Only the head of the chain is interesting for subscribers, forks are skipped.
+        if !msg.is_new_head {
+            return;
+        }
+        // Each transaction and account is queried once, however many subscriptions it has.
+        let mut tx_hashes = HashSet::new();
+        let mut account_ids = HashSet::new();
+        for (id, subscription) in self.subscriptions.iter() {
+            match subscription {
+                Subscription::NewHeads => self.notify(ctx, *id, &msg.block.header),
+                Subscription::NewBlocks => self.notify(ctx, *id, &msg.block),
+                Subscription::Transaction(tx_hash) => {
+                    tx_hashes.insert(*tx_hash);
+                }
+                Subscription::Account { account_id, .. } => {
+                    account_ids.insert(account_id.clone());
+                }
+            }
+        }
+        for tx_hash in tx_hashes {
+            self.check_transaction(tx_hash, ctx);
+        }
+        for account_id in account_ids {
+            self.check_account(account_id, ctx);
+        }
+    }
+}
+
+pub(crate) fn ws_handler(
+    req: HttpRequest,
+    stream: web::Payload,
+    handler: web::Data<JsonRpcHandler>,
+) -> Result<HttpResponse, HttpError> {
+    ws::start(
+        WsSession::new(handler.client_addr.clone(), handler.view_client_addr.clone()),
+        &req,
+        stream,
+    )
+}
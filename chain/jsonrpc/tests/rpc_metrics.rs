@@ -0,0 +1,43 @@
+use actix::{Actor, System};
+use futures::future::Future;
+
+use near_jsonrpc::test_utils::start_all;
+use near_network::test_utils::WaitOrTimeout;
+use near_primitives::test_utils::init_test_logger;
+
+/// Scrape metrics over HTTP until they include the blocks processed by the node.
+#[test]
+fn test_metrics() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(true);
+
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                actix::spawn(
+                    awc::Client::new()
+                        .get(format!("http://{}/metrics", addr))
+                        .send()
+                        .map_err(|err| panic!("Failed to send request: {:?}", err))
+                        .and_then(|mut response| {
+                            assert!(response.status().is_success());
+                            response.body().map_err(|err| panic!("Failed to read: {:?}", err))
+                        })
+                        .map(|body| {
+                            let text = String::from_utf8(body.to_vec()).unwrap();
+                            if text.contains("near_block_processed_total")
+                                && text.contains("near_block_processing_time_count")
+                            {
+                                System::current().stop();
+                            }
+                        }),
+                );
+            }),
+            100,
+            10000,
+        )
+        .start();
+    })
+    .unwrap();
+}
@@ -1,10 +1,15 @@
-use actix::System;
+use actix::{Actor, System};
 use futures::future;
 use futures::future::Future;
 
 use near_jsonrpc::client::new_client;
-use near_jsonrpc::test_utils::start_all;
-use near_primitives::test_utils::init_test_logger;
+use near_jsonrpc::test_utils::{start_all, start_all_with_validators};
+use near_light_client::LightClient;
+use near_network::test_utils::WaitOrTimeout;
+use near_primitives::rpc::{BlockId, EpochBlockProducers, Finality, QueryResponse};
+use near_primitives::serialize::BaseEncode;
+use near_primitives::test_utils::{get_public_key_from_seed, init_test_logger};
+use near_primitives::types::ValidatorStake;
 
 /// Retrieve blocks via json rpc
 #[test]
@@ -15,7 +20,7 @@ fn test_block() {
         let (_view_client_addr, addr) = start_all(false);
 
         let mut client = new_client(&format!("http://{}", addr));
-        actix::spawn(client.block(0).then(|res| {
+        actix::spawn(client.block(BlockId::Height(0)).then(|res| {
             assert_eq!(res.unwrap().header.height, 0);
             System::current().stop();
             future::result(Ok(()))
@@ -24,6 +29,114 @@ fn test_block() {
     .unwrap();
 }
 
+/// Retrieve the same block by height and then by its hash.
+#[test]
+fn test_block_by_hash() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(client.block(BlockId::Height(0)).then(move |res| {
+            let block = res.unwrap();
+            let mut client = new_client(&format!("http://{}", addr));
+            client.block(BlockId::Hash(block.hash())).then(move |res| {
+                let res = res.unwrap();
+                assert_eq!(res.header.height, 0);
+                assert_eq!(res.hash(), block.hash());
+                System::current().stop();
+                future::result(Ok(()))
+            })
+        }));
+    })
+    .unwrap();
+}
+
+/// Retrieve the latest block via finality tag.
+#[test]
+fn test_block_latest() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(client.block(BlockId::Finality(Finality::Latest)).then(|res| {
+            assert_eq!(res.unwrap().header.height, 0);
+            System::current().stop();
+            future::result(Ok(()))
+        }));
+    })
+    .unwrap();
+}
+
+/// Retrieve block header via json rpc.
+#[test]
+fn test_header() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(client.header(BlockId::Height(0)).then(|res| {
+            assert_eq!(res.unwrap().height, 0);
+            System::current().stop();
+            future::result(Ok(()))
+        }));
+    })
+    .unwrap();
+}
+
+/// Get the proof from genesis to the head of the chain and verify it with the light client.
+/// The only validator produces all the blocks, so they have all the stake approving them.
+#[test]
+fn test_light_client_proof() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all_with_validators(vec!["test1"], "test1");
+
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                let mut client = new_client(&format!("http://{}", addr));
+                let mut client1 = new_client(&format!("http://{}", addr));
+                actix::spawn(
+                    client
+                        .header(BlockId::Height(0))
+                        .and_then(move |genesis| {
+                            client1
+                                .light_client_proof(genesis.hash().to_base(), None)
+                                .map(|proof| (genesis, proof))
+                        })
+                        .map_err(|_| ())
+                        .map(|(genesis, proof)| {
+                            let block_producers = EpochBlockProducers {
+                                epoch_hash: genesis.epoch_hash,
+                                validators: vec![ValidatorStake::new(
+                                    "test1".to_string(),
+                                    get_public_key_from_seed("test1"),
+                                    1_000_000,
+                                )],
+                                seats: vec![0],
+                            };
+                            let mut light_client =
+                                LightClient::new(genesis, block_producers).unwrap();
+                            light_client.apply_proof(&proof).unwrap();
+                            assert_eq!(light_client.head(), proof.headers.last().unwrap());
+                            System::current().stop();
+                        }),
+                );
+            }),
+            100,
+            10000,
+        )
+        .start();
+    })
+    .unwrap();
+}
+
 /// Connect to json rpc and query the client.
 #[test]
 fn test_query() {
@@ -42,6 +155,59 @@ fn test_query() {
     .unwrap();
 }
 
+/// Query the client for the state as of the genesis block.
+#[test]
+fn test_query_at_block() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(
+            client.query_at("account/test".to_string(), "".to_string(), BlockId::Height(0)).then(
+                |res| {
+                    assert!(res.is_ok());
+                    System::current().stop();
+                    future::result(Ok(()))
+                },
+            ),
+        );
+    })
+    .unwrap();
+}
+
+/// Query the client with the proof of the state attached.
+#[test]
+fn test_query_with_proof() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(
+            client.query_with_proof("account/test".to_string(), "".to_string(), None).then(|res| {
+                match res.unwrap() {
+                    QueryResponse::Proven(response) => {
+                        assert!(response.proof.nodes.is_empty());
+                        match *response.result {
+                            QueryResponse::ViewAccount(account) => {
+                                assert_eq!(account.account_id, "test")
+                            }
+                            other => panic!("Unexpected result: {:?}", other),
+                        }
+                    }
+                    other => panic!("Unexpected response: {:?}", other),
+                }
+                System::current().stop();
+                future::result(Ok(()))
+            }),
+        );
+    })
+    .unwrap();
+}
+
 /// Retrieve client status via JSON RPC.
 #[test]
 fn test_status() {
@@ -0,0 +1,116 @@
+use actix::System;
+use awc::error::WsProtocolError;
+use awc::ws::{Frame, Message as WsMessage};
+use futures::{stream, Future, Sink, Stream};
+use serde_json::json;
+
+use near_jsonrpc::client::message::{from_slice, Message};
+use near_jsonrpc::test_utils::start_all;
+use near_jsonrpc::MAX_SUBSCRIPTIONS_PER_CONNECTION;
+use near_network::test_utils::wait_or_panic;
+use near_primitives::hash::hash;
+use near_primitives::serialize::BaseEncode;
+use near_primitives::test_utils::init_test_logger;
+
+/// Subscribe to new heads over WebSocket and wait for the first pushed header.
+#[test]
+fn test_subscribe_new_heads() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(true);
+
+        let request = Message::request("subscribe".to_string(), Some(json!(["newHeads"])));
+        actix::spawn(
+            awc::Client::new()
+                .ws(format!("ws://{}/ws", addr))
+                .connect()
+                .map_err(|err| panic!("Failed to connect: {:?}", err))
+                .and_then(move |(_, framed)| {
+                    framed
+                        .send(WsMessage::Text(serde_json::to_string(&request).unwrap()))
+                        .map_err(|err| panic!("Failed to send: {:?}", err))
+                })
+                .and_then(|framed| {
+                    // Response with the subscription id comes first, then the notifications.
+                    framed
+                        .skip(1)
+                        .into_future()
+                        .map_err(|(err, _)| panic!("Failed to receive: {:?}", err))
+                })
+                .map(|(frame, _)| {
+                    match frame {
+                        Some(Frame::Text(Some(bytes))) => match from_slice(&bytes) {
+                            Ok(Message::Notification(notification)) => {
+                                assert_eq!(notification.method, "subscription");
+                                let params = notification.params.unwrap();
+                                assert_eq!(params["subscription"], json!(0));
+                                assert!(params["result"]["height"].as_u64().unwrap() > 0);
+                            }
+                            other => panic!("Unexpected message: {:?}", other),
+                        },
+                        other => panic!("Unexpected frame: {:?}", other),
+                    }
+                    System::current().stop();
+                }),
+        );
+        wait_or_panic(10000);
+    })
+    .unwrap();
+}
+
+/// Subscriptions over the limit of a connection are rejected.
+#[test]
+fn test_too_many_subscriptions() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(true);
+
+        // Unknown transaction never gets notifications, so only responses are received.
+        let tx_hash = hash(&[1]).to_base();
+        let requests = (0..=MAX_SUBSCRIPTIONS_PER_CONNECTION)
+            .map(|_| {
+                let request =
+                    Message::request("subscribe".to_string(), Some(json!(["tx", tx_hash])));
+                WsMessage::Text(serde_json::to_string(&request).unwrap())
+            })
+            .collect::<Vec<_>>();
+        actix::spawn(
+            awc::Client::new()
+                .ws(format!("ws://{}/ws", addr))
+                .connect()
+                .map_err(|err| panic!("Failed to connect: {:?}", err))
+                .and_then(move |(_, framed)| {
+                    // Unlike `send_all`, sending one by one doesn't close the connection.
+                    stream::iter_ok::<_, WsProtocolError>(requests)
+                        .fold(framed, |framed, request| framed.send(request))
+                        .map_err(|err| panic!("Failed to send: {:?}", err))
+                })
+                .and_then(|framed| {
+                    framed
+                        .take(MAX_SUBSCRIPTIONS_PER_CONNECTION as u64 + 1)
+                        .collect()
+                        .map_err(|err| panic!("Failed to receive: {:?}", err))
+                })
+                .map(|frames| {
+                    let results = frames
+                        .into_iter()
+                        .map(|frame| match frame {
+                            Frame::Text(Some(bytes)) => match from_slice(&bytes) {
+                                Ok(Message::Response(response)) => response.result.is_ok(),
+                                other => panic!("Unexpected message: {:?}", other),
+                            },
+                            other => panic!("Unexpected frame: {:?}", other),
+                        })
+                        .collect::<Vec<_>>();
+                    let mut expected = vec![true; MAX_SUBSCRIPTIONS_PER_CONNECTION];
+                    expected.push(false);
+                    assert_eq!(results, expected);
+                    System::current().stop();
+                }),
+        );
+        wait_or_panic(10000);
+    })
+    .unwrap();
+}
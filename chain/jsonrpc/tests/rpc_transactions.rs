@@ -1,7 +1,10 @@
+use std::sync::{Arc, Mutex};
+
 use actix::{Actor, System};
 use futures::future::Future;
 use protobuf::Message;
 
+use near_client::GetBlock;
 use near_jsonrpc::client::new_client;
 use near_jsonrpc::test_utils::start_all;
 use near_network::test_utils::{wait_or_panic, WaitOrTimeout};
@@ -17,31 +20,36 @@ fn test_send_tx_async() {
     init_test_logger();
 
     System::run(|| {
-        let (_view_client_addr, addr) = start_all(true);
+        let (view_client_addr, addr) = start_all(true);
 
         let mut client = new_client(&format!("http://{}", addr));
-        let signer = InMemorySigner::from_seed("test1", "test1");
-        let tx = TransactionBody::send_money(1, "test1", "test2", 100).sign(&signer);
-        let tx_hash: String = (&tx.get_hash()).into();
+        let tx_hash: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
         let tx_hash2 = tx_hash.clone();
-        let proto: transaction_proto::SignedTransaction = tx.into();
-        actix::spawn(
+        let mut client2 = new_client(&format!("http://{}", addr));
+        actix::spawn(view_client_addr.send(GetBlock::Best).then(move |res| {
+            let block_hash = res.unwrap().unwrap().hash();
+            let signer = InMemorySigner::from_seed("test1", "test1");
+            let tx =
+                TransactionBody::send_money(1, "test1", "test2", 100, block_hash).sign(&signer);
+            let hash: String = (&tx.get_hash()).into();
+            *tx_hash.lock().unwrap() = Some(hash.clone());
+            let proto: transaction_proto::SignedTransaction = tx.into();
             client
                 .broadcast_tx_async(to_base64(&proto.write_to_bytes().unwrap()))
                 .map_err(|_| ())
-                .map(move |result| assert_eq!(tx_hash, result)),
-        );
+                .map(move |result| assert_eq!(hash, result))
+        }));
         WaitOrTimeout::new(
             Box::new(move |_| {
-                actix::spawn(
-                    client.tx(tx_hash2.clone()).map_err(|err| println!("Error: {:?}", err)).map(
+                if let Some(tx_hash) = tx_hash2.lock().unwrap().clone() {
+                    actix::spawn(client2.tx(tx_hash).map_err(|err| panic!("Error: {:?}", err)).map(
                         |result| {
                             if result.status == FinalTransactionStatus::Completed {
                                 System::current().stop();
                             }
                         },
-                    ),
-                )
+                    ))
+                }
             }),
             100,
             1000,
@@ -57,13 +65,15 @@ fn test_send_tx_commit() {
     init_test_logger();
 
     System::run(|| {
-        let (_view_client_addr, addr) = start_all(true);
+        let (view_client_addr, addr) = start_all(true);
 
         let mut client = new_client(&format!("http://{}", addr));
-        let signer = InMemorySigner::from_seed("test1", "test1");
-        let tx = TransactionBody::send_money(1, "test1", "test2", 100).sign(&signer);
-        let proto: transaction_proto::SignedTransaction = tx.into();
-        actix::spawn(
+        actix::spawn(view_client_addr.send(GetBlock::Best).then(move |res| {
+            let block_hash = res.unwrap().unwrap().hash();
+            let signer = InMemorySigner::from_seed("test1", "test1");
+            let tx =
+                TransactionBody::send_money(1, "test1", "test2", 100, block_hash).sign(&signer);
+            let proto: transaction_proto::SignedTransaction = tx.into();
             client
                 .broadcast_tx_commit(to_base64(&proto.write_to_bytes().unwrap()))
                 .map_err(|why| {
@@ -73,8 +83,8 @@ fn test_send_tx_commit() {
                 .map(move |result| {
                     assert_eq!(result.status, FinalTransactionStatus::Completed);
                     System::current().stop();
-                }),
-        );
+                })
+        }));
         wait_or_panic(10000);
     })
     .unwrap();
@@ -0,0 +1,252 @@
+//! Light client follows the chain by block headers only, without executing blocks or storing
+//! the state.
+//!
+//! Starting from a trusted header and block producers of its epoch, it checks that every next
+//! header is produced by the expected block producer and approved by more than 2/3 of the stake
+//! of the block producers of the parent's epoch. Validator sets of the
+//! following epochs come with the `LightClientProof` and are only accepted if every validator in
+//! them was a validator before or was proposed in one of the verified headers with the same stake,
+//! and has no more block producer seats than its stake is worth.
+
+use std::collections::{HashMap, HashSet};
+
+use near_primitives::block::BlockHeader;
+use near_primitives::crypto::signature::{verify, PublicKey};
+use near_primitives::hash::CryptoHash;
+use near_primitives::rpc::{EpochBlockProducers, LightClientProof};
+use near_primitives::types::{AccountId, Balance, BlockIndex, ValidatorStake};
+
+pub use crate::types::Error;
+
+pub mod types;
+
+/// Checks that block producers have seats and every seat refers to an existing validator.
+fn check_block_producers(block_producers: &EpochBlockProducers) -> Result<(), Error> {
+    if block_producers.seats.is_empty()
+        || block_producers.seats.iter().any(|index| *index >= block_producers.validators.len())
+    {
+        return Err(Error::InvalidBlockProducers(block_producers.epoch_hash));
+    }
+    Ok(())
+}
+
+/// Largest stake per seat that gives at least `num_seats` seats to the given stakes, the same way
+/// the validator manager assigns the seats.
+fn seat_threshold(stakes: &[Balance], num_seats: u64) -> Option<Balance> {
+    let total_stake: Balance = stakes.iter().sum();
+    if total_stake < Balance::from(num_seats) {
+        return None;
+    }
+    let (mut left, mut right): (Balance, Balance) = (1, total_stake + 1);
+    while right - left > 1 {
+        let mid = (left + right) / 2;
+        let seats: Balance = stakes.iter().map(|stake| stake / mid).sum();
+        if seats >= Balance::from(num_seats) {
+            left = mid;
+        } else {
+            right = mid;
+        }
+    }
+    Some(left)
+}
+
+/// Checks the validators of a new epoch against the stakes verified by the light client: each
+/// validator must have the stake it had in a known epoch or proposed in a verified header, and no
+/// more seats than its stake is worth. All seats are block producer seats, as fishermen don't get
+/// any yet.
+fn check_new_block_producers(
+    block_producers: &EpochBlockProducers,
+    known_stakes: &HashMap<(AccountId, PublicKey), HashSet<Balance>>,
+) -> Result<(), Error> {
+    check_block_producers(block_producers)?;
+    for validator in block_producers.validators.iter() {
+        let stakes = known_stakes
+            .get(&(validator.account_id.clone(), validator.public_key))
+            .ok_or_else(|| Error::UnknownValidator(validator.account_id.clone()))?;
+        if !stakes.contains(&validator.amount) {
+            return Err(Error::InvalidStake(validator.account_id.clone()));
+        }
+    }
+    let stakes: Vec<Balance> =
+        block_producers.validators.iter().map(|validator| validator.amount).collect();
+    let threshold = seat_threshold(&stakes, block_producers.seats.len() as u64)
+        .ok_or(Error::InvalidBlockProducers(block_producers.epoch_hash))?;
+    let mut seats = vec![0; block_producers.validators.len()];
+    for index in block_producers.seats.iter() {
+        seats[*index] += 1;
+    }
+    for (validator, seats) in block_producers.validators.iter().zip(seats) {
+        if seats > validator.amount / threshold {
+            return Err(Error::InvalidStake(validator.account_id.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Distinct block producers of the epoch in the order of their first seat.
+/// Position in this list is the position in the approval mask of the next block.
+pub fn approvers(block_producers: &EpochBlockProducers) -> Vec<&ValidatorStake> {
+    let mut included = HashSet::new();
+    block_producers
+        .seats
+        .iter()
+        .map(|index| &block_producers.validators[*index])
+        .filter(|validator| included.insert(&validator.account_id))
+        .collect()
+}
+
+/// Block producer of the block at given height.
+pub fn block_producer(
+    block_producers: &EpochBlockProducers,
+    height: BlockIndex,
+) -> Result<&ValidatorStake, Error> {
+    check_block_producers(block_producers)?;
+    let seat = block_producers.seats[(height % block_producers.seats.len() as u64) as usize];
+    Ok(&block_producers.validators[seat])
+}
+
+/// Verifies the header against block producers of its epoch and of the epoch of its parent,
+/// which approvals of the header are counted in. Producer of the header builds on the parent, so
+/// its stake counts as approving too.
+pub fn verify_header(
+    header: &BlockHeader,
+    block_producers: &EpochBlockProducers,
+    parent_block_producers: &EpochBlockProducers,
+) -> Result<(), Error> {
+    let hash = header.compute_hash();
+    if hash != header.hash() {
+        return Err(Error::InvalidHash(header.hash()));
+    }
+    if header.epoch_hash != block_producers.epoch_hash {
+        return Err(Error::UnknownEpoch(header.epoch_hash));
+    }
+    let producer = block_producer(block_producers, header.height)?;
+    if !header.verify_block_producer(&producer.public_key) {
+        return Err(Error::InvalidSignature(hash));
+    }
+
+    check_block_producers(parent_block_producers)?;
+    let approvers = approvers(parent_block_producers);
+    if header.approval_mask.len() > approvers.len()
+        || header.approval_mask.iter().filter(|approved| **approved).count()
+            != header.approval_sigs.len()
+    {
+        return Err(Error::InvalidApprovals(hash));
+    }
+    let approved = header
+        .approval_mask
+        .iter()
+        .zip(approvers.iter())
+        .filter_map(|(approved, approver)| if *approved { Some(approver) } else { None });
+    for (approver, signature) in approved.zip(header.approval_sigs.iter()) {
+        if !verify(header.prev_hash.as_ref(), signature, &approver.public_key) {
+            return Err(Error::InvalidApprovals(hash));
+        }
+    }
+    let total_stake: Balance = approvers.iter().map(|approver| approver.amount).sum();
+    let approved_stake: Balance = approvers
+        .iter()
+        .enumerate()
+        .filter(|(position, approver)| {
+            header.approval_mask.get(*position) == Some(&true)
+                || approver.account_id == producer.account_id
+        })
+        .map(|(_, approver)| approver.amount)
+        .sum();
+    if approved_stake * 3 <= total_stake * 2 {
+        return Err(Error::NotEnoughApprovals(hash));
+    }
+    Ok(())
+}
+
+/// Light client state: the latest verified header and validators of the epochs it went through.
+pub struct LightClient {
+    head: BlockHeader,
+    epochs: HashMap<CryptoHash, EpochBlockProducers>,
+    /// Stakes of the validators of the known epochs and of the verified proposals.
+    known_stakes: HashMap<(AccountId, PublicKey), HashSet<Balance>>,
+}
+
+impl LightClient {
+    /// Creates light client from the trusted header and block producers of its epoch.
+    /// Proposals made before the trusted header are not visible to the light client, so it's
+    /// expected to start from a header that is recent enough.
+    pub fn new(head: BlockHeader, block_producers: EpochBlockProducers) -> Result<Self, Error> {
+        if head.epoch_hash != block_producers.epoch_hash {
+            return Err(Error::UnknownEpoch(head.epoch_hash));
+        }
+        check_block_producers(&block_producers)?;
+        let mut known_stakes = HashMap::new();
+        add_known_stakes(&mut known_stakes, &block_producers.validators);
+        add_known_stakes(&mut known_stakes, &head.validator_proposal);
+        let mut epochs = HashMap::new();
+        epochs.insert(block_producers.epoch_hash, block_producers);
+        Ok(LightClient { head, epochs, known_stakes })
+    }
+
+    /// Latest verified header.
+    pub fn head(&self) -> &BlockHeader {
+        &self.head
+    }
+
+    /// Block producers of the epoch, if light client went through it.
+    pub fn get_epoch_block_producers(
+        &self,
+        epoch_hash: &CryptoHash,
+    ) -> Option<&EpochBlockProducers> {
+        self.epochs.get(epoch_hash)
+    }
+
+    /// Verifies headers of the proof one by one and moves the head to the last of them.
+    /// If any of the headers is invalid, the state of the light client is left unchanged.
+    pub fn apply_proof(&mut self, proof: &LightClientProof) -> Result<(), Error> {
+        if proof.headers.is_empty() {
+            return Err(Error::EmptyProof);
+        }
+        let mut epochs = self.epochs.clone();
+        let mut known_stakes = self.known_stakes.clone();
+        let mut last = &self.head;
+        for header in proof.headers.iter() {
+            if header.height <= last.height {
+                return Err(Error::InvalidHeaderOrder(header.height, last.height));
+            }
+            // Approvals are given for the parent, so they are counted in the epoch of the parent.
+            // Epochs only change along the chain, so if the header is in the same epoch as the
+            // last verified header, all the headers in between are in this epoch too.
+            let parent_epoch_hash =
+                if header.prev_hash == last.hash() || header.epoch_hash == last.epoch_hash {
+                    last.epoch_hash
+                } else {
+                    return Err(Error::MissingParent(header.hash()));
+                };
+            if !epochs.contains_key(&header.epoch_hash) {
+                let block_producers = proof
+                    .block_producers
+                    .iter()
+                    .find(|block_producers| block_producers.epoch_hash == header.epoch_hash)
+                    .ok_or(Error::UnknownEpoch(header.epoch_hash))?;
+                check_new_block_producers(block_producers, &known_stakes)?;
+                epochs.insert(header.epoch_hash, block_producers.clone());
+            }
+            verify_header(header, &epochs[&header.epoch_hash], &epochs[&parent_epoch_hash])?;
+            add_known_stakes(&mut known_stakes, &header.validator_proposal);
+            last = header;
+        }
+        self.head = last.clone();
+        self.epochs = epochs;
+        self.known_stakes = known_stakes;
+        Ok(())
+    }
+}
+
+fn add_known_stakes(
+    known_stakes: &mut HashMap<(AccountId, PublicKey), HashSet<Balance>>,
+    validators: &[ValidatorStake],
+) {
+    for validator in validators.iter() {
+        known_stakes
+            .entry((validator.account_id.clone(), validator.public_key))
+            .or_insert_with(HashSet::new)
+            .insert(validator.amount);
+    }
+}
@@ -0,0 +1,42 @@
+use failure::Fail;
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::{AccountId, BlockIndex};
+
+/// Possible errors when verifying headers with the light client.
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum Error {
+    /// Block producers of the epoch are not known to the light client.
+    #[fail(display = "Unknown epoch {}", _0)]
+    UnknownEpoch(CryptoHash),
+    /// Block producers of the epoch have no seats or refer to missing validators.
+    #[fail(display = "Invalid block producers of epoch {}", _0)]
+    InvalidBlockProducers(CryptoHash),
+    /// Validator of the new epoch was neither a validator before nor proposed in verified headers.
+    #[fail(display = "Unknown validator {}", _0)]
+    UnknownValidator(AccountId),
+    /// Stake or number of seats of the validator of the new epoch doesn't match verified stakes.
+    #[fail(display = "Invalid stake of validator {}", _0)]
+    InvalidStake(AccountId),
+    /// Hash of the header doesn't match its content.
+    #[fail(display = "Invalid header hash {}", _0)]
+    InvalidHash(CryptoHash),
+    /// Header is not signed by the block producer of its height.
+    #[fail(display = "Invalid block producer signature of header {}", _0)]
+    InvalidSignature(CryptoHash),
+    /// Approval mask or approval signatures of the header are invalid.
+    #[fail(display = "Invalid approvals of header {}", _0)]
+    InvalidApprovals(CryptoHash),
+    /// Header is approved by no more than 2/3 of the stake of the parent's block producers.
+    #[fail(display = "Not enough approvals of header {}", _0)]
+    NotEnoughApprovals(CryptoHash),
+    /// Headers don't go in the order of increasing heights.
+    #[fail(display = "Header at height {} doesn't follow height {}", _0, _1)]
+    InvalidHeaderOrder(BlockIndex, BlockIndex),
+    /// Header starts a new epoch, but its parent is not known to the light client.
+    #[fail(display = "Missing parent of header {}", _0)]
+    MissingParent(CryptoHash),
+    /// Proof doesn't contain any headers.
+    #[fail(display = "Empty proof")]
+    EmptyProof,
+}
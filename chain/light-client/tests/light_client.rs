@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use near_light_client::{Error, LightClient};
+use near_primitives::block::{Block, BlockHeader};
+use near_primitives::crypto::signer::{EDSigner, InMemorySigner};
+use near_primitives::hash::CryptoHash;
+use near_primitives::rpc::{EpochBlockProducers, LightClientProof};
+use near_primitives::types::{MerkleHash, ValidatorStake};
+
+fn signer(account_id: &str) -> Arc<InMemorySigner> {
+    Arc::new(InMemorySigner::from_seed(account_id, account_id))
+}
+
+fn stake(account_id: &str) -> ValidatorStake {
+    ValidatorStake::new(account_id.to_string(), signer(account_id).public_key(), 100)
+}
+
+fn block_producers(epoch_hash: CryptoHash, account_ids: &[&str]) -> EpochBlockProducers {
+    EpochBlockProducers {
+        epoch_hash,
+        validators: account_ids.iter().map(|account_id| stake(account_id)).collect(),
+        seats: (0..account_ids.len()).collect(),
+    }
+}
+
+fn produce(
+    prev: &BlockHeader,
+    epoch_hash: CryptoHash,
+    approvals: &[(usize, &str)],
+    validator_proposal: Vec<ValidatorStake>,
+    producer: &str,
+) -> BlockHeader {
+    let approvals: HashMap<_, _> = approvals
+        .iter()
+        .map(|(position, account_id)| (*position, signer(account_id).sign(prev.hash().as_ref())))
+        .collect();
+    Block::produce(
+        prev,
+        prev.height + 1,
+        MerkleHash::default(),
+        epoch_hash,
+        vec![],
+        approvals,
+        validator_proposal,
+        signer(producer),
+    )
+    .header
+}
+
+/// Chain of 4 blocks after genesis: `test3` is proposed at height 1 and replaces `test2` in the
+/// epoch that starts at height 3.
+fn setup() -> (LightClient, Vec<BlockHeader>) {
+    let genesis = BlockHeader::genesis(MerkleHash::default(), Utc::now());
+    let epoch1 = genesis.epoch_hash;
+    let h1 = produce(&genesis, epoch1, &[(0, "test1")], vec![stake("test3")], "test2");
+    let h2 = produce(&h1, epoch1, &[(1, "test2")], vec![], "test1");
+    let epoch2 = h2.hash();
+    let h3 = produce(&h2, epoch2, &[(0, "test1"), (1, "test2")], vec![], "test3");
+    let h4 = produce(&h3, epoch2, &[(1, "test3")], vec![], "test1");
+    let light_client =
+        LightClient::new(genesis, block_producers(epoch1, &["test1", "test2"])).unwrap();
+    (light_client, vec![h1, h2, h3, h4])
+}
+
+fn proof(headers: Vec<BlockHeader>, epoch2: CryptoHash) -> LightClientProof {
+    LightClientProof {
+        headers,
+        block_producers: vec![block_producers(epoch2, &["test1", "test3"])],
+    }
+}
+
+#[test]
+fn test_apply_proof() {
+    let (mut light_client, headers) = setup();
+    let epoch2 = headers[1].hash();
+    let target = headers[3].clone();
+    light_client.apply_proof(&proof(headers, epoch2)).unwrap();
+    assert_eq!(light_client.head(), &target);
+    assert!(light_client.get_epoch_block_producers(&epoch2).is_some());
+}
+
+#[test]
+fn test_apply_sparse_proof() {
+    let (mut light_client, headers) = setup();
+    let epoch2 = headers[1].hash();
+    let sparse = vec![headers[0].clone(), headers[2].clone()];
+    assert_eq!(
+        light_client.apply_proof(&proof(sparse, epoch2)),
+        Err(Error::MissingParent(headers[2].hash()))
+    );
+    let sparse = vec![headers[0].clone(), headers[1].clone(), headers[2].clone()];
+    light_client.apply_proof(&proof(sparse, epoch2)).unwrap();
+    assert_eq!(light_client.head(), &headers[2]);
+    // Header at height 2 is in the same epoch as genesis, so it's verified without its parent.
+    let (mut light_client, headers) = setup();
+    let sparse = vec![headers[1].clone()];
+    light_client.apply_proof(&proof(sparse, epoch2)).unwrap();
+    assert_eq!(light_client.head(), &headers[1]);
+}
+
+#[test]
+fn test_unknown_validator() {
+    let (mut light_client, headers) = setup();
+    let epoch2 = headers[1].hash();
+    let mut bad_proof = proof(headers, epoch2);
+    bad_proof.block_producers = vec![block_producers(epoch2, &["test1", "test4"])];
+    assert_eq!(
+        light_client.apply_proof(&bad_proof),
+        Err(Error::UnknownValidator("test4".to_string()))
+    );
+    assert_eq!(light_client.head().height, 0);
+    assert!(light_client.get_epoch_block_producers(&epoch2).is_none());
+}
+
+#[test]
+fn test_inflated_stake() {
+    let (mut light_client, headers) = setup();
+    let epoch2 = headers[1].hash();
+    let mut bad_proof = proof(headers.clone(), epoch2);
+    bad_proof.block_producers[0].validators[1].amount = 1000;
+    assert_eq!(light_client.apply_proof(&bad_proof), Err(Error::InvalidStake("test3".to_string())));
+    // Stakes are right, but `test1` takes more seats than its half of the stake is worth.
+    let mut bad_proof = proof(headers, epoch2);
+    bad_proof.block_producers[0].seats = vec![0, 0, 0, 1];
+    assert_eq!(light_client.apply_proof(&bad_proof), Err(Error::InvalidStake("test1".to_string())));
+    assert_eq!(light_client.head().height, 0);
+    assert!(light_client.get_epoch_block_producers(&epoch2).is_none());
+}
+
+#[test]
+fn test_not_enough_approvals() {
+    let (mut light_client, headers) = setup();
+    let epoch1 = light_client.head().epoch_hash;
+    // Producer alone has only half of the stake.
+    let unapproved = produce(&headers[0], epoch1, &[], vec![], "test1");
+    assert_eq!(
+        light_client.apply_proof(&proof(vec![headers[0].clone(), unapproved.clone()], epoch1)),
+        Err(Error::NotEnoughApprovals(unapproved.hash()))
+    );
+    // Producer of the new epoch was not a block producer of the parent's epoch, so it takes
+    // approvals of both block producers before it.
+    let epoch2 = headers[1].hash();
+    let unapproved = produce(&headers[1], epoch2, &[(0, "test1")], vec![], "test3");
+    assert_eq!(
+        light_client.apply_proof(&proof(
+            vec![headers[0].clone(), headers[1].clone(), unapproved.clone()],
+            epoch2
+        )),
+        Err(Error::NotEnoughApprovals(unapproved.hash()))
+    );
+    assert_eq!(light_client.head().height, 0);
+}
+
+#[test]
+fn test_invalid_headers() {
+    let (mut light_client, headers) = setup();
+    let genesis = light_client.head().clone();
+    let epoch1 = genesis.epoch_hash;
+
+    let wrong_producer = produce(&genesis, epoch1, &[], vec![], "test1");
+    assert_eq!(
+        light_client.apply_proof(&proof(vec![wrong_producer.clone()], epoch1)),
+        Err(Error::InvalidSignature(wrong_producer.hash()))
+    );
+
+    let wrong_approver = produce(&headers[0], epoch1, &[(0, "test2")], vec![], "test1");
+    assert_eq!(
+        light_client.apply_proof(&proof(vec![headers[0].clone(), wrong_approver.clone()], epoch1)),
+        Err(Error::InvalidApprovals(wrong_approver.hash()))
+    );
+
+    let unknown_approver = produce(&headers[0], epoch1, &[(2, "test3")], vec![], "test1");
+    assert_eq!(
+        light_client
+            .apply_proof(&proof(vec![headers[0].clone(), unknown_approver.clone()], epoch1)),
+        Err(Error::InvalidApprovals(unknown_approver.hash()))
+    );
+
+    let mut tampered = headers[0].clone();
+    tampered.validator_proposal = vec![stake("test4")];
+    assert_eq!(
+        light_client.apply_proof(&proof(vec![tampered], epoch1)),
+        Err(Error::InvalidHash(headers[0].hash()))
+    );
+
+    assert_eq!(
+        light_client.apply_proof(&proof(vec![headers[1].clone(), headers[0].clone()], epoch1)),
+        Err(Error::InvalidHeaderOrder(1, 2))
+    );
+    assert_eq!(light_client.head(), &genesis);
+}
@@ -1,21 +1,33 @@
 use std::convert::TryInto;
 use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use bytes::{BufMut, BytesMut};
-use protobuf::{parse_from_bytes, Message};
+use protobuf::{parse_from_bytes, CodedInputStream, Message};
 use tokio::codec::{Decoder, Encoder};
 
 use near_protos::network::PeerMessage as ProtoMessage;
 
 use crate::types::PeerMessage;
 
+/// Splits the stream into frames prefixed by their length, up to the maximum length.
 pub struct Codec {
-    max_length: u32,
+    max_length: Arc<AtomicUsize>,
 }
 
 impl Codec {
-    pub fn new() -> Self {
-        Codec { max_length: std::u32::MAX }
+    pub fn new(max_length: usize) -> Self {
+        Codec::with_max_length(Arc::new(AtomicUsize::new(max_length)))
+    }
+
+    /// Codec which maximum length can be changed through the given handle while it's used.
+    pub fn with_max_length(max_length: Arc<AtomicUsize>) -> Self {
+        Codec { max_length }
+    }
+
+    fn max_length(&self) -> usize {
+        self.max_length.load(Ordering::Relaxed)
     }
 }
 
@@ -24,7 +36,7 @@ impl Encoder for Codec {
     type Error = Error;
 
     fn encode(&mut self, item: Self::Item, buf: &mut BytesMut) -> Result<(), Error> {
-        if item.len() > self.max_length as usize {
+        if item.len() > self.max_length() {
             Err(Error::new(ErrorKind::InvalidInput, "Input is too long"))
         } else {
             // First four bytes is the length of the buffer.
@@ -48,6 +60,10 @@ impl Decoder for Codec {
         let mut len_bytes: [u8; 4] = [0; 4];
         len_bytes.copy_from_slice(&buf[0..4]);
         let len = unsafe { std::mem::transmute::<[u8; 4], u32>(len_bytes) }.to_le();
+        if len as usize > self.max_length() {
+            // Fail before the whole frame is received, so it's never kept in memory.
+            return Err(Error::new(ErrorKind::InvalidData, "Input is too long"));
+        }
         if buf.len() < 4 + len as usize {
             // not enough bytes, keep waiting
             Ok(None)
@@ -71,17 +87,45 @@ pub fn bytes_to_peer_message(bytes: &[u8]) -> Result<PeerMessage, Box<dyn std::e
     proto.try_into()
 }
 
+/// Returns `PeerMessage::message_type` of the encoded message from the tag of its first field,
+/// so the limits of the type can be checked before the message is decoded.
+pub fn peer_message_type(bytes: &[u8]) -> Option<&'static str> {
+    let (field_number, _) = CodedInputStream::from_bytes(bytes).read_tag_unpack().ok()?;
+    let message_type = match field_number {
+        1 => "Handshake",
+        2 => "PeersRequest",
+        3 => "PeersResponse",
+        4 => "BlockHeaderRequest",
+        5 => "BlockHeaders",
+        6 => "BlockHeaderAnnounce",
+        7 => "BlockRequest",
+        8 => "Block",
+        9 => "BlockApproval",
+        10 => "Transaction",
+        11 => "StateRequest",
+        12 => "StateResponse",
+        13 => "AnnounceAccount",
+        14 => "Routed",
+        15 => "Edges",
+        _ => return None,
+    };
+    Some(message_type)
+}
+
 #[cfg(test)]
 mod test {
+    use near_primitives::crypto::signature::DEFAULT_SIGNATURE;
+
     use crate::types::{Handshake, PeerChainInfo, PeerInfo};
 
     use super::*;
 
     fn test_codec(msg: PeerMessage) {
-        let mut codec = Codec::new();
+        let mut codec = Codec::new(std::u32::MAX as usize);
         let mut buffer = BytesMut::new();
         codec.encode(peer_message_to_bytes(msg.clone()).unwrap(), &mut buffer).unwrap();
         let decoded = codec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(peer_message_type(&decoded), Some(msg.message_type()));
         assert_eq!(bytes_to_peer_message(&decoded).unwrap(), msg);
     }
 
@@ -97,11 +141,48 @@ mod test {
                 height: 0,
                 total_weight: 0.into(),
             },
+            signature: DEFAULT_SIGNATURE,
+            observed_address: None,
         };
         let msg = PeerMessage::Handshake(fake_handshake);
         test_codec(msg);
     }
 
+    #[test]
+    fn test_max_length() {
+        let mut codec = Codec::new(10);
+        let mut buffer = BytesMut::new();
+        assert!(codec.encode(vec![0; 11], &mut buffer).is_err());
+        codec.encode(vec![0; 10], &mut buffer).unwrap();
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(vec![0; 10]));
+
+        // Frame that is too long fails as soon as its length is received.
+        let mut other_codec = Codec::new(100);
+        other_codec.encode(vec![0; 11], &mut buffer).unwrap();
+        buffer.truncate(4);
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_change_max_length() {
+        let max_length = Arc::new(AtomicUsize::new(10));
+        let mut codec = Codec::with_max_length(max_length.clone());
+        let mut buffer = BytesMut::new();
+        Codec::new(100).encode(vec![0; 11], &mut buffer).unwrap();
+        assert!(codec.decode(&mut buffer).is_err());
+        max_length.store(11, Ordering::Relaxed);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(vec![0; 11]));
+    }
+
+    #[test]
+    fn test_peer_message_type() {
+        test_codec(PeerMessage::PeersRequest);
+        test_codec(PeerMessage::BlockRequest(Default::default()));
+        assert_eq!(peer_message_type(&[]), None);
+        // Tag of the field 16, which is not a message type.
+        assert_eq!(peer_message_type(&[0x82, 0x01]), None);
+    }
+
     #[test]
     fn test_peer_message_info_gossip() {
         let peer_info1 = PeerInfo::random();
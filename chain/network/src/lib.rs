@@ -5,10 +5,14 @@ pub use types::{
 };
 
 mod codec;
+mod metrics;
 mod peer;
 mod peer_manager;
 pub mod peer_store;
 pub mod types;
 mod rate_counter;
+mod rate_limiter;
+mod routing;
+mod session;
 
 pub mod test_utils;
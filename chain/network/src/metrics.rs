@@ -0,0 +1,35 @@
+use lazy_static::lazy_static;
+use near_metrics::{IntCounter, IntGauge};
+
+lazy_static! {
+    pub static ref PEER_CONNECTIONS_TOTAL: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge(
+            "near_peer_connections_total",
+            "Number of connected peers"
+        );
+    pub static ref PEER_DATA_RECEIVED_BYTES: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_peer_data_received_bytes",
+            "Total data received from peers, in bytes"
+        );
+    pub static ref PEER_DATA_SENT_BYTES: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_peer_data_sent_bytes",
+            "Total data sent to peers, in bytes"
+        );
+    pub static ref PEER_MESSAGE_RECEIVED_TOTAL: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_peer_message_received_total",
+            "Number of messages received from peers"
+        );
+    pub static ref RECEIVED_BYTES_PER_SEC: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge(
+            "near_received_bytes_per_sec",
+            "Rate of data received from all the peers over the last minute, in bytes per second"
+        );
+    pub static ref SENT_BYTES_PER_SEC: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge(
+            "near_sent_bytes_per_sec",
+            "Rate of data sent to all the peers over the last minute, in bytes per second"
+        );
+}
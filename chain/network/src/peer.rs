@@ -1,6 +1,9 @@
-use std::cmp::max;
+use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::io;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use actix::io::{FramedWrite, WriteHandler};
@@ -12,21 +15,35 @@ use log::{debug, error, info, warn};
 use tokio::io::WriteHalf;
 use tokio::net::TcpStream;
 
+use near_primitives::crypto::signature::SecretKey;
 use near_primitives::hash::CryptoHash;
 use near_primitives::utils::DisplayOption;
 
-use crate::codec::{bytes_to_peer_message, peer_message_to_bytes, Codec};
+use crate::codec::{bytes_to_peer_message, peer_message_to_bytes, peer_message_type, Codec};
+use crate::metrics;
 use crate::rate_counter::RateCounter;
+use crate::rate_limiter::TokenBucket;
+use crate::session::{EphemeralKey, Session, SESSION_OVERHEAD};
 use crate::types::{
-    Ban, Consolidate, Handshake, NetworkClientMessages, PeerChainInfo, PeerInfo, PeerMessage,
+    Ban, Consolidate, ConsolidateResponse, EdgesRequest, Handshake, MessageLimits,
+    NetworkClientMessages, NewEdges, PeerBehaviour, PeerChainInfo, PeerInfo, PeerMessage,
     PeerStatsResult, PeerStatus, PeerType, PeersRequest, PeersResponse, QueryPeerStats,
-    ReasonForBan, SendMessage, Unregister,
+    ReasonForBan, ReportPeer, RoutedMessageFrom, SendMessage, Unregister,
 };
 use crate::{NetworkClientResponses, PeerManagerActor};
 
 /// Maximum number of requests and responses to track.
 const MAX_TRACK_SIZE: usize = 30;
 
+/// Maximum length of the frames received before the handshake is done, which are only the
+/// ephemeral key of the session and the handshake itself.
+pub const MAX_HANDSHAKE_FRAME_LENGTH: usize = 4 * 1024;
+
+/// Maximum length of the frames that carry messages within given limits.
+pub fn max_frame_length(message_limits: &MessageLimits) -> usize {
+    min(message_limits.max_size() + SESSION_OVERHEAD, std::u32::MAX as usize)
+}
+
 /// Maximum number of messages per minute from single peer.
 // TODO: current limit is way to high due to us sending lots of messages during sync.
 const MAX_PEER_MSG_PER_MIN: u64 = 50000;
@@ -58,10 +75,13 @@ impl Default for Tracker {
 impl Tracker {
     fn increment_received(&mut self, size: u64) {
         self.received_bytes.increment(size);
+        near_metrics::inc_counter(&metrics::PEER_MESSAGE_RECEIVED_TOTAL);
+        near_metrics::inc_counter_by(&metrics::PEER_DATA_RECEIVED_BYTES, size);
     }
 
     fn increment_sent(&mut self, size: u64) {
         self.sent_bytes.increment(size);
+        near_metrics::inc_counter_by(&metrics::PEER_DATA_SENT_BYTES, size);
     }
 
     fn has_received(&self, hash: CryptoHash) -> bool {
@@ -94,6 +114,8 @@ impl Tracker {
 pub struct Peer {
     /// This node's id and address (either listening or socket address).
     pub node_info: PeerInfo,
+    /// Secret key of this node's id, to sign the session.
+    secret_key: SecretKey,
     /// Peer address from connection.
     pub peer_addr: SocketAddr,
     /// Peer id and info. Present if outbound or ready.
@@ -104,8 +126,19 @@ pub struct Peer {
     pub peer_status: PeerStatus,
     /// Framed wrapper to send messages through the TCP connection.
     framed: FramedWrite<WriteHalf<TcpStream>, Codec>,
+    /// Maximum length of the received frames, shared with the codec of the stream.
+    /// Raised from `MAX_HANDSHAKE_FRAME_LENGTH` to fit any message once the handshake is done.
+    max_frame_length: Arc<AtomicUsize>,
+    /// Ephemeral key to establish the session, until the key of the other side is received.
+    ephemeral_key: Option<EphemeralKey>,
+    /// Encrypted session, established before the handshake.
+    session: Option<Session>,
     /// Handshake timeout.
     handshake_timeout: Duration,
+    /// Limits of the size and the rate of messages received from the peer.
+    message_limits: MessageLimits,
+    /// Rate limiters of the received messages by the message type.
+    rate_limiters: HashMap<&'static str, TokenBucket>,
     /// Peer manager recipient to break the dependency loop.
     peer_manager_addr: Addr<PeerManagerActor>,
     /// Addr for client to send messages related to the chain.
@@ -121,22 +154,31 @@ pub struct Peer {
 impl Peer {
     pub fn new(
         node_info: PeerInfo,
+        secret_key: SecretKey,
         peer_addr: SocketAddr,
         peer_info: Option<PeerInfo>,
         peer_type: PeerType,
         framed: FramedWrite<WriteHalf<TcpStream>, Codec>,
+        max_frame_length: Arc<AtomicUsize>,
         handshake_timeout: Duration,
+        message_limits: MessageLimits,
         peer_manager_addr: Addr<PeerManagerActor>,
         client_addr: Recipient<NetworkClientMessages>,
     ) -> Self {
         Peer {
             node_info,
+            secret_key,
             peer_addr,
             peer_info: peer_info.into(),
             peer_type,
             peer_status: PeerStatus::Connecting,
             framed,
+            max_frame_length,
+            ephemeral_key: Some(EphemeralKey::new()),
+            session: None,
             handshake_timeout,
+            message_limits,
+            rate_limiters: HashMap::default(),
             peer_manager_addr,
             client_addr,
             tracker: Default::default(),
@@ -145,6 +187,32 @@ impl Peer {
         }
     }
 
+    /// Checks that the received message is within the size and the rate limits of its type.
+    fn check_message_limits(
+        &mut self,
+        message_type: &'static str,
+        size: usize,
+    ) -> Result<(), String> {
+        let limit = self.message_limits.get(message_type);
+        if size > limit.max_size {
+            return Err(format!(
+                "{} of {} bytes is over the limit {}",
+                message_type, size, limit.max_size
+            ));
+        }
+        let rate_limiter = self
+            .rate_limiters
+            .entry(message_type)
+            .or_insert_with(|| TokenBucket::new(limit.burst, limit.rate));
+        if !rate_limiter.try_acquire() {
+            return Err(format!(
+                "{} is over the rate limit of {} per second",
+                message_type, limit.rate
+            ));
+        }
+        Ok(())
+    }
+
     /// Whether the peer is considered abusive due to sending too many messages.
     fn is_abusive(&self) -> bool {
         self.tracker.received_bytes.count_per_min() > MAX_PEER_MSG_PER_MIN
@@ -161,8 +229,16 @@ impl Peer {
             _ => (),
         };
         debug!(target: "network", "{:?}: Sending {:?} message to peer {}", self.node_info.id, msg, self.peer_info);
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => {
+                error!(target: "network", "Sending message to {} before the session is established", self.peer_info);
+                return;
+            }
+        };
         match peer_message_to_bytes(msg) {
             Ok(bytes) => {
+                let bytes = session.encrypt(&bytes);
                 self.tracker.increment_sent(bytes.len() as u64);
                 self.framed.write(bytes);
             }
@@ -170,6 +246,27 @@ impl Peer {
         };
     }
 
+    /// Establishes the session from the ephemeral key received from the other side.
+    /// Outbound peer starts the handshake as soon as the session is established.
+    fn start_session(&mut self, ctx: &mut Context<Peer>, remote_key: &[u8]) {
+        let ephemeral_key = match self.ephemeral_key.take() {
+            Some(ephemeral_key) => ephemeral_key,
+            None => return,
+        };
+        match Session::new(ephemeral_key, remote_key) {
+            Ok(session) => {
+                self.session = Some(session);
+                if self.peer_type == PeerType::Outbound {
+                    self.send_handshake(ctx);
+                }
+            }
+            Err(err) => {
+                info!(target: "network", "Failed to establish session with {}: {}", self.peer_addr, err);
+                ctx.stop();
+            }
+        }
+    }
+
     fn fetch_client_chain_info(&mut self, ctx: &mut Context<Peer>) {
         ctx.wait(self.client_addr.send(NetworkClientMessages::GetChainInfo).into_actor(self).then(
             move |res, act, _ctx| match res {
@@ -190,14 +287,24 @@ impl Peer {
         self.client_addr
             .send(NetworkClientMessages::GetChainInfo)
             .into_actor(self)
-            .then(move |res, act, _ctx| match res {
+            .then(move |res, act, ctx| match res {
                 Ok(NetworkClientResponses::ChainInfo { genesis, height, total_weight }) => {
+                    let signature = match act.session.as_ref() {
+                        Some(session) => session.sign(&act.secret_key),
+                        None => return actix::fut::err(()),
+                    };
                     let handshake = Handshake::new(
                         act.node_info.id,
                         act.node_info.addr_port(),
                         PeerChainInfo { genesis, height, total_weight },
+                        signature,
+                        Some(act.peer_addr),
                     );
                     act.send_message(PeerMessage::Handshake(handshake));
+                    // Inbound peer is ready once its handshake is sent.
+                    if act.peer_type == PeerType::Inbound && act.peer_status == PeerStatus::Ready {
+                        act.sync_edges(ctx);
+                    }
                     actix::fut::ok(())
                 }
                 Err(err) => {
@@ -209,6 +316,20 @@ impl Peer {
             .spawn(ctx);
     }
 
+    /// Sends all the edges known to the peer manager, so the peer learns the network graph.
+    fn sync_edges(&mut self, ctx: &mut Context<Peer>) {
+        self.peer_manager_addr
+            .send(EdgesRequest {})
+            .into_actor(self)
+            .then(|res, act, _ctx| {
+                if let Ok(edge_list) = res {
+                    act.send_message(PeerMessage::Edges(edge_list.edges));
+                }
+                actix::fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
     /// Process non handshake/peer related messages.
     fn receive_client_message(&mut self, ctx: &mut Context<Peer>, msg: PeerMessage) {
         let peer_id = match self.peer_info.as_ref() {
@@ -249,12 +370,10 @@ impl Peer {
             PeerMessage::BlockHeaders(headers) => {
                 NetworkClientMessages::BlockHeaders(headers, peer_id)
             }
-            PeerMessage::StateRequest(shard_id, hash) => {
-                NetworkClientMessages::StateRequest(shard_id, hash)
-            }
-            PeerMessage::StateResponse(shard_id, hash, payload, receipts) => {
-                NetworkClientMessages::StateResponse(shard_id, hash, payload, receipts)
+            PeerMessage::StateRequest(shard_id, hash, need_header, parts_ids) => {
+                NetworkClientMessages::StateRequest(shard_id, hash, need_header, parts_ids)
             }
+            PeerMessage::StateResponse(info) => NetworkClientMessages::StateResponse(info, peer_id),
             PeerMessage::AnnounceAccount(announce_account) => {
                 if announce_account.peer_id_sender() != peer_id {
                     // Ban peer if tries to impersonate another peer.
@@ -266,7 +385,9 @@ impl Peer {
             }
             PeerMessage::Handshake(_)
             | PeerMessage::PeersRequest
-            | PeerMessage::PeersResponse(_) => {
+            | PeerMessage::PeersResponse(_)
+            | PeerMessage::Routed(_)
+            | PeerMessage::Edges(_) => {
                 error!(target: "network", "Peer receive_client_message received unexpected type");
                 return;
             }
@@ -274,8 +395,8 @@ impl Peer {
         self.client_addr
             .send(network_client_msg)
             .into_actor(self)
-            .then(|res, act, ctx| {
-                // Ban peer if client thinks received data is bad.
+            .then(move |res, act, ctx| {
+                // Ban peer if client thinks received data is bad, report other behaviour.
                 match res {
                     Ok(NetworkClientResponses::InvalidTx(err)) => {
                         warn!(target: "network", "Received invalid tx from peer {}: {}", act.peer_info, err);
@@ -285,14 +406,17 @@ impl Peer {
                         act.peer_status = PeerStatus::Banned(ban_reason);
                         ctx.stop();
                     }
+                    Ok(NetworkClientResponses::Report { behaviour }) => {
+                        act.peer_manager_addr.do_send(ReportPeer { peer_id, behaviour });
+                    }
                     Ok(NetworkClientResponses::Block(block)) => {
                         act.send_message(PeerMessage::Block(block))
                     }
                     Ok(NetworkClientResponses::BlockHeaders(headers)) => {
                         act.send_message(PeerMessage::BlockHeaders(headers))
                     }
-                    Ok(NetworkClientResponses::StateResponse { shard_id, hash, payload, receipts }) => {
-                        act.send_message(PeerMessage::StateResponse(shard_id, hash, payload, receipts))
+                    Ok(NetworkClientResponses::StateResponse(info)) => {
+                        act.send_message(PeerMessage::StateResponse(info))
                     }
                     Err(err) => {
                         error!(
@@ -322,13 +446,19 @@ impl Actor for Peer {
         ctx.run_later(self.handshake_timeout, move |act, ctx| {
             if act.peer_status != PeerStatus::Ready {
                 info!(target: "network", "Handshake timeout expired for {}", act.peer_info);
+                if let Some(peer_info) = act.peer_info.as_ref() {
+                    act.peer_manager_addr.do_send(ReportPeer {
+                        peer_id: peer_info.id,
+                        behaviour: PeerBehaviour::Timeout,
+                    });
+                }
                 ctx.stop();
             }
         });
 
-        // If outbound peer, initiate handshake.
-        if self.peer_type == PeerType::Outbound {
-            self.send_handshake(ctx);
+        // Send the ephemeral key to establish the session, outbound peer initiates handshake after.
+        if let Some(ephemeral_key) = self.ephemeral_key.as_ref() {
+            self.framed.write(ephemeral_key.public_key());
         }
     }
 
@@ -350,6 +480,34 @@ impl WriteHandler<io::Error> for Peer {}
 impl StreamHandler<Vec<u8>, io::Error> for Peer {
     fn handle(&mut self, msg: Vec<u8>, ctx: &mut Self::Context) {
         self.tracker.increment_received(msg.len() as u64);
+        let msg = match self.session.as_mut() {
+            Some(session) => match session.decrypt(&msg) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    error!(target: "network", "Received invalid frame from {}: {}", self.peer_info, err);
+                    ctx.stop();
+                    return;
+                }
+            },
+            None => {
+                self.start_session(ctx, &msg);
+                return;
+            }
+        };
+        // Limits are checked by the type in the first tag, before the message is decoded.
+        let message_type = match peer_message_type(&msg) {
+            Some(message_type) => message_type,
+            None => {
+                error!(target: "network", "Received message of unknown type from {}", self.peer_info);
+                return;
+            }
+        };
+        if let Err(err) = self.check_message_limits(message_type, msg.len()) {
+            warn!(target: "network", "Received message from {} over the limits: {}", self.peer_info, err);
+            self.peer_status = PeerStatus::Banned(ReasonForBan::Abusive);
+            ctx.stop();
+            return;
+        }
         let peer_msg = match bytes_to_peer_message(&msg) {
             Ok(peer_msg) => peer_msg,
             Err(err) => {
@@ -357,9 +515,22 @@ impl StreamHandler<Vec<u8>, io::Error> for Peer {
                 return;
             }
         };
+        if peer_msg.message_type() != message_type {
+            error!(target: "network", "Received {} from {} with the first field of {}", peer_msg, self.peer_info, message_type);
+            return;
+        }
         match (self.peer_type, self.peer_status, peer_msg) {
             (_, PeerStatus::Connecting, PeerMessage::Handshake(handshake)) => {
                 debug!(target: "network", "{:?}: Received handshake {:?}", self.node_info.id, handshake);
+                let is_signed = match self.session.as_ref() {
+                    Some(session) => session.verify(&handshake.signature, &handshake.peer_id),
+                    None => false,
+                };
+                if !is_signed {
+                    warn!(target: "network", "Received handshake from {} with invalid signature of the session.", self.peer_addr);
+                    ctx.stop();
+                    return;
+                }
                 if handshake.chain_info.genesis != self.genesis {
                     info!(target: "network", "Received connection from node with different genesis.");
                     ctx.stop();
@@ -368,13 +539,22 @@ impl StreamHandler<Vec<u8>, io::Error> for Peer {
                     warn!(target: "network", "Received info about itself. Disconnecting this peer.");
                     ctx.stop();
                 }
-                let peer_info = PeerInfo {
-                    id: handshake.peer_id,
-                    addr: handshake
-                        .listen_port
-                        .map(|port| SocketAddr::new(self.peer_addr.ip(), port)),
-                    account_id: None,
+                // Outbound peer must be the one we meant to connect to.
+                if let Some(peer_info) = self.peer_info.as_ref() {
+                    if peer_info.id != handshake.peer_id {
+                        warn!(target: "network", "Received handshake of {} from {}, expected {}", handshake.peer_id, self.peer_addr, peer_info.id);
+                        ctx.stop();
+                        return;
+                    }
+                }
+                // Address of outbound peer is the one we dialed, for inbound peer the one it listens on.
+                let addr = match self.peer_type {
+                    PeerType::Outbound => Some(self.peer_addr),
+                    PeerType::Inbound => {
+                        handshake.listen_port.map(|port| SocketAddr::new(self.peer_addr.ip(), port))
+                    }
                 };
+                let peer_info = PeerInfo { id: handshake.peer_id, addr, account_id: None };
                 self.chain_info = handshake.chain_info;
                 self.peer_manager_addr
                     .send(Consolidate {
@@ -382,20 +562,30 @@ impl StreamHandler<Vec<u8>, io::Error> for Peer {
                         peer_info: peer_info.clone(),
                         peer_type: self.peer_type,
                         chain_info: handshake.chain_info,
+                        observed_address: handshake.observed_address,
                     })
                     .into_actor(self)
                     .then(move |res, act, ctx| {
                         match res {
-                            Ok(true) => {
+                            Ok(ConsolidateResponse::Accept) => {
                                 debug!(target: "network", "{:?}: Peer {:?} successfully consolidated", act.node_info.id, act.peer_addr);
                                 act.peer_info = Some(peer_info).into();
                                 act.peer_status = PeerStatus::Ready;
+                                act.max_frame_length.store(max_frame_length(&act.message_limits), Ordering::Relaxed);
                                 // Respond to handshake if it's inbound and connection was consolidated.
                                 if act.peer_type == PeerType::Inbound {
                                     act.send_handshake(ctx);
+                                } else {
+                                    act.sync_edges(ctx);
                                 }
                                 actix::fut::ok(())
                             },
+                            Ok(ConsolidateResponse::AlreadyConnected) if act.peer_type == PeerType::Inbound => {
+                                // The other side dials back to verify our address, it disconnects after our handshake.
+                                debug!(target: "network", "{:?}: Peer {} is already connected, responding with handshake.", act.node_info.id, handshake.peer_id);
+                                act.send_handshake(ctx);
+                                actix::fut::ok(())
+                            },
                             _ => {
                                 info!(target: "network", "{:?}: Peer with handshake {:?} wasn't consolidated, disconnecting.", act.node_info.id, handshake);
                                 ctx.stop();
@@ -420,7 +610,18 @@ impl StreamHandler<Vec<u8>, io::Error> for Peer {
             }
             (_, PeerStatus::Ready, PeerMessage::PeersResponse(peers)) => {
                 debug!(target: "network", "Received peers from {}: {} peers.", self.peer_info, peers.len());
-                self.peer_manager_addr.do_send(PeersResponse { peers });
+                self.peer_manager_addr.do_send(PeersResponse { peers, source: self.peer_addr });
+            }
+            (_, PeerStatus::Ready, PeerMessage::Edges(edges)) => {
+                if let Some(peer_info) = self.peer_info.as_ref() {
+                    self.peer_manager_addr.do_send(NewEdges { edges, from: peer_info.id });
+                }
+            }
+            (_, PeerStatus::Ready, PeerMessage::Routed(routed_message)) => {
+                if let Some(peer_info) = self.peer_info.as_ref() {
+                    self.peer_manager_addr
+                        .do_send(RoutedMessageFrom { msg: routed_message, from: peer_info.id });
+                }
             }
             (_, PeerStatus::Ready, msg) => {
                 self.receive_client_message(ctx, msg);
@@ -1,5 +1,7 @@
 use std::cmp;
 use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -14,29 +16,41 @@ use chrono::offset::TimeZone;
 use chrono::{DateTime, Utc};
 use futures::future;
 use log::{debug, error, info, warn};
-use rand::{thread_rng, Rng};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
 use tokio::codec::FramedRead;
 use tokio::io::AsyncRead;
 use tokio::net::{TcpListener, TcpStream};
 
-use near_primitives::types::AccountId;
 use near_store::Store;
 
 use crate::codec::Codec;
-use crate::peer::Peer;
-use crate::peer_store::PeerStore;
+use crate::metrics;
+use crate::peer::{max_frame_length, Peer, MAX_HANDSHAKE_FRAME_LENGTH};
+use crate::peer_store::{addr_group, PeerStore, BAN_PEER_SCORE};
+use crate::routing::RoutingTable;
 use crate::types::{
-    AnnounceAccount, Ban, Consolidate, FullPeerInfo, InboundTcpConnect, KnownPeerStatus,
-    NetworkInfo, OutboundTcpConnect, PeerId, PeerList, PeerMessage, PeerType, PeersRequest,
-    PeersResponse, QueryPeerStats, ReasonForBan, SendMessage, Unregister,
+    AnnounceAccount, Ban, Consolidate, ConsolidateResponse, Edge, EdgeList, EdgesRequest,
+    FullPeerInfo, InboundTcpConnect, KnownPeerStatus, NetworkInfo, NewEdges, OutboundTcpConnect,
+    PeerBehaviour, PeerId, PeerList, PeerMessage, PeerType, PeersRequest, PeersResponse,
+    QueryPeerStats, ReasonForBan, ReportPeer, RoutedMessage, RoutedMessageBody, RoutedMessageFrom,
+    RoutedMessageTarget, SendMessage, Unregister,
 };
 use crate::types::{
-    NetworkClientMessages, NetworkConfig, NetworkRequests, NetworkResponses, PeerInfo,
+    NetworkClientMessages, NetworkClientResponses, NetworkConfig, NetworkRequests,
+    NetworkResponses, PeerInfo,
 };
 
 /// How often to request peers from active peers.
 const REQUEST_PEERS_SECS: i64 = 60;
 
+/// How often to recalculate the routes if the network graph changed.
+const UPDATE_ROUTES_MILLIS: u64 = 500;
+
+/// Number of address groups of outbound peers that must observe the same IP address before we take
+/// it as external address.
+const MIN_ADDRESS_OBSERVATIONS: usize = 2;
+
 macro_rules! unwrap_or_error(($obj: expr, $error: expr) => (match $obj {
     Ok(result) => result,
     Err(err) => {
@@ -45,6 +59,34 @@ macro_rules! unwrap_or_error(($obj: expr, $error: expr) => (match $obj {
     }
 }));
 
+/// Returns IP address observed from the most address groups, if at least `min_count` of them agree
+/// on it. Peers within one group are likely run by the same operator, so they count once.
+fn most_observed_ip<'a, I>(observations: I, min_count: usize) -> Option<IpAddr>
+where
+    I: Iterator<Item = &'a AddressObservation>,
+{
+    let mut groups: HashMap<IpAddr, HashSet<&[u8]>> = HashMap::default();
+    for observation in observations {
+        groups
+            .entry(observation.ip)
+            .or_insert_with(HashSet::default)
+            .insert(&observation.group[..]);
+    }
+    groups
+        .into_iter()
+        .map(|(ip, groups)| (ip, groups.len()))
+        .filter(|(_, count)| *count >= min_count)
+        .max_by_key(|(ip, count)| (*count, *ip))
+        .map(|(ip, _)| ip)
+}
+
+/// IP address of this node as observed by an outbound peer.
+struct AddressObservation {
+    /// Address group of the peer.
+    group: Vec<u8>,
+    ip: IpAddr,
+}
+
 /// Contains information relevant to an active peer.
 struct ActivePeer {
     addr: Addr<Peer>,
@@ -57,60 +99,6 @@ struct ActivePeer {
     last_time_peer_requested: DateTime<Utc>,
 }
 
-enum RoutingTableUpdate {
-    NewAccount,
-    UpdatedAccount,
-    Ignore,
-}
-
-impl RoutingTableUpdate {
-    fn is_new(&self) -> bool {
-        match self {
-            RoutingTableUpdate::NewAccount => true,
-            _ => false,
-        }
-    }
-}
-
-// TODO: Clear routing table periodically
-struct RoutingTable {
-    account_peers: HashMap<AccountId, (PeerId, usize)>,
-}
-
-impl RoutingTable {
-    fn new() -> Self {
-        Self { account_peers: HashMap::new() }
-    }
-
-    fn update(&mut self, data: &AnnounceAccount) -> RoutingTableUpdate {
-        match self.account_peers.get(&data.account_id) {
-            // If this account id is already tracked in the routing table ...
-            Some((_, num_hops)) => {
-                // check if this connection is better than the one we keep track
-                // regarding number of intermediate hops.
-                if data.num_hops() < *num_hops {
-                    // and add it
-                    self.account_peers
-                        .insert(data.account_id.clone(), (data.peer_id_sender(), data.num_hops()));
-                    RoutingTableUpdate::UpdatedAccount
-                } else {
-                    RoutingTableUpdate::Ignore
-                }
-            }
-            // If we don't have this account id store it in the routing table.
-            None => {
-                self.account_peers
-                    .insert(data.account_id.clone(), (data.peer_id_sender(), data.num_hops()));
-                RoutingTableUpdate::NewAccount
-            }
-        }
-    }
-
-    fn get_route(&self, account_id: &AccountId) -> Option<&PeerId> {
-        self.account_peers.get(account_id).map(|(peer_id, _)| peer_id)
-    }
-}
-
 /// Actor that manages peers connections.
 pub struct PeerManagerActor {
     /// Networking configuration.
@@ -129,6 +117,12 @@ pub struct PeerManagerActor {
     routing_table: RoutingTable,
     /// Monitor peers attempts, used for fast checking in the beginning with exponential backoff.
     monitor_peers_attempts: u64,
+    /// IP address of this node as observed by each of the active outbound peers.
+    address_observations: HashMap<PeerId, AddressObservation>,
+    /// Inbound peers whose address is being verified by dialing back, with the address to verify.
+    addr_verifications: HashMap<PeerId, PeerInfo>,
+    /// Address advertised to peers, either configured or discovered from observations.
+    external_address: Option<SocketAddr>,
 }
 
 impl PeerManagerActor {
@@ -139,16 +133,21 @@ impl PeerManagerActor {
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let peer_store = PeerStore::new(store, &config.boot_nodes)?;
         debug!(target: "network", "Found known peers: {} (boot nodes={})", peer_store.len(), config.boot_nodes.len());
+        let peer_id = config.public_key.into();
+        let external_address = config.external_address;
         Ok(PeerManagerActor {
-            peer_id: config.public_key.into(),
+            peer_id,
             config,
             client_addr,
             peer_store,
             active_peers: HashMap::default(),
             outgoing_peers: HashSet::default(),
             // account_peers: HashMap::default(),
-            routing_table: RoutingTable::new(),
+            routing_table: RoutingTable::new(peer_id),
             monitor_peers_attempts: 0,
+            address_observations: HashMap::default(),
+            addr_verifications: HashMap::default(),
+            external_address,
         })
     }
 
@@ -165,6 +164,14 @@ impl PeerManagerActor {
             "Failed to save peer data"
         );
 
+        // Announce the connection to other peers, the new peer gets all the edges once it's ready.
+        let edge = self.routing_table.update_own_edge(
+            full_peer_info.peer_info.id,
+            false,
+            &self.config.secret_key,
+        );
+        self.broadcast_edges(vec![edge], None);
+
         self.active_peers.insert(
             full_peer_info.peer_info.id,
             ActivePeer {
@@ -175,6 +182,7 @@ impl PeerManagerActor {
                 last_time_peer_requested: Utc.timestamp(0, 0),
             },
         );
+        near_metrics::set_gauge(&metrics::PEER_CONNECTIONS_TOTAL, self.active_peers.len() as i64);
     }
 
     fn unregister_peer(&mut self, peer_id: PeerId) {
@@ -183,16 +191,111 @@ impl PeerManagerActor {
             self.outgoing_peers.remove(&peer_id);
             return;
         }
-        self.active_peers.remove(&peer_id);
+        self.remove_active_peer(&peer_id);
         unwrap_or_error!(self.peer_store.peer_disconnected(&peer_id), "Failed to save peer data");
     }
 
+    /// Removes the peer from active peers and announces that the connection is closed.
+    fn remove_active_peer(&mut self, peer_id: &PeerId) {
+        if self.active_peers.remove(peer_id).is_some() {
+            let edge = self.routing_table.update_own_edge(*peer_id, true, &self.config.secret_key);
+            self.broadcast_edges(vec![edge], None);
+        }
+        if self.address_observations.remove(peer_id).is_some() {
+            self.update_external_address();
+        }
+        self.addr_verifications.remove(peer_id);
+        near_metrics::set_gauge(&metrics::PEER_CONNECTIONS_TOTAL, self.active_peers.len() as i64);
+    }
+
     fn ban_peer(&mut self, peer_id: &PeerId, ban_reason: ReasonForBan) {
         info!(target: "network", "Banning peer {:?}", peer_id);
-        self.active_peers.remove(&peer_id);
+        self.remove_active_peer(peer_id);
         unwrap_or_error!(self.peer_store.peer_ban(peer_id, ban_reason), "Failed to save peer data");
     }
 
+    /// Records the address of this node observed by the outbound peer and updates the external
+    /// address.
+    fn observe_address(&mut self, peer_info: &PeerInfo, observed_address: SocketAddr) {
+        let group = addr_group(peer_info.addr.map(|addr| addr.ip()));
+        self.address_observations
+            .insert(peer_info.id, AddressObservation { group, ip: observed_address.ip() });
+        self.update_external_address();
+    }
+
+    /// Converges on the IP address most of the peers see us at, unless the address is configured.
+    /// The port is the one we listen on, since observed ports of inbound connections are random.
+    fn update_external_address(&mut self) {
+        if self.config.external_address.is_some() {
+            return;
+        }
+        let port = match self.config.addr {
+            Some(addr) => addr.port(),
+            None => return,
+        };
+        if let Some(ip) =
+            most_observed_ip(self.address_observations.values(), MIN_ADDRESS_OBSERVATIONS)
+        {
+            let external_address = SocketAddr::new(ip, port);
+            if self.external_address != Some(external_address) {
+                info!(target: "network", "{}: External address changed to {}", self.peer_id, external_address);
+                self.external_address = Some(external_address);
+            }
+        }
+    }
+
+    /// Verifies address of the inbound peer by connecting to it, so it can be shared with others.
+    /// The address is verified once the handshake proves that the same peer listens on it.
+    fn verify_peer_addr(&mut self, ctx: &mut Context<Self>, peer_info: PeerInfo) {
+        let addr = match peer_info.addr {
+            Some(addr) => addr,
+            None => return,
+        };
+        if self.addr_verifications.insert(peer_info.id, peer_info.clone()).is_some() {
+            return;
+        }
+        Resolver::from_registry()
+            .send(ConnectAddr(addr))
+            .into_actor(self)
+            .then(move |res, act, ctx| match res {
+                Ok(Ok(stream)) => {
+                    debug!(target: "network", "Dialed back {}", peer_info);
+                    act.connect_peer(ctx.address(), stream, PeerType::Outbound, Some(peer_info));
+                    actix::fut::ok(())
+                }
+                Ok(Err(err)) => {
+                    debug!(target: "network", "Failed to dial back {}: {}", addr, err);
+                    act.addr_verifications.remove(&peer_info.id);
+                    actix::fut::err(())
+                }
+                Err(err) => {
+                    debug!(target: "network", "Failed to dial back {}: {}", addr, err);
+                    act.addr_verifications.remove(&peer_info.id);
+                    actix::fut::err(())
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Updates score of the peer according to its behaviour and bans it if the score drops too low.
+    /// Returns true if the peer was banned.
+    fn report_peer(&mut self, peer_id: &PeerId, behaviour: PeerBehaviour) -> bool {
+        let score = match self.peer_store.update_score(peer_id, behaviour) {
+            Ok(score) => score,
+            Err(err) => {
+                error!(target: "network", "Failed to update score of peer {}: {}", peer_id, err);
+                return false;
+            }
+        };
+        debug!(target: "network", "Peer {} score {} after {:?}", peer_id, score, behaviour);
+        if score <= BAN_PEER_SCORE {
+            self.ban_peer(peer_id, behaviour.ban_reason());
+            true
+        } else {
+            false
+        }
+    }
+
     /// Connects peer with given TcpStream and optional information if it's outbound.
     fn connect_peer(
         &mut self,
@@ -202,9 +305,11 @@ impl PeerManagerActor {
         peer_info: Option<PeerInfo>,
     ) {
         let peer_id = self.peer_id;
+        let secret_key = self.config.secret_key.clone();
         let account_id = self.config.account_id.clone();
-        let server_addr = self.config.addr;
+        let server_addr = self.external_address.or(self.config.addr);
         let handshake_timeout = self.config.handshake_timeout;
+        let message_limits = self.config.message_limits.clone();
         let client_addr = self.client_addr.clone();
         Peer::create(move |ctx| {
             let server_addr = server_addr.unwrap_or_else(|| stream.local_addr().unwrap());
@@ -213,14 +318,23 @@ impl PeerManagerActor {
 
             // TODO: check if peer is banned or known based on IP address and port.
 
-            Peer::add_stream(FramedRead::new(read, Codec::new()), ctx);
+            // Frames are small until the handshake is done, peer raises the limit after that.
+            let read_max_length = Arc::new(AtomicUsize::new(MAX_HANDSHAKE_FRAME_LENGTH));
+            Peer::add_stream(
+                FramedRead::new(read, Codec::with_max_length(read_max_length.clone())),
+                ctx,
+            );
+            let write_max_length = max_frame_length(&message_limits);
             Peer::new(
                 PeerInfo { id: peer_id, addr: Some(server_addr), account_id },
+                secret_key,
                 remote_addr,
                 peer_info,
                 peer_type,
-                FramedWrite::new(write, Codec::new(), ctx),
+                FramedWrite::new(write, Codec::new(write_max_length), ctx),
+                read_max_length,
                 handshake_timeout,
+                message_limits,
                 recipient,
                 client_addr,
             )
@@ -232,7 +346,8 @@ impl PeerManagerActor {
             < (self.config.peer_max_count as usize)
     }
 
-    /// Returns single random peer with the most weight.
+    /// Returns peers with the most weight, best scored first.
+    /// Peers with negative score are only returned if there are no other peers with the most weight.
     fn most_weight_peers(&self) -> Vec<FullPeerInfo> {
         let max_weight = match self
             .active_peers
@@ -243,16 +358,23 @@ impl PeerManagerActor {
             Some(w) => w,
             None => return vec![],
         };
-        self.active_peers
+        let mut peers = self
+            .active_peers
             .values()
             .filter_map(|active_peer| {
                 if active_peer.full_peer_info.chain_info.total_weight == max_weight {
-                    Some(active_peer.full_peer_info.clone())
+                    let peer_id = active_peer.full_peer_info.peer_info.id;
+                    Some((self.peer_store.score(&peer_id), active_peer.full_peer_info.clone()))
                 } else {
                     None
                 }
             })
-            .collect::<Vec<_>>()
+            .collect::<Vec<_>>();
+        peers.sort_by(|(score1, _), (score2, _)| score2.cmp(score1));
+        if peers.iter().any(|(score, _)| *score >= 0) {
+            peers.retain(|(score, _)| *score >= 0);
+        }
+        peers.into_iter().map(|(_, full_peer_info)| full_peer_info).collect()
     }
 
     /// Returns bytes sent/received across all peers.
@@ -263,15 +385,15 @@ impl PeerManagerActor {
     }
 
     /// Get a random peer we are not connected to from the known list.
+    /// Peers are sampled with probability proportional to how far their score is from the ban.
     fn sample_random_peer(&self, ignore_list: &HashSet<PeerId>) -> Option<PeerInfo> {
         let unconnected_peers = self.peer_store.unconnected_peers(ignore_list);
-        let index = thread_rng().gen_range(0, std::cmp::max(unconnected_peers.len(), 1));
-
         unconnected_peers
-            .iter()
-            .enumerate()
-            .filter_map(|(i, v)| if i == index { Some(v.clone()) } else { None })
-            .next()
+            .choose_weighted(&mut thread_rng(), |peer_info| {
+                cmp::max(self.peer_store.score(&peer_info.id) - BAN_PEER_SCORE, 1)
+            })
+            .ok()
+            .cloned()
     }
 
     /// Query current peers for more peers.
@@ -302,8 +424,8 @@ impl PeerManagerActor {
                 .map_err(|err, _, _| error!("Failed sending message: {}", err))
                 .and_then(move |res, act, _| {
                     if res.is_abusive {
-                        warn!(target: "network", "Banning peer {} for abuse ({} sent, {} recv)", peer_id1, res.message_counts.0, res.message_counts.1);
-                        act.ban_peer(&peer_id1, ReasonForBan::Abusive);
+                        warn!(target: "network", "Peer {} is abusive ({} sent, {} recv)", peer_id1, res.message_counts.0, res.message_counts.1);
+                        act.report_peer(&peer_id1, PeerBehaviour::Misbehaviour(ReasonForBan::Abusive));
                     } else if let Some(active_peer) = act.active_peers.get_mut(&peer_id1) {
                         active_peer.full_peer_info.chain_info = res.chain_info;
                         active_peer.sent_bytes_per_sec = res.sent_bytes_per_sec;
@@ -381,6 +503,19 @@ impl PeerManagerActor {
             .spawn(ctx);
     }
 
+    /// Sends edges to all active peers except the one they were received from.
+    fn broadcast_edges(&self, edges: Vec<Edge>, from: Option<PeerId>) {
+        if edges.is_empty() {
+            return;
+        }
+        let msg = SendMessage { message: PeerMessage::Edges(edges) };
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            if Some(*peer_id) != from {
+                active_peer.addr.do_send(msg.clone());
+            }
+        }
+    }
+
     fn announce_account(&mut self, ctx: &mut Context<Self>, mut announce_account: AnnounceAccount) {
         // If this is a new account send an announcement to random set of peers.
         if self.routing_table.update(&announce_account).is_new() {
@@ -394,29 +529,101 @@ impl PeerManagerActor {
         }
     }
 
-    /// Send message to specific account.
-    fn send_message_to_account(
-        &self,
+    /// Signs new message from this peer and routes it to the target.
+    fn send_routed_message(
+        &mut self,
         ctx: &mut Context<Self>,
-        account_id: AccountId,
-        msg: SendMessage,
+        target: RoutedMessageTarget,
+        body: RoutedMessageBody,
     ) {
-        if let Some(peer_id) = self.routing_table.get_route(&account_id) {
-            if let Some(active_peer) = self.active_peers.get(peer_id) {
-                active_peer
-                    .addr
-                    .send(msg)
-                    .into_actor(self)
-                    .map_err(|e, _, _| error!("Failed sending message: {}", e))
-                    .and_then(|_, _, _| actix::fut::ok(()))
-                    .spawn(ctx);
-            } else {
-                error!(target: "network", "Missing peer {:?} that is related to account {}", peer_id, account_id);
+        let msg = RoutedMessage::new(
+            target,
+            self.peer_id,
+            &self.config.secret_key,
+            self.config.routed_message_ttl,
+            body,
+        );
+        if msg.expects_response() {
+            self.routing_table.add_route_back(msg.hash(), self.peer_id);
+        }
+        self.route_message(ctx, msg);
+    }
+
+    /// Sends routed message to the next hop towards its target or receives it if the target is
+    /// this peer. Messages that run out of hops are dropped.
+    fn route_message(&mut self, ctx: &mut Context<Self>, mut msg: RoutedMessage) {
+        let next_hop = match &msg.target {
+            RoutedMessageTarget::AccountId(account_id) => {
+                if self.config.account_id.as_ref() == Some(account_id) {
+                    Some(self.peer_id)
+                } else {
+                    self.routing_table.get_route(account_id)
+                }
+            }
+            RoutedMessageTarget::Hash(hash) => self.routing_table.take_route_back(hash),
+        };
+        let peer_id = match next_hop {
+            Some(peer_id) => peer_id,
+            None => {
+                warn!(target: "network", "No route for routed message to {:?}", msg.target);
+                return;
             }
+        };
+        if peer_id == self.peer_id {
+            self.receive_routed_message(ctx, msg);
+            return;
+        }
+        if msg.ttl == 0 {
+            debug!(target: "network", "Dropping routed message to {:?} with expired ttl", msg.target);
+            return;
+        }
+        msg.ttl -= 1;
+        if let Some(active_peer) = self.active_peers.get(&peer_id) {
+            active_peer.addr.do_send(SendMessage { message: PeerMessage::Routed(msg) });
         } else {
-            warn!(target: "network", "Unknown account {} in routing table.", account_id);
+            error!(target: "network", "Missing peer {:?} that is the next hop to {:?}", peer_id, msg.target);
         }
     }
+
+    /// Processes routed message that reached this peer.
+    fn receive_routed_message(&mut self, ctx: &mut Context<Self>, msg: RoutedMessage) {
+        let msg_hash = msg.hash();
+        let network_client_msg = match msg.body {
+            RoutedMessageBody::BlockApproval(account_id, hash, signature) => {
+                NetworkClientMessages::BlockApproval(account_id, hash, signature)
+            }
+            RoutedMessageBody::StateRequest(shard_id, hash, need_header, parts_ids) => {
+                NetworkClientMessages::StateRequest(shard_id, hash, need_header, parts_ids)
+            }
+            RoutedMessageBody::StateResponse(info) => {
+                NetworkClientMessages::StateResponse(info, msg.author)
+            }
+            RoutedMessageBody::ForwardTx(transaction) => {
+                NetworkClientMessages::Transaction(transaction)
+            }
+        };
+        self.client_addr
+            .send(network_client_msg)
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(NetworkClientResponses::StateResponse(info)) => {
+                        act.send_routed_message(
+                            ctx,
+                            RoutedMessageTarget::Hash(msg_hash),
+                            RoutedMessageBody::StateResponse(info),
+                        );
+                    }
+                    Err(err) => {
+                        error!(target: "network", "Failed sending routed message to client: {}", err);
+                        return actix::fut::err(());
+                    }
+                    _ => {}
+                };
+                actix::fut::ok(())
+            })
+            .spawn(ctx);
+    }
 }
 
 impl Actor for PeerManagerActor {
@@ -436,6 +643,11 @@ impl Actor for PeerManagerActor {
 
         // Start active peer stats querying.
         self.monitor_peer_stats(ctx);
+
+        // Recalculate routes at most once per period, however many edges are received.
+        ctx.run_interval(Duration::from_millis(UPDATE_ROUTES_MILLIS), |act, _ctx| {
+            act.routing_table.update_routes();
+        });
     }
 }
 
@@ -446,9 +658,20 @@ impl Handler<NetworkRequests> for PeerManagerActor {
         match msg {
             NetworkRequests::FetchInfo { level } => {
                 let (sent_bytes_per_sec, received_bytes_per_sec) = self.get_total_bytes_per_sec();
+                near_metrics::set_gauge(&metrics::SENT_BYTES_PER_SEC, sent_bytes_per_sec as i64);
+                near_metrics::set_gauge(
+                    &metrics::RECEIVED_BYTES_PER_SEC,
+                    received_bytes_per_sec as i64,
+                );
 
-                let routes =
-                    if level > 0 { Some(self.routing_table.account_peers.clone()) } else { None };
+                let (routes, graph) = if level > 0 {
+                    (
+                        Some(self.routing_table.account_peers().clone()),
+                        Some(self.routing_table.graph_view()),
+                    )
+                } else {
+                    (None, None)
+                };
 
                 NetworkResponses::Info(NetworkInfo {
                     num_active_peers: self.num_active_peers(),
@@ -457,6 +680,7 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                     sent_bytes_per_sec,
                     received_bytes_per_sec,
                     routes,
+                    graph,
                 })
             }
             NetworkRequests::Block { block } => {
@@ -465,17 +689,15 @@ impl Handler<NetworkRequests> for PeerManagerActor {
             }
             NetworkRequests::BlockHeaderAnnounce { header, approval } => {
                 if let Some(approval) = approval {
-                    if let Some(account_id) = &self.config.account_id {
-                        self.send_message_to_account(
+                    if let Some(account_id) = self.config.account_id.clone() {
+                        self.send_routed_message(
                             ctx,
-                            approval.target,
-                            SendMessage {
-                                message: PeerMessage::BlockApproval(
-                                    account_id.clone(),
-                                    approval.hash,
-                                    approval.signature,
-                                ),
-                            },
+                            RoutedMessageTarget::AccountId(approval.target),
+                            RoutedMessageBody::BlockApproval(
+                                account_id,
+                                approval.hash,
+                                approval.signature,
+                            ),
                         );
                     }
                 }
@@ -501,19 +723,58 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                 }
                 NetworkResponses::NoResponse
             }
-            NetworkRequests::StateRequest { shard_id, hash, peer_id } => {
+            NetworkRequests::StateRequest { shard_id, hash, need_header, parts_ids, peer_id } => {
                 if let Some(active_peer) = self.active_peers.get(&peer_id) {
                     active_peer.addr.do_send(SendMessage {
-                        message: PeerMessage::StateRequest(shard_id, hash),
+                        message: PeerMessage::StateRequest(shard_id, hash, need_header, parts_ids),
                     });
                 }
                 NetworkResponses::NoResponse
             }
+            NetworkRequests::StateRequestToAccount {
+                shard_id,
+                hash,
+                need_header,
+                parts_ids,
+                account_id,
+            } => {
+                self.send_routed_message(
+                    ctx,
+                    RoutedMessageTarget::AccountId(account_id),
+                    RoutedMessageBody::StateRequest(shard_id, hash, need_header, parts_ids),
+                );
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::ForwardTx { account_ids, transaction } => {
+                let mut routed = false;
+                for account_id in account_ids {
+                    if self.routing_table.get_route(&account_id).is_some() {
+                        routed = true;
+                        self.send_routed_message(
+                            ctx,
+                            RoutedMessageTarget::AccountId(account_id),
+                            RoutedMessageBody::ForwardTx(transaction.clone()),
+                        );
+                    }
+                }
+                if !routed {
+                    debug!(target: "network", "No route to block producers, gossiping transaction {}", transaction.get_hash());
+                    self.broadcast_message(
+                        ctx,
+                        SendMessage { message: PeerMessage::Transaction(transaction) },
+                    );
+                }
+                NetworkResponses::NoResponse
+            }
             NetworkRequests::BanPeer { peer_id, ban_reason } => {
                 if let Some(_) = self.active_peers.get(&peer_id) {
                     // TODO: send stop signal to the addr.
                 }
-                self.ban_peer(&peer_id, ban_reason);
+                self.report_peer(&peer_id, PeerBehaviour::Misbehaviour(ban_reason));
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::ReportPeer { peer_id, behaviour } => {
+                self.report_peer(&peer_id, behaviour);
                 NetworkResponses::NoResponse
             }
             NetworkRequests::AnnounceAccount(announce_account) => {
@@ -572,27 +833,53 @@ impl Handler<OutboundTcpConnect> for PeerManagerActor {
 }
 
 impl Handler<Consolidate> for PeerManagerActor {
-    type Result = bool;
-
-    fn handle(&mut self, msg: Consolidate, _ctx: &mut Self::Context) -> Self::Result {
+    type Result = ConsolidateResponse;
+
+    fn handle(&mut self, msg: Consolidate, ctx: &mut Self::Context) -> Self::Result {
+        // Handshake over the connection that dialed back the inbound peer proves its address.
+        if msg.peer_type == PeerType::Outbound {
+            if let Some(peer_info) = self.addr_verifications.remove(&msg.peer_info.id) {
+                debug!(target: "network", "Verified address of {}", peer_info);
+                if let Err(err) = self.peer_store.peer_addr_verified(&peer_info) {
+                    error!(target: "network", "Failed to save peer data: {}", err);
+                }
+                // Peers that asked for peers before the address was verified didn't receive it.
+                self.broadcast_message(
+                    ctx,
+                    SendMessage { message: PeerMessage::PeersResponse(vec![peer_info]) },
+                );
+                return ConsolidateResponse::Reject;
+            }
+        }
         // We already connected to this peer.
         if self.active_peers.contains_key(&msg.peer_info.id) {
-            return false;
+            return ConsolidateResponse::AlreadyConnected;
         }
         // This is incoming connection but we have this peer already in outgoing.
         // This only happens when both of us connect at the same time, break tie using higher peer id.
         if msg.peer_type == PeerType::Inbound && self.outgoing_peers.contains(&msg.peer_info.id) {
             // We pick connection that has lower id.
             if msg.peer_info.id > self.peer_id {
-                return false;
+                return ConsolidateResponse::Reject;
             }
         }
-        // TODO: double check that address is connectable and add account id.
+        // Address of outbound peer is verified by connecting to it, inbound peer is dialed back.
+        if msg.peer_type == PeerType::Outbound {
+            if let Err(err) = self.peer_store.peer_addr_verified(&msg.peer_info) {
+                error!(target: "network", "Failed to save peer data: {}", err);
+            }
+            // Only outbound peers are chosen by us, so inbound ones can't sway the external address.
+            if let Some(observed_address) = msg.observed_address {
+                self.observe_address(&msg.peer_info, observed_address);
+            }
+        } else if !self.peer_store.is_addr_verified(&msg.peer_info.id) {
+            self.verify_peer_addr(ctx, msg.peer_info.clone());
+        }
         self.register_peer(
             FullPeerInfo { peer_info: msg.peer_info, chain_info: msg.chain_info },
             msg.actor,
         );
-        true
+        ConsolidateResponse::Accept
     }
 }
 
@@ -608,7 +895,60 @@ impl Handler<Ban> for PeerManagerActor {
     type Result = ();
 
     fn handle(&mut self, msg: Ban, _ctx: &mut Self::Context) {
-        self.ban_peer(&msg.peer_id, msg.ban_reason);
+        // Peer is already disconnected, so unregister it unless it was banned.
+        if !self.report_peer(&msg.peer_id, PeerBehaviour::Misbehaviour(msg.ban_reason)) {
+            self.unregister_peer(msg.peer_id);
+        }
+    }
+}
+
+impl Handler<ReportPeer> for PeerManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReportPeer, _ctx: &mut Self::Context) {
+        self.report_peer(&msg.peer_id, msg.behaviour);
+    }
+}
+
+impl Handler<RoutedMessageFrom> for PeerManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RoutedMessageFrom, ctx: &mut Self::Context) {
+        let RoutedMessageFrom { msg, from } = msg;
+        if !msg.verify() {
+            warn!(target: "network", "Received routed message from {} with invalid signature", from);
+            self.report_peer(&from, PeerBehaviour::Misbehaviour(ReasonForBan::InvalidSignature));
+            return;
+        }
+        if msg.expects_response() {
+            self.routing_table.add_route_back(msg.hash(), from);
+        }
+        self.route_message(ctx, msg);
+    }
+}
+
+impl Handler<EdgesRequest> for PeerManagerActor {
+    type Result = EdgeList;
+
+    fn handle(&mut self, _msg: EdgesRequest, _ctx: &mut Self::Context) -> Self::Result {
+        EdgeList { edges: self.routing_table.edges() }
+    }
+}
+
+impl Handler<NewEdges> for PeerManagerActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: NewEdges, _ctx: &mut Self::Context) {
+        if !msg.edges.iter().all(Edge::verify) {
+            warn!(target: "network", "Received edges from {} with invalid signature", msg.from);
+            self.report_peer(
+                &msg.from,
+                PeerBehaviour::Misbehaviour(ReasonForBan::InvalidSignature),
+            );
+            return;
+        }
+        let new_edges = self.routing_table.add_edges(msg.edges);
+        self.broadcast_edges(new_edges, Some(msg.from));
     }
 }
 
@@ -624,8 +964,43 @@ impl Handler<PeersResponse> for PeerManagerActor {
     type Result = ();
 
     fn handle(&mut self, mut msg: PeersResponse, _ctx: &mut Self::Context) {
-        self.peer_store.add_peers(
-            msg.peers.drain(..).filter(|peer_info| peer_info.id != self.peer_id).collect(),
+        unwrap_or_error!(
+            self.peer_store.add_peers(
+                msg.peers.drain(..).filter(|peer_info| peer_info.id != self.peer_id).collect(),
+                msg.source.ip(),
+            ),
+            "Failed to save peer data"
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn observation(group: &[u8], ip: IpAddr) -> AddressObservation {
+        AddressObservation { group: group.to_vec(), ip }
+    }
+
+    #[test]
+    fn external_address_observations() {
+        let ip1: IpAddr = "1.2.3.4".parse().unwrap();
+        let ip2: IpAddr = "5.6.7.8".parse().unwrap();
+        assert_eq!(most_observed_ip(Vec::<AddressObservation>::new().iter(), 2), None);
+        let observations = vec![observation(&[1], ip1), observation(&[2], ip2)];
+        assert_eq!(most_observed_ip(observations.iter(), 2), None);
+        assert_eq!(most_observed_ip(observations.iter(), 1), Some(ip2));
+        let observations =
+            vec![observation(&[1], ip1), observation(&[2], ip2), observation(&[3], ip1)];
+        assert_eq!(most_observed_ip(observations.iter(), 2), Some(ip1));
+        // Peers of a single address group count once.
+        let observations = vec![
+            observation(&[1], ip2),
+            observation(&[2], ip1),
+            observation(&[1], ip2),
+            observation(&[3], ip1),
+            observation(&[1], ip2),
+        ];
+        assert_eq!(most_observed_ip(observations.iter(), 2), Some(ip1));
+    }
+}
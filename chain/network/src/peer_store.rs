@@ -1,22 +1,64 @@
 use std::collections::{hash_map::Iter, HashMap, HashSet};
 use std::convert::TryInto;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use chrono::Utc;
-use log::debug;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use log::{debug, warn};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::{thread_rng, Rng};
 
-use near_store::{Store, COL_PEERS};
+use near_primitives::hash::hash;
+use near_store::{Store, StoreUpdate, COL_PEERS};
 
 use crate::types::{
-    FullPeerInfo, KnownPeerState, KnownPeerStatus, NetworkConfig, PeerId, PeerInfo, ReasonForBan,
+    FullPeerInfo, KnownPeerState, KnownPeerStatus, NetworkConfig, PeerBehaviour, PeerId, PeerInfo,
+    ReasonForBan,
 };
 
+/// Highest score peer can get for its good behaviour.
+pub const MAX_PEER_SCORE: i32 = 100;
+
+/// Peer is banned when its score drops to this value.
+pub const BAN_PEER_SCORE: i32 = -100;
+
+/// Number of buckets for peers we heard about from other peers but haven't connected to.
+pub const NEW_BUCKETS_COUNT: usize = 64;
+
+/// Number of buckets for peers with addresses we managed to connect to.
+pub const TRIED_BUCKETS_COUNT: usize = 16;
+
+/// Maximum number of peers in each bucket.
+pub const BUCKET_SIZE: usize = 32;
+
+/// Key of the bucket salt in the peers column.
+const SALT_KEY: &[u8] = b"PEER_STORE_SALT";
+
+/// Bucket of the address book. Peers are spread across buckets by their address group and the
+/// group of the peer that told us about them, so a single source can fill only a few buckets.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Bucket {
+    New(usize),
+    Tried(usize),
+}
+
+/// Group of addresses likely controlled by the same operator: /16 for IPv4 and /32 for IPv6.
+pub fn addr_group(ip: Option<IpAddr>) -> Vec<u8> {
+    match ip {
+        Some(IpAddr::V4(ip)) => ip.octets()[..2].to_vec(),
+        Some(IpAddr::V6(ip)) => ip.octets()[..4].to_vec(),
+        None => vec![],
+    }
+}
+
 /// Known peers store, maintaining cache of known peers and connection to storage to save/load them.
+/// Known peers form a bounded address book split into "tried" and "new" buckets.
 pub struct PeerStore {
     store: Arc<Store>,
     peer_states: HashMap<PeerId, KnownPeerState>,
+    buckets: HashMap<Bucket, HashSet<PeerId>>,
+    /// Random salt of bucket indices, so others can't predict which bucket a peer goes into.
+    salt: [u8; 32],
 }
 
 impl PeerStore {
@@ -24,24 +66,126 @@ impl PeerStore {
         store: Arc<Store>,
         boot_nodes: &[PeerInfo],
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut peer_states = HashMap::default();
+        let mut store_update = store.store_update();
+        // Salt is generated once and reused on restarts, so known peers keep their buckets.
+        let mut salt = [0u8; 32];
+        match store.get(COL_PEERS, SALT_KEY)? {
+            Some(ref value) if value.len() == salt.len() => salt.copy_from_slice(value),
+            _ => {
+                salt = thread_rng().gen();
+                store_update.set(COL_PEERS, SALT_KEY, &salt);
+            }
+        }
+        let mut peer_store = PeerStore {
+            store: store.clone(),
+            peer_states: HashMap::default(),
+            buckets: HashMap::default(),
+            salt,
+        };
         for (key, value) in store.iter(COL_PEERS) {
+            if &key[..] == SALT_KEY {
+                continue;
+            }
             let key: Vec<u8> = key.into();
             let value: Vec<u8> = value.into();
             let peer_id: PeerId = key.try_into()?;
-            let mut peer_state: KnownPeerState = value.try_into()?;
+            let mut peer_state: KnownPeerState = match value.try_into() {
+                Ok(peer_state) => peer_state,
+                Err(err) => {
+                    warn!(target: "network", "Skipping peer {} that failed to load: {}", peer_id, err);
+                    continue;
+                }
+            };
             match peer_state.status {
                 KnownPeerStatus::Banned(_, _) => {}
                 _ => peer_state.status = KnownPeerStatus::NotConnected
             };
-            peer_states.insert(peer_id, peer_state);
+            // Peers that don't fit into full buckets anymore are dropped.
+            if !peer_store.insert_peer(peer_id, peer_state, false, &mut store_update) {
+                store_update.delete(COL_PEERS, peer_id.as_ref());
+            }
         }
         for peer_info in boot_nodes.iter() {
-            if !peer_states.contains_key(&peer_info.id) {
-                peer_states.insert(peer_info.id, KnownPeerState::new(peer_info.clone()));
+            if !peer_store.peer_states.contains_key(&peer_info.id) {
+                // Boot nodes come from the config, so their addresses are trusted.
+                let mut peer_state = KnownPeerState::new(peer_info.clone());
+                peer_state.addr_verified = true;
+                peer_store.insert_peer(peer_info.id, peer_state, true, &mut store_update);
             }
         }
-        Ok(PeerStore { store, peer_states })
+        store_update.commit()?;
+        Ok(peer_store)
+    }
+
+    /// Index of the bucket, derived from the salted hash of the given parts.
+    fn bucket_index(&self, parts: &[&[u8]], count: usize) -> usize {
+        let mut data = self.salt.to_vec();
+        for part in parts {
+            data.extend_from_slice(&(part.len() as u32).to_le_bytes());
+            data.extend_from_slice(part);
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash(&data).as_ref()[..8]);
+        (u64::from_le_bytes(bytes) % count as u64) as usize
+    }
+
+    /// Peers with verified addresses go to the tried buckets by their address group and id,
+    /// others go to the new buckets by the address group of the source and of the peer.
+    fn bucket(&self, peer_id: &PeerId, peer_state: &KnownPeerState) -> Bucket {
+        let group = addr_group(peer_state.peer_info.addr.map(|addr| addr.ip()));
+        if peer_state.addr_verified {
+            Bucket::Tried(self.bucket_index(&[&group[..], peer_id.as_ref()], TRIED_BUCKETS_COUNT))
+        } else {
+            let source_group = addr_group(peer_state.source);
+            Bucket::New(self.bucket_index(&[&source_group[..], &group[..]], NEW_BUCKETS_COUNT))
+        }
+    }
+
+    /// Adds peer to its bucket. If the bucket is full, evicts random peer that is neither connected
+    /// nor banned. Returns false if there was no room for the peer, unless it's forced in.
+    fn insert_peer(
+        &mut self,
+        peer_id: PeerId,
+        peer_state: KnownPeerState,
+        force: bool,
+        store_update: &mut StoreUpdate,
+    ) -> bool {
+        let bucket = self.bucket(&peer_id, &peer_state);
+        let peer_ids = self.buckets.entry(bucket).or_insert_with(HashSet::default);
+        if peer_ids.len() >= BUCKET_SIZE {
+            let peer_states = &self.peer_states;
+            let evicted = peer_ids
+                .iter()
+                .filter(|peer_id| match peer_states.get(*peer_id).map(|p| &p.status) {
+                    Some(KnownPeerStatus::Connected) | Some(KnownPeerStatus::Banned(_, _)) => false,
+                    _ => true,
+                })
+                .choose(&mut thread_rng())
+                .cloned();
+            match evicted {
+                Some(evicted) => {
+                    debug!(target: "network", "Evicting peer {} from {:?}", evicted, bucket);
+                    peer_ids.remove(&evicted);
+                    self.peer_states.remove(&evicted);
+                    store_update.delete(COL_PEERS, evicted.as_ref());
+                }
+                None if !force => return false,
+                None => {}
+            }
+        }
+        peer_ids.insert(peer_id);
+        self.peer_states.insert(peer_id, peer_state);
+        true
+    }
+
+    /// Removes peer from the address book, returning its state.
+    fn remove_peer(&mut self, peer_id: &PeerId) -> Option<KnownPeerState> {
+        let peer_state = self.peer_states.remove(peer_id)?;
+        let bucket = self.bucket(peer_id, &peer_state);
+        if let Some(peer_ids) = self.buckets.get_mut(&bucket) {
+            peer_ids.remove(peer_id);
+        }
+        Some(peer_state)
     }
 
     pub fn len(&self) -> usize {
@@ -52,14 +196,17 @@ impl PeerStore {
         &mut self,
         peer_info: &FullPeerInfo,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let entry = self
-            .peer_states
-            .entry(peer_info.peer_info.id)
-            .or_insert(KnownPeerState::new(peer_info.peer_info.clone()));
+        let peer_id = peer_info.peer_info.id;
+        let mut store_update = self.store.store_update();
+        if !self.peer_states.contains_key(&peer_id) {
+            let mut peer_state = KnownPeerState::new(peer_info.peer_info.clone());
+            peer_state.source = peer_info.peer_info.addr.map(|addr| addr.ip());
+            self.insert_peer(peer_id, peer_state, true, &mut store_update);
+        }
+        let entry = self.peer_states.get_mut(&peer_id).unwrap();
         entry.last_seen = Utc::now();
         entry.status = KnownPeerStatus::Connected;
-        let mut store_update = self.store.store_update();
-        store_update.set_ser(COL_PEERS, peer_info.peer_info.id.as_ref(), entry)?;
+        store_update.set_ser(COL_PEERS, peer_id.as_ref(), entry)?;
         store_update.commit().map_err(|err| err.into())
     }
 
@@ -69,7 +216,11 @@ impl PeerStore {
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
             peer_state.last_seen = Utc::now();
-            peer_state.status = KnownPeerStatus::NotConnected;
+            // Peer that is disconnected after the ban stays banned.
+            match peer_state.status {
+                KnownPeerStatus::Banned(_, _) => {}
+                _ => peer_state.status = KnownPeerStatus::NotConnected,
+            };
             let mut store_update = self.store.store_update();
             store_update.set_ser(COL_PEERS, peer_id.as_ref(), peer_state)?;
             store_update.commit().map_err(|err| err.into())
@@ -78,6 +229,31 @@ impl PeerStore {
         }
     }
 
+    /// Records that we managed to connect to the peer at the given address, so it can be shared.
+    pub fn peer_addr_verified(
+        &mut self,
+        peer_info: &PeerInfo,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if peer_info.addr.is_none() {
+            return Err(format!("Peer {} has no address to verify", peer_info.id).into());
+        }
+        // Moves the peer to the tried bucket of its address.
+        let mut peer_state = self
+            .remove_peer(&peer_info.id)
+            .unwrap_or_else(|| KnownPeerState::new(peer_info.clone()));
+        peer_state.peer_info.addr = peer_info.addr;
+        peer_state.addr_verified = true;
+        let mut store_update = self.store.store_update();
+        store_update.set_ser(COL_PEERS, peer_info.id.as_ref(), &peer_state)?;
+        self.insert_peer(peer_info.id, peer_state, true, &mut store_update);
+        store_update.commit().map_err(|err| err.into())
+    }
+
+    /// Whether the address of the peer was checked by connecting to it.
+    pub fn is_addr_verified(&self, peer_id: &PeerId) -> bool {
+        self.peer_states.get(peer_id).map(|peer_state| peer_state.addr_verified).unwrap_or(false)
+    }
+
     pub fn peer_ban(
         &mut self,
         peer_id: &PeerId,
@@ -97,6 +273,8 @@ impl PeerStore {
     pub fn peer_unban(&mut self, peer_id: &PeerId) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
             peer_state.status = KnownPeerStatus::NotConnected;
+            // Unbanned peer starts over with neutral score.
+            peer_state.score = 0;
             let mut store_update = self.store.store_update();
             store_update.set_ser(COL_PEERS, peer_id.as_ref(), peer_state)?;
             store_update.commit().map_err(|err| err.into())
@@ -105,6 +283,33 @@ impl PeerStore {
         }
     }
 
+    /// Changes score of the peer according to its behaviour, returns the new score.
+    /// Score is capped by `MAX_PEER_SCORE`, it's up to the caller to ban the peer if the score
+    /// drops to `BAN_PEER_SCORE`.
+    pub fn update_score(
+        &mut self,
+        peer_id: &PeerId,
+        behaviour: PeerBehaviour,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
+            peer_state.score = std::cmp::max(
+                BAN_PEER_SCORE,
+                std::cmp::min(MAX_PEER_SCORE, peer_state.score + behaviour.score_change()),
+            );
+            let mut store_update = self.store.store_update();
+            store_update.set_ser(COL_PEERS, peer_id.as_ref(), peer_state)?;
+            store_update.commit()?;
+            Ok(peer_state.score)
+        } else {
+            Err(format!("Peer {} is missing in the peer store", peer_id).into())
+        }
+    }
+
+    /// Score of the peer, unknown peers have neutral score.
+    pub fn score(&self, peer_id: &PeerId) -> i32 {
+        self.peer_states.get(peer_id).map(|peer_state| peer_state.score).unwrap_or(0)
+    }
+
     fn find_peers<F>(&self, mut filter: F, count: u32) -> Vec<PeerInfo>
     where
         F: FnMut(&KnownPeerState) -> bool,
@@ -122,23 +327,41 @@ impl PeerStore {
     }
 
     /// Return unconnected or peers with unknown status that we can try to connect to.
+    /// Takes a random peer from each bucket, either only tried or only new ones with equal chance,
+    /// so peers from a single source or address group can't dominate the selection.
     pub fn unconnected_peers(&self, ignore_list: &HashSet<PeerId>) -> Vec<PeerInfo> {
-        self.find_peers(
-            |p| {
-                (p.status == KnownPeerStatus::NotConnected || p.status == KnownPeerStatus::Unknown)
-                    && !ignore_list.contains(&p.peer_info.id)
-            },
-            0,
-        )
+        let mut rng = thread_rng();
+        let (mut tried, mut new) = (vec![], vec![]);
+        for (bucket, peer_ids) in self.buckets.iter() {
+            let peer_info = peer_ids
+                .iter()
+                .filter_map(|peer_id| self.peer_states.get(peer_id))
+                .filter(|p| {
+                    (p.status == KnownPeerStatus::NotConnected || p.status == KnownPeerStatus::Unknown)
+                        && !ignore_list.contains(&p.peer_info.id)
+                })
+                .choose(&mut rng)
+                .map(|p| p.peer_info.clone());
+            match (bucket, peer_info) {
+                (Bucket::Tried(_), Some(peer_info)) => tried.push(peer_info),
+                (Bucket::New(_), Some(peer_info)) => new.push(peer_info),
+                (_, None) => {}
+            }
+        }
+        if tried.is_empty() || (!new.is_empty() && rng.gen::<bool>()) {
+            new
+        } else {
+            tried
+        }
     }
 
-    /// Return healthy known peers up to given amount.
+    /// Return healthy known peers with verified addresses up to given amount.
     pub fn healthy_peers(&self, max_count: u32) -> Vec<PeerInfo> {
         // TODO: better healthy peer definition here.
         self.find_peers(
             |p| match p.status {
                 KnownPeerStatus::Banned(_, _) => false,
-                _ => true,
+                _ => p.addr_verified,
             },
             max_count,
         )
@@ -167,18 +390,32 @@ impl PeerStore {
         }
         let mut store_update = self.store.store_update();
         for peer_id in to_remove {
-            self.peer_states.remove(&peer_id);
+            self.remove_peer(&peer_id);
             store_update.delete(COL_PEERS, peer_id.as_ref());
         }
         store_update.commit().map_err(|err| err.into())
     }
 
-    pub fn add_peers(&mut self, mut peers: Vec<PeerInfo>) {
+    /// Adds peers received from the peer at the given address to the new buckets. Their addresses
+    /// are not trusted until we connect to them, so they are only used for dialing and are not
+    /// shared further. Peers that don't fit into their bucket are ignored.
+    pub fn add_peers(
+        &mut self,
+        mut peers: Vec<PeerInfo>,
+        source: IpAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut store_update = self.store.store_update();
         for peer_info in peers.drain(..) {
             if !self.peer_states.contains_key(&peer_info.id) {
-                self.peer_states.insert(peer_info.id, KnownPeerState::new(peer_info));
+                let peer_id = peer_info.id;
+                let mut peer_state = KnownPeerState::new(peer_info);
+                peer_state.source = Some(source);
+                if self.insert_peer(peer_id, peer_state, false, &mut store_update) {
+                    store_update.set_ser(COL_PEERS, peer_id.as_ref(), &self.peer_states[&peer_id])?;
+                }
             }
         }
+        store_update.commit().map_err(|err| err.into())
     }
 }
 
@@ -197,6 +434,14 @@ mod test {
         }
     }
 
+    fn source() -> IpAddr {
+        "1.2.3.4".parse().unwrap()
+    }
+
+    fn gen_peer_info_with_addr(addr: &str) -> PeerInfo {
+        PeerInfo { addr: Some(addr.parse().unwrap()), ..gen_peer_info() }
+    }
+
     #[test]
     fn ban_store() {
         let tmp_dir = tempdir::TempDir::new("_test_store_ban").unwrap();
@@ -216,4 +461,131 @@ mod test {
             assert_eq!(peer_store_new.healthy_peers(3).iter().count(), 1);
         }
     }
+
+    #[test]
+    fn score_store() {
+        let tmp_dir = tempdir::TempDir::new("_test_store_score").unwrap();
+        let peer_info = gen_peer_info();
+        let boot_nodes = vec![peer_info.clone()];
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &boot_nodes).unwrap();
+            assert_eq!(peer_store.score(&peer_info.id), 0);
+            assert_eq!(peer_store.update_score(&peer_info.id, PeerBehaviour::Timeout).unwrap(), -5);
+            for _ in 0..200 {
+                peer_store.update_score(&peer_info.id, PeerBehaviour::UsefulBlock).unwrap();
+            }
+            assert_eq!(peer_store.score(&peer_info.id), MAX_PEER_SCORE);
+            let misbehaviour = PeerBehaviour::Misbehaviour(ReasonForBan::BadBlock);
+            assert_eq!(peer_store.update_score(&peer_info.id, misbehaviour).unwrap(), 50);
+        }
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &boot_nodes).unwrap();
+            assert_eq!(peer_store.score(&peer_info.id), 50);
+            for _ in 0..10 {
+                peer_store.update_score(&peer_info.id, PeerBehaviour::UselessMessage).unwrap();
+            }
+            assert_eq!(peer_store.score(&peer_info.id), 40);
+            peer_store.peer_ban(&peer_info.id, ReasonForBan::LowScore).unwrap();
+            peer_store.peer_unban(&peer_info.id).unwrap();
+            assert_eq!(peer_store.score(&peer_info.id), 0);
+        }
+    }
+
+    #[test]
+    fn verify_addr_store() {
+        let tmp_dir = tempdir::TempDir::new("_test_store_verify_addr").unwrap();
+        let mut peer_info = gen_peer_info();
+        peer_info.addr = Some("127.0.0.1:24567".parse().unwrap());
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &[]).unwrap();
+            peer_store.add_peers(vec![peer_info.clone()], source()).unwrap();
+            assert_eq!(peer_store.unconnected_peers(&HashSet::new()).len(), 1);
+            assert!(peer_store.healthy_peers(3).is_empty());
+            peer_info.addr = Some("127.0.0.1:24568".parse().unwrap());
+            peer_store.peer_addr_verified(&peer_info).unwrap();
+            assert_eq!(peer_store.healthy_peers(3), vec![peer_info.clone()]);
+            // Addresses from other peers don't override the verified one.
+            let mut other_peer_info = peer_info.clone();
+            other_peer_info.addr = Some("127.0.0.2:24567".parse().unwrap());
+            peer_store.add_peers(vec![other_peer_info], source()).unwrap();
+            assert_eq!(peer_store.healthy_peers(3), vec![peer_info.clone()]);
+        }
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let peer_store = PeerStore::new(store, &[]).unwrap();
+            assert!(peer_store.is_addr_verified(&peer_info.id));
+            assert_eq!(peer_store.healthy_peers(3), vec![peer_info]);
+        }
+    }
+
+    #[test]
+    fn bounded_buckets() {
+        let tmp_dir = tempdir::TempDir::new("_test_store_buckets").unwrap();
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &[]).unwrap();
+            // Peers from a single source within a single address group fill only one bucket.
+            let peers = (0..2 * BUCKET_SIZE)
+                .map(|i| gen_peer_info_with_addr(&format!("10.0.{}.{}:24567", i / 256, i % 256)))
+                .collect::<Vec<_>>();
+            peer_store.add_peers(peers, source()).unwrap();
+            assert_eq!(peer_store.len(), BUCKET_SIZE);
+            assert_eq!(peer_store.unconnected_peers(&HashSet::new()).len(), 1);
+        }
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let peer_store = PeerStore::new(store, &[]).unwrap();
+            assert_eq!(peer_store.len(), BUCKET_SIZE);
+        }
+    }
+
+    #[test]
+    fn salt_persisted() {
+        let tmp_dir = tempdir::TempDir::new("_test_store_salt").unwrap();
+        let (salt, num_peers) = {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let mut peer_store = PeerStore::new(store, &[]).unwrap();
+            // Peers from many address groups and sources fill up most of the new buckets, so with
+            // another salt some of them wouldn't fit after the restart.
+            for i in 0..16 {
+                let peers = (0..256)
+                    .map(|j| gen_peer_info_with_addr(&format!("{}.{}.0.1:24567", 10 + i, j)))
+                    .collect::<Vec<_>>();
+                peer_store.add_peers(peers, format!("1.{}.0.1", i).parse().unwrap()).unwrap();
+            }
+            assert!(peer_store.len() > NEW_BUCKETS_COUNT * BUCKET_SIZE / 2);
+            (peer_store.salt, peer_store.len())
+        };
+        {
+            let store = create_store(tmp_dir.path().to_str().unwrap());
+            let peer_store = PeerStore::new(store, &[]).unwrap();
+            assert_eq!(peer_store.salt, salt);
+            assert_eq!(peer_store.len(), num_peers);
+        }
+    }
+
+    #[test]
+    fn connected_peers_not_evicted() {
+        let tmp_dir = tempdir::TempDir::new("_test_store_eviction").unwrap();
+        let store = create_store(tmp_dir.path().to_str().unwrap());
+        let mut peer_store = PeerStore::new(store, &[]).unwrap();
+        let peers = (0..BUCKET_SIZE)
+            .map(|i| gen_peer_info_with_addr(&format!("10.0.0.{}:24567", i)))
+            .collect::<Vec<_>>();
+        peer_store.add_peers(peers.clone(), source()).unwrap();
+        for peer_info in peers.iter() {
+            let full_peer_info = FullPeerInfo {
+                peer_info: peer_info.clone(),
+                chain_info: Default::default(),
+            };
+            peer_store.peer_connected(&full_peer_info).unwrap();
+        }
+        // The bucket is full of connected peers, so there is no room for another one.
+        peer_store.add_peers(vec![gen_peer_info_with_addr("10.0.1.1:24567")], source()).unwrap();
+        assert_eq!(peer_store.len(), BUCKET_SIZE);
+        assert!(peer_store.unconnected_peers(&HashSet::new()).is_empty());
+    }
 }
@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+/// Token bucket rate limiter: allows bursts of up to `capacity` events, refilled at `rate` events
+/// per second.
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, rate: u32) -> Self {
+        TokenBucket {
+            capacity: capacity as f64,
+            rate: rate as f64,
+            tokens: capacity as f64,
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Takes a token for a new event, returns false if there are no tokens left.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        if now > self.last_update {
+            let elapsed = now - self.last_update;
+            let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+            self.tokens = (self.tokens + elapsed_secs * self.rate).min(self.capacity);
+            self.last_update = now;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(3, 2);
+        let start = bucket.last_update;
+        for _ in 0..3 {
+            assert!(bucket.try_acquire_at(start));
+        }
+        assert!(!bucket.try_acquire_at(start));
+        // One token is refilled in half a second.
+        assert!(!bucket.try_acquire_at(start + Duration::from_millis(400)));
+        assert!(bucket.try_acquire_at(start + Duration::from_millis(600)));
+        assert!(!bucket.try_acquire_at(start + Duration::from_millis(600)));
+        // Bucket is never filled above the capacity.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire_at(later));
+        }
+        assert!(!bucket.try_acquire_at(later));
+    }
+}
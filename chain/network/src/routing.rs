@@ -0,0 +1,602 @@
+//! Routing of messages to peers and accounts we are not directly connected to.
+//!
+//! Every peer signs and gossips changes of its own connections as edges. Connection between two
+//! peers is active only while both of them announce it, so either of them can remove it and no
+//! other peer can add or remove it. Messages are forwarded along the shortest paths in the graph of
+//! active connections.
+//!
+//! Only the part of the network reachable from this peer is kept: announcements are accepted if
+//! one of the peers is reachable, and the ones between unreachable peers are dropped when routes
+//! are recalculated. Number of announcements by a single peer and in total is limited as well.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::Utc;
+use rand::seq::IteratorRandom;
+use rand::thread_rng;
+
+use near_primitives::crypto::signature::SecretKey;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::AccountId;
+
+use crate::types::{AnnounceAccount, Edge, NetworkGraphView, PeerId};
+
+/// Maximum number of requests to remember the route back for.
+const ROUTE_BACK_CACHE_SIZE: usize = 10_000;
+
+/// Maximum number of connections announced by a single peer that are kept.
+/// Announcements of closed connections are dropped first to make room for the new ones.
+pub const MAX_EDGES_PER_PEER: usize = 128;
+
+/// Maximum number of announcements kept in total, not counting the ones of this peer.
+pub const MAX_EDGES: usize = 100_000;
+
+/// Graph of connections between peers, as seen from the source peer.
+pub struct Graph {
+    source: PeerId,
+    /// Latest announcement of each peer about each of its connections.
+    edges: HashMap<(PeerId, PeerId), Edge>,
+    /// Peers each peer announced connections with, to limit the announcements of a single peer.
+    announced: HashMap<PeerId, HashSet<PeerId>>,
+}
+
+impl Graph {
+    pub fn new(source: PeerId) -> Self {
+        Graph { source, edges: HashMap::new(), announced: HashMap::new() }
+    }
+
+    /// Applies the edge if it's newer than the known announcement of the same connection by the
+    /// same peer and it fits into the limits. Returns whether the graph changed.
+    pub fn add_edge(&mut self, edge: Edge) -> bool {
+        let key = (edge.peer0, edge.peer1);
+        if let Some(known_edge) = self.edges.get(&key) {
+            if known_edge.nonce >= edge.nonce {
+                return false;
+            }
+            self.edges.insert(key, edge);
+            return true;
+        }
+        if edge.peer0 != self.source && self.edges.len() >= MAX_EDGES {
+            return false;
+        }
+        let announced = self.announced.entry(edge.peer0).or_insert_with(HashSet::new);
+        if edge.peer0 != self.source && announced.len() >= MAX_EDGES_PER_PEER {
+            let edges = &self.edges;
+            let oldest_removed = announced
+                .iter()
+                .filter_map(|peer1| edges.get(&(edge.peer0, *peer1)))
+                .filter(|known_edge| known_edge.removed)
+                .min_by_key(|known_edge| known_edge.nonce)
+                .map(|known_edge| known_edge.peer1);
+            match oldest_removed {
+                Some(peer1) => {
+                    announced.remove(&peer1);
+                    self.edges.remove(&(edge.peer0, peer1));
+                }
+                None => return false,
+            }
+        }
+        announced.insert(edge.peer1);
+        self.edges.insert(key, edge);
+        true
+    }
+
+    /// Removes the announcements of the connections between peers that are not in the given set.
+    pub fn retain_peers(&mut self, peers: &HashSet<PeerId>) {
+        let announced = &mut self.announced;
+        self.edges.retain(|(peer0, peer1), _| {
+            let retain = peers.contains(peer0) || peers.contains(peer1);
+            if !retain {
+                if let Some(announced_peers) = announced.get_mut(peer0) {
+                    announced_peers.remove(peer1);
+                }
+            }
+            retain
+        });
+        self.announced.retain(|_, peers| !peers.is_empty());
+    }
+
+    /// Nonce of the latest announcement of the connection by the first peer.
+    pub fn nonce(&self, peer0: &PeerId, peer1: &PeerId) -> Option<u64> {
+        self.edges.get(&(*peer0, *peer1)).map(|edge| edge.nonce)
+    }
+
+    /// All the known announcements, to sync with other peers.
+    pub fn edges(&self) -> Vec<Edge> {
+        self.edges.values().cloned().collect()
+    }
+
+    fn is_announced(&self, peer0: &PeerId, peer1: &PeerId) -> bool {
+        self.edges.get(&(*peer0, *peer1)).map(|edge| !edge.removed).unwrap_or(false)
+    }
+
+    /// Whether both peers announce the connection between them.
+    pub fn is_active(&self, peer0: &PeerId, peer1: &PeerId) -> bool {
+        self.is_announced(peer0, peer1) && self.is_announced(peer1, peer0)
+    }
+
+    /// Pairs of connected peers, each pair appears once.
+    pub fn active_edges(&self) -> Vec<(PeerId, PeerId)> {
+        self.edges
+            .keys()
+            .filter(|(peer0, peer1)| peer0 < peer1 && self.is_active(peer0, peer1))
+            .cloned()
+            .collect()
+    }
+
+    /// For each peer reachable from the source, returns the neighbours of the source that are the
+    /// first hop on the shortest paths to it.
+    pub fn calculate_next_hops(&self) -> HashMap<PeerId, HashSet<PeerId>> {
+        let mut adjacency: HashMap<PeerId, Vec<PeerId>> = HashMap::new();
+        for (peer0, peer1) in self.active_edges() {
+            adjacency.entry(peer0).or_insert_with(Vec::new).push(peer1);
+            adjacency.entry(peer1).or_insert_with(Vec::new).push(peer0);
+        }
+
+        let mut distances = HashMap::new();
+        let mut next_hops: HashMap<PeerId, HashSet<PeerId>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        distances.insert(self.source, 0);
+        for &neighbour in adjacency.get(&self.source).into_iter().flatten() {
+            distances.insert(neighbour, 1);
+            next_hops.insert(neighbour, vec![neighbour].into_iter().collect());
+            queue.push_back(neighbour);
+        }
+        // Peers are visited by the distance, so all the shortest paths to the peer are known
+        // before it's visited.
+        while let Some(peer_id) = queue.pop_front() {
+            let distance = distances[&peer_id] + 1;
+            let hops = next_hops[&peer_id].clone();
+            for &neighbour in adjacency.get(&peer_id).into_iter().flatten() {
+                match distances.get(&neighbour) {
+                    None => {
+                        distances.insert(neighbour, distance);
+                        next_hops.insert(neighbour, hops.clone());
+                        queue.push_back(neighbour);
+                    }
+                    Some(&known_distance) if known_distance == distance => {
+                        next_hops.get_mut(&neighbour).unwrap().extend(hops.iter().cloned());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        next_hops
+    }
+}
+
+pub enum RoutingTableUpdate {
+    NewAccount,
+    UpdatedAccount,
+    Ignore,
+}
+
+impl RoutingTableUpdate {
+    pub fn is_new(&self) -> bool {
+        match self {
+            RoutingTableUpdate::NewAccount => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct RoutingTable {
+    /// First hop and number of hops of the announcement of each account.
+    account_peers: HashMap<AccountId, (PeerId, usize)>,
+    /// Peers that own the announced accounts.
+    account_owners: HashMap<AccountId, PeerId>,
+    graph: Graph,
+    /// Next hops on the shortest paths to the peers, recalculated by `update_routes`.
+    peer_forwarding: HashMap<PeerId, HashSet<PeerId>>,
+    /// Peers reachable from this peer. Grows as edges are added and shrinks when routes are
+    /// recalculated, so it may contain peers that are no longer reachable until then.
+    reachable_peers: HashSet<PeerId>,
+    /// Whether the graph changed since the routes were calculated.
+    routes_outdated: bool,
+    /// Peers that sent us routed requests by the request hash, to route the responses back.
+    route_back: HashMap<CryptoHash, PeerId>,
+    /// Hashes in `route_back` from the oldest, to forget them when there are too many.
+    route_back_order: VecDeque<CryptoHash>,
+}
+
+impl RoutingTable {
+    pub fn new(peer_id: PeerId) -> Self {
+        Self {
+            account_peers: HashMap::new(),
+            account_owners: HashMap::new(),
+            graph: Graph::new(peer_id),
+            peer_forwarding: HashMap::new(),
+            reachable_peers: vec![peer_id].into_iter().collect(),
+            routes_outdated: false,
+            route_back: HashMap::new(),
+            route_back_order: VecDeque::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &AnnounceAccount) -> RoutingTableUpdate {
+        self.account_owners.insert(data.account_id.clone(), data.header().peer_id);
+        match self.account_peers.get(&data.account_id) {
+            // If this account id is already tracked in the routing table ...
+            Some((_, num_hops)) => {
+                // check if this connection is better than the one we keep track
+                // regarding number of intermediate hops.
+                if data.num_hops() < *num_hops {
+                    // and add it
+                    self.account_peers
+                        .insert(data.account_id.clone(), (data.peer_id_sender(), data.num_hops()));
+                    RoutingTableUpdate::UpdatedAccount
+                } else {
+                    RoutingTableUpdate::Ignore
+                }
+            }
+            // If we don't have this account id store it in the routing table.
+            None => {
+                self.account_peers
+                    .insert(data.account_id.clone(), (data.peer_id_sender(), data.num_hops()));
+                RoutingTableUpdate::NewAccount
+            }
+        }
+    }
+
+    pub fn account_peers(&self) -> &HashMap<AccountId, (PeerId, usize)> {
+        &self.account_peers
+    }
+
+    /// Next hop towards the peer that owns the account. Uses the network graph if the owner is
+    /// reachable in it, otherwise the peer the account was announced by.
+    pub fn get_route(&self, account_id: &AccountId) -> Option<PeerId> {
+        self.account_owners
+            .get(account_id)
+            .and_then(|peer_id| self.find_route(peer_id))
+            .or_else(|| self.account_peers.get(account_id).map(|(peer_id, _)| *peer_id))
+    }
+
+    /// Next hop on one of the shortest paths to the peer.
+    pub fn find_route(&self, peer_id: &PeerId) -> Option<PeerId> {
+        self.peer_forwarding
+            .get(peer_id)
+            .and_then(|next_hops| next_hops.iter().choose(&mut thread_rng()).cloned())
+    }
+
+    /// Signs and applies the change of the connection between this peer and the given one.
+    /// Returns the edge to announce to other peers.
+    pub fn update_own_edge(
+        &mut self,
+        peer_id: PeerId,
+        removed: bool,
+        secret_key: &SecretKey,
+    ) -> Edge {
+        let source = self.graph.source;
+        // Nonce keeps growing over restarts of the node.
+        let nonce = cmp::max(
+            self.graph.nonce(&source, &peer_id).map_or(0, |nonce| nonce + 1),
+            Utc::now().timestamp_millis() as u64,
+        );
+        let edge = Edge::new(source, peer_id, nonce, removed, secret_key);
+        self.add_edges(vec![edge.clone()]);
+        edge
+    }
+
+    /// Applies the edges that connect to the reachable peers, including the peers that become
+    /// reachable through the other given edges. Returns the ones that changed the graph and should
+    /// be announced further.
+    pub fn add_edges(&mut self, edges: Vec<Edge>) -> Vec<Edge> {
+        let mut edges_by_peer: HashMap<PeerId, Vec<usize>> = HashMap::new();
+        for (index, edge) in edges.iter().enumerate() {
+            edges_by_peer.entry(edge.peer0).or_insert_with(Vec::new).push(index);
+            edges_by_peer.entry(edge.peer1).or_insert_with(Vec::new).push(index);
+        }
+        let mut queued: Vec<bool> = edges
+            .iter()
+            .map(|edge| {
+                self.reachable_peers.contains(&edge.peer0)
+                    || self.reachable_peers.contains(&edge.peer1)
+            })
+            .collect();
+        let mut queue: VecDeque<usize> = (0..edges.len()).filter(|&index| queued[index]).collect();
+        let mut new_edges = vec![];
+        while let Some(index) = queue.pop_front() {
+            let edge = &edges[index];
+            if !self.graph.add_edge(edge.clone()) {
+                continue;
+            }
+            new_edges.push(edge.clone());
+            if !self.graph.is_active(&edge.peer0, &edge.peer1) {
+                continue;
+            }
+            for peer_id in [edge.peer0, edge.peer1].iter() {
+                if self.reachable_peers.insert(*peer_id) {
+                    for &other in edges_by_peer.get(peer_id).into_iter().flatten() {
+                        if !queued[other] {
+                            queued[other] = true;
+                            queue.push_back(other);
+                        }
+                    }
+                }
+            }
+        }
+        if !new_edges.is_empty() {
+            self.routes_outdated = true;
+        }
+        new_edges
+    }
+
+    /// Recalculates the routes if the graph changed since the last time and drops the edges and
+    /// the accounts of the peers that are no longer reachable. Called periodically, so bursts of
+    /// edges don't cause a recalculation each.
+    pub fn update_routes(&mut self) {
+        if !self.routes_outdated {
+            return;
+        }
+        self.peer_forwarding = self.graph.calculate_next_hops();
+        self.reachable_peers = self.peer_forwarding.keys().cloned().collect();
+        self.reachable_peers.insert(self.graph.source);
+        self.graph.retain_peers(&self.reachable_peers);
+        // Forget the announcements received through the peers that are no longer reachable, and
+        // the accounts that can't be reached through the graph either.
+        let reachable_peers = &self.reachable_peers;
+        self.account_peers.retain(|_, (peer_id, _)| reachable_peers.contains(peer_id));
+        let account_peers = &self.account_peers;
+        self.account_owners.retain(|account_id, peer_id| {
+            reachable_peers.contains(peer_id) || account_peers.contains_key(account_id)
+        });
+        self.routes_outdated = false;
+    }
+
+    pub fn edges(&self) -> Vec<Edge> {
+        self.graph.edges()
+    }
+
+    pub fn graph_view(&self) -> NetworkGraphView {
+        NetworkGraphView {
+            edges: self
+                .graph
+                .active_edges()
+                .into_iter()
+                .map(|(peer0, peer1)| (peer0.to_string(), peer1.to_string()))
+                .collect(),
+            next_hops: self
+                .peer_forwarding
+                .iter()
+                .map(|(peer_id, next_hops)| {
+                    (peer_id.to_string(), next_hops.iter().map(PeerId::to_string).collect())
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add_route_back(&mut self, hash: CryptoHash, peer_id: PeerId) {
+        if self.route_back.insert(hash, peer_id).is_none() {
+            self.route_back_order.push_back(hash);
+            if self.route_back_order.len() > ROUTE_BACK_CACHE_SIZE {
+                if let Some(oldest) = self.route_back_order.pop_front() {
+                    self.route_back.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Returns the peer to send the response to the request with given hash to.
+    /// Each request gets at most one response, so the route is forgotten.
+    pub fn take_route_back(&mut self, hash: &CryptoHash) -> Option<PeerId> {
+        self.route_back.remove(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use near_primitives::crypto::signature::{get_key_pair, sign};
+
+    use super::*;
+
+    fn gen_peers(count: usize) -> Vec<(PeerId, SecretKey)> {
+        (0..count)
+            .map(|_| {
+                let (public_key, secret_key) = get_key_pair();
+                (PeerId::from(public_key), secret_key)
+            })
+            .collect()
+    }
+
+    /// Connects two peers by the announcements of both of them.
+    fn connect(graph: &mut Graph, peers: &[(PeerId, SecretKey)], i: usize, j: usize, nonce: u64) {
+        for &(from, to) in [(i, j), (j, i)].iter() {
+            graph.add_edge(Edge::new(peers[from].0, peers[to].0, nonce, false, &peers[from].1));
+        }
+    }
+
+    fn next_hops(graph: &Graph, peers: &[(PeerId, SecretKey)], target: usize) -> Vec<PeerId> {
+        let mut hops: Vec<_> = graph
+            .calculate_next_hops()
+            .get(&peers[target].0)
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        hops.sort();
+        hops
+    }
+
+    #[test]
+    fn test_shortest_paths() {
+        let peers = gen_peers(5);
+        let mut graph = Graph::new(peers[0].0);
+        // 0 - 1 - 3 - 4
+        //  \     /
+        //   - 2 -
+        for &(i, j) in [(0, 1), (0, 2), (1, 3), (2, 3), (3, 4)].iter() {
+            connect(&mut graph, &peers, i, j, 1);
+        }
+        assert_eq!(graph.active_edges().len(), 5);
+        assert_eq!(next_hops(&graph, &peers, 1), vec![peers[1].0]);
+        let mut both = vec![peers[1].0, peers[2].0];
+        both.sort();
+        assert_eq!(next_hops(&graph, &peers, 3), both);
+        assert_eq!(next_hops(&graph, &peers, 4), both);
+        assert!(graph.calculate_next_hops().get(&peers[0].0).is_none());
+    }
+
+    #[test]
+    fn test_edge_announcements() {
+        let peers = gen_peers(3);
+        let mut graph = Graph::new(peers[0].0);
+        connect(&mut graph, &peers, 0, 1, 1);
+        // Connection announced only by one side is not active.
+        assert!(graph.add_edge(Edge::new(peers[1].0, peers[2].0, 1, false, &peers[1].1)));
+        assert_eq!(graph.active_edges().len(), 1);
+        assert!(next_hops(&graph, &peers, 2).is_empty());
+        graph.add_edge(Edge::new(peers[2].0, peers[1].0, 1, false, &peers[2].1));
+        assert_eq!(next_hops(&graph, &peers, 2), vec![peers[1].0]);
+
+        // Either side can remove the connection, old announcements are ignored.
+        assert!(graph.add_edge(Edge::new(peers[2].0, peers[1].0, 2, true, &peers[2].1)));
+        assert!(!graph.add_edge(Edge::new(peers[2].0, peers[1].0, 1, false, &peers[2].1)));
+        assert!(next_hops(&graph, &peers, 2).is_empty());
+        assert_eq!(graph.active_edges().len(), 1);
+    }
+
+    #[test]
+    fn test_edge_signature() {
+        let peers = gen_peers(2);
+        let edge = Edge::new(peers[0].0, peers[1].0, 1, false, &peers[0].1);
+        assert!(edge.verify());
+        // Peer can't announce connections of other peers.
+        assert!(!Edge::new(peers[0].0, peers[1].0, 1, false, &peers[1].1).verify());
+        let mut removed = edge.clone();
+        removed.removed = true;
+        assert!(!removed.verify());
+    }
+
+    /// Announcements of the connection by both peers.
+    fn edges(peers: &[(PeerId, SecretKey)], i: usize, j: usize, nonce: u64) -> Vec<Edge> {
+        vec![
+            Edge::new(peers[i].0, peers[j].0, nonce, false, &peers[i].1),
+            Edge::new(peers[j].0, peers[i].0, nonce, false, &peers[j].1),
+        ]
+    }
+
+    #[test]
+    fn test_routing_table_churn() {
+        let peers = gen_peers(4);
+        let mut routing_table = RoutingTable::new(peers[0].0);
+        // 0 - 1 - 3 and 0 - 2 - 3, peer 3 owns account "test".
+        for &i in [1, 2].iter() {
+            routing_table.update_own_edge(peers[i].0, false, &peers[0].1);
+            routing_table.add_edges(vec![Edge::new(peers[i].0, peers[0].0, 1, false, &peers[i].1)]);
+        }
+        for &(i, j) in [(1, 3), (2, 3)].iter() {
+            routing_table.add_edges(edges(&peers, i, j, 1));
+        }
+        routing_table.update_routes();
+        let header_hash = CryptoHash::default();
+        let signature = sign(header_hash.as_ref(), &peers[3].1);
+        let mut announce = AnnounceAccount::new(
+            "test".to_string(),
+            CryptoHash::default(),
+            peers[3].0,
+            header_hash,
+            signature,
+        );
+        announce.extend(peers[1].0, &peers[1].1);
+        routing_table.update(&announce);
+
+        // After the connection to the peer the account was announced through is closed,
+        // messages are routed through another peer.
+        routing_table.update_own_edge(peers[1].0, true, &peers[0].1);
+        routing_table.update_routes();
+        for _ in 0..10 {
+            assert_eq!(routing_table.get_route(&"test".to_string()), Some(peers[2].0));
+        }
+        // Duplicate edges don't change the graph.
+        assert!(routing_table.add_edges(routing_table.edges()).is_empty());
+    }
+
+    #[test]
+    fn test_accounts_of_unreachable_peers() {
+        let peers = gen_peers(3);
+        let mut routing_table = RoutingTable::new(peers[0].0);
+        // 0 - 1 - 2, peer 2 owns account "test".
+        routing_table.update_own_edge(peers[1].0, false, &peers[0].1);
+        routing_table.add_edges(vec![Edge::new(peers[1].0, peers[0].0, 1, false, &peers[1].1)]);
+        routing_table.add_edges(edges(&peers, 1, 2, 1));
+        routing_table.update_routes();
+        let header_hash = CryptoHash::default();
+        let signature = sign(header_hash.as_ref(), &peers[2].1);
+        let mut announce = AnnounceAccount::new(
+            "test".to_string(),
+            CryptoHash::default(),
+            peers[2].0,
+            header_hash,
+            signature,
+        );
+        announce.extend(peers[1].0, &peers[1].1);
+        assert!(routing_table.update(&announce).is_new());
+        assert_eq!(routing_table.get_route(&"test".to_string()), Some(peers[1].0));
+
+        // Once the only path to the owner is gone, the account is forgotten and a new
+        // announcement of it is relayed again.
+        routing_table.update_own_edge(peers[1].0, true, &peers[0].1);
+        routing_table.update_routes();
+        assert!(routing_table.account_peers().is_empty());
+        assert_eq!(routing_table.get_route(&"test".to_string()), None);
+        routing_table.update_own_edge(peers[1].0, false, &peers[0].1);
+        routing_table.add_edges(vec![Edge::new(peers[1].0, peers[0].0, 3, false, &peers[1].1)]);
+        routing_table.update_routes();
+        assert!(routing_table.update(&announce).is_new());
+    }
+
+    #[test]
+    fn test_edges_of_unreachable_peers() {
+        let peers = gen_peers(5);
+        let mut routing_table = RoutingTable::new(peers[0].0);
+        // Connection between peers that are not connected to this one is ignored.
+        assert!(routing_table.add_edges(edges(&peers, 3, 4, 1)).is_empty());
+
+        // Edges are applied when they connect to this peer, in any order.
+        let mut batch = edges(&peers, 2, 3, 1);
+        batch.extend(edges(&peers, 1, 2, 1));
+        batch.extend(edges(&peers, 0, 1, 1));
+        assert_eq!(routing_table.add_edges(batch).len(), 6);
+        routing_table.update_routes();
+        assert_eq!(routing_table.find_route(&peers[3].0), Some(peers[1].0));
+
+        // Once peer 1 disconnects, peers behind it are unreachable and their edges are dropped.
+        routing_table.add_edges(vec![Edge::new(peers[1].0, peers[0].0, 2, true, &peers[1].1)]);
+        routing_table.update_routes();
+        assert_eq!(routing_table.find_route(&peers[3].0), None);
+        let mut known_edges: Vec<_> =
+            routing_table.edges().into_iter().map(|edge| (edge.peer0, edge.peer1)).collect();
+        known_edges.sort();
+        let mut expected = vec![(peers[0].0, peers[1].0), (peers[1].0, peers[0].0)];
+        expected.sort();
+        assert_eq!(known_edges, expected);
+        assert!(routing_table.add_edges(edges(&peers, 2, 3, 2)).is_empty());
+    }
+
+    #[test]
+    fn test_edges_per_peer_limit() {
+        let peers = gen_peers(2);
+        let mut graph = Graph::new(peers[0].0);
+        let others = gen_peers(MAX_EDGES_PER_PEER + 1);
+        for (i, (other, _)) in others[..MAX_EDGES_PER_PEER].iter().enumerate() {
+            let removed = i % 2 == 0;
+            let nonce = i as u64 + 1;
+            assert!(graph.add_edge(Edge::new(peers[1].0, *other, nonce, removed, &peers[1].1)));
+        }
+        // Announcement of the oldest closed connection makes room for the new one.
+        let new_edge = Edge::new(peers[1].0, others[MAX_EDGES_PER_PEER].0, 1, false, &peers[1].1);
+        assert!(graph.add_edge(new_edge.clone()));
+        assert_eq!(graph.edges().len(), MAX_EDGES_PER_PEER);
+        assert!(graph.nonce(&peers[1].0, &others[0].0).is_none());
+
+        // Peer can't announce more open connections than the limit.
+        let mut graph = Graph::new(peers[0].0);
+        for (other, _) in others[..MAX_EDGES_PER_PEER].iter() {
+            assert!(graph.add_edge(Edge::new(peers[1].0, *other, 1, false, &peers[1].1)));
+        }
+        assert!(!graph.add_edge(new_edge));
+        // Known connections can still be updated.
+        assert!(graph.add_edge(Edge::new(peers[1].0, others[0].0, 2, true, &peers[1].1)));
+    }
+}
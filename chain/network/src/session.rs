@@ -0,0 +1,181 @@
+//! Authenticated encryption of the connection between peers.
+//!
+//! Right after the connection is open, both sides send their ephemeral curve25519 public keys in
+//! plain text. Keys of the session, one for each direction, are derived from the shared secret of
+//! the ephemeral keys, and all the following frames, starting with the `Handshake`, are encrypted
+//! with them. Every frame carries the counter of the frames sent before it in the nonce, so frames
+//! can't be dropped, reordered or replayed without being noticed.
+//!
+//! `Handshake` carries the signature of both ephemeral keys by the key of the sender's `PeerId`.
+//! Only the owner of the `PeerId` can make such a signature, and it's only valid in this session.
+
+use exonum_sodiumoxide::crypto::{box_, secretbox};
+
+use near_primitives::crypto::signature::{sign, verify, SecretKey, Signature};
+use near_primitives::hash::hash;
+
+use crate::types::PeerId;
+
+/// Number of bytes encryption adds to every frame.
+pub const SESSION_OVERHEAD: usize = secretbox::MACBYTES;
+
+/// Ephemeral key pair of this side of the connection, used once to establish the session.
+pub struct EphemeralKey {
+    public_key: box_::PublicKey,
+    secret_key: box_::SecretKey,
+}
+
+impl EphemeralKey {
+    pub fn new() -> Self {
+        let (public_key, secret_key) = box_::gen_keypair();
+        EphemeralKey { public_key, secret_key }
+    }
+
+    /// Public key to send to the other side.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public_key.0.to_vec()
+    }
+}
+
+/// Keys and counters of the established session.
+pub struct Session {
+    local_key: box_::PublicKey,
+    remote_key: box_::PublicKey,
+    send_key: secretbox::Key,
+    recv_key: secretbox::Key,
+    send_nonce: u64,
+    recv_nonce: u64,
+}
+
+impl Session {
+    /// Establishes session from this side's ephemeral key and the ephemeral key of the other side.
+    pub fn new(ephemeral_key: EphemeralKey, remote_key: &[u8]) -> Result<Self, String> {
+        let remote_key = box_::PublicKey::from_slice(remote_key)
+            .ok_or_else(|| format!("Invalid session key of length {}", remote_key.len()))?;
+        let local_key = ephemeral_key.public_key;
+        let shared_secret = box_::precompute(&remote_key, &ephemeral_key.secret_key);
+        let send_key = derive_key(&shared_secret, &local_key, &remote_key);
+        let recv_key = derive_key(&shared_secret, &remote_key, &local_key);
+        Ok(Session { local_key, remote_key, send_key, recv_key, send_nonce: 0, recv_nonce: 0 })
+    }
+
+    /// Signs ephemeral keys of the session to prove that this side owns the secret key.
+    pub fn sign(&self, secret_key: &SecretKey) -> Signature {
+        sign(&transcript(&self.local_key, &self.remote_key), secret_key)
+    }
+
+    /// Checks that the other side of the session owns the given peer id.
+    pub fn verify(&self, signature: &Signature, peer_id: &PeerId) -> bool {
+        verify(&transcript(&self.remote_key, &self.local_key), signature, &peer_id.public_key())
+    }
+
+    pub fn encrypt(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let nonce = nonce(self.send_nonce);
+        self.send_nonce += 1;
+        secretbox::seal(bytes, &nonce, &self.send_key)
+    }
+
+    pub fn decrypt(&mut self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let nonce = nonce(self.recv_nonce);
+        let result = secretbox::open(bytes, &nonce, &self.recv_key)
+            .map_err(|_| format!("Failed to decrypt frame {}", self.recv_nonce))?;
+        self.recv_nonce += 1;
+        Ok(result)
+    }
+}
+
+/// Key of one direction of the session: from the owner of the first key to the owner of the second.
+fn derive_key(
+    shared_secret: &box_::PrecomputedKey,
+    from: &box_::PublicKey,
+    to: &box_::PublicKey,
+) -> secretbox::Key {
+    let mut bytes = shared_secret.0.to_vec();
+    bytes.extend_from_slice(&from.0);
+    bytes.extend_from_slice(&to.0);
+    let mut key = [0; secretbox::KEYBYTES];
+    key.copy_from_slice(hash(&bytes).as_ref());
+    secretbox::Key(key)
+}
+
+/// Data signed by the owner of the first key to authenticate the session.
+fn transcript(from: &box_::PublicKey, to: &box_::PublicKey) -> Vec<u8> {
+    let mut bytes = b"near-peer-session".to_vec();
+    bytes.extend_from_slice(&from.0);
+    bytes.extend_from_slice(&to.0);
+    bytes
+}
+
+fn nonce(counter: u64) -> secretbox::Nonce {
+    let mut nonce = [0; secretbox::NONCEBYTES];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    secretbox::Nonce(nonce)
+}
+
+#[cfg(test)]
+mod test {
+    use near_primitives::crypto::signature::get_key_pair;
+
+    use super::*;
+
+    fn connect() -> (Session, Session) {
+        let (key1, key2) = (EphemeralKey::new(), EphemeralKey::new());
+        let (public_key1, public_key2) = (key1.public_key(), key2.public_key());
+        (Session::new(key1, &public_key2).unwrap(), Session::new(key2, &public_key1).unwrap())
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let (mut session1, mut session2) = connect();
+        for i in 0..3 {
+            let message = vec![i; 10];
+            let encrypted = session1.encrypt(&message);
+            assert_ne!(encrypted[..message.len()], message[..]);
+            assert_eq!(session2.decrypt(&encrypted).unwrap(), message);
+        }
+        let encrypted = session2.encrypt(b"response");
+        assert_eq!(session1.decrypt(&encrypted).unwrap(), b"response".to_vec());
+    }
+
+    #[test]
+    fn test_tampered_frames() {
+        let (mut session1, mut session2) = connect();
+        let first = session1.encrypt(b"first");
+        let second = session1.encrypt(b"second");
+        // Frames out of order.
+        assert!(session2.decrypt(&second).is_err());
+        let mut changed = first.clone();
+        changed[0] ^= 1;
+        assert!(session2.decrypt(&changed).is_err());
+        assert_eq!(session2.decrypt(&first).unwrap(), b"first".to_vec());
+        // Replayed frame.
+        assert!(session2.decrypt(&first).is_err());
+        // Frame encrypted for another direction.
+        let reflected = session2.encrypt(b"reflected");
+        assert!(session2.decrypt(&reflected).is_err());
+    }
+
+    #[test]
+    fn test_session_signature() {
+        let (alice_public_key, alice_secret_key) = get_key_pair();
+        let (_, mallory_secret_key) = get_key_pair();
+        let alice_id = PeerId::from(alice_public_key);
+
+        let (alice_session, bob_session) = connect();
+        let signature = alice_session.sign(&alice_secret_key);
+        assert!(bob_session.verify(&signature, &alice_id));
+        // Signature is only valid on the other side of the session.
+        assert!(!alice_session.verify(&signature, &alice_id));
+
+        // Mallory can't claim to be Alice with a signature by another key.
+        let (mallory_session, bob_session) = connect();
+        assert!(!bob_session.verify(&mallory_session.sign(&mallory_secret_key), &alice_id));
+        // Nor by replaying Alice's signature from another session.
+        assert!(!bob_session.verify(&signature, &alice_id));
+    }
+
+    #[test]
+    fn test_invalid_session_key() {
+        assert!(Session::new(EphemeralKey::new(), &[0; 10]).is_err());
+    }
+}
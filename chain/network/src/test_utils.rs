@@ -1,14 +1,23 @@
+use std::collections::BTreeMap;
 use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
-use actix::{Actor, AsyncContext, Context, System};
+use actix::{Actor, AsyncContext, Context, Handler, Recipient, System};
 use futures::future::Future;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use tokio::timer::Delay;
 
 use near_primitives::crypto::signature::get_key_pair;
 use near_primitives::test_utils::get_key_pair_from_seed;
+use near_primitives::types::AccountId;
 
-use crate::types::{NetworkConfig, PeerInfo};
+use crate::types::{
+    FullPeerInfo, MessageLimits, NetworkClientMessages, NetworkClientResponses, NetworkConfig,
+    NetworkInfo, NetworkRequests, NetworkResponses, PeerChainInfo, PeerId, PeerInfo,
+    ROUTED_MESSAGE_TTL,
+};
 use futures::future;
 
 /// Returns available port.
@@ -38,6 +47,9 @@ impl NetworkConfig {
             peer_expiration_duration: Duration::from_secs(60 * 60),
             max_send_peers: 512,
             peer_stats_period: Duration::from_secs(5),
+            external_address: None,
+            message_limits: MessageLimits::default(),
+            routed_message_ttl: ROUTED_MESSAGE_TTL,
         }
     }
 }
@@ -128,3 +140,396 @@ impl Actor for WaitOrTimeout {
         self.wait_or_timeout(ctx);
     }
 }
+
+/// Parameters of the simulated network.
+#[derive(Clone, Debug)]
+pub struct SimulatorConfig {
+    /// Seed of the random generator that decides latencies and drops of messages.
+    pub seed: u64,
+    /// Minimum latency of a message in milliseconds of virtual time.
+    pub min_latency_ms: u64,
+    /// Maximum latency of a message in milliseconds of virtual time.
+    pub max_latency_ms: u64,
+    /// Probability that a message is dropped.
+    pub drop_probability: f64,
+    /// Real time between advances of the virtual clock.
+    pub tick_period: Duration,
+    /// Milliseconds of virtual time passing on each tick.
+    pub tick_ms: u64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            seed: 0,
+            min_latency_ms: 10,
+            max_latency_ms: 50,
+            drop_probability: 0.0,
+            tick_period: Duration::from_millis(10),
+            tick_ms: 10,
+        }
+    }
+}
+
+struct SimulatedNode {
+    peer_info: PeerInfo,
+    client: Option<Recipient<NetworkClientMessages>>,
+    /// Latest chain information announced by the node.
+    chain_info: PeerChainInfo,
+    /// Nodes can only talk to the nodes in the same partition.
+    partition: usize,
+}
+
+/// Message on its way to the client of the node.
+struct Delivery {
+    from: usize,
+    to: usize,
+    msg: NetworkClientMessages,
+}
+
+struct SimulatorState {
+    config: SimulatorConfig,
+    rng: StdRng,
+    /// Current virtual time in milliseconds.
+    now: u64,
+    nodes: Vec<SimulatedNode>,
+    /// Messages in flight by the virtual time of delivery and the order they were sent in.
+    queue: BTreeMap<(u64, u64), Delivery>,
+    num_sent: u64,
+}
+
+impl SimulatorState {
+    fn is_connected(&self, from: usize, to: usize) -> bool {
+        from != to && self.nodes[from].partition == self.nodes[to].partition
+    }
+
+    fn node_by_peer_id(&self, peer_id: &PeerId) -> Option<usize> {
+        self.nodes.iter().position(|node| &node.peer_info.id == peer_id)
+    }
+
+    fn node_by_account_id(&self, account_id: &AccountId) -> Option<usize> {
+        self.nodes.iter().position(|node| node.peer_info.account_id.as_ref() == Some(account_id))
+    }
+
+    /// Schedules delivery of the message after random latency, unless it's dropped.
+    fn send(&mut self, from: usize, to: usize, msg: NetworkClientMessages) {
+        if !self.is_connected(from, to) || self.rng.gen::<f64>() < self.config.drop_probability {
+            return;
+        }
+        let latency =
+            self.rng.gen_range(self.config.min_latency_ms, self.config.max_latency_ms + 1);
+        self.num_sent += 1;
+        self.queue.insert((self.now + latency, self.num_sent), Delivery { from, to, msg });
+    }
+
+    fn broadcast<F>(&mut self, from: usize, msg: F)
+    where
+        F: Fn(PeerId) -> NetworkClientMessages,
+    {
+        let peer_id = self.nodes[from].peer_info.id;
+        for to in 0..self.nodes.len() {
+            self.send(from, to, msg(peer_id));
+        }
+    }
+
+    fn update_chain_info(&mut self, from: usize, height: u64, total_weight: near_chain::Weight) {
+        let chain_info = &mut self.nodes[from].chain_info;
+        if total_weight > chain_info.total_weight {
+            chain_info.height = height;
+            chain_info.total_weight = total_weight;
+        }
+    }
+
+    fn network_info(&self, index: usize) -> NetworkInfo {
+        let active_peers = (0..self.nodes.len())
+            .filter(|to| self.is_connected(index, *to) && self.nodes[*to].client.is_some())
+            .map(|to| FullPeerInfo {
+                peer_info: self.nodes[to].peer_info.clone(),
+                chain_info: self.nodes[to].chain_info,
+            })
+            .collect::<Vec<_>>();
+        let max_weight = active_peers.iter().map(|peer| peer.chain_info.total_weight).max();
+        NetworkInfo {
+            num_active_peers: active_peers.len(),
+            peer_max_count: self.nodes.len() as u32,
+            most_weight_peers: active_peers
+                .iter()
+                .filter(|peer| Some(peer.chain_info.total_weight) == max_weight)
+                .cloned()
+                .collect(),
+            sent_bytes_per_sec: 0,
+            received_bytes_per_sec: 0,
+            routes: None,
+            graph: None,
+        }
+    }
+
+    /// Handles request of the node the same way `PeerManagerActor` does, but delivers the
+    /// messages to the clients of other nodes directly.
+    fn handle_request(&mut self, from: usize, request: NetworkRequests) -> NetworkResponses {
+        match request {
+            NetworkRequests::FetchInfo { .. } => {
+                return NetworkResponses::Info(self.network_info(from))
+            }
+            NetworkRequests::Block { block } => {
+                self.update_chain_info(from, block.header.height, block.header.total_weight);
+                self.broadcast(from, |peer_id| {
+                    NetworkClientMessages::Block(block.clone(), peer_id, false)
+                });
+            }
+            NetworkRequests::BlockHeaderAnnounce { header, approval } => {
+                self.update_chain_info(from, header.height, header.total_weight);
+                if let Some(approval) = approval {
+                    let account_id = self.nodes[from].peer_info.account_id.clone();
+                    if let (Some(account_id), Some(to)) =
+                        (account_id, self.node_by_account_id(&approval.target))
+                    {
+                        let msg = NetworkClientMessages::BlockApproval(
+                            account_id,
+                            approval.hash,
+                            approval.signature,
+                        );
+                        self.send(from, to, msg);
+                    }
+                }
+                self.broadcast(from, |peer_id| {
+                    NetworkClientMessages::BlockHeader(header.clone(), peer_id)
+                });
+            }
+            NetworkRequests::BlockRequest { hash, peer_id } => {
+                if let Some(to) = self.node_by_peer_id(&peer_id) {
+                    self.send(from, to, NetworkClientMessages::BlockRequest(hash));
+                }
+            }
+            NetworkRequests::BlockHeadersRequest { hashes, peer_id } => {
+                if let Some(to) = self.node_by_peer_id(&peer_id) {
+                    self.send(from, to, NetworkClientMessages::BlockHeadersRequest(hashes));
+                }
+            }
+            NetworkRequests::StateRequest { shard_id, hash, need_header, parts_ids, peer_id } => {
+                if let Some(to) = self.node_by_peer_id(&peer_id) {
+                    let msg =
+                        NetworkClientMessages::StateRequest(shard_id, hash, need_header, parts_ids);
+                    self.send(from, to, msg);
+                }
+            }
+            NetworkRequests::StateRequestToAccount {
+                shard_id,
+                hash,
+                need_header,
+                parts_ids,
+                account_id,
+            } => {
+                if let Some(to) = self.node_by_account_id(&account_id) {
+                    let msg =
+                        NetworkClientMessages::StateRequest(shard_id, hash, need_header, parts_ids);
+                    self.send(from, to, msg);
+                }
+            }
+            NetworkRequests::ForwardTx { account_ids, transaction } => {
+                let targets = account_ids
+                    .iter()
+                    .filter_map(|account_id| self.node_by_account_id(account_id))
+                    .filter(|to| self.is_connected(from, *to))
+                    .collect::<Vec<_>>();
+                if targets.is_empty() {
+                    self.broadcast(from, |_| {
+                        NetworkClientMessages::Transaction(transaction.clone())
+                    });
+                }
+                for to in targets {
+                    self.send(from, to, NetworkClientMessages::Transaction(transaction.clone()));
+                }
+            }
+            NetworkRequests::BanPeer { .. }
+            | NetworkRequests::ReportPeer { .. }
+            | NetworkRequests::AnnounceAccount(_) => {}
+        }
+        NetworkResponses::NoResponse
+    }
+}
+
+/// Network of in-process nodes that replaces `PeerManagerActor` in tests. Messages between the
+/// clients are delivered by a virtual clock with latencies and drops decided by a random generator
+/// with the given seed, and nodes can be split into partitions that can't talk to each other.
+///
+/// # Example
+///
+/// ```ignore
+/// let simulator = NetworkSimulator::new(SimulatorConfig::default(), peers);
+/// // Create client of each node with `simulator.peer_manager(i).start().recipient()` as its
+/// // network and register it with `simulator.set_client(i, client.recipient())`.
+/// simulator.start();
+/// simulator.partition(&[&[0, 1], &[2, 3]]);
+/// ```
+#[derive(Clone)]
+pub struct NetworkSimulator {
+    state: Arc<RwLock<SimulatorState>>,
+    /// Called with the virtual time after each advance of the clock.
+    tick_handlers: Arc<RwLock<Vec<Box<dyn Fn(u64)>>>>,
+}
+
+impl NetworkSimulator {
+    /// Creates network of nodes with given peer infos. Account ids in the peer infos are used to
+    /// deliver messages to validators.
+    pub fn new(config: SimulatorConfig, peers: Vec<PeerInfo>) -> Self {
+        let nodes = peers
+            .into_iter()
+            .map(|peer_info| SimulatedNode {
+                peer_info,
+                client: None,
+                chain_info: PeerChainInfo::default(),
+                partition: 0,
+            })
+            .collect();
+        let state = SimulatorState {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            now: 0,
+            nodes,
+            queue: BTreeMap::new(),
+            num_sent: 0,
+        };
+        NetworkSimulator {
+            state: Arc::new(RwLock::new(state)),
+            tick_handlers: Arc::new(RwLock::new(vec![])),
+        }
+    }
+
+    /// Peer manager of the node with given index, to be passed to its client.
+    pub fn peer_manager(&self, index: usize) -> SimulatedPeerManager {
+        SimulatedPeerManager { index, state: self.state.clone() }
+    }
+
+    /// Registers client of the node with given index, so it starts receiving messages.
+    pub fn set_client(&self, index: usize, client: Recipient<NetworkClientMessages>) {
+        self.state.write().unwrap().nodes[index].client = Some(client);
+    }
+
+    /// Starts advancing the virtual clock.
+    pub fn start(&self) {
+        SimulatorClock { simulator: self.clone() }.start();
+    }
+
+    /// Current virtual time in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.state.read().unwrap().now
+    }
+
+    /// Registers callback to run with the virtual time after each advance of the clock, once the
+    /// messages that are due are delivered. Used to drive timers of the nodes by the same clock.
+    pub fn on_tick<F: Fn(u64) + 'static>(&self, f: F) {
+        self.tick_handlers.write().unwrap().push(Box::new(f));
+    }
+
+    /// Splits the nodes into groups that can't talk to each other. Nodes not in any of the groups
+    /// are isolated. Messages in flight between different groups are lost.
+    pub fn partition(&self, groups: &[&[usize]]) {
+        for (index, node) in self.state.write().unwrap().nodes.iter_mut().enumerate() {
+            node.partition = groups
+                .iter()
+                .position(|group| group.contains(&index))
+                .unwrap_or(groups.len() + index);
+        }
+    }
+
+    /// Joins all the nodes back into a single network.
+    pub fn heal(&self) {
+        for node in self.state.write().unwrap().nodes.iter_mut() {
+            node.partition = 0;
+        }
+    }
+
+    /// Advances the virtual clock, delivers the messages that are due and runs the tick handlers.
+    pub fn advance(&self, ms: u64) {
+        let (now, due) = {
+            let mut state = self.state.write().unwrap();
+            state.now += ms;
+            let keys = state
+                .queue
+                .range(..=(state.now, std::u64::MAX))
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>();
+            let due =
+                keys.into_iter().filter_map(|key| state.queue.remove(&key)).collect::<Vec<_>>();
+            (state.now, due)
+        };
+        for delivery in due {
+            self.deliver(delivery);
+        }
+        for handler in self.tick_handlers.read().unwrap().iter() {
+            handler(now);
+        }
+    }
+
+    /// Passes the message to the client and sends its response back to the author.
+    fn deliver(&self, delivery: Delivery) {
+        let Delivery { from, to, msg } = delivery;
+        let client = {
+            let state = self.state.read().unwrap();
+            if !state.is_connected(from, to) {
+                return;
+            }
+            match state.nodes[to].client.clone() {
+                Some(client) => client,
+                None => return,
+            }
+        };
+        let state = self.state.clone();
+        actix::spawn(client.send(msg).then(move |res| {
+            let mut state = state.write().unwrap();
+            let peer_id = state.nodes[to].peer_info.id;
+            let response = match res {
+                Ok(NetworkClientResponses::Block(block)) => {
+                    Some(NetworkClientMessages::Block(block, peer_id, true))
+                }
+                Ok(NetworkClientResponses::BlockHeaders(headers)) => {
+                    Some(NetworkClientMessages::BlockHeaders(headers, peer_id))
+                }
+                Ok(NetworkClientResponses::StateResponse(info)) => {
+                    Some(NetworkClientMessages::StateResponse(info, peer_id))
+                }
+                _ => None,
+            };
+            if let Some(response) = response {
+                state.send(to, from, response);
+            }
+            future::result(Ok(()))
+        }));
+    }
+}
+
+/// Replaces `PeerManagerActor` of a single node in the simulated network.
+pub struct SimulatedPeerManager {
+    index: usize,
+    state: Arc<RwLock<SimulatorState>>,
+}
+
+impl Actor for SimulatedPeerManager {
+    type Context = Context<Self>;
+}
+
+impl Handler<NetworkRequests> for SimulatedPeerManager {
+    type Result = NetworkResponses;
+
+    fn handle(&mut self, msg: NetworkRequests, _ctx: &mut Context<Self>) -> Self::Result {
+        self.state.write().unwrap().handle_request(self.index, msg)
+    }
+}
+
+/// Advances the virtual clock of the simulator periodically.
+struct SimulatorClock {
+    simulator: NetworkSimulator,
+}
+
+impl Actor for SimulatorClock {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        let tick_period = self.simulator.state.read().unwrap().config.tick_period;
+        ctx.run_interval(tick_period, |act, _ctx| {
+            let tick_ms = act.simulator.state.read().unwrap().config.tick_ms;
+            act.simulator.advance(tick_ms);
+        });
+    }
+}
@@ -1,34 +1,44 @@
+use std::cmp;
 use std::convert::From;
 use std::convert::{Into, TryFrom, TryInto};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
 use actix::dev::{MessageResponse, ResponseChannel};
 use actix::{Actor, Addr, Message};
 use chrono::{DateTime, Utc};
 use protobuf::well_known_types::UInt32Value;
-use protobuf::{RepeatedField, SingularPtrField};
+use protobuf::{Message as ProtoMessage, RepeatedField, SingularPtrField};
 use serde_derive::{Deserialize, Serialize};
 use tokio::net::TcpStream;
 
-use near_chain::{Block, BlockApproval, BlockHeader, Weight};
-use near_primitives::crypto::signature::{sign, PublicKey, SecretKey, Signature};
+use near_chain::{
+    Block, BlockApproval, BlockHeader, ShardStateSyncResponse, ShardStateSyncResponseHeader, Weight,
+};
+use near_primitives::crypto::signature::{
+    sign, verify, PublicKey, SecretKey, Signature, DEFAULT_SIGNATURE,
+};
+use near_primitives::crypto::signer::announce_account_hash;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::logging::pretty_str;
 use near_primitives::serialize::{BaseEncode, Decode};
-use near_primitives::transaction::{ReceiptTransaction, SignedTransaction};
+use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::{AccountId, BlockIndex, ShardId};
 use near_primitives::utils::{proto_to_type, to_string_value};
 use near_protos::network as network_proto;
+use near_store::StatePart;
 
 use crate::peer::Peer;
 use std::collections::HashMap;
 
 /// Current latest version of the protocol
-pub const PROTOCOL_VERSION: u32 = 2;
+pub const PROTOCOL_VERSION: u32 = 6;
+
+/// Default number of hops routed messages can make before they are dropped.
+pub const ROUTED_MESSAGE_TTL: u32 = 100;
 
 /// Peer id is the public key.
 #[derive(Copy, Clone, Eq, PartialOrd, Ord, PartialEq, Serialize, Deserialize)]
@@ -219,11 +229,29 @@ pub struct Handshake {
     pub listen_port: Option<u16>,
     /// Peer's chain information.
     pub chain_info: PeerChainInfo,
+    /// Signature of the connection session by the key of the peer id.
+    pub signature: Signature,
+    /// Address of the receiver as the sender sees it, so the receiver can learn its external
+    /// address.
+    pub observed_address: Option<SocketAddr>,
 }
 
 impl Handshake {
-    pub fn new(peer_id: PeerId, listen_port: Option<u16>, chain_info: PeerChainInfo) -> Self {
-        Handshake { version: PROTOCOL_VERSION, peer_id, listen_port, chain_info }
+    pub fn new(
+        peer_id: PeerId,
+        listen_port: Option<u16>,
+        chain_info: PeerChainInfo,
+        signature: Signature,
+        observed_address: Option<SocketAddr>,
+    ) -> Self {
+        Handshake {
+            version: PROTOCOL_VERSION,
+            peer_id,
+            listen_port,
+            chain_info,
+            signature,
+            observed_address,
+        }
     }
 }
 
@@ -234,7 +262,17 @@ impl TryFrom<network_proto::Handshake> for Handshake {
         let listen_port = proto.listen_port.into_option().map(|v| v.value as u16);
         let peer_id: PublicKey = proto.peer_id.try_into().map_err(|e| format!("{}", e))?;
         let chain_info = proto_to_type(proto.chain_info)?;
-        Ok(Handshake { version: proto.version, peer_id: peer_id.into(), listen_port, chain_info })
+        let signature: Signature = proto.signature.try_into().map_err(|e| format!("{}", e))?;
+        let observed_address =
+            proto.observed_address.into_option().and_then(|s| s.value.parse::<SocketAddr>().ok());
+        Ok(Handshake {
+            version: proto.version,
+            peer_id: peer_id.into(),
+            listen_port,
+            chain_info,
+            signature,
+            observed_address,
+        })
     }
 }
 
@@ -250,6 +288,10 @@ impl From<Handshake> for network_proto::Handshake {
             peer_id: handshake.peer_id.into(),
             listen_port,
             chain_info: SingularPtrField::some(handshake.chain_info.into()),
+            signature: handshake.signature.into(),
+            observed_address: SingularPtrField::from_option(
+                handshake.observed_address.map(|addr| to_string_value(format!("{}", addr))),
+            ),
             ..Default::default()
         }
     }
@@ -321,7 +363,7 @@ impl AnnounceAccount {
         peer_id: &PeerId,
         epoch: CryptoHash,
     ) -> CryptoHash {
-        hash([account_id.as_bytes(), peer_id.as_ref(), epoch.as_ref()].concat().as_slice())
+        announce_account_hash(account_id, peer_id.as_ref(), &epoch)
     }
 
     pub fn header_hash(&self) -> CryptoHash {
@@ -385,6 +427,307 @@ impl From<AnnounceAccount> for network_proto::AnnounceAccount {
     }
 }
 
+/// Change of the connection between two peers, announced and signed by the first of them.
+/// Connection is only considered active while both peers announce it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Edge {
+    /// Peer that announces the change.
+    pub peer0: PeerId,
+    pub peer1: PeerId,
+    /// Orders announcements of the same connection by the same peer, the latest one is in effect.
+    pub nonce: u64,
+    /// Whether the connection was closed.
+    pub removed: bool,
+    /// Signature of the edge hash by the announcing peer.
+    pub signature: Signature,
+}
+
+impl Edge {
+    pub fn new(
+        peer0: PeerId,
+        peer1: PeerId,
+        nonce: u64,
+        removed: bool,
+        secret_key: &SecretKey,
+    ) -> Self {
+        let hash = Edge::build_hash(&peer0, &peer1, nonce, removed);
+        let signature = sign(hash.as_ref(), secret_key);
+        Edge { peer0, peer1, nonce, removed, signature }
+    }
+
+    pub fn build_hash(peer0: &PeerId, peer1: &PeerId, nonce: u64, removed: bool) -> CryptoHash {
+        hash(
+            [peer0.as_ref(), peer1.as_ref(), &nonce.to_le_bytes(), &[removed as u8]]
+                .concat()
+                .as_slice(),
+        )
+    }
+
+    /// Checks that the edge was signed by the announcing peer.
+    pub fn verify(&self) -> bool {
+        let hash = Edge::build_hash(&self.peer0, &self.peer1, self.nonce, self.removed);
+        verify(hash.as_ref(), &self.signature, &self.peer0.public_key())
+    }
+}
+
+impl TryFrom<network_proto::Edge> for Edge {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(proto: network_proto::Edge) -> Result<Self, Self::Error> {
+        let peer0: PeerId = proto.peer0.try_into().map_err(|e| format!("{}", e))?;
+        let peer1: PeerId = proto.peer1.try_into().map_err(|e| format!("{}", e))?;
+        let signature: Signature = proto.signature.try_into().map_err(|e| format!("{}", e))?;
+        Ok(Edge { peer0, peer1, nonce: proto.nonce, removed: proto.removed, signature })
+    }
+}
+
+impl From<Edge> for network_proto::Edge {
+    fn from(edge: Edge) -> network_proto::Edge {
+        network_proto::Edge {
+            peer0: edge.peer0.into(),
+            peer1: edge.peer1.into(),
+            nonce: edge.nonce,
+            removed: edge.removed,
+            signature: edge.signature.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Response to the state request for the shard at the given block.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct StateResponseInfo {
+    pub shard_id: ShardId,
+    pub hash: CryptoHash,
+    pub shard_state: ShardStateSyncResponse,
+}
+
+impl TryFrom<network_proto::StateResponse> for StateResponseInfo {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(proto: network_proto::StateResponse) -> Result<Self, Self::Error> {
+        let header = match proto.header.into_option() {
+            Some(header) => Some(ShardStateSyncResponseHeader {
+                part_boundaries: header.part_boundaries.into_vec(),
+                total_size: header.total_size,
+                receipts: header
+                    .receipts
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, _>>()?,
+            }),
+            None => None,
+        };
+        let parts = proto
+            .parts
+            .into_iter()
+            .map(|part| StatePart {
+                part_id: part.part_id,
+                items: part.items.into_iter().map(|item| (item.key, item.value)).collect(),
+                proof: part.proof.into_vec(),
+            })
+            .collect();
+        Ok(StateResponseInfo {
+            shard_id: proto.shard_id,
+            hash: proto.hash.try_into()?,
+            shard_state: ShardStateSyncResponse { header, parts },
+        })
+    }
+}
+
+impl From<StateResponseInfo> for network_proto::StateResponse {
+    fn from(info: StateResponseInfo) -> network_proto::StateResponse {
+        let header = SingularPtrField::from_option(info.shard_state.header.map(|header| {
+            network_proto::StateResponseHeader {
+                part_boundaries: RepeatedField::from_vec(header.part_boundaries),
+                total_size: header.total_size,
+                receipts: RepeatedField::from_iter(
+                    header.receipts.into_iter().map(std::convert::Into::into),
+                ),
+                ..Default::default()
+            }
+        }));
+        let parts = RepeatedField::from_iter(info.shard_state.parts.into_iter().map(|part| {
+            network_proto::StatePart {
+                part_id: part.part_id,
+                items: RepeatedField::from_iter(part.items.into_iter().map(|(key, value)| {
+                    network_proto::StatePartItem { key, value, ..Default::default() }
+                })),
+                proof: RepeatedField::from_vec(part.proof),
+                ..Default::default()
+            }
+        }));
+        network_proto::StateResponse {
+            shard_id: info.shard_id,
+            hash: info.hash.into(),
+            header,
+            parts,
+            ..Default::default()
+        }
+    }
+}
+
+/// Destination of the routed message.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RoutedMessageTarget {
+    /// Validator with given account id, reached through the peers that announced the account.
+    AccountId(AccountId),
+    /// Author of the request with given hash, reached back along the path of the request.
+    Hash(CryptoHash),
+}
+
+/// Messages that can be routed to peers we are not directly connected to.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RoutedMessageBody {
+    BlockApproval(AccountId, CryptoHash, Signature),
+    StateRequest(ShardId, CryptoHash, bool, Vec<u64>),
+    StateResponse(StateResponseInfo),
+    /// Transaction forwarded to the block producer that can include it.
+    ForwardTx(SignedTransaction),
+}
+
+/// Message forwarded hop by hop until it reaches its target.
+/// Hash of the message identifies the request, so the response can be routed back to its author.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RoutedMessage {
+    pub target: RoutedMessageTarget,
+    /// Peer that created the message.
+    pub author: PeerId,
+    /// Signature of the message hash by the author.
+    pub signature: Signature,
+    /// Number of hops the message can still make before it's dropped.
+    pub ttl: u32,
+    pub body: RoutedMessageBody,
+}
+
+impl RoutedMessage {
+    pub fn new(
+        target: RoutedMessageTarget,
+        author: PeerId,
+        secret_key: &SecretKey,
+        ttl: u32,
+        body: RoutedMessageBody,
+    ) -> Self {
+        let mut msg = RoutedMessage { target, author, signature: DEFAULT_SIGNATURE, ttl, body };
+        msg.signature = sign(msg.hash().as_ref(), secret_key);
+        msg
+    }
+
+    /// Hash of the message, which doesn't depend on the signature and the hops made so far.
+    pub fn hash(&self) -> CryptoHash {
+        let mut proto: network_proto::RoutedMessage = self.clone().into();
+        proto.signature = vec![];
+        proto.ttl = 0;
+        hash(&proto.write_to_bytes().expect("Failed to serialize"))
+    }
+
+    /// Checks that the message was signed by its author.
+    pub fn verify(&self) -> bool {
+        verify(self.hash().as_ref(), &self.signature, &self.author.public_key())
+    }
+
+    /// Whether the target is expected to route the response back to the author.
+    pub fn expects_response(&self) -> bool {
+        match self.body {
+            RoutedMessageBody::StateRequest(_, _, _, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<network_proto::RoutedMessage> for RoutedMessage {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(proto: network_proto::RoutedMessage) -> Result<Self, Self::Error> {
+        let target = match proto.target {
+            Some(network_proto::RoutedMessage_oneof_target::account_id(account_id)) => {
+                RoutedMessageTarget::AccountId(account_id)
+            }
+            Some(network_proto::RoutedMessage_oneof_target::hash(hash)) => {
+                RoutedMessageTarget::Hash(hash.try_into()?)
+            }
+            None => return Err(format!("Unexpected empty routed message target").into()),
+        };
+        let body = match proto.body {
+            Some(network_proto::RoutedMessage_oneof_body::block_approval(block_approval)) => {
+                RoutedMessageBody::BlockApproval(
+                    block_approval.account_id,
+                    block_approval.hash.try_into()?,
+                    block_approval.signature.try_into()?,
+                )
+            }
+            Some(network_proto::RoutedMessage_oneof_body::state_request(state_request)) => {
+                RoutedMessageBody::StateRequest(
+                    state_request.shard_id,
+                    state_request.hash.try_into()?,
+                    state_request.need_header,
+                    state_request.part_ids,
+                )
+            }
+            Some(network_proto::RoutedMessage_oneof_body::state_response(state_response)) => {
+                RoutedMessageBody::StateResponse(state_response.try_into()?)
+            }
+            Some(network_proto::RoutedMessage_oneof_body::forward_tx(transaction)) => {
+                RoutedMessageBody::ForwardTx(transaction.try_into()?)
+            }
+            None => return Err(format!("Unexpected empty routed message body").into()),
+        };
+        let author: PeerId = proto.author.try_into().map_err(|e| format!("{}", e))?;
+        let signature: Signature = proto.signature.try_into().map_err(|e| format!("{}", e))?;
+        Ok(RoutedMessage { target, author, signature, ttl: proto.ttl, body })
+    }
+}
+
+impl From<RoutedMessage> for network_proto::RoutedMessage {
+    fn from(msg: RoutedMessage) -> network_proto::RoutedMessage {
+        let target = match msg.target {
+            RoutedMessageTarget::AccountId(account_id) => {
+                network_proto::RoutedMessage_oneof_target::account_id(account_id)
+            }
+            RoutedMessageTarget::Hash(hash) => {
+                network_proto::RoutedMessage_oneof_target::hash(hash.into())
+            }
+        };
+        let body = match msg.body {
+            RoutedMessageBody::BlockApproval(account_id, hash, signature) => {
+                network_proto::RoutedMessage_oneof_body::block_approval(
+                    network_proto::BlockApproval {
+                        account_id,
+                        hash: hash.into(),
+                        signature: signature.into(),
+                        ..Default::default()
+                    },
+                )
+            }
+            RoutedMessageBody::StateRequest(shard_id, hash, need_header, part_ids) => {
+                network_proto::RoutedMessage_oneof_body::state_request(
+                    network_proto::StateRequest {
+                        shard_id,
+                        hash: hash.into(),
+                        need_header,
+                        part_ids,
+                        ..Default::default()
+                    },
+                )
+            }
+            RoutedMessageBody::StateResponse(info) => {
+                network_proto::RoutedMessage_oneof_body::state_response(info.into())
+            }
+            RoutedMessageBody::ForwardTx(transaction) => {
+                network_proto::RoutedMessage_oneof_body::forward_tx(transaction.into())
+            }
+        };
+        network_proto::RoutedMessage {
+            target: Some(target),
+            author: msg.author.into(),
+            signature: msg.signature.into(),
+            ttl: msg.ttl,
+            body: Some(body),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum PeerMessage {
     Handshake(Handshake),
@@ -402,32 +745,46 @@ pub enum PeerMessage {
 
     Transaction(SignedTransaction),
 
-    StateRequest(ShardId, CryptoHash),
-    StateResponse(ShardId, CryptoHash, Vec<u8>, Vec<ReceiptTransaction>),
+    /// Request for the state of the shard at the given block: whether the header is needed
+    /// and which parts of the state to send.
+    StateRequest(ShardId, CryptoHash, bool, Vec<u64>),
+    StateResponse(StateResponseInfo),
 
     AnnounceAccount(AnnounceAccount),
+
+    Routed(RoutedMessage),
+    Edges(Vec<Edge>),
 }
 
-impl fmt::Display for PeerMessage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl PeerMessage {
+    /// Name of the message type, used for logging and to look up limits of the message.
+    pub fn message_type(&self) -> &'static str {
         match self {
-            PeerMessage::Handshake(_) => f.write_str("Handshake"),
-            PeerMessage::PeersRequest => f.write_str("PeersRequest"),
-            PeerMessage::PeersResponse(_) => f.write_str("PeersResponse"),
-            PeerMessage::BlockHeadersRequest(_) => f.write_str("BlockHeaderRequest"),
-            PeerMessage::BlockHeaders(_) => f.write_str("BlockHeaders"),
-            PeerMessage::BlockHeaderAnnounce(_) => f.write_str("BlockHeaderAnnounce"),
-            PeerMessage::BlockRequest(_) => f.write_str("BlockRequest"),
-            PeerMessage::Block(_) => f.write_str("Block"),
-            PeerMessage::BlockApproval(_, _, _) => f.write_str("BlockApproval"),
-            PeerMessage::Transaction(_) => f.write_str("Transaction"),
-            PeerMessage::StateRequest(_, _) => f.write_str("StateRequest"),
-            PeerMessage::StateResponse(_, _, _, _) => f.write_str("StateResponse"),
-            PeerMessage::AnnounceAccount(_) => f.write_str("AnnounceAccount"),
+            PeerMessage::Handshake(_) => "Handshake",
+            PeerMessage::PeersRequest => "PeersRequest",
+            PeerMessage::PeersResponse(_) => "PeersResponse",
+            PeerMessage::BlockHeadersRequest(_) => "BlockHeaderRequest",
+            PeerMessage::BlockHeaders(_) => "BlockHeaders",
+            PeerMessage::BlockHeaderAnnounce(_) => "BlockHeaderAnnounce",
+            PeerMessage::BlockRequest(_) => "BlockRequest",
+            PeerMessage::Block(_) => "Block",
+            PeerMessage::BlockApproval(_, _, _) => "BlockApproval",
+            PeerMessage::Transaction(_) => "Transaction",
+            PeerMessage::StateRequest(_, _, _, _) => "StateRequest",
+            PeerMessage::StateResponse(_) => "StateResponse",
+            PeerMessage::AnnounceAccount(_) => "AnnounceAccount",
+            PeerMessage::Routed(_) => "Routed",
+            PeerMessage::Edges(_) => "Edges",
         }
     }
 }
 
+impl fmt::Display for PeerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message_type())
+    }
+}
+
 impl TryFrom<network_proto::PeerMessage> for PeerMessage {
     type Error = Box<dyn std::error::Error>;
 
@@ -488,23 +845,28 @@ impl TryFrom<network_proto::PeerMessage> for PeerMessage {
                 Ok(PeerMessage::StateRequest(
                     state_request.shard_id,
                     state_request.hash.try_into()?,
+                    state_request.need_header,
+                    state_request.part_ids,
                 ))
             }
             Some(network_proto::PeerMessage_oneof_message_type::state_response(state_response)) => {
-                Ok(PeerMessage::StateResponse(
-                    state_response.shard_id,
-                    state_response.hash.try_into()?,
-                    state_response.payload,
-                    state_response
-                        .receipts
+                Ok(PeerMessage::StateResponse(state_response.try_into()?))
+            }
+            Some(network_proto::PeerMessage_oneof_message_type::announce_account(
+                announce_account,
+            )) => announce_account.try_into().map(PeerMessage::AnnounceAccount),
+            Some(network_proto::PeerMessage_oneof_message_type::routed(routed)) => {
+                routed.try_into().map(PeerMessage::Routed)
+            }
+            Some(network_proto::PeerMessage_oneof_message_type::edges(edges)) => {
+                Ok(PeerMessage::Edges(
+                    edges
+                        .edges
                         .into_iter()
                         .map(TryInto::try_into)
                         .collect::<Result<Vec<_>, _>>()?,
                 ))
             }
-            Some(network_proto::PeerMessage_oneof_message_type::announce_account(
-                announce_account,
-            )) => announce_account.try_into().map(PeerMessage::AnnounceAccount),
             None => Err(format!("Unexpected empty message body").into()),
         }
     }
@@ -571,33 +933,38 @@ impl From<PeerMessage> for network_proto::PeerMessage {
                 };
                 Some(network_proto::PeerMessage_oneof_message_type::block_headers(block_headers))
             }
-            PeerMessage::StateRequest(shard_id, hash) => {
+            PeerMessage::StateRequest(shard_id, hash, need_header, part_ids) => {
                 let state_request = network_proto::StateRequest {
                     shard_id,
                     hash: hash.into(),
+                    need_header,
+                    part_ids,
                     cached_size: Default::default(),
                     unknown_fields: Default::default(),
                 };
                 Some(network_proto::PeerMessage_oneof_message_type::state_request(state_request))
             }
-            PeerMessage::StateResponse(shard_id, hash, payload, receipts) => {
-                let state_response = network_proto::StateResponse {
-                    shard_id,
-                    hash: hash.into(),
-                    payload,
-                    receipts: RepeatedField::from_iter(
-                        receipts.into_iter().map(std::convert::Into::into),
-                    ),
-                    cached_size: Default::default(),
-                    unknown_fields: Default::default(),
-                };
-                Some(network_proto::PeerMessage_oneof_message_type::state_response(state_response))
+            PeerMessage::StateResponse(info) => {
+                Some(network_proto::PeerMessage_oneof_message_type::state_response(info.into()))
             }
             PeerMessage::AnnounceAccount(announce_account) => {
                 Some(network_proto::PeerMessage_oneof_message_type::announce_account(
                     announce_account.into(),
                 ))
             }
+            PeerMessage::Routed(routed) => {
+                Some(network_proto::PeerMessage_oneof_message_type::routed(routed.into()))
+            }
+            PeerMessage::Edges(edges) => {
+                let edges = network_proto::Edges {
+                    edges: RepeatedField::from_iter(
+                        edges.into_iter().map(std::convert::Into::into),
+                    ),
+                    cached_size: Default::default(),
+                    unknown_fields: Default::default(),
+                };
+                Some(network_proto::PeerMessage_oneof_message_type::edges(edges))
+            }
         };
         network_proto::PeerMessage { message_type, ..Default::default() }
     }
@@ -623,6 +990,75 @@ pub struct NetworkConfig {
     pub max_send_peers: u32,
     /// Duration for checking on stats from the peers.
     pub peer_stats_period: Duration,
+    /// Address to advertise to peers, discovered from the addresses peers observe if not given.
+    pub external_address: Option<SocketAddr>,
+    /// Limits of the size and the rate of messages received from each peer.
+    pub message_limits: MessageLimits,
+    /// Number of hops routed messages can make before they are dropped.
+    pub routed_message_ttl: u32,
+}
+
+/// Limits of the size and the rate of messages of one type received from a peer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageLimit {
+    /// Maximum size of the encoded message in bytes.
+    pub max_size: usize,
+    /// Number of messages that can be received at once.
+    pub burst: u32,
+    /// Number of messages per second that can be received over time.
+    pub rate: u32,
+}
+
+impl MessageLimit {
+    pub fn new(max_size: usize, burst: u32, rate: u32) -> Self {
+        MessageLimit { max_size, burst, rate }
+    }
+}
+
+/// Limits of messages received from a peer by the message type.
+/// Peer that sends messages over the limits is banned as abusive.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Limits of the types that are not in `limits`.
+    pub default: MessageLimit,
+    /// Limits by `PeerMessage::message_type`.
+    pub limits: HashMap<String, MessageLimit>,
+}
+
+impl MessageLimits {
+    pub fn get(&self, message_type: &str) -> &MessageLimit {
+        self.limits.get(message_type).unwrap_or(&self.default)
+    }
+
+    /// Maximum size of any message.
+    pub fn max_size(&self) -> usize {
+        self.limits.values().map(|limit| limit.max_size).fold(self.default.max_size, cmp::max)
+    }
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        const KB: usize = 1024;
+        const MB: usize = 1024 * 1024;
+        let limits = vec![
+            ("PeersRequest", MessageLimit::new(KB, 10, 1)),
+            ("BlockHeaderRequest", MessageLimit::new(64 * KB, 50, 10)),
+            ("BlockHeaders", MessageLimit::new(16 * MB, 50, 10)),
+            ("BlockRequest", MessageLimit::new(KB, 200, 100)),
+            ("Block", MessageLimit::new(16 * MB, 200, 100)),
+            ("StateRequest", MessageLimit::new(64 * KB, 50, 10)),
+            ("StateResponse", MessageLimit::new(64 * MB, 50, 10)),
+            ("Routed", MessageLimit::new(64 * MB, 1000, 500)),
+            ("Edges", MessageLimit::new(16 * MB, 100, 50)),
+        ];
+        MessageLimits {
+            default: MessageLimit::new(MB, 1000, 500),
+            limits: limits
+                .into_iter()
+                .map(|(message_type, limit)| (message_type.to_string(), limit))
+                .collect(),
+        }
+    }
 }
 
 /// Status of the known peers.
@@ -641,6 +1077,13 @@ pub struct KnownPeerState {
     pub status: KnownPeerStatus,
     pub first_seen: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
+    /// Reputation of the peer, changed by its behaviour. Peer is banned when it drops too low.
+    pub score: i32,
+    /// Whether we managed to connect to the peer at its address. Only verified addresses are
+    /// shared with other peers.
+    pub addr_verified: bool,
+    /// Address of the peer that told us about this peer, used to pick its bucket in the store.
+    pub source: Option<IpAddr>,
 }
 
 impl KnownPeerState {
@@ -650,6 +1093,9 @@ impl KnownPeerState {
             status: KnownPeerStatus::Unknown,
             first_seen: Utc::now(),
             last_seen: Utc::now(),
+            score: 0,
+            addr_verified: false,
+            source: None,
         }
     }
 }
@@ -688,6 +1134,18 @@ pub struct SendMessage {
     pub message: PeerMessage,
 }
 
+/// Response to the consolidation of a new peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsolidateResponse {
+    /// Connection should be kept.
+    Accept,
+    /// Connection should be dropped.
+    Reject,
+    /// Connection should be dropped, since the peer is already connected. Inbound peer still
+    /// responds with its handshake, so the other side can verify the address it dialed back.
+    AlreadyConnected,
+}
+
 /// Actor message to consolidate potential new peer.
 /// Returns if connection should be kept or dropped.
 pub struct Consolidate {
@@ -695,10 +1153,24 @@ pub struct Consolidate {
     pub peer_info: PeerInfo,
     pub peer_type: PeerType,
     pub chain_info: PeerChainInfo,
+    /// Address of this node as the peer sees it.
+    pub observed_address: Option<SocketAddr>,
 }
 
 impl Message for Consolidate {
-    type Result = bool;
+    type Result = ConsolidateResponse;
+}
+
+impl<A, M> MessageResponse<A, M> for ConsolidateResponse
+where
+    A: Actor,
+    M: Message<Result = ConsolidateResponse>,
+{
+    fn handle<R: ResponseChannel<M>>(self, _: &mut A::Context, tx: Option<R>) {
+        if let Some(tx) = tx {
+            tx.send(self)
+        }
+    }
 }
 
 /// Unregister message from Peer to PeerManager.
@@ -722,6 +1194,8 @@ impl Message for PeersRequest {
 #[derive(Message)]
 pub struct PeersResponse {
     pub peers: Vec<PeerInfo>,
+    /// Address of the peer that sent the response.
+    pub source: SocketAddr,
 }
 
 impl<A, M> MessageResponse<A, M> for PeerList
@@ -736,6 +1210,36 @@ where
     }
 }
 
+pub struct EdgeList {
+    pub edges: Vec<Edge>,
+}
+
+/// Requesting all known edges from peer manager to sync them with a new peer.
+pub struct EdgesRequest {}
+
+impl Message for EdgesRequest {
+    type Result = EdgeList;
+}
+
+impl<A, M> MessageResponse<A, M> for EdgeList
+where
+    A: Actor,
+    M: Message<Result = EdgeList>,
+{
+    fn handle<R: ResponseChannel<M>>(self, _: &mut A::Context, tx: Option<R>) {
+        if let Some(tx) = tx {
+            tx.send(self)
+        }
+    }
+}
+
+/// Received edges from another peer.
+#[derive(Message)]
+pub struct NewEdges {
+    pub edges: Vec<Edge>,
+    pub from: PeerId,
+}
+
 /// Ban reason.
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize)]
 pub enum ReasonForBan {
@@ -749,6 +1253,7 @@ pub enum ReasonForBan {
     InvalidSignature = 7,
     InvalidPeerId = 8,
     InvalidHash = 9,
+    LowScore = 10,
 }
 
 #[derive(Message)]
@@ -757,6 +1262,53 @@ pub struct Ban {
     pub ban_reason: ReasonForBan,
 }
 
+/// Behaviour of the peer that changes its score.
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum PeerBehaviour {
+    /// Sent a block that was accepted by the chain.
+    UsefulBlock,
+    /// Sent a message that was of no use, e.g. a block that wasn't requested and is already known.
+    UselessMessage,
+    /// Didn't respond to a request in time.
+    Timeout,
+    /// Sent invalid data.
+    Misbehaviour(ReasonForBan),
+}
+
+impl PeerBehaviour {
+    /// Change of the peer score caused by this behaviour.
+    pub fn score_change(&self) -> i32 {
+        match self {
+            PeerBehaviour::UsefulBlock => 1,
+            PeerBehaviour::UselessMessage => -1,
+            PeerBehaviour::Timeout => -5,
+            PeerBehaviour::Misbehaviour(_) => -50,
+        }
+    }
+
+    /// Reason to give when the peer is banned after this behaviour.
+    pub fn ban_reason(&self) -> ReasonForBan {
+        match self {
+            PeerBehaviour::Misbehaviour(ban_reason) => *ban_reason,
+            _ => ReasonForBan::LowScore,
+        }
+    }
+}
+
+/// Report behaviour of the peer to the peer manager.
+#[derive(Message)]
+pub struct ReportPeer {
+    pub peer_id: PeerId,
+    pub behaviour: PeerBehaviour,
+}
+
+/// Routed message received from the peer, to be delivered or forwarded by the peer manager.
+#[derive(Message)]
+pub struct RoutedMessageFrom {
+    pub msg: RoutedMessage,
+    pub from: PeerId,
+}
+
 #[derive(Debug)]
 pub enum NetworkRequests {
     /// Fetch information from the network.
@@ -772,12 +1324,32 @@ pub enum NetworkRequests {
     BlockRequest { hash: CryptoHash, peer_id: PeerId },
     /// Request given block headers.
     BlockHeadersRequest { hashes: Vec<CryptoHash>, peer_id: PeerId },
-    /// Request state for given shard at given state root.
-    StateRequest { shard_id: ShardId, hash: CryptoHash, peer_id: PeerId },
-    /// Ban given peer.
+    /// Request state header and / or parts for given shard at given block from given peer.
+    StateRequest {
+        shard_id: ShardId,
+        hash: CryptoHash,
+        need_header: bool,
+        parts_ids: Vec<u64>,
+        peer_id: PeerId,
+    },
+    /// Request state header and / or parts for given shard at given block from the validator
+    /// with given account id, routed through other peers if it's not connected directly.
+    StateRequestToAccount {
+        shard_id: ShardId,
+        hash: CryptoHash,
+        need_header: bool,
+        parts_ids: Vec<u64>,
+        account_id: AccountId,
+    },
+    /// Report misbehaviour of given peer, which is banned if its score drops too low.
     BanPeer { peer_id: PeerId, ban_reason: ReasonForBan },
+    /// Report behaviour of the peer, which changes its score.
+    ReportPeer { peer_id: PeerId, behaviour: PeerBehaviour },
     /// Announce account
     AnnounceAccount(AnnounceAccount),
+    /// Forward transaction to the block producers with given account ids. If none of them can be
+    /// reached, the transaction is gossiped to all peers.
+    ForwardTx { account_ids: Vec<AccountId>, transaction: SignedTransaction },
 }
 
 /// Combines peer address info and chain information.
@@ -796,6 +1368,17 @@ pub struct NetworkInfo {
     pub received_bytes_per_sec: u64,
     // Only send full routes to accounts on demand
     pub routes: Option<HashMap<AccountId, (PeerId, usize)>>,
+    /// Graph of the network, only sent on demand.
+    pub graph: Option<NetworkGraphView>,
+}
+
+/// Connections between peers known to this node and how it routes messages to them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkGraphView {
+    /// Pairs of connected peers.
+    pub edges: Vec<(String, String)>,
+    /// Peers we are connected to that are the next hop on the shortest paths to each known peer.
+    pub next_hops: HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug)]
@@ -838,10 +1421,10 @@ pub enum NetworkClientMessages {
     BlockHeadersRequest(Vec<CryptoHash>),
     /// Request a block.
     BlockRequest(CryptoHash),
-    /// State request.
-    StateRequest(ShardId, CryptoHash),
+    /// State request: shard, block hash, whether header is needed and ids of requested parts.
+    StateRequest(ShardId, CryptoHash, bool, Vec<u64>),
     /// State response.
-    StateResponse(ShardId, CryptoHash, Vec<u8>, Vec<ReceiptTransaction>),
+    StateResponse(StateResponseInfo, PeerId),
     /// Account announcement that needs to be validated before being processed
     AnnounceAccount(AnnounceAccount),
 }
@@ -851,10 +1434,15 @@ pub enum NetworkClientResponses {
     NoResponse,
     /// Valid transaction inserted into mempool as response to Transaction.
     ValidTx,
+    /// Valid transaction forwarded to the block producers, now or earlier, as response to
+    /// Transaction.
+    RequestRouted,
     /// Invalid transaction inserted into mempool as response to Transaction.
     InvalidTx(String),
     /// Ban peer for malicious behaviour.
     Ban { ban_reason: ReasonForBan },
+    /// Report behaviour of the peer that sent the message.
+    Report { behaviour: PeerBehaviour },
     /// Chain information.
     ChainInfo { genesis: CryptoHash, height: BlockIndex, total_weight: Weight },
     /// Block response.
@@ -862,12 +1450,7 @@ pub enum NetworkClientResponses {
     /// Headers response.
     BlockHeaders(Vec<BlockHeader>),
     /// Response to state request.
-    StateResponse {
-        shard_id: ShardId,
-        hash: CryptoHash,
-        payload: Vec<u8>,
-        receipts: Vec<ReceiptTransaction>,
-    },
+    StateResponse(StateResponseInfo),
 }
 
 impl<A, M> MessageResponse<A, M> for NetworkClientResponses
@@ -0,0 +1,226 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use actix::actors::mocker::Mocker;
+use actix::{Actor, Addr, AsyncContext, System};
+use chrono::Utc;
+use futures::future;
+use futures::future::Future;
+
+use near_chain::{Block, BlockApproval, ShardStateSyncResponse};
+use near_client::ClientActor;
+use near_network::test_utils::{convert_boot_nodes, open_port, WaitOrTimeout};
+use near_network::types::{AnnounceAccount, NetworkInfo, PeerId, StateResponseInfo};
+use near_network::{
+    NetworkClientMessages, NetworkClientResponses, NetworkConfig, NetworkRequests,
+    NetworkResponses, PeerManagerActor,
+};
+use near_primitives::crypto::signature::sign;
+use near_primitives::hash::CryptoHash;
+use near_primitives::test_utils::{get_key_pair_from_seed, init_test_logger};
+use near_store::test_utils::create_test_store;
+
+type ClientMock = Mocker<ClientActor>;
+
+/// Messages received by the client of the node.
+#[derive(Default)]
+struct Received {
+    block_approval: AtomicBool,
+    state_response: AtomicBool,
+}
+
+/// Peer manager with a client that passes account announcements back to the peer manager,
+/// answers state requests and records routed messages it receives.
+fn make_peer_manager(
+    seed: &'static str,
+    port: u16,
+    boot_nodes: Vec<(&str, u16)>,
+    peer_max_count: u32,
+    received: Arc<Received>,
+) -> Addr<PeerManagerActor> {
+    let store = create_test_store();
+    let mut config = NetworkConfig::from_seed(seed, port);
+    config.boot_nodes = convert_boot_nodes(boot_nodes);
+    config.peer_max_count = peer_max_count;
+    PeerManagerActor::create(move |ctx| {
+        let peer_manager = ctx.address();
+        let client_addr = ClientMock::mock(Box::new(move |msg, _ctx| {
+            let msg = msg.downcast_ref::<NetworkClientMessages>().unwrap();
+            match msg {
+                NetworkClientMessages::GetChainInfo => {
+                    Box::new(Some(NetworkClientResponses::ChainInfo {
+                        genesis: Default::default(),
+                        height: 1,
+                        total_weight: 1.into(),
+                    }))
+                }
+                NetworkClientMessages::AnnounceAccount(announce_account) => {
+                    peer_manager
+                        .do_send(NetworkRequests::AnnounceAccount(announce_account.clone()));
+                    Box::new(Some(NetworkClientResponses::NoResponse))
+                }
+                NetworkClientMessages::BlockApproval(account_id, _, _) => {
+                    assert_eq!(account_id, "test1");
+                    received.block_approval.store(true, Ordering::Relaxed);
+                    Box::new(Some(NetworkClientResponses::NoResponse))
+                }
+                NetworkClientMessages::StateRequest(shard_id, hash, _, _) => {
+                    Box::new(Some(NetworkClientResponses::StateResponse(StateResponseInfo {
+                        shard_id: *shard_id,
+                        hash: *hash,
+                        shard_state: ShardStateSyncResponse { header: None, parts: vec![] },
+                    })))
+                }
+                NetworkClientMessages::StateResponse(info, peer_id) => {
+                    assert_eq!(info.shard_id, 1);
+                    assert_eq!(*peer_id, PeerId::from(get_key_pair_from_seed("test3").0));
+                    received.state_response.store(true, Ordering::Relaxed);
+                    Box::new(Some(NetworkClientResponses::NoResponse))
+                }
+                _ => Box::new(Some(NetworkClientResponses::NoResponse)),
+            }
+        }))
+        .start();
+        PeerManagerActor::new(store, config, client_addr.recipient()).unwrap()
+    })
+}
+
+fn announce_account(account_id: &str) -> NetworkRequests {
+    let (public_key, secret_key) = get_key_pair_from_seed(account_id);
+    let peer_id = PeerId::from(public_key);
+    let epoch = CryptoHash::default();
+    let hash = AnnounceAccount::build_header_hash(&account_id.to_string(), &peer_id, epoch);
+    let signature = sign(hash.as_ref(), &secret_key);
+    NetworkRequests::AnnounceAccount(AnnounceAccount::new(
+        account_id.to_string(),
+        epoch,
+        peer_id,
+        hash,
+        signature,
+    ))
+}
+
+/// Connects three nodes in a line test1 - test2 - test3, so test1 and test3 are not connected
+/// directly. Once all of them are connected, announces the account of test3 and then keeps sending
+/// the request from test1 until the given condition holds.
+fn check_routed_message(
+    request: fn() -> NetworkRequests,
+    condition: fn(&Received, &Received) -> bool,
+) {
+    init_test_logger();
+
+    System::run(move || {
+        let (port1, port2, port3) = (open_port(), open_port(), open_port());
+        let (received1, received3) = (Arc::new(Received::default()), Arc::new(Received::default()));
+        let pm1 = make_peer_manager("test1", port1, vec![("test2", port2)], 1, received1.clone());
+        let pm2 = make_peer_manager("test2", port2, vec![], 10, Arc::new(Received::default()));
+        let pm3 = make_peer_manager("test3", port3, vec![("test2", port2)], 1, received3.clone());
+        let announced = Arc::new(AtomicBool::new(false));
+
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                if condition(&received1, &received3) {
+                    System::current().stop();
+                } else if announced.load(Ordering::Relaxed) {
+                    pm1.do_send(request());
+                } else {
+                    let pm3 = pm3.clone();
+                    let announced = announced.clone();
+                    let fetch_info = |pm: &Addr<PeerManagerActor>| {
+                        pm.send(NetworkRequests::FetchInfo { level: 0 }).map(|res| match res {
+                            NetworkResponses::Info(NetworkInfo { num_active_peers, .. }) => {
+                                num_active_peers
+                            }
+                            _ => 0,
+                        })
+                    };
+                    // Announce once all the connections are ready, so the announcement reaches
+                    // test1 through test2.
+                    actix::spawn(fetch_info(&pm2).join(fetch_info(&pm3)).then(move |res| {
+                        if res.unwrap() == (2, 1) && !announced.load(Ordering::Relaxed) {
+                            announced.store(true, Ordering::Relaxed);
+                            pm3.do_send(announce_account("test3"));
+                        }
+                        future::result(Ok(()))
+                    }));
+                }
+            }),
+            100,
+            5000,
+        )
+        .start();
+    })
+    .unwrap();
+}
+
+/// Block approval reaches the validator through another peer.
+#[test]
+fn routed_block_approval() {
+    check_routed_message(
+        || {
+            let header = Block::genesis(CryptoHash::default(), Utc::now()).header;
+            let approval = BlockApproval {
+                hash: header.hash(),
+                signature: sign(header.hash().as_ref(), &get_key_pair_from_seed("test1").1),
+                target: "test3".to_string(),
+            };
+            NetworkRequests::BlockHeaderAnnounce { header, approval: Some(approval) }
+        },
+        |_, received3| received3.block_approval.load(Ordering::Relaxed),
+    );
+}
+
+/// State request reaches the validator through another peer and the response is routed back.
+#[test]
+fn routed_state_request() {
+    check_routed_message(
+        || NetworkRequests::StateRequestToAccount {
+            shard_id: 1,
+            hash: CryptoHash::default(),
+            need_header: true,
+            parts_ids: vec![],
+            account_id: "test3".to_string(),
+        },
+        |received1, _| received1.state_response.load(Ordering::Relaxed),
+    );
+}
+
+/// Node learns connections between other peers and routes to them through its neighbour.
+#[test]
+fn network_graph() {
+    init_test_logger();
+
+    System::run(|| {
+        let (port1, port2, port3) = (open_port(), open_port(), open_port());
+        let received = Arc::new(Received::default());
+        let pm1 = make_peer_manager("test1", port1, vec![("test2", port2)], 1, received.clone());
+        let _pm2 = make_peer_manager("test2", port2, vec![], 10, received.clone());
+        let _pm3 = make_peer_manager("test3", port3, vec![("test2", port2)], 1, received);
+        let peer_id2 = PeerId::from(get_key_pair_from_seed("test2").0).to_string();
+        let peer_id3 = PeerId::from(get_key_pair_from_seed("test3").0).to_string();
+
+        WaitOrTimeout::new(
+            Box::new(move |_| {
+                let (peer_id2, peer_id3) = (peer_id2.clone(), peer_id3.clone());
+                actix::spawn(pm1.send(NetworkRequests::FetchInfo { level: 1 }).then(move |res| {
+                    if let NetworkResponses::Info(NetworkInfo { graph: Some(graph), .. }) =
+                        res.unwrap()
+                    {
+                        let has_edge = graph.edges.iter().any(|(peer0, peer1)| {
+                            (peer0 == &peer_id2 && peer1 == &peer_id3)
+                                || (peer0 == &peer_id3 && peer1 == &peer_id2)
+                        });
+                        if has_edge && graph.next_hops.get(&peer_id3) == Some(&vec![peer_id2]) {
+                            System::current().stop();
+                        }
+                    }
+                    future::result(Ok(()))
+                }));
+            }),
+            100,
+            5000,
+        )
+        .start();
+    })
+    .unwrap();
+}
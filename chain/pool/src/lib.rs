@@ -2,105 +2,476 @@ use std::collections::btree_map::BTreeMap;
 use std::collections::HashMap;
 
 use near_chain::{Block, ValidTransaction};
+use near_primitives::crypto::signature::PublicKey;
 use near_primitives::transaction::SignedTransaction;
-use near_primitives::types::{AccountId, Nonce};
+use near_primitives::types::{AccountId, BlockIndex, Nonce};
 
 pub use crate::types::Error;
 
+mod metrics;
 pub mod types;
 
+/// Transaction in the pool together with the order in which it was received.
+/// There are no transaction fees yet, so the order of receiving decides both which accounts and
+/// access keys take their turns first when preparing a block and which transactions are evicted
+/// first from a full pool: the ones that waited the longest.
+#[derive(Clone, Debug)]
+pub struct PoolTransaction {
+    /// Sequence number of the insertion into the pool, lower means received earlier.
+    pub sequence: u64,
+    /// Height of the block referenced by the transaction, used to drop expired transactions.
+    pub reference_block_index: BlockIndex,
+    pub transaction: SignedTransaction,
+}
+
 /// Transaction pool: keeps track of transactions that were not yet accepted into the block chain.
 pub struct TransactionPool {
     num_transactions: usize,
-    /// Transactions grouped by account and ordered by nonce.
-    pub transactions: HashMap<AccountId, BTreeMap<Nonce, SignedTransaction>>,
+    /// Maximum total number of transactions in the pool.
+    max_size: usize,
+    /// Maximum number of transactions from a single account in the pool.
+    max_size_per_account: usize,
+    /// Sequence number for the next inserted transaction.
+    next_sequence: u64,
+    /// Number of transactions in the pool from each account, over all its access keys.
+    account_sizes: HashMap<AccountId, usize>,
+    /// Transactions grouped by account and access key, ordered by nonce. Every access key has
+    /// its own nonces, so transactions signed with different keys don't conflict.
+    pub transactions: HashMap<(AccountId, PublicKey), BTreeMap<Nonce, PoolTransaction>>,
 }
 
 impl TransactionPool {
-    pub fn new() -> Self {
-        TransactionPool { num_transactions: 0, transactions: HashMap::default() }
+    pub fn new(max_size: usize, max_size_per_account: usize) -> Self {
+        TransactionPool {
+            num_transactions: 0,
+            max_size,
+            max_size_per_account,
+            next_sequence: 0,
+            account_sizes: HashMap::default(),
+            transactions: HashMap::default(),
+        }
     }
 
     /// Insert a valid transaction into the pool that passed validation.
-    pub fn insert_transaction(&mut self, valid_transaction: ValidTransaction) {
-        let account = valid_transaction.transaction.body.get_originator();
-        let nonce = valid_transaction.transaction.body.get_nonce();
+    /// If the pool is full, the oldest transaction that is last in the nonce order of its access
+    /// key is evicted. Transactions with the same account, access key and nonce as already known
+    /// are ignored.
+    pub fn insert_transaction(&mut self, valid_transaction: ValidTransaction) -> Result<(), Error> {
+        let ValidTransaction { transaction, public_key, reference_block_index } = valid_transaction;
+        let key = (transaction.body.get_originator(), public_key);
+        let nonce = transaction.body.get_nonce();
+        let last_nonce = match self.transactions.get(&key) {
+            Some(key_transactions) if key_transactions.contains_key(&nonce) => return Ok(()),
+            Some(key_transactions) => key_transactions.keys().next_back().cloned(),
+            None => None,
+        };
+        if self.account_sizes.get(&key.0).cloned().unwrap_or_default() >= self.max_size_per_account
+        {
+            // Transaction can only replace the one with the highest nonce from the same key.
+            match last_nonce {
+                Some(last_nonce) if nonce < last_nonce => self.remove_transaction(&key, last_nonce),
+                _ => return Err(Error::TooManyTransactions(key.0)),
+            }
+        }
+        if self.num_transactions >= self.max_size {
+            self.evict_oldest();
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
         self.num_transactions += 1;
+        *self.account_sizes.entry(key.0.clone()).or_insert(0) += 1;
         self.transactions
-            .entry(account)
+            .entry(key)
             .or_insert_with(BTreeMap::new)
-            .insert(nonce, valid_transaction.transaction);
+            .insert(nonce, PoolTransaction { sequence, reference_block_index, transaction });
+        near_metrics::set_gauge(&metrics::TRANSACTION_POOL_SIZE, self.num_transactions as i64);
+        Ok(())
     }
 
     /// Take transactions from the pool, in the appropriate order to be put in a new block.
     /// Ensure that on average they will fit into expected weight.
+    /// Accounts take turns in the order of their oldest transaction, one transaction per turn, and
+    /// the access keys of an account take turns within the turns of the account, so signing with
+    /// more keys doesn't give an account a bigger share of the block. Transactions signed with
+    /// each key are taken in the order of nonces.
     pub fn prepare_transactions(
         &mut self,
         expected_weight: u32,
     ) -> Result<Vec<SignedTransaction>, Error> {
-        // TODO: pack transactions better.
-        let result = self
-            .transactions
-            .values()
-            .flat_map(BTreeMap::values)
-            .take(expected_weight as usize)
-            .cloned()
+        let limit = expected_weight as usize;
+        let mut account_queues: HashMap<&AccountId, Vec<_>> = HashMap::new();
+        for ((account_id, _), txs) in self.transactions.iter() {
+            account_queues.entry(account_id).or_insert_with(Vec::new).push(txs.values());
+        }
+        // Queues of the access keys of each account with the index of the key to take next.
+        let mut accounts: Vec<_> = account_queues
+            .into_iter()
+            .map(|(_, mut queues)| {
+                queues.sort_by_key(|txs| txs.clone().map(|tx| tx.sequence).min());
+                (0, queues)
+            })
             .collect();
+        accounts.sort_by_key(|(_, queues)| queues[0].clone().map(|tx| tx.sequence).min());
+        let mut result = vec![];
+        while result.len() < limit {
+            let mut round = vec![];
+            for (next_key, queues) in accounts.iter_mut() {
+                for _ in 0..queues.len() {
+                    let index = *next_key % queues.len();
+                    *next_key += 1;
+                    if let Some(tx) = queues[index].next() {
+                        round.push(tx);
+                        break;
+                    }
+                }
+            }
+            if round.is_empty() {
+                break;
+            }
+            let remaining = limit - result.len();
+            result.extend(round.into_iter().take(remaining).map(|tx| tx.transaction.clone()));
+        }
         Ok(result)
     }
 
     /// Quick reconciliation step - evict all transactions that already in the block
-    /// or became invalid after it.
+    /// or became invalid after it. Only transactions signed with the same access key are affected.
     pub fn reconcile_block(&mut self, block: &Block) {
         for transaction in block.transactions.iter() {
-            let account = transaction.body.get_originator();
+            let public_key = match transaction.public_key {
+                Some(public_key) => public_key,
+                None => continue,
+            };
+            let key = (transaction.body.get_originator(), public_key);
             let nonce = transaction.body.get_nonce();
-            let mut remove_map = false;
-            if let Some(map) = self.transactions.get_mut(&account) {
-                map.remove(&nonce);
-                remove_map = map.is_empty();
-            }
-            if remove_map {
-                self.num_transactions -= 1;
-                self.transactions.remove(&account);
+            let stale_nonces: Vec<Nonce> = match self.transactions.get(&key) {
+                Some(map) => map.range(..=nonce).map(|(nonce, _)| *nonce).collect(),
+                None => continue,
+            };
+            for stale_nonce in stale_nonces {
+                self.remove_transaction(&key, stale_nonce);
             }
         }
     }
 
+    /// Evict all transactions that refer to blocks below `min_reference_block_index`,
+    /// as they can't be included into the chain anymore.
+    pub fn remove_expired(&mut self, min_reference_block_index: BlockIndex) {
+        let expired: Vec<((AccountId, PublicKey), Nonce)> = self
+            .transactions
+            .iter()
+            .flat_map(|(key, txs)| {
+                txs.iter()
+                    .filter(|(_, tx)| tx.reference_block_index < min_reference_block_index)
+                    .map(move |(nonce, _)| (key.clone(), *nonce))
+            })
+            .collect();
+        for (key, nonce) in expired {
+            self.remove_transaction(&key, nonce);
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.num_transactions
     }
+
+    fn remove_transaction(&mut self, key: &(AccountId, PublicKey), nonce: Nonce) {
+        let mut remove_map = false;
+        if let Some(map) = self.transactions.get_mut(key) {
+            if map.remove(&nonce).is_some() {
+                self.num_transactions -= 1;
+                near_metrics::set_gauge(
+                    &metrics::TRANSACTION_POOL_SIZE,
+                    self.num_transactions as i64,
+                );
+                let account_size = self.account_sizes.get_mut(&key.0).expect("Account is counted");
+                *account_size -= 1;
+                if *account_size == 0 {
+                    self.account_sizes.remove(&key.0);
+                }
+            }
+            remove_map = map.is_empty();
+        }
+        if remove_map {
+            self.transactions.remove(key);
+        }
+    }
+
+    /// Evicts the oldest transaction among the ones with the highest nonce of each access key,
+    /// so remaining transactions of the key still have consecutive nonces.
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .transactions
+            .iter()
+            .filter_map(|(key, txs)| {
+                txs.iter().next_back().map(|(nonce, tx)| (tx.sequence, key.clone(), *nonce))
+            })
+            .min();
+        if let Some((_, key, nonce)) = oldest {
+            self.remove_transaction(&key, nonce);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use rand::seq::SliceRandom;
     use rand::thread_rng;
 
-    use near_chain::ValidTransaction;
+    use near_chain::{Block, ValidTransaction};
     use near_primitives::crypto::signer::InMemorySigner;
-    use near_primitives::transaction::TransactionBody;
+    use near_primitives::hash::CryptoHash;
+    use near_primitives::transaction::{SignedTransaction, TransactionBody};
+    use near_primitives::types::{Balance, MerkleHash};
 
     use crate::TransactionPool;
-    use near_primitives::types::Balance;
+
+    fn send_money(signer: &InMemorySigner, nonce: u64) -> SignedTransaction {
+        TransactionBody::send_money(
+            nonce,
+            &signer.account_id,
+            "bob.near",
+            nonce as Balance,
+            CryptoHash::default(),
+        )
+        .sign(signer)
+    }
+
+    fn valid_tx(transaction: SignedTransaction) -> ValidTransaction {
+        ValidTransaction {
+            public_key: transaction.public_key.unwrap(),
+            transaction,
+            reference_block_index: 0,
+        }
+    }
+
+    fn nonces(transactions: &[SignedTransaction]) -> Vec<(String, u64)> {
+        transactions.iter().map(|tx| (tx.body.get_originator(), tx.body.get_nonce())).collect()
+    }
 
     /// Add transactions of nonce from 1..10 in random order. Check that mempool
     /// orders them correctly.
     #[test]
     fn test_order_nonce() {
         let signer = InMemorySigner::from_seed("alice.near", "alice.near");
-        let mut transactions: Vec<_> = (1..10)
-            .map(|i| TransactionBody::send_money(i, "alice.near", "bob.near", i as Balance).sign(&signer))
-            .collect();
-        let mut pool = TransactionPool::new();
+        let mut transactions: Vec<_> = (1..10).map(|i| send_money(&signer, i)).collect();
+        let mut pool = TransactionPool::new(100, 100);
         let mut rng = thread_rng();
         transactions.shuffle(&mut rng);
         for tx in transactions {
-            pool.insert_transaction(ValidTransaction { transaction: tx });
+            pool.insert_transaction(valid_tx(tx)).unwrap();
         }
         let transactions = pool.prepare_transactions(10).unwrap();
         let nonces: Vec<u64> = transactions.iter().map(|tx| tx.body.get_nonce()).collect();
         assert_eq!(nonces, (1..10).collect::<Vec<u64>>())
     }
 
+    /// Accounts take turns in the order of their oldest transaction.
+    #[test]
+    fn test_round_robin() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let mut pool = TransactionPool::new(100, 100);
+        for tx in vec![
+            send_money(&alice, 1),
+            send_money(&alice, 2),
+            send_money(&alice, 3),
+            send_money(&carol, 2),
+            send_money(&carol, 1),
+        ] {
+            pool.insert_transaction(valid_tx(tx)).unwrap();
+        }
+        let (a, c) = ("alice.near".to_string(), "carol.near".to_string());
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![(a.clone(), 1), (c.clone(), 1), (a.clone(), 2), (c.clone(), 2), (a.clone(), 3)]
+        );
+        assert_eq!(nonces(&pool.prepare_transactions(3).unwrap()).len(), 3);
+    }
+
+    /// Access keys of an account share the turns of the account, so an account with many keys
+    /// doesn't take more of the block than an account with one key.
+    #[test]
+    fn test_round_robin_access_keys() {
+        let alice_keys: Vec<_> = ["alice1", "alice2", "alice3"]
+            .iter()
+            .map(|seed| InMemorySigner::from_seed("alice.near", seed))
+            .collect();
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let mut pool = TransactionPool::new(100, 100);
+        for signer in alice_keys.iter() {
+            for nonce in 1..3 {
+                pool.insert_transaction(valid_tx(send_money(signer, nonce))).unwrap();
+            }
+        }
+        for nonce in 1..4 {
+            pool.insert_transaction(valid_tx(send_money(&carol, nonce))).unwrap();
+        }
+        let transactions = pool.prepare_transactions(6).unwrap();
+        let (a, c) = ("alice.near".to_string(), "carol.near".to_string());
+        assert_eq!(
+            nonces(&transactions),
+            vec![
+                (a.clone(), 1),
+                (c.clone(), 1),
+                (a.clone(), 1),
+                (c.clone(), 2),
+                (a.clone(), 1),
+                (c.clone(), 3)
+            ]
+        );
+        // Keys of the account take turns in the order of their oldest transaction.
+        let keys: Vec<_> = transactions.iter().step_by(2).map(|tx| tx.public_key).collect();
+        assert_eq!(
+            keys,
+            alice_keys.iter().map(|signer| Some(signer.public_key)).collect::<Vec<_>>()
+        );
+    }
+
+    /// Pool size and number of transactions per account are bounded.
+    #[test]
+    fn test_limits() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let mut pool = TransactionPool::new(4, 3);
+        for nonce in 1..4 {
+            pool.insert_transaction(valid_tx(send_money(&alice, nonce))).unwrap();
+        }
+        assert!(pool.insert_transaction(valid_tx(send_money(&alice, 5))).is_err());
+        // Lower nonce replaces the last transaction of the account.
+        pool.insert_transaction(valid_tx(send_money(&alice, 0))).unwrap();
+        assert_eq!(pool.len(), 3);
+        // Duplicates are ignored.
+        pool.insert_transaction(valid_tx(send_money(&alice, 0))).unwrap();
+        assert_eq!(pool.len(), 3);
+
+        pool.insert_transaction(valid_tx(send_money(&carol, 1))).unwrap();
+        assert_eq!(pool.len(), 4);
+        // Full pool evicts the oldest transaction that is last for its account.
+        pool.insert_transaction(valid_tx(send_money(&carol, 2))).unwrap();
+        assert_eq!(pool.len(), 4);
+        let (a, c) = ("alice.near".to_string(), "carol.near".to_string());
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![(a.clone(), 0), (c.clone(), 1), (a.clone(), 1), (c.clone(), 2)]
+        );
+    }
+
+    /// Full pool evicts transactions in the order they were received, only taking the ones that
+    /// are last in the nonce order of their access key.
+    #[test]
+    fn test_eviction_order() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let dave = InMemorySigner::from_seed("dave.near", "dave.near");
+        let mut pool = TransactionPool::new(3, 100);
+        for tx in vec![send_money(&alice, 1), send_money(&carol, 1), send_money(&alice, 2)] {
+            pool.insert_transaction(valid_tx(tx)).unwrap();
+        }
+        let (a, c, d) =
+            ("alice.near".to_string(), "carol.near".to_string(), "dave.near".to_string());
+        // Alice's first transaction is the oldest, but it's followed by her second one.
+        pool.insert_transaction(valid_tx(send_money(&dave, 1))).unwrap();
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![(a.clone(), 1), (d.clone(), 1), (a.clone(), 2)]
+        );
+        pool.insert_transaction(valid_tx(send_money(&dave, 2))).unwrap();
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![(a.clone(), 1), (d.clone(), 1), (d.clone(), 2)]
+        );
+        pool.insert_transaction(valid_tx(send_money(&carol, 2))).unwrap();
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![(d.clone(), 1), (c.clone(), 2), (d.clone(), 2)]
+        );
+    }
+
+    /// Transactions with nonces not higher than the ones in the block are removed.
+    #[test]
+    fn test_reconcile_block() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let mut pool = TransactionPool::new(100, 100);
+        for tx in (1..5).map(|nonce| send_money(&alice, nonce)).chain(vec![send_money(&carol, 1)]) {
+            pool.insert_transaction(valid_tx(tx)).unwrap();
+        }
+        assert_eq!(pool.len(), 5);
+        let genesis = Block::genesis(MerkleHash::default(), chrono::Utc::now());
+        let block = Block::produce(
+            &genesis.header,
+            1,
+            MerkleHash::default(),
+            CryptoHash::default(),
+            vec![send_money(&alice, 2), send_money(&carol, 1)],
+            HashMap::default(),
+            vec![],
+            std::sync::Arc::new(alice.clone()),
+        );
+        pool.reconcile_block(&block);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![("alice.near".to_string(), 3), ("alice.near".to_string(), 4)]
+        );
+    }
+
+    /// Access keys of the same account have independent nonces, so the same nonce signed with
+    /// another key is not a duplicate and is not removed by a block with the first one.
+    #[test]
+    fn test_access_keys_same_nonce() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let alice2 = InMemorySigner::from_seed("alice.near", "alice2");
+        let mut pool = TransactionPool::new(100, 100);
+        for tx in vec![send_money(&alice, 1), send_money(&alice2, 1), send_money(&alice2, 2)] {
+            pool.insert_transaction(valid_tx(tx)).unwrap();
+        }
+        assert_eq!(pool.len(), 3);
+        let genesis = Block::genesis(MerkleHash::default(), chrono::Utc::now());
+        let block = Block::produce(
+            &genesis.header,
+            1,
+            MerkleHash::default(),
+            CryptoHash::default(),
+            vec![send_money(&alice, 1)],
+            HashMap::default(),
+            vec![],
+            std::sync::Arc::new(alice.clone()),
+        );
+        pool.reconcile_block(&block);
+        assert_eq!(pool.len(), 2);
+        let transactions = pool.prepare_transactions(10).unwrap();
+        assert!(transactions.iter().all(|tx| tx.public_key == Some(alice2.public_key)));
+        assert_eq!(
+            nonces(&transactions),
+            vec![("alice.near".to_string(), 1), ("alice.near".to_string(), 2)]
+        );
+    }
+
+    /// Transactions referring to blocks that are too old are removed.
+    #[test]
+    fn test_remove_expired() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let carol = InMemorySigner::from_seed("carol.near", "carol.near");
+        let mut pool = TransactionPool::new(100, 100);
+        for (tx, reference_block_index) in
+            vec![(send_money(&alice, 1), 1), (send_money(&alice, 2), 5), (send_money(&carol, 1), 3)]
+        {
+            pool.insert_transaction(ValidTransaction {
+                public_key: tx.public_key.unwrap(),
+                transaction: tx,
+                reference_block_index,
+            })
+            .unwrap();
+        }
+        pool.remove_expired(4);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(
+            nonces(&pool.prepare_transactions(10).unwrap()),
+            vec![("alice.near".to_string(), 2)]
+        );
+    }
 }
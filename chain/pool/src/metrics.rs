@@ -0,0 +1,10 @@
+use lazy_static::lazy_static;
+use near_metrics::IntGauge;
+
+lazy_static! {
+    pub static ref TRANSACTION_POOL_SIZE: near_metrics::Result<IntGauge> =
+        near_metrics::try_create_int_gauge(
+            "near_transaction_pool_size",
+            "Number of transactions in the pool"
+        );
+}
@@ -1,6 +1,7 @@
 use failure::Fail;
 
 use near_chain::ValidTransaction;
+use near_primitives::types::AccountId;
 
 /// Possible errors whe interacting with transaction pool.
 #[derive(Debug, Fail)]
@@ -8,6 +9,9 @@ pub enum Error {
     /// An invalid pool entry caused by underlying tx validation error
     #[fail(display = "Invalid Tx {}", _0)]
     InvalidTx(String),
+    /// Account has too many transactions in the pool.
+    #[fail(display = "Too many pending transactions from {}", _0)]
+    TooManyTransactions(AccountId),
     /// Other kinds of error (not yet pulled out into meaningful errors).
     #[fail(display = "General pool error {}", _0)]
     Other(String),
@@ -0,0 +1,104 @@
+//! Prometheus metrics of the node.
+//!
+//! Metrics are defined in `metrics` modules of the crates that update them, usually as
+//! `lazy_static` results of the `try_create_*` functions, and registered in the default registry
+//! on first access. Failing to register a metric (e.g. because of a duplicate name) only logs an
+//! error: updates of such metric are ignored, so metrics never affect the node itself.
+
+use log::error;
+use prometheus::{Encoder, HistogramOpts, Opts, TextEncoder};
+pub use prometheus::{Histogram, HistogramTimer, IntCounter, IntGauge, Result};
+
+/// Creates and registers a counter.
+pub fn try_create_int_counter(name: &str, help: &str) -> Result<IntCounter> {
+    let counter = IntCounter::with_opts(Opts::new(name, help))?;
+    register(Box::new(counter.clone()), name)?;
+    Ok(counter)
+}
+
+/// Creates and registers a gauge.
+pub fn try_create_int_gauge(name: &str, help: &str) -> Result<IntGauge> {
+    let gauge = IntGauge::with_opts(Opts::new(name, help))?;
+    register(Box::new(gauge.clone()), name)?;
+    Ok(gauge)
+}
+
+/// Creates and registers a histogram with the default buckets, which suit durations in seconds.
+pub fn try_create_histogram(name: &str, help: &str) -> Result<Histogram> {
+    let histogram = Histogram::with_opts(HistogramOpts::new(name, help))?;
+    register(Box::new(histogram.clone()), name)?;
+    Ok(histogram)
+}
+
+fn register(collector: Box<dyn prometheus::core::Collector>, name: &str) -> Result<()> {
+    prometheus::register(collector).map_err(|err| {
+        error!(target: "metrics", "Failed to register metric {}: {}", name, err);
+        err
+    })
+}
+
+pub fn inc_counter(counter: &Result<IntCounter>) {
+    if let Ok(counter) = counter {
+        counter.inc();
+    }
+}
+
+pub fn inc_counter_by(counter: &Result<IntCounter>, value: u64) {
+    if let Ok(counter) = counter {
+        counter.inc_by(value as i64);
+    }
+}
+
+pub fn set_gauge(gauge: &Result<IntGauge>, value: i64) {
+    if let Ok(gauge) = gauge {
+        gauge.set(value);
+    }
+}
+
+/// Starts the timer, which records the elapsed time in the histogram when it's dropped.
+pub fn start_timer(histogram: &Result<Histogram>) -> Option<HistogramTimer> {
+    histogram.as_ref().ok().map(Histogram::start_timer)
+}
+
+/// Records the time elapsed since the timer started.
+pub fn stop_timer(timer: Option<HistogramTimer>) {
+    if let Some(timer) = timer {
+        timer.observe_duration();
+    }
+}
+
+/// Returns all the registered metrics in the Prometheus text format, together with the content
+/// type of the format.
+pub fn encode() -> Result<(Vec<u8>, String)> {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder.encode(&prometheus::gather(), &mut buffer)?;
+    Ok((buffer, encoder.format_type().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let counter = try_create_int_counter("near_test_counter", "Test counter");
+        inc_counter_by(&counter, 3);
+        let gauge = try_create_int_gauge("near_test_gauge", "Test gauge");
+        set_gauge(&gauge, 7);
+        let histogram = try_create_histogram("near_test_histogram", "Test histogram");
+        stop_timer(start_timer(&histogram));
+
+        let (buffer, format_type) = encode().unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(format_type.starts_with("text/plain"));
+        assert!(text.contains("near_test_counter 3"));
+        assert!(text.contains("near_test_gauge 7"));
+        assert!(text.contains("near_test_histogram_count 1"));
+
+        // Metric with the same name can't be registered again, but updating it is still safe.
+        let duplicate = try_create_int_counter("near_test_counter", "Test counter");
+        assert!(duplicate.is_err());
+        inc_counter(&duplicate);
+    }
+}
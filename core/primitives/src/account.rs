@@ -1,25 +1,21 @@
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
-use std::iter::FromIterator;
 
-use protobuf::well_known_types::{BytesValue, StringValue};
 use protobuf::{RepeatedField, SingularPtrField};
 
 use near_protos::access_key as access_key_proto;
 use near_protos::account as account_proto;
 
-use crate::crypto::signature::PublicKey;
 use crate::hash::CryptoHash;
 use crate::logging;
-use crate::serialize::{base_format, u128_dec_format, vec_base_format};
+use crate::serialize::{base_format, u128_dec_format};
 use crate::types::{AccountId, Balance, BlockIndex, Nonce, StorageUsage};
+use crate::utils::to_string_value;
 
-/// Per account information stored in the state.
+/// Per account information stored in the state. Keys of the account are stored separately as
+/// access keys, each with its own nonce.
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
-     #[serde(with = "vec_base_format")]
-    pub public_keys: Vec<PublicKey>,
-    pub nonce: Nonce,
     // amount + staked is the total value of the account
     #[serde(with = "u128_dec_format")]
     pub amount: Balance,
@@ -34,16 +30,8 @@ pub struct Account {
 }
 
 impl Account {
-    pub fn new(public_keys: Vec<PublicKey>, amount: Balance, code_hash: CryptoHash) -> Self {
-        Account {
-            public_keys,
-            nonce: 0,
-            amount,
-            staked: 0,
-            code_hash,
-            storage_usage: 0,
-            storage_paid_at: 0,
-        }
+    pub fn new(amount: Balance, code_hash: CryptoHash) -> Self {
+        Account { amount, staked: 0, code_hash, storage_usage: 0, storage_paid_at: 0 }
     }
 
     /// Try debiting the balance by the given amount.
@@ -67,12 +55,6 @@ impl TryFrom<account_proto::Account> for Account {
 
     fn try_from(account: account_proto::Account) -> Result<Self, Self::Error> {
         Ok(Account {
-            public_keys: account
-                .public_keys
-                .into_iter()
-                .map(TryInto::try_into)
-                .collect::<Result<Vec<_>, _>>()?,
-            nonce: account.nonce,
             amount: account.amount.unwrap_or_default().try_into()?,
             staked: account.staked.unwrap_or_default().try_into()?,
             code_hash: account.code_hash.try_into()?,
@@ -85,10 +67,6 @@ impl TryFrom<account_proto::Account> for Account {
 impl From<Account> for account_proto::Account {
     fn from(account: Account) -> Self {
         account_proto::Account {
-            public_keys: RepeatedField::from_iter(
-                account.public_keys.iter().map(std::convert::Into::into),
-            ),
-            nonce: account.nonce,
             amount: SingularPtrField::some(account.amount.into()),
             staked: SingularPtrField::some(account.staked.into()),
             code_hash: account.code_hash.into(),
@@ -100,29 +78,58 @@ impl From<Account> for account_proto::Account {
     }
 }
 
-/// Limited Access key to use owner's account with the fixed public_key.
+/// Access key to use owner's account with the fixed public_key.
 /// Access Key is stored under the key of owner's `account_id` and the `public_key`.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct AccessKey {
+    /// Transactions signed with this key must have a larger nonce, which becomes the new nonce.
+    /// Every key has its own nonce, so transactions signed with different keys don't collide.
+    pub nonce: Nonce,
+    /// Transactions that can be signed with this key.
+    pub permission: AccessKeyPermission,
+}
+
+impl AccessKey {
+    /// Access key that can sign any transaction of the account.
+    pub fn full_access() -> Self {
+        AccessKey { nonce: 0, permission: AccessKeyPermission::FullAccess }
+    }
+}
+
+/// Transactions that can be signed with the access key.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+pub enum AccessKeyPermission {
+    /// Key can sign any transaction of the account, including adding and deleting keys.
+    FullAccess,
+    /// Key can only sign function calls to the given contract and methods.
+    FunctionCall(FunctionCallPermission),
+}
+
+/// Limits of the access key that can only sign function calls.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct FunctionCallPermission {
     /// Balance amount on this Access Key. Can be used to pay for the transactions.
     #[serde(with = "u128_dec_format")]
-    pub amount: Balance,
+    pub allowance: Balance,
     /// Owner of the balance of this Access Key. None means the account owner.
     pub balance_owner: Option<AccountId>,
     /// Contract ID that can be called with this Access Key. None means the account owner.
     /// Access key only allows to call given contract_id.
     pub contract_id: Option<AccountId>,
-    /// The only method name that can be called with this Access Key. None means any method name.
-    pub method_name: Option<Vec<u8>>,
+    /// Method names that can be called with this Access Key. Empty means any method name.
+    pub method_names: Vec<Vec<u8>>,
 }
 
-impl fmt::Debug for AccessKey {
+impl fmt::Debug for FunctionCallPermission {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("AccessKey")
-            .field("amount", &self.amount)
+        f.debug_struct("FunctionCallPermission")
+            .field("allowance", &self.allowance)
             .field("balance_owner", &self.balance_owner)
             .field("contract_id", &self.contract_id)
-            .field("method_name", &self.method_name.as_ref().map(|v| logging::pretty_utf8(&v)))
+            .field(
+                "method_names",
+                &self.method_names.iter().map(|v| logging::pretty_utf8(&v)).collect::<Vec<_>>(),
+            )
             .finish()
     }
 }
@@ -131,36 +138,73 @@ impl TryFrom<access_key_proto::AccessKey> for AccessKey {
     type Error = Box<dyn std::error::Error>;
 
     fn try_from(access_key: access_key_proto::AccessKey) -> Result<Self, Self::Error> {
-        Ok(AccessKey {
-            amount: access_key.amount.unwrap_or_default().try_into()?,
-            balance_owner: access_key.balance_owner.into_option().map(|s| s.value),
-            contract_id: access_key.contract_id.into_option().map(|s| s.value),
-            method_name: access_key.method_name.into_option().map(|s| s.value),
-        })
+        use access_key_proto::AccessKey_oneof_permission::*;
+        let permission = match access_key.permission {
+            Some(full_access(_)) => AccessKeyPermission::FullAccess,
+            Some(function_call(permission)) => {
+                AccessKeyPermission::FunctionCall(FunctionCallPermission {
+                    allowance: permission.allowance.unwrap_or_default().try_into()?,
+                    balance_owner: permission.balance_owner.into_option().map(|s| s.value),
+                    contract_id: permission.contract_id.into_option().map(|s| s.value),
+                    method_names: permission.method_names.into_vec(),
+                })
+            }
+            None => return Err("No such access key permission".into()),
+        };
+        Ok(AccessKey { nonce: access_key.nonce, permission })
     }
 }
 
 impl From<AccessKey> for access_key_proto::AccessKey {
     fn from(access_key: AccessKey) -> Self {
+        use access_key_proto::AccessKey_oneof_permission;
+        let permission = match access_key.permission {
+            AccessKeyPermission::FullAccess => {
+                AccessKey_oneof_permission::full_access(Default::default())
+            }
+            AccessKeyPermission::FunctionCall(permission) => {
+                let permission = access_key_proto::FunctionCallPermission {
+                    allowance: SingularPtrField::some(permission.allowance.into()),
+                    balance_owner: SingularPtrField::from_option(
+                        permission.balance_owner.map(to_string_value),
+                    ),
+                    contract_id: SingularPtrField::from_option(
+                        permission.contract_id.map(to_string_value),
+                    ),
+                    method_names: RepeatedField::from_vec(permission.method_names),
+                    ..Default::default()
+                };
+                AccessKey_oneof_permission::function_call(permission)
+            }
+        };
         access_key_proto::AccessKey {
-            amount: SingularPtrField::some(access_key.amount.into()),
-            balance_owner: SingularPtrField::from_option(access_key.balance_owner.map(|v| {
-                let mut res = StringValue::new();
-                res.set_value(v);
-                res
-            })),
-            contract_id: SingularPtrField::from_option(access_key.contract_id.map(|v| {
-                let mut res = StringValue::new();
-                res.set_value(v);
-                res
-            })),
-            method_name: SingularPtrField::from_option(access_key.method_name.map(|v| {
-                let mut res = BytesValue::new();
-                res.set_value(v);
-                res
-            })),
-            cached_size: Default::default(),
-            unknown_fields: Default::default(),
+            nonce: access_key.nonce,
+            permission: Some(permission),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_key_proto_roundtrip() {
+        for access_key in vec![
+            AccessKey { nonce: 7, permission: AccessKeyPermission::FullAccess },
+            AccessKey {
+                nonce: 3,
+                permission: AccessKeyPermission::FunctionCall(FunctionCallPermission {
+                    allowance: 10,
+                    balance_owner: Some("bob.near".to_string()),
+                    contract_id: Some("eve.near".to_string()),
+                    method_names: vec![b"run".to_vec(), b"stop".to_vec()],
+                }),
+            },
+        ] {
+            let proto: access_key_proto::AccessKey = access_key.clone().into();
+            assert_eq!(AccessKey::try_from(proto).unwrap(), access_key);
         }
     }
 }
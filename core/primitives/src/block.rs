@@ -10,7 +10,7 @@ use protobuf::{Message as ProtoMessage, RepeatedField, SingularPtrField};
 use near_protos::chain as chain_proto;
 
 use crate::crypto::signature::{verify, PublicKey, Signature, DEFAULT_SIGNATURE};
-use crate::crypto::signer::EDSigner;
+use crate::crypto::signer::{EDSigner, SignerError};
 use crate::hash::{hash, CryptoHash};
 use crate::serialize::{base_format, vec_base_format};
 use crate::transaction::SignedTransaction;
@@ -103,6 +103,36 @@ impl BlockHeader {
         epoch_hash: CryptoHash,
         signer: Arc<dyn EDSigner>,
     ) -> Self {
+        Self::try_new(
+            height,
+            prev_hash,
+            prev_state_root,
+            tx_root,
+            timestamp,
+            approval_mask,
+            approval_sigs,
+            total_weight,
+            validator_proposal,
+            epoch_hash,
+            signer,
+        )
+        .expect("Failed to sign the block header")
+    }
+
+    /// Creates the block header, failing if the signer refuses to sign it.
+    pub fn try_new(
+        height: BlockIndex,
+        prev_hash: CryptoHash,
+        prev_state_root: MerkleHash,
+        tx_root: MerkleHash,
+        timestamp: DateTime<Utc>,
+        approval_mask: Vec<bool>,
+        approval_sigs: Vec<Signature>,
+        total_weight: Weight,
+        validator_proposal: Vec<ValidatorStake>,
+        epoch_hash: CryptoHash,
+        signer: Arc<dyn EDSigner>,
+    ) -> Result<Self, SignerError> {
         let hb = Self::header_body(
             height,
             prev_hash,
@@ -119,10 +149,10 @@ impl BlockHeader {
         let hash = hash(&bytes);
         let h = chain_proto::BlockHeader {
             body: SingularPtrField::some(hb),
-            signature: signer.sign(hash.as_ref()).into(),
+            signature: signer.sign_block_header(height, &hash)?.into(),
             ..Default::default()
         };
-        h.try_into().expect("Failed to parse just created header")
+        Ok(h.try_into().expect("Failed to parse just created header"))
     }
 
     pub fn genesis(state_root: MerkleHash, timestamp: DateTime<Utc>) -> Self {
@@ -151,6 +181,24 @@ impl BlockHeader {
         self.hash
     }
 
+    /// Computes hash of the header from its content. Unlike `hash`, doesn't rely on the cached
+    /// value, which comes as is from deserialization of possibly untrusted data.
+    pub fn compute_hash(&self) -> CryptoHash {
+        let header_body = Self::header_body(
+            self.height,
+            self.prev_hash,
+            self.prev_state_root,
+            self.tx_root,
+            self.timestamp,
+            self.approval_mask.clone(),
+            self.approval_sigs.clone(),
+            self.total_weight,
+            self.validator_proposal.clone(),
+            self.epoch_hash,
+        );
+        hash(&header_body.write_to_bytes().expect("Failed to serialize"))
+    }
+
     /// Verifies that given public key produced the block.
     pub fn verify_block_producer(&self, public_key: &PublicKey) -> bool {
         verify(self.hash.as_ref(), &self.signature, public_key)
@@ -251,10 +299,34 @@ impl Block {
         state_root: MerkleHash,
         epoch_hash: CryptoHash,
         transactions: Vec<SignedTransaction>,
-        mut approvals: HashMap<usize, Signature>,
+        approvals: HashMap<usize, Signature>,
         validator_proposal: Vec<ValidatorStake>,
         signer: Arc<dyn EDSigner>,
     ) -> Self {
+        Self::try_produce(
+            prev,
+            height,
+            state_root,
+            epoch_hash,
+            transactions,
+            approvals,
+            validator_proposal,
+            signer,
+        )
+        .expect("Failed to sign the block")
+    }
+
+    /// Produces new block like `produce`, failing if the signer refuses to sign it.
+    pub fn try_produce(
+        prev: &BlockHeader,
+        height: BlockIndex,
+        state_root: MerkleHash,
+        epoch_hash: CryptoHash,
+        transactions: Vec<SignedTransaction>,
+        mut approvals: HashMap<usize, Signature>,
+        validator_proposal: Vec<ValidatorStake>,
+        signer: Arc<dyn EDSigner>,
+    ) -> Result<Self, SignerError> {
         // TODO: merkelize transactions.
         let tx_root = CryptoHash::default();
         let (approval_mask, approval_sigs) = if let Some(max_approver) = approvals.keys().max() {
@@ -266,8 +338,8 @@ impl Block {
             (vec![], vec![])
         };
         let total_weight = (prev.total_weight.to_num() + (approval_sigs.len() as u64) + 1).into();
-        Block {
-            header: BlockHeader::new(
+        Ok(Block {
+            header: BlockHeader::try_new(
                 height,
                 prev.hash(),
                 state_root,
@@ -279,9 +351,9 @@ impl Block {
                 validator_proposal,
                 epoch_hash,
                 signer,
-            ),
+            )?,
             transactions,
-        }
+        })
     }
 
     pub fn hash(&self) -> CryptoHash {
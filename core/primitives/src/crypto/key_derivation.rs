@@ -0,0 +1,142 @@
+//! Deriving many account keys from one BIP39 mnemonic, following SLIP-0010 for ed25519.
+
+use std::error::Error;
+
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use exonum_sodiumoxide::crypto::sign::ed25519;
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+use crate::crypto::signature::{PublicKey, SecretKey};
+
+/// Derivation path of the first key, the last index selects the key.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/397'/0'";
+
+/// ed25519 supports only hardened derivation, so all indices of the path are hardened.
+const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+/// Generates new 12 words English mnemonic.
+pub fn generate_mnemonic() -> String {
+    Mnemonic::new(MnemonicType::Words12, Language::English).phrase().to_string()
+}
+
+/// Seed of the mnemonic protected with the given passphrase, empty if none.
+pub fn seed_from_mnemonic(phrase: &str, passphrase: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mnemonic =
+        Mnemonic::from_phrase(phrase, Language::English).map_err(|err| err.to_string())?;
+    Ok(Seed::new(&mnemonic, passphrase).as_bytes().to_vec())
+}
+
+/// Parses the derivation path like `m/44'/397'/0'` into indices. All indices must be hardened.
+pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(format!("Derivation path {} must start with m", path).into());
+    }
+    parts
+        .map(|part| -> Result<u32, Box<dyn Error>> {
+            let digits = part.trim_end_matches(|c| c == '\'' || c == 'h');
+            if digits.len() == part.len() {
+                return Err(format!("Index {} in derivation path must be hardened", part).into());
+            }
+            match digits.parse::<u32>() {
+                Ok(index) if index < HARDENED_OFFSET => Ok(index),
+                _ => Err(format!("Invalid index {} in derivation path", part).into()),
+            }
+        })
+        .collect()
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_varkey(key).expect("HMAC accepts keys of any size");
+    for data in data {
+        mac.input(data);
+    }
+    let result = mac.result().code();
+    let (mut left, mut right) = ([0; 32], [0; 32]);
+    left.copy_from_slice(&result[..32]);
+    right.copy_from_slice(&result[32..]);
+    (left, right)
+}
+
+/// Derives the private key at the given path from the seed.
+fn derive_private_key(seed: &[u8], path: &[u32]) -> [u8; 32] {
+    let (mut key, mut chain_code) = hmac_sha512(b"ed25519 seed", &[seed]);
+    for index in path {
+        let index = (index | HARDENED_OFFSET).to_be_bytes();
+        let (child_key, child_chain_code) =
+            hmac_sha512(&chain_code, &[&[0u8][..], &key[..], &index[..]]);
+        key = child_key;
+        chain_code = child_chain_code;
+    }
+    key
+}
+
+/// Derives the key pair at the given path from the seed.
+pub fn derive_key_pair(seed: &[u8], path: &[u32]) -> (PublicKey, SecretKey) {
+    let key = derive_private_key(seed, path);
+    let (public_key, secret_key) = ed25519::keypair_from_seed(&ed25519::Seed(key));
+    (PublicKey(public_key), SecretKey(secret_key))
+}
+
+/// Derives the key pair at the given path, like `m/44'/397'/0'`, from the mnemonic protected with
+/// the given passphrase.
+pub fn key_pair_from_mnemonic(
+    phrase: &str,
+    passphrase: &str,
+    path: &str,
+) -> Result<(PublicKey, SecretKey), Box<dyn Error>> {
+    let seed = seed_from_mnemonic(phrase, passphrase)?;
+    Ok(derive_key_pair(&seed, &parse_derivation_path(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Test vector 1 for ed25519 from SLIP-0010.
+    #[test]
+    fn slip10_test_vector() {
+        let seed = (0..16).collect::<Vec<u8>>();
+        for (path, private_key, public_key) in vec![
+            (
+                "m",
+                "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+                "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+            ),
+            (
+                "m/0'",
+                "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+                "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+            ),
+            (
+                "m/0'/1'",
+                "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+                "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+            ),
+        ] {
+            let path = parse_derivation_path(path).unwrap();
+            assert_eq!(to_hex(&derive_private_key(&seed, &path)), private_key);
+            let (derived_public_key, secret_key) = derive_key_pair(&seed, &path);
+            assert_eq!(to_hex(derived_public_key.as_ref()), public_key);
+            assert_eq!(to_hex(&secret_key.0[..32]), private_key);
+        }
+    }
+
+    #[test]
+    fn mnemonic_keys() {
+        let phrase = generate_mnemonic();
+        assert_eq!(phrase.split(' ').count(), 12);
+        let first = key_pair_from_mnemonic(&phrase, "", DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(first.0, key_pair_from_mnemonic(&phrase, "", "m/44'/397'/0'").unwrap().0);
+        assert_ne!(first.0, key_pair_from_mnemonic(&phrase, "", "m/44'/397'/1'").unwrap().0);
+        assert_ne!(first.0, key_pair_from_mnemonic(&phrase, "other", "m/44'/397'/0'").unwrap().0);
+        assert!(key_pair_from_mnemonic("not a mnemonic", "", DEFAULT_DERIVATION_PATH).is_err());
+        assert!(parse_derivation_path("m/44'/397/0'").is_err());
+        assert!(parse_derivation_path("44'/397'/0'").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod aggregate_signature;
 pub mod group_signature;
+pub mod key_derivation;
+pub mod remote_signer;
 pub mod signature;
 pub mod signer;
@@ -0,0 +1,366 @@
+//! Signer that keeps the validator secret key in a separate signer process instead of the node.
+//! Node sends newline-delimited JSON requests over a TCP connection and the signer answers each of
+//! them with a single line response.
+//!
+//! Block headers and approvals are signed with their height, so the signer can refuse to sign two
+//! different ones at the same height. This guards against a node that is buggy or restarted with
+//! lost state. Header and approval signatures are over the 32 bytes hash, so the signer refuses to
+//! sign arbitrary data of that size, which could be a hash signed around the guard.
+//!
+//! Every connection starts with the signer sending a random challenge, that the node answers with
+//! its HMAC under the secret key shared by the node and the signer. Only authenticated connections
+//! are served.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use exonum_sodiumoxide::crypto::hash::sha256::DIGESTBYTES;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+
+use crate::crypto::signature::{verify, PublicKey, Signature};
+use crate::crypto::signer::{EDSigner, SignerError};
+use crate::hash::CryptoHash;
+use crate::serialize::{base_bytes_format, base_format};
+use crate::types::{AccountId, BlockIndex};
+
+/// Timeout for connecting to the signer and for the signer to answer.
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimal length of the secret key shared by the node and the signer.
+pub const AUTH_KEY_MIN_LEN: usize = 16;
+
+/// Request from the node to the signer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SignRequest {
+    /// HMAC of the challenge under the shared secret key, must be the first request.
+    Auth(#[serde(with = "base_bytes_format")] Vec<u8>),
+    PublicKey,
+    /// Arbitrary data, signed without double signing protection. Data of the hash size is
+    /// refused.
+    Data(#[serde(with = "base_bytes_format")] Vec<u8>),
+    /// Announcement that the account is reachable through the peer in the epoch, the signer
+    /// computes the hash to sign itself.
+    AnnounceAccount {
+        account_id: AccountId,
+        #[serde(with = "base_bytes_format")]
+        peer_id: Vec<u8>,
+        #[serde(with = "base_format")]
+        epoch: CryptoHash,
+    },
+    BlockHeader {
+        height: BlockIndex,
+        #[serde(with = "base_format")]
+        hash: CryptoHash,
+    },
+    Approval {
+        height: BlockIndex,
+        #[serde(with = "base_format")]
+        hash: CryptoHash,
+    },
+}
+
+/// Response of the signer to the request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SignResponse {
+    /// Random challenge sent by the signer when the connection is opened.
+    Challenge(#[serde(with = "base_bytes_format")] Vec<u8>),
+    Authenticated,
+    PublicKey(#[serde(with = "base_format")] PublicKey),
+    Signature(#[serde(with = "base_format")] Signature),
+    /// Signer has already signed a different message of the same kind at this height.
+    DoubleSign(BlockIndex),
+    Error(String),
+}
+
+/// Reads the secret key shared by the node and the signer from the file.
+pub fn read_auth_key(path: &Path) -> io::Result<Vec<u8>> {
+    let key = fs::read(path)?;
+    let len = key.iter().rposition(|&c| c != b'\n' && c != b'\r').map_or(0, |i| i + 1);
+    if len < AUTH_KEY_MIN_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Auth key must be at least {} bytes", AUTH_KEY_MIN_LEN),
+        ));
+    }
+    Ok(key[..len].to_vec())
+}
+
+/// Random challenge for the connecting node.
+pub fn new_challenge() -> Vec<u8> {
+    thread_rng().gen::<[u8; 32]>().to_vec()
+}
+
+fn challenge_mac(auth_key: &[u8], challenge: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(auth_key).expect("HMAC accepts keys of any size");
+    mac.input(challenge);
+    mac
+}
+
+/// Answer to the challenge proving the knowledge of the shared secret key.
+pub fn answer_challenge(auth_key: &[u8], challenge: &[u8]) -> Vec<u8> {
+    challenge_mac(auth_key, challenge).result().code().to_vec()
+}
+
+/// Checks the answer to the challenge in constant time.
+pub fn verify_challenge(auth_key: &[u8], challenge: &[u8], answer: &[u8]) -> bool {
+    challenge_mac(auth_key, challenge).verify(answer).is_ok()
+}
+
+/// Height and hash of the last signed block header or approval.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedMessage {
+    pub height: BlockIndex,
+    #[serde(with = "base_format")]
+    pub hash: CryptoHash,
+}
+
+/// Last block header and approval signed by the signer. Signer persists it before answering, so
+/// it doesn't sign a different message at the same height after restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DoubleSignGuard {
+    pub last_block_header: Option<SignedMessage>,
+    pub last_approval: Option<SignedMessage>,
+}
+
+impl DoubleSignGuard {
+    /// Loads the guard from the given file, or returns the empty one if the file doesn't exist.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Saves the guard, replacing the file at once so the guard is not lost if the signer stops
+    /// in the middle of writing.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Records the block header to sign, unless a different header was signed at this height or a
+    /// header was signed at a greater height.
+    pub fn check_block_header(
+        &mut self,
+        height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<(), SignerError> {
+        Self::check(&mut self.last_block_header, height, hash)
+    }
+
+    /// Records the approval to sign, unless a different block was approved at this height or a
+    /// block was approved at a greater height.
+    pub fn check_approval(
+        &mut self,
+        height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<(), SignerError> {
+        Self::check(&mut self.last_approval, height, hash)
+    }
+
+    /// Refuses arbitrary data of the hash size, since signing it could give a signature of a
+    /// block header or approval that the guard hasn't checked.
+    pub fn check_data(&self, data: &[u8]) -> Result<(), SignerError> {
+        if data.len() == DIGESTBYTES {
+            return Err(SignerError::Other(format!(
+                "Refused to sign {} bytes of data, it could be a block header or approval hash",
+                data.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn check(
+        last: &mut Option<SignedMessage>,
+        height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<(), SignerError> {
+        if let Some(last) = last {
+            if last.height > height || (last.height == height && &last.hash != hash) {
+                return Err(SignerError::DoubleSign(height));
+            }
+        }
+        *last = Some(SignedMessage { height, hash: *hash });
+        Ok(())
+    }
+}
+
+/// Signer that forwards signing requests to the signer process.
+pub struct RemoteSigner {
+    addr: SocketAddr,
+    auth_key: Vec<u8>,
+    public_key: PublicKey,
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl RemoteSigner {
+    /// Connects to the signer at the given address, authenticates with the shared secret key and
+    /// fetches its public key.
+    pub fn connect(addr: SocketAddr, auth_key: Vec<u8>) -> Result<Self, SignerError> {
+        let mut connection =
+            Self::open(addr, &auth_key).map_err(|err| SignerError::Other(err.to_string()))?;
+        match Self::exchange(&mut connection, &SignRequest::PublicKey) {
+            Ok(SignResponse::PublicKey(public_key)) => Ok(RemoteSigner {
+                addr,
+                auth_key,
+                public_key,
+                connection: Mutex::new(Some(connection)),
+            }),
+            Ok(response) => Err(SignerError::Other(format!("Unexpected response {:?}", response))),
+            Err(err) => Err(SignerError::Other(err.to_string())),
+        }
+    }
+
+    fn open(addr: SocketAddr, auth_key: &[u8]) -> io::Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect_timeout(&addr, SIGNER_TIMEOUT)?;
+        stream.set_read_timeout(Some(SIGNER_TIMEOUT))?;
+        stream.set_write_timeout(Some(SIGNER_TIMEOUT))?;
+        let mut connection = BufReader::new(stream);
+        let challenge = match Self::read_response(&mut connection)? {
+            SignResponse::Challenge(challenge) => challenge,
+            response => return Err(Self::unexpected(response)),
+        };
+        let answer = SignRequest::Auth(answer_challenge(auth_key, &challenge));
+        match Self::exchange(&mut connection, &answer)? {
+            SignResponse::Authenticated => Ok(connection),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    fn unexpected(response: SignResponse) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected response {:?}", response))
+    }
+
+    fn read_response(connection: &mut BufReader<TcpStream>) -> io::Result<SignResponse> {
+        let mut response = String::new();
+        if connection.read_line(&mut response)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Signer closed connection"));
+        }
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    fn exchange(
+        connection: &mut BufReader<TcpStream>,
+        request: &SignRequest,
+    ) -> io::Result<SignResponse> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        connection.get_mut().write_all(line.as_bytes())?;
+        Self::read_response(connection)
+    }
+
+    /// Sends the request, reconnecting if the previous connection was lost.
+    fn request(&self, request: &SignRequest) -> Result<Signature, SignerError> {
+        let mut connection = self.connection.lock().expect("Remote signer lock is poisoned");
+        if connection.is_none() {
+            *connection = Some(
+                Self::open(self.addr, &self.auth_key)
+                    .map_err(|err| SignerError::Other(err.to_string()))?,
+            );
+        }
+        let response = Self::exchange(connection.as_mut().unwrap(), request);
+        if response.is_err() {
+            *connection = None;
+        }
+        match response {
+            Ok(SignResponse::Signature(signature)) => Ok(signature),
+            Ok(SignResponse::DoubleSign(height)) => Err(SignerError::DoubleSign(height)),
+            Ok(SignResponse::Error(err)) => Err(SignerError::Other(err)),
+            Ok(response) => Err(SignerError::Other(format!("Unexpected response {:?}", response))),
+            Err(err) => Err(SignerError::Other(err.to_string())),
+        }
+    }
+}
+
+impl EDSigner for RemoteSigner {
+    #[inline]
+    fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Infallible signing required by the trait. Node signs only through the fallible methods, so
+    /// a failing signer doesn't bring it down.
+    fn sign(&self, data: &[u8]) -> Signature {
+        match self.try_sign(data) {
+            Ok(signature) => signature,
+            Err(err) => panic!("Remote signer at {} failed to sign: {}", self.addr, err),
+        }
+    }
+
+    fn try_sign(&self, data: &[u8]) -> Result<Signature, SignerError> {
+        self.request(&SignRequest::Data(data.to_vec()))
+    }
+
+    fn verify(&self, data: &[u8], signature: &Signature) -> bool {
+        verify(data, signature, &self.public_key)
+    }
+
+    fn sign_announce_account(
+        &self,
+        account_id: &AccountId,
+        peer_id: &[u8],
+        epoch: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        self.request(&SignRequest::AnnounceAccount {
+            account_id: account_id.clone(),
+            peer_id: peer_id.to_vec(),
+            epoch: *epoch,
+        })
+    }
+
+    fn sign_block_header(
+        &self,
+        height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        self.request(&SignRequest::BlockHeader { height, hash: *hash })
+    }
+
+    fn sign_approval(
+        &self,
+        height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        self.request(&SignRequest::Approval { height, hash: *hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::hash;
+
+    use super::*;
+
+    #[test]
+    fn double_sign_guard() {
+        let mut guard = DoubleSignGuard::default();
+        let (hash1, hash2) = (hash(&[1]), hash(&[2]));
+        assert!(guard.check_block_header(5, &hash1).is_ok());
+        assert!(guard.check_block_header(5, &hash1).is_ok());
+        assert_eq!(guard.check_block_header(5, &hash2), Err(SignerError::DoubleSign(5)));
+        assert_eq!(guard.check_block_header(4, &hash2), Err(SignerError::DoubleSign(4)));
+        assert!(guard.check_block_header(6, &hash2).is_ok());
+        // Approvals are guarded separately from block headers.
+        assert!(guard.check_approval(5, &hash2).is_ok());
+        assert_eq!(guard.check_approval(5, &hash1), Err(SignerError::DoubleSign(5)));
+        // Hash sized data could be the header signed around the guard.
+        assert!(guard.check_data(hash2.as_ref()).is_err());
+        assert!(guard.check_data(b"telemetry").is_ok());
+    }
+
+    #[test]
+    fn challenge() {
+        let challenge = new_challenge();
+        let answer = answer_challenge(b"0123456789abcdef", &challenge);
+        assert!(verify_challenge(b"0123456789abcdef", &challenge, &answer));
+        assert!(!verify_challenge(b"fedcba9876543210", &challenge, &answer));
+        assert!(!verify_challenge(b"0123456789abcdef", &new_challenge(), &answer));
+    }
+}
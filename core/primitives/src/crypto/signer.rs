@@ -1,24 +1,65 @@
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
 
+use exonum_sodiumoxide::crypto::{pwhash, secretbox};
 use rand::distributions::Alphanumeric;
 use rand::rngs::OsRng;
 use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::crypto::aggregate_signature::BlsPublicKey;
 use crate::crypto::signature::{get_key_pair, sign, verify, PublicKey, SecretKey, Signature};
-use crate::serialize::base_format;
-use crate::types::{AccountId, PartialSignature};
+use crate::hash::{hash, CryptoHash};
+use crate::serialize::{base_bytes_format, base_format, BaseDecode, BaseEncode};
+use crate::types::{AccountId, BlockIndex, PartialSignature};
 
 /// Trait to abstract the signer account.
 pub trait AccountSigner: Sync + Send {
     fn account_id(&self) -> AccountId;
 }
 
+/// Error of signing block headers and approvals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignerError {
+    /// Signer has already signed a different block header or approval at this height.
+    DoubleSign(BlockIndex),
+    /// Signer is unreachable or failed to sign.
+    Other(String),
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::DoubleSign(height) => {
+                write!(f, "Refused to sign a different message at height {}", height)
+            }
+            SignerError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SignerError {}
+
+/// Hash of the announcement that the account is reachable through the peer in the epoch.
+pub fn announce_account_hash(
+    account_id: &AccountId,
+    peer_id: &[u8],
+    epoch: &CryptoHash,
+) -> CryptoHash {
+    hash([account_id.as_bytes(), peer_id, epoch.as_ref()].concat().as_slice())
+}
+
 /// Trait to abstract the way transaction signing with ed25519.
 /// Can be used to not keep private key in the given binary via cross-process communication.
 pub trait EDSigner: Sync + Send {
@@ -26,6 +67,41 @@ pub trait EDSigner: Sync + Send {
     fn sign(&self, data: &[u8]) -> Signature;
     fn verify(&self, data: &[u8], signature: &Signature) -> bool;
 
+    /// Signs the data like `sign`, but returns an error instead of panicking if the signer fails.
+    fn try_sign(&self, data: &[u8]) -> Result<Signature, SignerError> {
+        Ok(self.sign(data))
+    }
+
+    /// Signs the announcement that the account is reachable through the peer in the epoch.
+    fn sign_announce_account(
+        &self,
+        account_id: &AccountId,
+        peer_id: &[u8],
+        epoch: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        Ok(self.sign(announce_account_hash(account_id, peer_id, epoch).as_ref()))
+    }
+
+    /// Signs the hash of the block header at the given height. Signers protecting against double
+    /// signing refuse to sign two different headers at the same height.
+    fn sign_block_header(
+        &self,
+        _height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        Ok(self.sign(hash.as_ref()))
+    }
+
+    /// Signs the approval of the block with the given hash and height. Signers protecting against
+    /// double signing refuse to approve two different blocks at the same height.
+    fn sign_approval(
+        &self,
+        _height: BlockIndex,
+        hash: &CryptoHash,
+    ) -> Result<Signature, SignerError> {
+        Ok(self.sign(hash.as_ref()))
+    }
+
     fn write_to_file(&self, _path: &Path) {
         unimplemented!();
     }
@@ -38,6 +114,181 @@ pub trait BLSSigner: Sync + Send {
     fn bls_sign(&self, data: &[u8]) -> PartialSignature;
 }
 
+/// Environment variable with the passphrase of the encrypted key files.
+pub const PASSPHRASE_ENV_VAR: &str = "NEAR_KEY_PASSPHRASE";
+/// Environment variable with the path to the file with the passphrase of the encrypted key files.
+pub const PASSPHRASE_FILE_ENV_VAR: &str = "NEAR_KEY_PASSPHRASE_FILE";
+
+/// Field of the key file with the plaintext secret key.
+const SECRET_KEY_FIELD: &str = "secret_key";
+/// Field of the key file with the encrypted secret key, replaces the plaintext one.
+const ENCRYPTED_SECRET_KEY_FIELD: &str = "encrypted_secret_key";
+
+/// Source of the passphrase to decrypt the key files with.
+pub enum PassphraseSource {
+    /// Value of the given environment variable.
+    Env(String),
+    /// Content of the given file without the trailing newline.
+    File(PathBuf),
+    /// Asked on the terminal.
+    Prompt,
+}
+
+impl PassphraseSource {
+    /// Takes the passphrase from `NEAR_KEY_PASSPHRASE` if it is set, otherwise from the file at
+    /// `NEAR_KEY_PASSPHRASE_FILE` if it is set, otherwise asks for it on the terminal.
+    pub fn from_env() -> Self {
+        if env::var_os(PASSPHRASE_ENV_VAR).is_some() {
+            PassphraseSource::Env(PASSPHRASE_ENV_VAR.to_string())
+        } else if let Some(path) = env::var_os(PASSPHRASE_FILE_ENV_VAR) {
+            PassphraseSource::File(PathBuf::from(path))
+        } else {
+            PassphraseSource::Prompt
+        }
+    }
+
+    /// Reads the passphrase of the given key file.
+    pub fn read(&self, key_file_path: &Path) -> Result<String, Box<dyn Error>> {
+        Ok(match self {
+            PassphraseSource::Env(var) => env::var(var)?,
+            PassphraseSource::File(path) => {
+                fs::read_to_string(path)?.trim_end_matches(|c| c == '\n' || c == '\r').to_string()
+            }
+            PassphraseSource::Prompt => rpassword::read_password_from_tty(Some(&format!(
+                "Passphrase for {}: ",
+                key_file_path.display()
+            )))?,
+        })
+    }
+}
+
+/// Secret key encrypted with XSalsa20-Poly1305 under the key derived from the passphrase with
+/// scrypt. Stored in the key file in place of the plaintext secret key.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedSecretKey {
+    #[serde(with = "base_bytes_format")]
+    pub salt: Vec<u8>,
+    pub opslimit: usize,
+    pub memlimit: usize,
+    #[serde(with = "base_bytes_format")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "base_bytes_format")]
+    pub ciphertext: Vec<u8>,
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &pwhash::Salt,
+    opslimit: usize,
+    memlimit: usize,
+) -> Result<secretbox::Key, Box<dyn Error>> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    pwhash::derive_key(
+        &mut key.0,
+        passphrase.as_bytes(),
+        salt,
+        pwhash::OpsLimit(opslimit),
+        pwhash::MemLimit(memlimit),
+    )
+    .map_err(|_| "Failed to derive the key from the passphrase")?;
+    Ok(key)
+}
+
+/// Key derivation limits of the key file capped at the ones of libsodium for sensitive data, so a
+/// crafted key file can't make the node spend unbounded time and memory on decryption.
+fn key_derivation_limits(opslimit: usize, memlimit: usize) -> (usize, usize) {
+    let pwhash::OpsLimit(max_opslimit) = pwhash::OPSLIMIT_SENSITIVE;
+    let pwhash::MemLimit(max_memlimit) = pwhash::MEMLIMIT_SENSITIVE;
+    (std::cmp::min(opslimit, max_opslimit), std::cmp::min(memlimit, max_memlimit))
+}
+
+impl EncryptedSecretKey {
+    pub fn encrypt(secret_key: &SecretKey, passphrase: &str) -> Self {
+        let salt = pwhash::gen_salt();
+        let pwhash::OpsLimit(opslimit) = pwhash::OPSLIMIT_INTERACTIVE;
+        let pwhash::MemLimit(memlimit) = pwhash::MEMLIMIT_INTERACTIVE;
+        let key = derive_key(passphrase, &salt, opslimit, memlimit)
+            .expect("Failed to derive the key from the passphrase");
+        let nonce = secretbox::gen_nonce();
+        let ciphertext = secretbox::seal(&secret_key.0[..], &nonce, &key);
+        Self { salt: salt.0.to_vec(), opslimit, memlimit, nonce: nonce.0.to_vec(), ciphertext }
+    }
+
+    pub fn decrypt(&self, passphrase: &str) -> Result<SecretKey, Box<dyn Error>> {
+        let salt = pwhash::Salt::from_slice(&self.salt).ok_or("Invalid salt of the secret key")?;
+        let nonce =
+            secretbox::Nonce::from_slice(&self.nonce).ok_or("Invalid nonce of the secret key")?;
+        let (opslimit, memlimit) = key_derivation_limits(self.opslimit, self.memlimit);
+        let key = derive_key(passphrase, &salt, opslimit, memlimit)?;
+        let bytes = secretbox::open(&self.ciphertext, &nonce, &key)
+            .map_err(|_| "Wrong passphrase or corrupted secret key")?;
+        SecretKey::try_from(bytes.as_slice())
+    }
+}
+
+/// Reads the key file, decrypting the secret key with the passphrase from the given source if the
+/// file is encrypted. The passphrase is only read for the encrypted files.
+pub fn read_key_file<T: DeserializeOwned>(
+    path: &Path,
+    passphrase: &PassphraseSource,
+) -> Result<T, Box<dyn Error>> {
+    let mut value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if let Some(encrypted) =
+        value.as_object_mut().and_then(|fields| fields.remove(ENCRYPTED_SECRET_KEY_FIELD))
+    {
+        let encrypted: EncryptedSecretKey = serde_json::from_value(encrypted)?;
+        let secret_key = encrypted.decrypt(&passphrase.read(path)?)?;
+        value[SECRET_KEY_FIELD] = Value::String(secret_key.to_base());
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Writes the key file readable only by the owner into a temporary file and renames it over the
+/// original once it's on disk, so the key is not lost if writing is interrupted.
+fn replace_key_file(path: &Path, content: &str) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    // File left by an interrupted write may have other permissions.
+    if tmp_path.exists() {
+        fs::remove_file(&tmp_path)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(tmp_path, path)
+}
+
+/// Replaces the plaintext secret key in the key file with the one encrypted with the passphrase.
+pub fn encrypt_key_file(path: &Path, passphrase: &str) -> Result<(), Box<dyn Error>> {
+    let mut value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let fields = value.as_object_mut().ok_or("Key file is not a JSON object")?;
+    let secret_key = match fields.remove(SECRET_KEY_FIELD) {
+        Some(Value::String(secret_key)) => SecretKey::from_base(&secret_key)?,
+        _ => return Err(format!("No plaintext secret key in {}", path.display()).into()),
+    };
+    let encrypted = EncryptedSecretKey::encrypt(&secret_key, passphrase);
+    fields.insert(ENCRYPTED_SECRET_KEY_FIELD.to_string(), serde_json::to_value(encrypted)?);
+    replace_key_file(path, &serde_json::to_string_pretty(&value)?)?;
+    Ok(())
+}
+
+/// Replaces the encrypted secret key in the key file with the plaintext one.
+pub fn decrypt_key_file(path: &Path, passphrase: &str) -> Result<(), Box<dyn Error>> {
+    let mut value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let fields = value.as_object_mut().ok_or("Key file is not a JSON object")?;
+    let encrypted: EncryptedSecretKey = match fields.remove(ENCRYPTED_SECRET_KEY_FIELD) {
+        Some(encrypted) => serde_json::from_value(encrypted)?,
+        None => return Err(format!("No encrypted secret key in {}", path.display()).into()),
+    };
+    let secret_key = encrypted.decrypt(passphrase)?;
+    fields.insert(SECRET_KEY_FIELD.to_string(), Value::String(secret_key.to_base()));
+    replace_key_file(path, &serde_json::to_string_pretty(&value)?)?;
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct KeyFile {
     #[serde(with = "base_format")]
@@ -70,10 +321,9 @@ pub fn get_key_file(key_store_path: &Path, public_key: Option<String>) -> KeyFil
 
     let mut key_files = fs::read_dir(key_store_path).unwrap();
     let key_file = key_files.next();
-    let key_file_string = if key_files.count() != 0 {
+    let key_file_path = if key_files.count() != 0 {
         if let Some(p) = public_key {
-            let key_file_path = key_store_path.join(Path::new(&p));
-            fs::read_to_string(key_file_path).unwrap()
+            key_store_path.join(Path::new(&p))
         } else {
             println!(
                 "Public key must be specified when there is more than one \
@@ -82,10 +332,10 @@ pub fn get_key_file(key_store_path: &Path, public_key: Option<String>) -> KeyFil
             process::exit(4);
         }
     } else {
-        fs::read_to_string(key_file.unwrap().unwrap().path()).unwrap()
+        key_file.unwrap().unwrap().path()
     };
 
-    serde_json::from_str(&key_file_string).unwrap()
+    read_key_file(&key_file_path, &PassphraseSource::from_env()).unwrap()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -127,15 +377,9 @@ pub fn get_block_producer_key_file(
     if key_files_count == 0 && key_file.is_none() {
         panic!("No key file found in {:?}. Run `cargo run --package keystore -- keygen --test-seed alice.near` to set up testing keys.", key_store_path);
     }
-    let key_file_string = if key_files_count > 0 {
+    let key_file_path = if key_files_count > 0 {
         if let Some(p) = public_key {
-            let key_file_path = key_store_path.join(Path::new(&p));
-            match fs::read_to_string(key_file_path.clone()) {
-                Ok(content) => content,
-                Err(err) => {
-                    panic!("Failed to read key file {:?} with error: {}", key_file_path, err);
-                }
-            }
+            key_store_path.join(Path::new(&p))
         } else {
             println!(
                 "Public key must be specified when there is more than one \
@@ -144,16 +388,15 @@ pub fn get_block_producer_key_file(
             process::exit(4);
         }
     } else {
-        let path = key_file.unwrap().unwrap().path();
-        match fs::read_to_string(path.clone()) {
-            Ok(content) => content,
-            Err(err) => {
-                panic!("Failed to read key file {:?} with error: {}", path, err);
-            }
-        }
+        key_file.unwrap().unwrap().path()
     };
 
-    serde_json::from_str(&key_file_string).unwrap()
+    match read_key_file(&key_file_path, &PassphraseSource::from_env()) {
+        Ok(key_file) => key_file,
+        Err(err) => {
+            panic!("Failed to read key file {:?} with error: {}", key_file_path, err);
+        }
+    }
 }
 
 pub fn get_or_create_key_file(
@@ -192,12 +435,18 @@ impl InMemorySigner {
         Self { account_id, public_key, secret_key }
     }
 
-    /// Read key file into signer.
+    /// Read key file into signer, taking the passphrase of the encrypted key file from the
+    /// environment or the terminal.
     pub fn from_file(path: &Path) -> Self {
-        let mut file = File::open(path).expect("Could not open key file.");
-        let mut content = String::new();
-        file.read_to_string(&mut content).expect("Could not read from key file.");
-        InMemorySigner::from(content.as_str())
+        Self::from_file_with_passphrase(path, &PassphraseSource::from_env())
+    }
+
+    /// Read key file into signer, decrypting it with the passphrase from the given source.
+    pub fn from_file_with_passphrase(path: &Path, passphrase: &PassphraseSource) -> Self {
+        match read_key_file(path, passphrase) {
+            Ok(signer) => signer,
+            Err(err) => panic!("Failed to read key file {:?} with error: {}", path, err),
+        }
     }
 
     /// Initialize `InMemorySigner` with a random ED25519 and BLS keys, and random account id. Used
@@ -259,3 +508,61 @@ impl EDSigner for InMemorySigner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_secret_key() {
+        let (_, secret_key) = get_key_pair();
+        let encrypted = EncryptedSecretKey::encrypt(&secret_key, "passphrase");
+        assert!(encrypted.decrypt("passphrase").unwrap() == secret_key);
+        assert!(encrypted.decrypt("wrong passphrase").is_err());
+    }
+
+    #[test]
+    fn encrypted_key_file() {
+        let signer = InMemorySigner::new("test".to_string());
+        let path = env::temp_dir().join(format!("near_key_file_{}", signer.public_key));
+        signer.write_to_file(&path);
+
+        encrypt_key_file(&path, "passphrase").unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&signer.secret_key.to_base()));
+        assert!(encrypt_key_file(&path, "passphrase").is_err());
+
+        env::set_var("NEAR_TEST_KEY_PASSPHRASE", "passphrase");
+        let source = PassphraseSource::Env("NEAR_TEST_KEY_PASSPHRASE".to_string());
+        let loaded = InMemorySigner::from_file_with_passphrase(&path, &source);
+        assert_eq!(loaded.account_id, signer.account_id);
+        assert!(loaded.secret_key == signer.secret_key);
+        let key_file: KeyFile = read_key_file(&path, &source).unwrap();
+        assert_eq!(key_file.public_key, signer.public_key);
+
+        decrypt_key_file(&path, "passphrase").unwrap();
+        let loaded = InMemorySigner::from(fs::read_to_string(&path).unwrap().as_str());
+        assert!(loaded.secret_key == signer.secret_key);
+        assert!(!path.with_extension("tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn key_derivation_limits_are_capped() {
+        let pwhash::OpsLimit(opslimit) = pwhash::OPSLIMIT_INTERACTIVE;
+        let pwhash::MemLimit(memlimit) = pwhash::MEMLIMIT_INTERACTIVE;
+        assert_eq!(key_derivation_limits(opslimit, memlimit), (opslimit, memlimit));
+        let pwhash::OpsLimit(max_opslimit) = pwhash::OPSLIMIT_SENSITIVE;
+        let pwhash::MemLimit(max_memlimit) = pwhash::MEMLIMIT_SENSITIVE;
+        assert_eq!(
+            key_derivation_limits(std::usize::MAX, std::usize::MAX),
+            (max_opslimit, max_memlimit)
+        );
+    }
+}
@@ -5,19 +5,37 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::account::AccessKey;
+use crate::block::BlockHeader;
 use crate::crypto::signature::PublicKey;
 use crate::hash::CryptoHash;
-use crate::serialize::{base_format, u128_dec_format, vec_base_format};
-use crate::types::{AccountId, Balance, BlockIndex, MerkleHash, Nonce, Version};
+use crate::serialize::{base_format, u128_dec_format, vec_base_bytes_format, vec_base_format};
+use crate::types::{AccountId, Balance, BlockIndex, MerkleHash, ValidatorStake, Version};
+
+/// Finality of the requested block. There is no finality gadget yet, so the only option is the
+/// latest block known to the node.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Finality {
+    Latest,
+}
+
+/// Reference to a block by its height, base58 encoded hash or finality tag.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum BlockId {
+    Height(BlockIndex),
+    Finality(Finality),
+    Hash(#[serde(with = "base_format")] CryptoHash),
+}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct AccountViewCallResult {
     pub account_id: AccountId,
-    pub nonce: Nonce,
     #[serde(with = "u128_dec_format")]
     pub amount: Balance,
     #[serde(with = "u128_dec_format")]
     pub stake: Balance,
+    /// Full access keys of the account. Nonces and other keys are in the access key queries.
     #[serde(with = "vec_base_format")]
     pub public_keys: Vec<PublicKey>,
     #[serde(with = "base_format")]
@@ -41,6 +59,24 @@ pub struct QueryError {
     pub logs: Vec<String>,
 }
 
+/// Encoded trie nodes proving the state read by the query against the state root of the block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StateProof {
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
+    #[serde(with = "base_format")]
+    pub state_root: MerkleHash,
+    #[serde(with = "vec_base_bytes_format")]
+    pub nodes: Vec<Vec<u8>>,
+}
+
+/// Query result together with the proof of the state it was read from.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProvenQueryResponse {
+    pub result: Box<QueryResponse>,
+    pub proof: StateProof,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum QueryResponse {
@@ -50,6 +86,8 @@ pub enum QueryResponse {
     Error(QueryError),
     AccessKey(Option<AccessKey>),
     AccessKeyList(Vec<(PublicKey, AccessKey)>),
+    /// Only returned when the proof is requested, has to stay the last to be deserialized.
+    Proven(ProvenQueryResponse),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -85,6 +123,28 @@ pub struct StatusResponse {
     pub sync_info: StatusSyncInfo,
 }
 
+/// Block producers of the epoch, enough to verify headers that refer to it by `epoch_hash`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpochBlockProducers {
+    #[serde(with = "base_format")]
+    pub epoch_hash: CryptoHash,
+    /// Validators of the epoch with their staking keys.
+    pub validators: Vec<ValidatorStake>,
+    /// Block producer seats as indices into `validators`: block at height `h` is produced
+    /// by `validators[seats[h % seats.len()]]`.
+    pub seats: Vec<usize>,
+}
+
+/// Headers and block producers needed for a light client to move from a trusted header to
+/// a newer one: every header with validator proposals, both headers around each change of
+/// the epoch and the target header as the last one, in the order of heights.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LightClientProof {
+    pub headers: Vec<BlockHeader>,
+    /// Block producers of every epoch the headers refer to.
+    pub block_producers: Vec<EpochBlockProducers>,
+}
+
 impl TryFrom<QueryResponse> for AccountViewCallResult {
     type Error = String;
 
@@ -211,6 +211,36 @@ pub mod base_bytes_format {
     }
 }
 
+pub mod vec_base_bytes_format {
+    use serde::de;
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{from_base, to_base};
+
+    pub fn serialize<S>(data: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(data.len()))?;
+        for element in data {
+            seq.serialize_element(&to_base(element))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let elements = Vec::<String>::deserialize(deserializer)?;
+        elements
+            .iter()
+            .map(|s| from_base(s).map_err(|err| de::Error::custom(err.to_string())))
+            .collect()
+    }
+}
+
 pub mod u128_dec_format {
     use serde::de;
     use serde::{Deserialize, Deserializer, Serializer};
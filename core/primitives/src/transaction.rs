@@ -32,12 +32,20 @@ pub enum TransactionBody {
     AddKey(AddKeyTransaction),
     DeleteKey(DeleteKeyTransaction),
     DeleteAccount(DeleteAccountTransaction),
+    Batch(BatchTransaction),
 }
 
 impl TransactionBody {
-    pub fn send_money(nonce: Nonce, originator: &str, receiver: &str, amount: Balance) -> Self {
+    pub fn send_money(
+        nonce: Nonce,
+        originator: &str,
+        receiver: &str,
+        amount: Balance,
+        block_hash: CryptoHash,
+    ) -> Self {
         TransactionBody::SendMoney(SendMoneyTransaction {
             nonce,
+            block_hash,
             originator: originator.to_string(),
             receiver: receiver.to_string(),
             amount,
@@ -48,6 +56,8 @@ impl TransactionBody {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct CreateAccountTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     pub new_account_id: AccountId,
     #[serde(with = "u128_dec_format")]
@@ -63,6 +73,7 @@ impl TryFrom<transaction_proto::CreateAccountTransaction> for CreateAccountTrans
     fn try_from(t: transaction_proto::CreateAccountTransaction) -> Result<Self, Self::Error> {
         Ok(CreateAccountTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             new_account_id: t.new_account_id,
             amount: t.amount.unwrap_or_default().try_into()?,
@@ -75,6 +86,7 @@ impl From<CreateAccountTransaction> for transaction_proto::CreateAccountTransact
     fn from(t: CreateAccountTransaction) -> transaction_proto::CreateAccountTransaction {
         transaction_proto::CreateAccountTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             new_account_id: t.new_account_id,
             amount: SingularPtrField::some(t.amount.into()),
@@ -88,6 +100,7 @@ impl fmt::Debug for CreateAccountTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CreateAccountTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("originator", &format_args!("{}", &self.originator))
             .field("new_account_id", &format_args!("{}", &self.new_account_id))
             .field("amount", &format_args!("{}", &self.amount))
@@ -99,6 +112,8 @@ impl fmt::Debug for CreateAccountTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct DeployContractTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub contract_id: AccountId,
     pub wasm_byte_array: Vec<u8>,
 }
@@ -107,6 +122,7 @@ impl fmt::Debug for DeployContractTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("DeployContractTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("contract_id", &format_args!("{}", &self.contract_id))
             .field(
                 "wasm_byte_array",
@@ -116,13 +132,16 @@ impl fmt::Debug for DeployContractTransaction {
     }
 }
 
-impl From<transaction_proto::DeployContractTransaction> for DeployContractTransaction {
-    fn from(t: transaction_proto::DeployContractTransaction) -> Self {
-        DeployContractTransaction {
+impl TryFrom<transaction_proto::DeployContractTransaction> for DeployContractTransaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(t: transaction_proto::DeployContractTransaction) -> Result<Self, Self::Error> {
+        Ok(DeployContractTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             contract_id: t.contract_id,
             wasm_byte_array: t.wasm_byte_array,
-        }
+        })
     }
 }
 
@@ -130,6 +149,7 @@ impl From<DeployContractTransaction> for transaction_proto::DeployContractTransa
     fn from(t: DeployContractTransaction) -> transaction_proto::DeployContractTransaction {
         transaction_proto::DeployContractTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             contract_id: t.contract_id,
             wasm_byte_array: t.wasm_byte_array,
             ..Default::default()
@@ -140,6 +160,8 @@ impl From<DeployContractTransaction> for transaction_proto::DeployContractTransa
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct FunctionCallTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     pub contract_id: AccountId,
     pub method_name: Vec<u8>,
@@ -154,6 +176,7 @@ impl TryFrom<transaction_proto::FunctionCallTransaction> for FunctionCallTransac
     fn try_from(t: transaction_proto::FunctionCallTransaction) -> Result<Self, Self::Error> {
         Ok(FunctionCallTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             contract_id: t.contract_id,
             method_name: t.method_name,
@@ -167,6 +190,7 @@ impl From<FunctionCallTransaction> for transaction_proto::FunctionCallTransactio
     fn from(t: FunctionCallTransaction) -> Self {
         transaction_proto::FunctionCallTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             contract_id: t.contract_id,
             method_name: t.method_name,
@@ -181,6 +205,7 @@ impl fmt::Debug for FunctionCallTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FunctionCallTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("originator", &format_args!("{}", &self.originator))
             .field("contract_id", &format_args!("{}", &self.contract_id))
             .field("method_name", &format_args!("{}", logging::pretty_utf8(&self.method_name)))
@@ -193,6 +218,8 @@ impl fmt::Debug for FunctionCallTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct SendMoneyTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     pub receiver: AccountId,
     #[serde(with = "u128_dec_format")]
@@ -205,6 +232,7 @@ impl TryFrom<transaction_proto::SendMoneyTransaction> for SendMoneyTransaction {
     fn try_from(t: transaction_proto::SendMoneyTransaction) -> Result<Self, Self::Error> {
         Ok(SendMoneyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             receiver: t.receiver,
             amount: t.amount.unwrap_or_default().try_into()?,
@@ -216,6 +244,7 @@ impl From<SendMoneyTransaction> for transaction_proto::SendMoneyTransaction {
     fn from(t: SendMoneyTransaction) -> Self {
         transaction_proto::SendMoneyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             receiver: t.receiver,
             amount: SingularPtrField::some(t.amount.into()),
@@ -227,6 +256,8 @@ impl From<SendMoneyTransaction> for transaction_proto::SendMoneyTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct StakeTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     #[serde(with = "u128_dec_format")]
     pub amount: Balance,
@@ -239,6 +270,7 @@ impl TryFrom<transaction_proto::StakeTransaction> for StakeTransaction {
     fn try_from(t: transaction_proto::StakeTransaction) -> Result<Self, Self::Error> {
         Ok(StakeTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             amount: t.amount.unwrap_or_default().try_into()?,
             public_key: t.public_key,
@@ -250,6 +282,7 @@ impl From<StakeTransaction> for transaction_proto::StakeTransaction {
     fn from(t: StakeTransaction) -> transaction_proto::StakeTransaction {
         transaction_proto::StakeTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             amount: SingularPtrField::some(t.amount.into()),
             public_key: t.public_key,
@@ -261,6 +294,8 @@ impl From<StakeTransaction> for transaction_proto::StakeTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct SwapKeyTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     // one of the current keys to the account that will be swapped out
     #[serde(with = "base_bytes_format")]
@@ -269,14 +304,17 @@ pub struct SwapKeyTransaction {
     pub new_key: Vec<u8>,
 }
 
-impl From<transaction_proto::SwapKeyTransaction> for SwapKeyTransaction {
-    fn from(t: transaction_proto::SwapKeyTransaction) -> Self {
-        SwapKeyTransaction {
+impl TryFrom<transaction_proto::SwapKeyTransaction> for SwapKeyTransaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(t: transaction_proto::SwapKeyTransaction) -> Result<Self, Self::Error> {
+        Ok(SwapKeyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             cur_key: t.cur_key,
             new_key: t.new_key,
-        }
+        })
     }
 }
 
@@ -284,6 +322,7 @@ impl From<SwapKeyTransaction> for transaction_proto::SwapKeyTransaction {
     fn from(t: SwapKeyTransaction) -> transaction_proto::SwapKeyTransaction {
         transaction_proto::SwapKeyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             cur_key: t.cur_key,
             new_key: t.new_key,
@@ -296,6 +335,7 @@ impl fmt::Debug for SwapKeyTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("SwapKeyTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("originator", &format_args!("{}", &self.originator))
             .field("cur_key", &format_args!("{}", logging::pretty_utf8(&self.cur_key)))
             .field("new_key", &format_args!("{}", logging::pretty_utf8(&self.new_key)))
@@ -306,10 +346,13 @@ impl fmt::Debug for SwapKeyTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct AddKeyTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     #[serde(with = "base_bytes_format")]
     pub new_key: Vec<u8>,
-    pub access_key: Option<AccessKey>,
+    /// Key to add. Its nonce is the nonce from which transactions signed with the new key start.
+    pub access_key: AccessKey,
 }
 
 impl TryFrom<transaction_proto::AddKeyTransaction> for AddKeyTransaction {
@@ -318,12 +361,10 @@ impl TryFrom<transaction_proto::AddKeyTransaction> for AddKeyTransaction {
     fn try_from(t: transaction_proto::AddKeyTransaction) -> Result<Self, Self::Error> {
         Ok(AddKeyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator: t.originator,
             new_key: t.new_key,
-            access_key: t
-                .access_key
-                .into_option()
-                .map_or(Ok(None), |x| AccessKey::try_from(x).map(Some))?,
+            access_key: proto_to_result(t.access_key)?.try_into()?,
         })
     }
 }
@@ -332,9 +373,10 @@ impl From<AddKeyTransaction> for transaction_proto::AddKeyTransaction {
     fn from(t: AddKeyTransaction) -> transaction_proto::AddKeyTransaction {
         transaction_proto::AddKeyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             new_key: t.new_key,
-            access_key: SingularPtrField::from_option(t.access_key.map(std::convert::Into::into)),
+            access_key: SingularPtrField::some(t.access_key.into()),
             ..Default::default()
         }
     }
@@ -344,8 +386,10 @@ impl fmt::Debug for AddKeyTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddKeyTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("originator", &format_args!("{}", &self.originator))
             .field("new_key", &format_args!("{}", logging::pretty_utf8(&self.new_key)))
+            .field("access_key", &self.access_key)
             .finish()
     }
 }
@@ -353,13 +397,22 @@ impl fmt::Debug for AddKeyTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct DeleteKeyTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator: AccountId,
     pub cur_key: Vec<u8>,
 }
 
-impl From<transaction_proto::DeleteKeyTransaction> for DeleteKeyTransaction {
-    fn from(t: transaction_proto::DeleteKeyTransaction) -> Self {
-        DeleteKeyTransaction { nonce: t.nonce, originator: t.originator, cur_key: t.cur_key }
+impl TryFrom<transaction_proto::DeleteKeyTransaction> for DeleteKeyTransaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(t: transaction_proto::DeleteKeyTransaction) -> Result<Self, Self::Error> {
+        Ok(DeleteKeyTransaction {
+            nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
+            originator: t.originator,
+            cur_key: t.cur_key,
+        })
     }
 }
 
@@ -367,6 +420,7 @@ impl From<DeleteKeyTransaction> for transaction_proto::DeleteKeyTransaction {
     fn from(t: DeleteKeyTransaction) -> transaction_proto::DeleteKeyTransaction {
         transaction_proto::DeleteKeyTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator: t.originator,
             cur_key: t.cur_key,
             ..Default::default()
@@ -378,6 +432,7 @@ impl fmt::Debug for DeleteKeyTransaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("DeleteKeyTransaction")
             .field("nonce", &format_args!("{}", &self.nonce))
+            .field("block_hash", &format_args!("{}", &self.block_hash))
             .field("originator", &format_args!("{}", &self.originator))
             .field("cur_key", &format_args!("{}", logging::pretty_utf8(&self.cur_key)))
             .finish()
@@ -387,17 +442,22 @@ impl fmt::Debug for DeleteKeyTransaction {
 #[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct DeleteAccountTransaction {
     pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
     pub originator_id: AccountId,
     pub receiver_id: AccountId,
 }
 
-impl From<transaction_proto::DeleteAccountTransaction> for DeleteAccountTransaction {
-    fn from(t: transaction_proto::DeleteAccountTransaction) -> Self {
-        DeleteAccountTransaction {
+impl TryFrom<transaction_proto::DeleteAccountTransaction> for DeleteAccountTransaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(t: transaction_proto::DeleteAccountTransaction) -> Result<Self, Self::Error> {
+        Ok(DeleteAccountTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
             originator_id: t.originator_id,
             receiver_id: t.receiver_id,
-        }
+        })
     }
 }
 
@@ -405,6 +465,7 @@ impl From<DeleteAccountTransaction> for transaction_proto::DeleteAccountTransact
     fn from(t: DeleteAccountTransaction) -> Self {
         transaction_proto::DeleteAccountTransaction {
             nonce: t.nonce,
+            block_hash: t.block_hash.into(),
             originator_id: t.originator_id,
             receiver_id: t.receiver_id,
             ..Default::default()
@@ -412,6 +473,183 @@ impl From<DeleteAccountTransaction> for transaction_proto::DeleteAccountTransact
     }
 }
 
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct DeployContractAction {
+    #[serde(with = "base_bytes_format")]
+    pub code: Vec<u8>,
+}
+
+impl fmt::Debug for DeployContractAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeployContractAction")
+            .field("code", &format_args!("{}", logging::pretty_utf8(&self.code)))
+            .finish()
+    }
+}
+
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct TransferAction {
+    #[serde(with = "u128_dec_format")]
+    pub amount: Balance,
+}
+
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct StakeAction {
+    #[serde(with = "u128_dec_format")]
+    pub amount: Balance,
+    pub public_key: String,
+}
+
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AddKeyAction {
+    #[serde(with = "base_bytes_format")]
+    pub public_key: Vec<u8>,
+    pub access_key: AccessKey,
+}
+
+impl fmt::Debug for AddKeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AddKeyAction")
+            .field("public_key", &format_args!("{}", logging::pretty_utf8(&self.public_key)))
+            .field("access_key", &self.access_key)
+            .finish()
+    }
+}
+
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct DeleteKeyAction {
+    #[serde(with = "base_bytes_format")]
+    pub public_key: Vec<u8>,
+}
+
+impl fmt::Debug for DeleteKeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeleteKeyAction")
+            .field("public_key", &format_args!("{}", logging::pretty_utf8(&self.public_key)))
+            .finish()
+    }
+}
+
+/// Single step of a `BatchTransaction`. Actions are applied in order to the receiver of the batch.
+/// There is no function call action: contract calls run asynchronously as receipts, so a failing
+/// call couldn't revert the rest of the batch.
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum Action {
+    /// Creates the receiver account. Must come before any other action on the new account.
+    CreateAccount,
+    DeployContract(DeployContractAction),
+    Transfer(TransferAction),
+    Stake(StakeAction),
+    AddKey(AddKeyAction),
+    DeleteKey(DeleteKeyAction),
+}
+
+impl TryFrom<transaction_proto::Action> for Action {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(a: transaction_proto::Action) -> Result<Self, Self::Error> {
+        use transaction_proto::Action_oneof_action::*;
+        match a.action {
+            Some(create_account(_)) => Ok(Action::CreateAccount),
+            Some(deploy_contract(a)) => {
+                Ok(Action::DeployContract(DeployContractAction { code: a.code }))
+            }
+            Some(transfer(a)) => Ok(Action::Transfer(TransferAction {
+                amount: a.amount.unwrap_or_default().try_into()?,
+            })),
+            Some(stake(a)) => Ok(Action::Stake(StakeAction {
+                amount: a.amount.unwrap_or_default().try_into()?,
+                public_key: a.public_key,
+            })),
+            Some(add_key(a)) => Ok(Action::AddKey(AddKeyAction {
+                public_key: a.public_key,
+                access_key: proto_to_result(a.access_key)?.try_into()?,
+            })),
+            Some(delete_key(a)) => {
+                Ok(Action::DeleteKey(DeleteKeyAction { public_key: a.public_key }))
+            }
+            None => Err("No such action type".into()),
+        }
+    }
+}
+
+impl From<Action> for transaction_proto::Action {
+    fn from(a: Action) -> transaction_proto::Action {
+        use transaction_proto::Action_oneof_action;
+        let action = match a {
+            Action::CreateAccount => Action_oneof_action::create_account(Default::default()),
+            Action::DeployContract(a) => {
+                Action_oneof_action::deploy_contract(transaction_proto::DeployContractAction {
+                    code: a.code,
+                    ..Default::default()
+                })
+            }
+            Action::Transfer(a) => {
+                Action_oneof_action::transfer(transaction_proto::TransferAction {
+                    amount: SingularPtrField::some(a.amount.into()),
+                    ..Default::default()
+                })
+            }
+            Action::Stake(a) => Action_oneof_action::stake(transaction_proto::StakeAction {
+                amount: SingularPtrField::some(a.amount.into()),
+                public_key: a.public_key,
+                ..Default::default()
+            }),
+            Action::AddKey(a) => Action_oneof_action::add_key(transaction_proto::AddKeyAction {
+                public_key: a.public_key,
+                access_key: SingularPtrField::some(a.access_key.into()),
+                ..Default::default()
+            }),
+            Action::DeleteKey(a) => {
+                Action_oneof_action::delete_key(transaction_proto::DeleteKeyAction {
+                    public_key: a.public_key,
+                    ..Default::default()
+                })
+            }
+        };
+        transaction_proto::Action { action: Some(action), ..Default::default() }
+    }
+}
+
+/// Transaction that applies an ordered list of actions to a single receiver. Either all actions
+/// succeed or the state is left untouched.
+#[derive(Hash, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct BatchTransaction {
+    pub nonce: Nonce,
+    #[serde(with = "base_format")]
+    pub block_hash: CryptoHash,
+    pub originator: AccountId,
+    pub receiver_id: AccountId,
+    pub actions: Vec<Action>,
+}
+
+impl TryFrom<transaction_proto::BatchTransaction> for BatchTransaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(t: transaction_proto::BatchTransaction) -> Result<Self, Self::Error> {
+        Ok(BatchTransaction {
+            nonce: t.nonce,
+            block_hash: t.block_hash.try_into()?,
+            originator: t.originator,
+            receiver_id: t.receiver_id,
+            actions: t.actions.into_iter().map(Action::try_from).collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+impl From<BatchTransaction> for transaction_proto::BatchTransaction {
+    fn from(t: BatchTransaction) -> Self {
+        transaction_proto::BatchTransaction {
+            nonce: t.nonce,
+            block_hash: t.block_hash.into(),
+            originator: t.originator,
+            receiver_id: t.receiver_id,
+            actions: RepeatedField::from_iter(t.actions.into_iter().map(std::convert::Into::into)),
+            ..Default::default()
+        }
+    }
+}
+
 impl TransactionBody {
     pub fn get_nonce(&self) -> u64 {
         match self {
@@ -424,6 +662,24 @@ impl TransactionBody {
             TransactionBody::AddKey(t) => t.nonce,
             TransactionBody::DeleteKey(t) => t.nonce,
             TransactionBody::DeleteAccount(t) => t.nonce,
+            TransactionBody::Batch(t) => t.nonce,
+        }
+    }
+
+    /// Hash of the block this transaction refers to. The transaction can only be included
+    /// into the descendants of this block within the transaction validity period.
+    pub fn get_block_hash(&self) -> CryptoHash {
+        match self {
+            TransactionBody::Stake(t) => t.block_hash,
+            TransactionBody::SendMoney(t) => t.block_hash,
+            TransactionBody::DeployContract(t) => t.block_hash,
+            TransactionBody::FunctionCall(t) => t.block_hash,
+            TransactionBody::CreateAccount(t) => t.block_hash,
+            TransactionBody::SwapKey(t) => t.block_hash,
+            TransactionBody::AddKey(t) => t.block_hash,
+            TransactionBody::DeleteKey(t) => t.block_hash,
+            TransactionBody::DeleteAccount(t) => t.block_hash,
+            TransactionBody::Batch(t) => t.block_hash,
         }
     }
 
@@ -438,6 +694,7 @@ impl TransactionBody {
             TransactionBody::AddKey(t) => t.originator.clone(),
             TransactionBody::DeleteKey(t) => t.originator.clone(),
             TransactionBody::DeleteAccount(t) => t.originator_id.clone(),
+            TransactionBody::Batch(t) => t.originator.clone(),
         }
     }
 
@@ -453,6 +710,7 @@ impl TransactionBody {
             TransactionBody::AddKey(_) => None,
             TransactionBody::DeleteKey(_) => None,
             TransactionBody::DeleteAccount(_) => None,
+            TransactionBody::Batch(t) => Some(t.receiver_id.clone()),
         }
     }
 
@@ -494,6 +752,10 @@ impl TransactionBody {
                 let proto: transaction_proto::DeleteAccountTransaction = t.into();
                 proto.write_to_bytes()
             }
+            TransactionBody::Batch(t) => {
+                let proto: transaction_proto::BatchTransaction = t.into();
+                proto.write_to_bytes()
+            }
         };
         let bytes = bytes.unwrap();
         hash(&bytes)
@@ -505,7 +767,7 @@ pub struct SignedTransaction {
     pub body: TransactionBody,
     #[serde(with = "base_format")]
     pub signature: StructSignature,
-    // In case this TX uses AccessKey, it needs to provide the public_key
+    // Access key that signed the TX. Transactions without it are rejected
     #[serde(with = "option_base_format")]
     pub public_key: Option<PublicKey>,
     #[serde(with = "base_format")]
@@ -530,6 +792,7 @@ impl SignedTransaction {
     pub fn empty() -> SignedTransaction {
         let body = TransactionBody::SendMoney(SendMoneyTransaction {
             nonce: 0,
+            block_hash: CryptoHash::default(),
             originator: AccountId::default(),
             receiver: AccountId::default(),
             amount: 0,
@@ -569,7 +832,7 @@ impl TryFrom<transaction_proto::SignedTransaction> for SignedTransaction {
             }
             Some(transaction_proto::SignedTransaction_oneof_body::deploy_contract(t)) => {
                 bytes = t.write_to_bytes();
-                TransactionBody::DeployContract(DeployContractTransaction::from(t))
+                TransactionBody::DeployContract(DeployContractTransaction::try_from(t)?)
             }
             Some(transaction_proto::SignedTransaction_oneof_body::function_call(t)) => {
                 bytes = t.write_to_bytes();
@@ -585,7 +848,7 @@ impl TryFrom<transaction_proto::SignedTransaction> for SignedTransaction {
             }
             Some(transaction_proto::SignedTransaction_oneof_body::swap_key(t)) => {
                 bytes = t.write_to_bytes();
-                TransactionBody::SwapKey(SwapKeyTransaction::from(t))
+                TransactionBody::SwapKey(SwapKeyTransaction::try_from(t)?)
             }
             Some(transaction_proto::SignedTransaction_oneof_body::add_key(t)) => {
                 bytes = t.write_to_bytes();
@@ -597,7 +860,11 @@ impl TryFrom<transaction_proto::SignedTransaction> for SignedTransaction {
             }
             Some(transaction_proto::SignedTransaction_oneof_body::delete_account(t)) => {
                 bytes = t.write_to_bytes();
-                TransactionBody::DeleteAccount(DeleteAccountTransaction::from(t))
+                TransactionBody::DeleteAccount(DeleteAccountTransaction::try_from(t)?)
+            }
+            Some(transaction_proto::SignedTransaction_oneof_body::batch(t)) => {
+                bytes = t.write_to_bytes();
+                TransactionBody::Batch(BatchTransaction::try_from(t)?)
             }
             None => return Err("No such transaction body type".into()),
         };
@@ -645,6 +912,9 @@ impl From<SignedTransaction> for transaction_proto::SignedTransaction {
             TransactionBody::DeleteAccount(t) => {
                 transaction_proto::SignedTransaction_oneof_body::delete_account(t.into())
             }
+            TransactionBody::Batch(t) => {
+                transaction_proto::SignedTransaction_oneof_body::batch(t.into())
+            }
         };
         transaction_proto::SignedTransaction {
             body: Some(body),
@@ -1186,4 +1456,42 @@ mod tests {
         let invalid_keys = vec![wrong_public_key];
         assert!(!verify_transaction_signature(&transaction, &invalid_keys));
     }
+
+    #[test]
+    fn test_block_hash_is_signed() {
+        let body = TransactionBody::send_money(1, "alice.near", "bob.near", 10, hash(&[1]));
+        let other_body = TransactionBody::send_money(1, "alice.near", "bob.near", 10, hash(&[2]));
+        assert_ne!(body.get_hash(), other_body.get_hash());
+
+        let transaction = SignedTransaction::new(DEFAULT_SIGNATURE, body, None);
+        let proto: transaction_proto::SignedTransaction = transaction.clone().into();
+        let decoded = SignedTransaction::try_from(proto).unwrap();
+        assert_eq!(decoded.body.get_block_hash(), hash(&[1]));
+        assert_eq!(decoded.get_hash(), transaction.get_hash());
+    }
+
+    #[test]
+    fn test_batch_transaction_proto_roundtrip() {
+        let body = TransactionBody::Batch(BatchTransaction {
+            nonce: 1,
+            block_hash: hash(&[1]),
+            originator: "alice.near".to_string(),
+            receiver_id: "bob.near".to_string(),
+            actions: vec![
+                Action::CreateAccount,
+                Action::Transfer(TransferAction { amount: 10 }),
+                Action::AddKey(AddKeyAction {
+                    public_key: vec![1, 2, 3],
+                    access_key: AccessKey::full_access(),
+                }),
+                Action::DeployContract(DeployContractAction { code: vec![0, 1] }),
+            ],
+        });
+        let transaction = SignedTransaction::new(DEFAULT_SIGNATURE, body.clone(), None);
+        let proto: transaction_proto::SignedTransaction = transaction.clone().into();
+        let decoded = SignedTransaction::try_from(proto).unwrap();
+        assert_eq!(decoded.body, body);
+        assert_eq!(decoded.get_hash(), transaction.get_hash());
+        assert_eq!(decoded.body.get_contract_id(), Some("bob.near".to_string()));
+    }
 }
@@ -1,4 +1,4 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 use std::{fmt, io};
 
@@ -25,10 +25,13 @@ use near_protos::account as account_proto;
 use near_protos::receipt as receipt_proto;
 
 pub use crate::trie::{
+    proof::verify_prefix_proof, proof::verify_proof, state_parts::state_part_range,
+    state_parts::StatePart, state_parts::StatePartsInfo, state_parts::STATE_PART_MAX_SIZE,
     update::TrieUpdate, update::TrieUpdateIterator, Trie, TrieChanges, TrieIterator,
     WrappedTrieChanges,
 };
 
+mod metrics;
 pub mod test_utils;
 mod trie;
 
@@ -45,7 +48,11 @@ pub const COL_PROPOSALS: Option<u32> = Some(9);
 pub const COL_VALIDATORS: Option<u32> = Some(10);
 pub const COL_LAST_EPOCH_PROPOSALS: Option<u32> = Some(11);
 pub const COL_VALIDATOR_PROPOSALS: Option<u32> = Some(12);
-const NUM_COLS: u32 = 13;
+pub const COL_STATE_HEADERS: Option<u32> = Some(13);
+pub const COL_STATE_PARTS: Option<u32> = Some(14);
+pub const COL_TRIE_CHANGES: Option<u32> = Some(15);
+pub const COL_BLOCK_PER_HEIGHT: Option<u32> = Some(16);
+const NUM_COLS: u32 = 17;
 
 pub struct Store {
     storage: Arc<dyn KeyValueDB>,
@@ -268,6 +275,23 @@ pub fn get_access_key_raw(state_update: &TrieUpdate, key: &[u8]) -> Option<Acces
         .and_then(|value: access_key_proto::AccessKey| value.try_into().ok())
 }
 
+/// All access keys of the account together with their public keys.
+pub fn get_access_keys(
+    state_update: &TrieUpdate,
+    account_id: &AccountId,
+) -> Vec<(PublicKey, AccessKey)> {
+    let prefix = prefix_for_access_key(account_id);
+    let mut access_keys = vec![];
+    state_update.for_keys_with_prefix(&prefix, |key| {
+        if let (Ok(public_key), Some(access_key)) =
+            (PublicKey::try_from(&key[prefix.len()..]), get_access_key_raw(state_update, key))
+        {
+            access_keys.push((public_key, access_key));
+        }
+    });
+    access_keys
+}
+
 pub fn set_callback(state_update: &mut TrieUpdate, id: &[u8], callback: &Callback) {
     let proto: receipt_proto::Callback = callback.clone().into();
     set_proto(state_update, key_for_callback(id), &proto);
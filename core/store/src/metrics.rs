@@ -0,0 +1,15 @@
+use lazy_static::lazy_static;
+use near_metrics::IntCounter;
+
+lazy_static! {
+    pub static ref TRIE_CACHE_HITS: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_trie_cache_hits",
+            "Number of trie nodes read from the cache"
+        );
+    pub static ref TRIE_CACHE_MISSES: near_metrics::Result<IntCounter> =
+        near_metrics::try_create_int_counter(
+            "near_trie_cache_misses",
+            "Number of trie nodes read from the storage because they were not in the cache"
+        );
+}
@@ -9,14 +9,17 @@ use cached::{Cached, SizedCache};
 pub use kvdb::DBValue;
 use kvdb::{DBOp, DBTransaction};
 use log::error;
+use serde_derive::{Deserialize, Serialize};
 
 use near_primitives::hash::{hash, CryptoHash};
 
-use crate::{Store, StoreUpdate, COL_STATE};
+use crate::{metrics, Store, StoreUpdate, COL_STATE};
 
 use self::nibble_slice::NibbleSlice;
 
 mod nibble_slice;
+pub mod proof;
+pub mod state_parts;
 pub mod update;
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
@@ -323,8 +326,10 @@ impl TrieCachingStorage {
     fn retrieve_raw_bytes(&self, hash: &CryptoHash) -> Option<(Vec<u8>)> {
         let mut guard = self.cache.lock().expect(POISONED_LOCK_ERR);
         if let Some(val) = (*guard).cache_get(hash) {
+            near_metrics::inc_counter(&metrics::TRIE_CACHE_HITS);
             val.clone()
         } else {
+            near_metrics::inc_counter(&metrics::TRIE_CACHE_MISSES);
             let result = if let Ok(Some(bytes)) = self.store.get(COL_STATE, hash.as_ref()) {
                 Some(bytes)
             } else {
@@ -338,8 +343,10 @@ impl TrieCachingStorage {
     fn retrieve_rc(&self, hash: &CryptoHash) -> Option<u32> {
         let mut guard = self.cache.lock().expect(POISONED_LOCK_ERR);
         if let Some(val) = (*guard).cache_get(hash) {
+            near_metrics::inc_counter(&metrics::TRIE_CACHE_HITS);
             val.as_ref().map(|vec| RcTrieNode::decode_raw(&vec).expect("failed to decode").1)
         } else {
+            near_metrics::inc_counter(&metrics::TRIE_CACHE_MISSES);
             let val = if let Ok(Some(bytes)) = self.store.get(COL_STATE, hash.as_ref()) {
                 Some(bytes)
             } else {
@@ -391,6 +398,7 @@ pub struct Trie {
 /// Having old_root and values in deletions allows to apply TrieChanges in reverse
 ///
 /// StoreUpdate are the changes from current state refcount to refcount + delta.
+#[derive(Serialize, Deserialize)]
 pub struct TrieChanges {
     #[allow(dead_code)]
     old_root: CryptoHash,
@@ -417,13 +425,13 @@ impl TrieChanges {
         Ok(())
     }
 
-    pub fn deletions_into(
-        &self,
+    fn decrement_refcounts_into(
+        nodes: &[(CryptoHash, Vec<u8>, u32)],
         trie: Arc<Trie>,
         store_update: &mut StoreUpdate,
     ) -> Result<(), Box<dyn std::error::Error>> {
         store_update.trie = Some(trie.clone());
-        for (key, value, rc) in self.deletions.iter() {
+        for (key, value, rc) in nodes.iter() {
             let storage_rc = trie.storage.retrieve_rc(&key).unwrap_or_default();
             assert!(*rc <= storage_rc);
             if *rc < storage_rc {
@@ -436,6 +444,24 @@ impl TrieChanges {
         Ok(())
     }
 
+    pub fn deletions_into(
+        &self,
+        trie: Arc<Trie>,
+        store_update: &mut StoreUpdate,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::decrement_refcounts_into(&self.deletions, trie, store_update)
+    }
+
+    /// Decrements refcounts of the inserted nodes, discarding the state which was created by
+    /// these changes (e.g. when removing a fork).
+    pub fn revert_insertions_into(
+        &self,
+        trie: Arc<Trie>,
+        store_update: &mut StoreUpdate,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::decrement_refcounts_into(&self.insertions, trie, store_update)
+    }
+
     pub fn into(
         self,
         trie: Arc<Trie>,
@@ -471,6 +497,10 @@ impl WrappedTrieChanges {
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.trie_changes.deletions_into(self.trie.clone(), store_update)
     }
+
+    pub fn trie_changes(&self) -> &TrieChanges {
+        &self.trie_changes
+    }
 }
 
 enum FlattenNodesCrumb {
@@ -530,6 +560,16 @@ impl Trie {
         self.storage.retrieve_node(hash)
     }
 
+    /// Returns encoded node without the reference count, as it is hashed.
+    fn retrieve_raw_node(&self, hash: &CryptoHash) -> Result<Vec<u8>, String> {
+        match self.storage.retrieve_raw_bytes(hash) {
+            Some(bytes) => RcTrieNode::decode_raw(&bytes)
+                .map(|(data, _)| data.to_vec())
+                .map_err(|_| format!("Failed to decode node {}", hash)),
+            None => Err(format!("Node {} not found in storage", hash)),
+        }
+    }
+
     fn lookup(&self, root: &CryptoHash, mut key: NibbleSlice) -> Result<Option<Vec<u8>>, String> {
         let mut hash = *root;
 
@@ -1618,4 +1658,59 @@ mod tests {
         let trie2 = Arc::new(Trie::new(store));
         assert_eq!(trie2.get(&root, b"doge"), Some(b"coin".to_vec()));
     }
+
+    #[test]
+    fn test_trie_remove_old_state_and_forks() {
+        let store = create_test_store();
+        let trie = Arc::new(Trie::new(store.clone()));
+        let initial = vec![
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"docu".to_vec(), Some(b"value".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        let root1 = test_populate_trie(trie.clone(), &Trie::empty_root(), initial);
+        let apply_insertions = |changes: &super::TrieChanges| {
+            let mut store_update = store.store_update();
+            changes.insertions_into(trie.clone(), &mut store_update).unwrap();
+            store_update.commit().unwrap();
+        };
+
+        // Two children of the same state, only insertions are applied as done by the chain.
+        let changes2 = trie
+            .update(&root1, vec![(b"doge".to_vec(), Some(b"much coin".to_vec()))].into_iter())
+            .unwrap();
+        apply_insertions(&changes2);
+        let changes3 = trie
+            .update(&root1, vec![(b"dog".to_vec(), Some(b"puppy".to_vec()))].into_iter())
+            .unwrap();
+        apply_insertions(&changes3);
+        let (root2, root3) = (changes2.new_root, changes3.new_root);
+
+        // Remove the fork.
+        let mut store_update = store.store_update();
+        changes3.revert_insertions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(trie.get(&root3, b"dog"), None);
+        assert_eq!(trie.get(&root1, b"doge"), Some(b"coin".to_vec()));
+        assert_eq!(trie.get(&root2, b"doge"), Some(b"much coin".to_vec()));
+
+        // Remove the parent state.
+        let mut store_update = store.store_update();
+        changes2.deletions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(trie.get(&root1, b"doge"), None);
+        assert_eq!(trie.get(&root2, b"doge"), Some(b"much coin".to_vec()));
+        assert_eq!(trie.get(&root2, b"horse"), Some(b"stallion".to_vec()));
+
+        // Only nodes of the remaining state are left in the storage.
+        let store2 = create_test_store();
+        let trie2 = Arc::new(Trie::new(store2.clone()));
+        let final_state = vec![
+            (b"doge".to_vec(), Some(b"much coin".to_vec())),
+            (b"docu".to_vec(), Some(b"value".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        assert_eq!(test_populate_trie(trie2, &Trie::empty_root(), final_state), root2);
+        assert_eq!(store.iter(COL_STATE).count(), store2.iter(COL_STATE).count());
+    }
 }
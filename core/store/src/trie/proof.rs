@@ -0,0 +1,216 @@
+//! Proofs that a key is present in the state with a given value or is absent from it.
+//!
+//! Proof of a single key is the list of encoded trie nodes on the path from the state root to
+//! the key, starting from the root. Each node is checked against the hash referenced by its
+//! parent, so the proof can be verified knowing only the state root. Proof of a prefix contains
+//! all the nodes of the subtree under the prefix as well, which proves that no key is omitted.
+
+use std::collections::HashMap;
+
+use near_primitives::hash::{hash, CryptoHash};
+
+use super::nibble_slice::NibbleSlice;
+use super::state_parts::get_prefix_range;
+use super::{RawTrieNode, Trie};
+
+/// Walks from the state root to the key, taking nodes from `get_node`.
+/// Returns the value stored under the key, if there is one.
+fn walk_to_key<F>(
+    get_node: &mut F,
+    state_root: &CryptoHash,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, String>
+where
+    F: FnMut(&CryptoHash) -> Result<Vec<u8>, String>,
+{
+    let mut node_hash = *state_root;
+    let mut key = NibbleSlice::new(key);
+    loop {
+        if node_hash == Trie::empty_root() {
+            return Ok(None);
+        }
+        let bytes = get_node(&node_hash)?;
+        let node = RawTrieNode::decode(&bytes)
+            .map_err(|_| format!("Failed to decode node {}", node_hash))?;
+        match node {
+            RawTrieNode::Leaf(existing_key, value) => {
+                return Ok(if NibbleSlice::from_encoded(&existing_key).0 == key {
+                    Some(value)
+                } else {
+                    None
+                });
+            }
+            RawTrieNode::Extension(existing_key, child) => {
+                let existing_key = NibbleSlice::from_encoded(&existing_key).0;
+                if key.starts_with(&existing_key) {
+                    node_hash = child;
+                    key = key.mid(existing_key.len());
+                } else {
+                    return Ok(None);
+                }
+            }
+            RawTrieNode::Branch(children, value) => {
+                if key.is_empty() {
+                    return Ok(value);
+                }
+                match children[key.at(0) as usize] {
+                    Some(child) => {
+                        node_hash = child;
+                        key = key.mid(1);
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+    }
+}
+
+impl Trie {
+    /// Returns the proof of the value stored under the key, or of its absence.
+    pub fn get_proof(
+        &self,
+        state_root: &CryptoHash,
+        key: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut proof = vec![];
+        let mut get_node = |node_hash: &CryptoHash| -> Result<Vec<u8>, String> {
+            let bytes = self.retrieve_raw_node(node_hash)?;
+            proof.push(bytes.clone());
+            Ok(bytes)
+        };
+        walk_to_key(&mut get_node, state_root, key)?;
+        Ok(proof)
+    }
+
+    /// Returns the proof of all the items which keys start with given prefix.
+    pub fn get_prefix_proof(
+        &self,
+        state_root: &CryptoHash,
+        prefix: &[u8],
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        let get_node = |node_hash: &CryptoHash| self.retrieve_raw_node(node_hash);
+        let (_, proof) = get_prefix_range(&get_node, state_root, prefix)?;
+        Ok(proof)
+    }
+}
+
+/// Verifies the proof of a single key against the state root.
+/// Returns the value stored under the key, or `None` if the proof shows that the key is absent.
+pub fn verify_proof(
+    state_root: &CryptoHash,
+    key: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Option<Vec<u8>>, String> {
+    let mut nodes = proof.iter();
+    let mut get_node = |node_hash: &CryptoHash| match nodes.next() {
+        Some(node) if hash(node) == *node_hash => Ok(node.clone()),
+        Some(_) => Err(format!("Node {} doesn't match the proof", node_hash)),
+        None => Err(format!("Node {} is missing in the proof", node_hash)),
+    };
+    let value = walk_to_key(&mut get_node, state_root, key)?;
+    if nodes.next().is_some() {
+        return Err("Proof contains nodes that are not on the path to the key".to_string());
+    }
+    Ok(value)
+}
+
+/// Verifies the proof of a prefix against the state root.
+/// Returns all the items which keys start with the prefix, in the order of keys.
+pub fn verify_prefix_proof(
+    state_root: &CryptoHash,
+    prefix: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+    let nodes: HashMap<CryptoHash, &Vec<u8>> =
+        proof.iter().map(|node| (hash(node), node)).collect();
+    let get_node = |node_hash: &CryptoHash| match nodes.get(node_hash) {
+        Some(node) => Ok(node.to_vec()),
+        None => Err(format!("Node {} is missing in the proof", node_hash)),
+    };
+    get_prefix_range(&get_node, state_root, prefix).map(|(items, _)| items)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::create_trie;
+
+    use super::*;
+
+    fn populate_trie(trie: &std::sync::Arc<Trie>, items: &[(Vec<u8>, Vec<u8>)]) -> CryptoHash {
+        let changes = items.iter().map(|(key, value)| (key.clone(), Some(value.clone())));
+        let (store_update, root) =
+            trie.update(&Trie::empty_root(), changes).unwrap().into(trie.clone()).unwrap();
+        store_update.commit().unwrap();
+        root
+    }
+
+    fn test_items() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut items: Vec<_> = (0..50u32)
+            .map(|i| (format!("key{}", i * 7).into_bytes(), vec![i as u8; (i % 5) as usize + 1]))
+            .collect();
+        items.push((b"k".to_vec(), b"short".to_vec()));
+        items.push((b"key".to_vec(), b"value".to_vec()));
+        items.push((b"other".to_vec(), b"value".to_vec()));
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn test_proof_inclusion_and_exclusion() {
+        let trie = create_trie();
+        let items = test_items();
+        let root = populate_trie(&trie, &items);
+        for (key, value) in items.iter() {
+            let proof = trie.get_proof(&root, key).unwrap();
+            assert_eq!(verify_proof(&root, key, &proof), Ok(Some(value.clone())));
+        }
+        for key in [b"ke".to_vec(), b"key1".to_vec(), b"key7000".to_vec(), b"zzz".to_vec()].iter() {
+            let proof = trie.get_proof(&root, key).unwrap();
+            assert_eq!(verify_proof(&root, key, &proof), Ok(None));
+        }
+        let empty_root = Trie::empty_root();
+        let proof = trie.get_proof(&empty_root, b"key").unwrap();
+        assert!(proof.is_empty());
+        assert_eq!(verify_proof(&empty_root, b"key", &proof), Ok(None));
+    }
+
+    #[test]
+    fn test_proof_invalid() {
+        let trie = create_trie();
+        let items = test_items();
+        let root = populate_trie(&trie, &items);
+        let proof = trie.get_proof(&root, b"key14").unwrap();
+
+        assert!(verify_proof(&CryptoHash::default(), b"key14", &proof).is_err());
+
+        let mut missing_node = proof.clone();
+        missing_node.pop();
+        assert!(verify_proof(&root, b"key14", &missing_node).is_err());
+
+        let mut changed_node = proof.clone();
+        changed_node.last_mut().unwrap().push(1);
+        assert!(verify_proof(&root, b"key14", &changed_node).is_err());
+
+        let mut extra_node = proof.clone();
+        extra_node.push(proof[0].clone());
+        assert!(verify_proof(&root, b"key14", &extra_node).is_err());
+    }
+
+    #[test]
+    fn test_prefix_proof() {
+        let trie = create_trie();
+        let items = test_items();
+        let root = populate_trie(&trie, &items);
+        let expected: Vec<_> =
+            items.iter().filter(|(key, _)| key.starts_with(b"key1")).cloned().collect();
+        let proof = trie.get_prefix_proof(&root, b"key1").unwrap();
+        assert_eq!(verify_prefix_proof(&root, b"key1", &proof), Ok(expected));
+
+        let proof = trie.get_prefix_proof(&root, b"none").unwrap();
+        assert_eq!(verify_prefix_proof(&root, b"none", &proof), Ok(vec![]));
+
+        let mut missing_node = trie.get_prefix_proof(&root, b"key1").unwrap();
+        missing_node.pop();
+        assert!(verify_prefix_proof(&root, b"key1", &missing_node).is_err());
+    }
+}
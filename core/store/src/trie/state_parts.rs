@@ -0,0 +1,364 @@
+//! Splitting the state into parts that can be downloaded and verified independently.
+//!
+//! Keys of the trie are split into consecutive ranges of bounded size, given by the first keys of
+//! the parts. Each part carries the key-value pairs of its range together with every trie node that
+//! is on the path from the root to any key in the range. This allows to check that the part
+//! contains exactly the items that exist in the state in the range of its index without having the
+//! rest of the state, so parts that pass the check always add up to the whole state.
+
+use std::collections::{HashMap, HashSet};
+
+use serde_derive::{Deserialize, Serialize};
+
+use near_primitives::hash::{hash, CryptoHash};
+
+use super::nibble_slice::NibbleSlice;
+use super::{RawTrieNode, Trie};
+
+/// Maximum total size of keys and values in a single state part.
+pub const STATE_PART_MAX_SIZE: u64 = 256 * 1024;
+
+/// Part of the state of a shard with the proof against the state root.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StatePart {
+    /// Index of this part, starting from 0.
+    pub part_id: u64,
+    /// Key-value pairs of the state in this part, in the order of keys.
+    pub items: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Encoded trie nodes on the paths from the state root to the items of this part.
+    pub proof: Vec<Vec<u8>>,
+}
+
+impl StatePart {
+    /// Total size of keys and values in this part.
+    pub fn size(&self) -> u64 {
+        items_size(&self.items)
+    }
+}
+
+/// Result of splitting the state into parts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatePartsInfo {
+    /// First keys of the parts after the first one, in increasing order.
+    pub boundaries: Vec<Vec<u8>>,
+    /// Total size of keys and values in the state.
+    pub total_size: u64,
+}
+
+impl StatePartsInfo {
+    pub fn num_parts(&self) -> u64 {
+        self.boundaries.len() as u64 + 1
+    }
+}
+
+/// Range of keys of the part with given index: part `i` covers keys from boundary `i - 1`
+/// inclusive to boundary `i` exclusive, the first part starts from the empty key and the last one
+/// has no upper bound. Returns `None` if there is no such part.
+pub fn state_part_range(boundaries: &[Vec<u8>], part_id: u64) -> Option<(&[u8], Option<&[u8]>)> {
+    let part_id = part_id as usize;
+    if part_id > boundaries.len() {
+        return None;
+    }
+    let from = if part_id == 0 { &[][..] } else { &boundaries[part_id - 1][..] };
+    Some((from, boundaries.get(part_id).map(|to| &to[..])))
+}
+
+fn items_size(items: &[(Vec<u8>, Vec<u8>)]) -> u64 {
+    items.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum()
+}
+
+fn bytes_to_nibbles(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        result.push(byte >> 4);
+        result.push(byte & 0x0f);
+    }
+    result
+}
+
+fn nibbles_to_bytes(nibbles: &[u8]) -> Result<Vec<u8>, String> {
+    if nibbles.len() % 2 != 0 {
+        return Err("Key has odd number of nibbles".to_string());
+    }
+    Ok(nibbles.chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect())
+}
+
+/// Checks if the key is in the range [from, to), no upper bound if `to` is `None`.
+fn in_range(key: &[u8], from: &[u8], to: Option<&[u8]>) -> bool {
+    from <= key && to.map_or(true, |to| key < to)
+}
+
+/// Checks if there may be a key with given prefix in the range [from, to).
+fn prefix_intersects(prefix: &[u8], from: &[u8], to: Option<&[u8]>) -> bool {
+    let from_prefix = &from[..std::cmp::min(from.len(), prefix.len())];
+    from_prefix <= prefix && to.map_or(true, |to| prefix < to)
+}
+
+/// Walks the subtree of `hash` located at `prefix` and visits all nodes that may contain keys
+/// in the range [from, to) (given in nibbles). Collects the items from the range and
+/// the encoded nodes that were visited.
+fn visit_range<F>(
+    get_node: &F,
+    hash: &CryptoHash,
+    prefix: &mut Vec<u8>,
+    from: &[u8],
+    to: Option<&[u8]>,
+    items: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    nodes: &mut Vec<Vec<u8>>,
+    visited: &mut HashSet<CryptoHash>,
+) -> Result<(), String>
+where
+    F: Fn(&CryptoHash) -> Result<Vec<u8>, String>,
+{
+    if !prefix_intersects(prefix, from, to) {
+        return Ok(());
+    }
+    let bytes = get_node(hash)?;
+    let node =
+        RawTrieNode::decode(&bytes).map_err(|_| format!("Failed to decode node {}", hash))?;
+    if visited.insert(*hash) {
+        nodes.push(bytes);
+    }
+    match node {
+        RawTrieNode::Leaf(key, value) => {
+            let prefix_len = prefix.len();
+            prefix.extend(NibbleSlice::from_encoded(&key).0.iter());
+            if in_range(prefix, from, to) {
+                items.push((nibbles_to_bytes(prefix)?, value));
+            }
+            prefix.truncate(prefix_len);
+        }
+        RawTrieNode::Branch(children, value) => {
+            if let Some(value) = value {
+                if in_range(prefix, from, to) {
+                    items.push((nibbles_to_bytes(prefix)?, value));
+                }
+            }
+            for (index, child) in children.iter().enumerate() {
+                if let Some(child) = child {
+                    prefix.push(index as u8);
+                    visit_range(get_node, child, prefix, from, to, items, nodes, visited)?;
+                    prefix.pop();
+                }
+            }
+        }
+        RawTrieNode::Extension(key, child) => {
+            let prefix_len = prefix.len();
+            prefix.extend(NibbleSlice::from_encoded(&key).0.iter());
+            visit_range(get_node, &child, prefix, from, to, items, nodes, visited)?;
+            prefix.truncate(prefix_len);
+        }
+    }
+    Ok(())
+}
+
+/// Returns items in the range [from, to) (given in nibbles) and the nodes required to prove them.
+fn get_nibble_range<F>(
+    get_node: &F,
+    state_root: &CryptoHash,
+    from: &[u8],
+    to: Option<&[u8]>,
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>), String>
+where
+    F: Fn(&CryptoHash) -> Result<Vec<u8>, String>,
+{
+    let mut items = vec![];
+    let mut nodes = vec![];
+    if *state_root == Trie::empty_root() {
+        return Ok((items, nodes));
+    }
+    visit_range(
+        get_node,
+        state_root,
+        &mut vec![],
+        from,
+        to,
+        &mut items,
+        &mut nodes,
+        &mut HashSet::new(),
+    )?;
+    Ok((items, nodes))
+}
+
+/// Returns items in the range [from, to) and the nodes required to prove them.
+fn get_range<F>(
+    get_node: &F,
+    state_root: &CryptoHash,
+    from: &[u8],
+    to: Option<&[u8]>,
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>), String>
+where
+    F: Fn(&CryptoHash) -> Result<Vec<u8>, String>,
+{
+    let to = to.map(bytes_to_nibbles);
+    get_nibble_range(get_node, state_root, &bytes_to_nibbles(from), to.as_ref().map(|to| &to[..]))
+}
+
+/// Returns items which keys start with `prefix` and the nodes required to prove them.
+pub(super) fn get_prefix_range<F>(
+    get_node: &F,
+    state_root: &CryptoHash,
+    prefix: &[u8],
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>), String>
+where
+    F: Fn(&CryptoHash) -> Result<Vec<u8>, String>,
+{
+    let from = bytes_to_nibbles(prefix);
+    // Nibbles are less than 16, so every key with the prefix is below this bound.
+    let mut to = from.clone();
+    to.push(16);
+    get_nibble_range(get_node, state_root, &from, Some(&to))
+}
+
+impl Trie {
+    /// Splits the state with given root into parts with at most `max_part_size` bytes of
+    /// keys and values each (unless a single item is larger). The split only depends on the
+    /// content of the state, so all nodes produce the same parts for the same state root.
+    pub fn get_state_parts_info(
+        &self,
+        state_root: &CryptoHash,
+        max_part_size: u64,
+    ) -> Result<StatePartsInfo, Box<dyn std::error::Error>> {
+        let mut boundaries = vec![];
+        let mut total_size = 0;
+        let mut part_size = 0;
+        for item in self.iter(state_root)? {
+            let (key, value) = item?;
+            let item_size = (key.len() + value.len()) as u64;
+            if part_size > 0 && part_size + item_size > max_part_size {
+                boundaries.push(key);
+                part_size = 0;
+            }
+            part_size += item_size;
+            total_size += item_size;
+        }
+        Ok(StatePartsInfo { boundaries, total_size })
+    }
+
+    /// Returns the part of the state with given index in the split by given boundaries, with
+    /// the proof.
+    pub fn get_state_part(
+        &self,
+        state_root: &CryptoHash,
+        part_id: u64,
+        boundaries: &[Vec<u8>],
+    ) -> Result<StatePart, Box<dyn std::error::Error>> {
+        let (from, to) = state_part_range(boundaries, part_id)
+            .ok_or_else(|| format!("State part {} is out of range", part_id))?;
+        let get_node = |hash: &CryptoHash| self.retrieve_raw_node(hash);
+        let (items, proof) = get_range(&get_node, state_root, from, to)?;
+        Ok(StatePart { part_id, items, proof })
+    }
+
+    /// Checks that the state part contains exactly the items that are stored under given
+    /// state root in the range of keys of its index in the split by given boundaries.
+    pub fn validate_state_part(
+        state_root: &CryptoHash,
+        boundaries: &[Vec<u8>],
+        part: &StatePart,
+    ) -> bool {
+        let (from, to) = match state_part_range(boundaries, part.part_id) {
+            Some(range) => range,
+            None => return false,
+        };
+        let nodes: HashMap<CryptoHash, &Vec<u8>> =
+            part.proof.iter().map(|node| (hash(node), node)).collect();
+        let get_node = |hash: &CryptoHash| match nodes.get(hash) {
+            Some(node) => Ok(node.to_vec()),
+            None => Err(format!("Node {} is missing in the proof", hash)),
+        };
+        match get_range(&get_node, state_root, from, to) {
+            Ok((items, _)) => items == part.items,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::create_trie;
+
+    use super::*;
+
+    fn populate_trie(trie: &std::sync::Arc<Trie>, items: &[(Vec<u8>, Vec<u8>)]) -> CryptoHash {
+        let changes = items.iter().map(|(key, value)| (key.clone(), Some(value.clone())));
+        let (store_update, root) =
+            trie.update(&Trie::empty_root(), changes).unwrap().into(trie.clone()).unwrap();
+        store_update.commit().unwrap();
+        root
+    }
+
+    fn test_items() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut items: Vec<_> = (0..200u32)
+            .map(|i| (format!("key{}", i * 7).into_bytes(), vec![i as u8; (i % 13) as usize + 1]))
+            .collect();
+        items.push((b"k".to_vec(), b"short".to_vec()));
+        items.push((b"key".to_vec(), b"value".to_vec()));
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn test_state_parts_cover_state() {
+        let trie = create_trie();
+        let items = test_items();
+        let root = populate_trie(&trie, &items);
+        let info = trie.get_state_parts_info(&root, 100).unwrap();
+        assert!(info.num_parts() > 1);
+        assert_eq!(info.total_size, items_size(&items));
+        let mut all_items = vec![];
+        for part_id in 0..info.num_parts() {
+            let part = trie.get_state_part(&root, part_id, &info.boundaries).unwrap();
+            assert!(!part.items.is_empty());
+            assert!(Trie::validate_state_part(&root, &info.boundaries, &part));
+            all_items.extend(part.items);
+        }
+        assert_eq!(all_items, items);
+        assert!(trie.get_state_part(&root, info.num_parts(), &info.boundaries).is_err());
+    }
+
+    #[test]
+    fn test_state_parts_empty_state() {
+        let trie = create_trie();
+        let root = Trie::empty_root();
+        let info = trie.get_state_parts_info(&root, 100).unwrap();
+        assert_eq!(info.num_parts(), 1);
+        let part = trie.get_state_part(&root, 0, &info.boundaries).unwrap();
+        assert!(part.items.is_empty());
+        assert!(Trie::validate_state_part(&root, &info.boundaries, &part));
+    }
+
+    #[test]
+    fn test_state_parts_invalid() {
+        let trie = create_trie();
+        let items = test_items();
+        let root = populate_trie(&trie, &items);
+        let info = trie.get_state_parts_info(&root, 100).unwrap();
+        let boundaries = &info.boundaries;
+        let part = trie.get_state_part(&root, 1, boundaries).unwrap();
+
+        let mut missing_item = part.clone();
+        missing_item.items.remove(1);
+        assert!(!Trie::validate_state_part(&root, boundaries, &missing_item));
+
+        let mut changed_value = part.clone();
+        changed_value.items[0].1.push(1);
+        assert!(!Trie::validate_state_part(&root, boundaries, &changed_value));
+
+        let mut missing_node = part.clone();
+        missing_node.proof.pop();
+        assert!(!Trie::validate_state_part(&root, boundaries, &missing_node));
+
+        assert!(!Trie::validate_state_part(&CryptoHash::default(), boundaries, &part));
+        let empty_part = StatePart { part_id: 1, items: vec![], proof: part.proof.clone() };
+        assert!(!Trie::validate_state_part(&root, boundaries, &empty_part));
+
+        // Valid part of another range is rejected.
+        let mut other_part = trie.get_state_part(&root, 2, boundaries).unwrap();
+        assert!(Trie::validate_state_part(&root, boundaries, &other_part));
+        other_part.part_id = 1;
+        assert!(!Trie::validate_state_part(&root, boundaries, &other_part));
+        other_part.part_id = info.num_parts();
+        assert!(!Trie::validate_state_part(&root, boundaries, &other_part));
+    }
+}
@@ -15,11 +15,13 @@ use serde_derive::{Deserialize, Serialize};
 
 use near_client::BlockProducer;
 use near_client::ClientConfig;
+use near_client::Clock;
 use near_jsonrpc::RpcConfig;
 use near_network::test_utils::open_port;
-use near_network::types::PROTOCOL_VERSION;
+use near_network::types::{MessageLimits, PROTOCOL_VERSION, ROUTED_MESSAGE_TTL};
 use near_network::NetworkConfig;
-use near_primitives::account::Account;
+use near_primitives::account::{AccessKey, Account};
+use near_primitives::crypto::remote_signer::{read_auth_key, RemoteSigner};
 use near_primitives::crypto::signer::{EDSigner, InMemorySigner, KeyFile};
 use near_primitives::hash::hash;
 use near_primitives::serialize::{to_base64, u128_dec_format};
@@ -58,11 +60,25 @@ pub const EXPECTED_EPOCH_LENGTH: BlockIndex = (5 * 60) / MIN_BLOCK_PRODUCTION_DE
 /// Criterion for kicking out validators.
 pub const VALIDATOR_KICKOUT_THRESHOLD: f64 = 0.9;
 
+/// Number of blocks for which a transaction is valid after the block it refers to.
+pub const TRANSACTION_VALIDITY_PERIOD: BlockIndex = 2 * EXPECTED_EPOCH_LENGTH;
+
+fn default_transaction_validity_period() -> BlockIndex {
+    TRANSACTION_VALIDITY_PERIOD
+}
+
 /// Fast mode constants for testing/developing.
 pub const FAST_MIN_BLOCK_PRODUCTION_DELAY: u64 = 10;
 pub const FAST_MAX_BLOCK_PRODUCTION_DELAY: u64 = 100;
 pub const FAST_EPOCH_LENGTH: u64 = 60;
 
+/// Default limits of the transaction pool.
+pub const TX_POOL_MAX_SIZE: usize = 10_000;
+pub const TX_POOL_MAX_SIZE_PER_ACCOUNT: usize = 100;
+
+/// Number of epochs of blocks and state kept by non archival nodes.
+pub const DEFAULT_GC_NUM_EPOCHS_TO_KEEP: BlockIndex = 5;
+
 pub const CONFIG_FILENAME: &str = "config.json";
 pub const GENESIS_CONFIG_FILENAME: &str = "genesis.json";
 pub const NODE_KEY_FILE: &str = "node_key.json";
@@ -75,7 +91,7 @@ pub struct Network {
     /// Address to listen for incoming connections.
     pub addr: String,
     /// Address to advertise to peers for them to connect.
-    /// If empty, will use the same port as the addr, and will introspect on the listener.
+    /// If empty, will use the same port as the addr, and the IP address peers observe us at.
     pub external_address: String,
     /// Comma separated list of nodes to connect to.
     pub boot_nodes: String,
@@ -89,6 +105,9 @@ pub struct Network {
     pub skip_sync_wait: bool,
     /// Ban window for peers who misbehave.
     pub ban_window: Duration,
+    /// Limits of the size and the rate of messages received from a peer by the message type.
+    #[serde(default)]
+    pub message_limits: MessageLimits,
 }
 
 impl Default for Network {
@@ -102,11 +121,13 @@ impl Default for Network {
             reconnect_delay: Duration::from_secs(60),
             skip_sync_wait: false,
             ban_window: Duration::from_secs(3 * 60 * 60),
+            message_limits: MessageLimits::default(),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Consensus {
     /// Minimum number of peers to start syncing.
     pub min_num_peers: usize,
@@ -116,6 +137,10 @@ pub struct Consensus {
     pub max_block_production_delay: Duration,
     /// Produce empty blocks, use `false` for testing.
     pub produce_empty_blocks: bool,
+    /// Maximum number of transactions in the transaction pool.
+    pub tx_pool_max_size: usize,
+    /// Maximum number of transactions from a single account in the transaction pool.
+    pub tx_pool_max_size_per_account: usize,
 }
 
 impl Default for Consensus {
@@ -125,10 +150,22 @@ impl Default for Consensus {
             min_block_production_delay: Duration::from_secs(MIN_BLOCK_PRODUCTION_DELAY),
             max_block_production_delay: Duration::from_secs(MAX_BLOCK_PRODUCTION_DELAY),
             produce_empty_blocks: true,
+            tx_pool_max_size: TX_POOL_MAX_SIZE,
+            tx_pool_max_size_per_account: TX_POOL_MAX_SIZE_PER_ACCOUNT,
         }
     }
 }
 
+/// Validator account signed for by a remote signer process.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RemoteSignerConfig {
+    pub account_id: AccountId,
+    /// Address of the signer process.
+    pub addr: String,
+    /// File with the secret key shared with the signer process, relative to the home directory.
+    pub auth_key_file: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct Config {
@@ -139,6 +176,12 @@ pub struct Config {
     pub telemetry: TelemetryConfig,
     pub network: Network,
     pub consensus: Consensus,
+    /// Keep all the blocks and state instead of garbage collecting old ones.
+    pub archive: bool,
+    /// Number of epochs of blocks and state to keep if not archival.
+    pub gc_num_epochs_to_keep: BlockIndex,
+    /// Sign blocks and approvals with the remote signer instead of the validator key file.
+    pub remote_signer: Option<RemoteSignerConfig>,
 }
 
 impl Default for Config {
@@ -151,6 +194,9 @@ impl Default for Config {
             telemetry: TelemetryConfig::default(),
             network: Network::default(),
             consensus: Consensus::default(),
+            archive: false,
+            gc_num_epochs_to_keep: DEFAULT_GC_NUM_EPOCHS_TO_KEEP,
+            remote_signer: None,
         }
     }
 }
@@ -220,6 +266,12 @@ impl NearConfig {
                 block_fetch_horizon: 50,
                 state_fetch_horizon: 5,
                 block_header_fetch_horizon: 50,
+                archive: config.archive,
+                gc_num_epochs_to_keep: config.gc_num_epochs_to_keep,
+                tx_pool_max_size: config.consensus.tx_pool_max_size,
+                tx_pool_max_size_per_account: config.consensus.tx_pool_max_size_per_account,
+                tx_routing_height_horizon: 4,
+                clock: Clock::Real,
             },
             network_config: NetworkConfig {
                 public_key: network_key_pair.public_key,
@@ -248,6 +300,13 @@ impl NearConfig {
                 max_send_peers: 512,
                 peer_expiration_duration: Duration::from_secs(7 * 24 * 60 * 60),
                 peer_stats_period: Duration::from_secs(5),
+                external_address: if config.network.external_address.is_empty() {
+                    None
+                } else {
+                    Some(config.network.external_address.parse().unwrap())
+                },
+                message_limits: config.network.message_limits,
+                routed_message_ttl: ROUTED_MESSAGE_TTL,
             },
             telemetry_config: config.telemetry,
             rpc_config: config.rpc,
@@ -266,7 +325,12 @@ impl NearConfig {
         self.config.write_to_file(&dir.join(CONFIG_FILENAME));
 
         if let Some(block_producer) = &self.block_producer {
-            block_producer.signer.write_to_file(&dir.join(self.config.validator_key_file.clone()));
+            // Remote signer keeps the validator key with itself.
+            if self.config.remote_signer.is_none() {
+                block_producer
+                    .signer
+                    .write_to_file(&dir.join(self.config.validator_key_file.clone()));
+            }
         }
 
         let network_signer = InMemorySigner::from_secret_key(
@@ -310,6 +374,10 @@ pub struct GenesisConfig {
     pub epoch_length: BlockIndex,
     /// Criterion for kicking out validators
     pub validator_kickout_threshold: f64,
+    /// Number of blocks after the referenced block during which a transaction can be included.
+    /// Genesis files written before it was introduced get `TRANSACTION_VALIDITY_PERIOD`.
+    #[serde(default = "default_transaction_validity_period")]
+    pub transaction_validity_period: BlockIndex,
     /// Runtime configuration (mostly economics constants).
     pub runtime_config: RuntimeConfig,
     /// List of initial validators.
@@ -338,16 +406,19 @@ impl GenesisConfig {
             records[0].push(StateRecord::Account {
                 account_id: account.to_string(),
                 account: Account {
-                    nonce: 0,
                     amount: TESTING_INIT_BALANCE
                         - if i < num_validators { TESTING_INIT_STAKE } else { 0 },
-                    public_keys: vec![signer.public_key],
                     code_hash,
                     staked: if i < num_validators { TESTING_INIT_STAKE } else { 0 },
                     storage_usage: 0,
                     storage_paid_at: 0,
                 },
             });
+            records[0].push(StateRecord::AccessKey {
+                account_id: account.to_string(),
+                public_key: signer.public_key.to_readable(),
+                access_key: AccessKey::full_access(),
+            });
             records[0].push(StateRecord::Contract {
                 account_id: account.to_string(),
                 code: encoded_test_contract.clone(),
@@ -363,6 +434,7 @@ impl GenesisConfig {
             dynamic_resharding: false,
             epoch_length: FAST_EPOCH_LENGTH,
             validator_kickout_threshold: VALIDATOR_KICKOUT_THRESHOLD,
+            transaction_validity_period: TRANSACTION_VALIDITY_PERIOD,
             runtime_config: Default::default(),
             validators,
             records,
@@ -387,7 +459,7 @@ impl GenesisConfig {
                     amount: TESTING_INIT_STAKE,
                 });
             }
-            records.push(StateRecord::account(
+            records.extend(StateRecord::account(
                 &account_id,
                 &signer.public_key.to_readable().0,
                 TESTING_INIT_BALANCE - if i < num_validators { TESTING_INIT_STAKE } else { 0 },
@@ -404,6 +476,7 @@ impl GenesisConfig {
             dynamic_resharding: false,
             epoch_length: FAST_EPOCH_LENGTH,
             validator_kickout_threshold: VALIDATOR_KICKOUT_THRESHOLD,
+            transaction_validity_period: TRANSACTION_VALIDITY_PERIOD,
             runtime_config: Default::default(),
             validators,
             records: vec![records],
@@ -538,18 +611,19 @@ pub fn init_configs(
                 dynamic_resharding: false,
                 epoch_length: if fast { FAST_EPOCH_LENGTH } else { EXPECTED_EPOCH_LENGTH },
                 validator_kickout_threshold: VALIDATOR_KICKOUT_THRESHOLD,
+                transaction_validity_period: TRANSACTION_VALIDITY_PERIOD,
                 runtime_config: Default::default(),
                 validators: vec![AccountInfo {
                     account_id: account_id.clone(),
                     public_key: signer.public_key.to_readable(),
                     amount: TESTING_INIT_STAKE,
                 }],
-                records: vec![vec![StateRecord::account(
+                records: vec![StateRecord::account(
                     &account_id,
                     &signer.public_key.to_readable().0,
                     TESTING_INIT_BALANCE,
                     TESTING_INIT_STAKE,
-                )]],
+                )],
             };
             genesis_config.write_to_file(&dir.join(config.genesis_file));
             info!(target: "near", "Generated node key, validator key, genesis file in {}", dir.to_str().unwrap());
@@ -569,7 +643,7 @@ pub fn create_testnet_configs_from_seeds(
         (0..seeds.len()).map(|_| InMemorySigner::from_random()).collect::<Vec<_>>();
     let mut records = vec![vec![]];
     for (i, seed) in seeds.iter().enumerate() {
-        records[0].push(StateRecord::account(
+        records[0].extend(StateRecord::account(
             seed,
             &signers[i].public_key.to_readable().0,
             TESTING_INIT_BALANCE,
@@ -596,6 +670,7 @@ pub fn create_testnet_configs_from_seeds(
         dynamic_resharding: false,
         epoch_length: FAST_EPOCH_LENGTH,
         validator_kickout_threshold: VALIDATOR_KICKOUT_THRESHOLD,
+        transaction_validity_period: TRANSACTION_VALIDITY_PERIOD,
         runtime_config: Default::default(),
         validators,
         records,
@@ -663,7 +738,24 @@ pub fn init_testnet_configs(
 pub fn load_config(dir: &Path) -> NearConfig {
     let config = Config::from_file(&dir.join(CONFIG_FILENAME));
     let genesis_config = GenesisConfig::from_file(&dir.join(config.genesis_file.clone()));
-    let block_producer = if dir.join(config.validator_key_file.clone()).exists() {
+    let block_producer = if let Some(remote_signer) = &config.remote_signer {
+        let addr = remote_signer.addr.parse().expect("Failed to parse remote signer address");
+        let auth_key_path = dir.join(&remote_signer.auth_key_file);
+        let auth_key = match read_auth_key(&auth_key_path) {
+            Ok(auth_key) => auth_key,
+            Err(err) => {
+                panic!("Failed to read remote signer auth key {:?}: {}", auth_key_path, err)
+            }
+        };
+        let signer = match RemoteSigner::connect(addr, auth_key) {
+            Ok(signer) => signer,
+            Err(err) => panic!("Failed to connect to remote signer at {}: {}", addr, err),
+        };
+        Some(BlockProducer {
+            account_id: remote_signer.account_id.clone(),
+            signer: Arc::new(signer),
+        })
+    } else if dir.join(config.validator_key_file.clone()).exists() {
         let signer =
             Arc::new(InMemorySigner::from_file(&dir.join(config.validator_key_file.clone())));
         Some(BlockProducer::from(signer))
@@ -696,9 +788,8 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_deserialize() {
-        let data = json!({
+    fn genesis_json() -> serde_json::Value {
+        json!({
             "protocol_version": PROTOCOL_VERSION,
             "genesis_time": "2019-05-07T00:10:14.434719Z",
             "chain_id": "test-chain-XYQAS",
@@ -709,9 +800,15 @@ mod tests {
             "epoch_length": 100,
             "runtime_config": {},
             "validator_kickout_threshold": 0.9,
+            "transaction_validity_period": 100,
             "validators": [{"account_id": "alice.near", "public_key": "6fgp5mkRgsTWfd5UWw1VwHbNLLDYeLxrxw3jrkCeXNWq", "amount": "50"}],
             "records": [[]],
-        });
+        })
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let data = genesis_json();
         let spec = GenesisConfig::from(data.to_string().as_str());
         assert_eq!(
             spec.validators[0],
@@ -724,4 +821,13 @@ mod tests {
             }
         );
     }
+
+    /// Genesis written before the transaction validity period was introduced still loads.
+    #[test]
+    fn test_deserialize_without_transaction_validity_period() {
+        let mut data = genesis_json();
+        data.as_object_mut().unwrap().remove("transaction_validity_period");
+        let spec = GenesisConfig::from(data.to_string().as_str());
+        assert_eq!(spec.transaction_validity_period, TRANSACTION_VALIDITY_PERIOD);
+    }
 }
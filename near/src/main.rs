@@ -59,6 +59,7 @@ fn main() {
             .arg(Arg::with_name("network-addr").long("network-addr").help("Customize network listening address (useful for running multiple nodes on the same machine)").takes_value(true))
             .arg(Arg::with_name("rpc-addr").long("rpc-addr").help("Customize RPC listening address (useful for running multiple nodes on the same machine)").takes_value(true))
             .arg(Arg::with_name("telemetry-url").long("telemetry-url").help("Customize telemetry url").takes_value(true))
+            .arg(Arg::with_name("archive").long("archive").help("Keep all the blocks and state, don't garbage collect old data").takes_value(false))
         )
         .subcommand(SubCommand::with_name("unsafe_reset_data").about("(unsafe) Remove all the data, effectively resetting node to genesis state (keeps genesis and config)"))
         .subcommand(SubCommand::with_name("unsafe_reset_all").about("(unsafe) Remove all the config, keys, data and effectively removing all information about the network"))
@@ -128,6 +129,9 @@ fn main() {
             if let Some(telemetry_url) = args.value_of("telemetry-url") {
                 near_config.telemetry_config.endpoints.push(telemetry_url.to_string());
             }
+            if args.is_present("archive") {
+                near_config.client_config.archive = true;
+            }
 
             let system = System::new("NEAR");
             start_with_config(home_dir, near_config);
@@ -1,10 +1,13 @@
+use std::cmp;
 use std::collections::{BTreeSet, HashSet};
 use std::convert::TryFrom;
-use std::io::{Cursor, Read, Write};
+use std::io::Cursor;
+use std::ops::Range;
 use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
+use cached::{Cached, SizedCache};
 use kvdb::DBValue;
 use log::{debug, error, info};
 
@@ -14,16 +17,18 @@ use near_chain::{
 use near_primitives::account::{AccessKey, Account};
 use near_primitives::crypto::signature::{verify, PublicKey, Signature};
 use near_primitives::hash::{hash, CryptoHash};
-use near_primitives::rpc::{AccountViewCallResult, QueryResponse, ViewStateResult};
+use near_primitives::rpc::{
+    AccountViewCallResult, EpochBlockProducers, QueryResponse, ViewStateResult,
+};
 use near_primitives::transaction::{ReceiptTransaction, SignedTransaction, TransactionResult};
 use near_primitives::types::{AccountId, BlockIndex, MerkleHash, ShardId, ValidatorStake};
 use near_primitives::utils::prefix_for_access_key;
 use near_store::{
-    get_access_key_raw, get_account, set_account, Store, StoreUpdate, Trie, TrieUpdate,
-    WrappedTrieChanges,
+    get_access_key_raw, get_account, set_account, StatePart, StatePartsInfo, Store, StoreUpdate,
+    Trie, TrieUpdate, WrappedTrieChanges, STATE_PART_MAX_SIZE,
 };
 use near_verifier::TransactionVerifier;
-use node_runtime::adapter::query_client;
+use node_runtime::adapter::{query_client, query_proof};
 use node_runtime::ethereum::EthashProvider;
 use node_runtime::state_viewer::TrieViewer;
 use node_runtime::{ApplyState, Runtime, ETHASH_CACHE_PATH};
@@ -33,6 +38,13 @@ use crate::validator_manager::{ValidatorEpochConfig, ValidatorManager};
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
 
+/// Number of most recently requested state roots to keep split into parts, to serve state sync
+/// requests without scanning the whole state again.
+const STATE_PARTS_CACHE_SIZE: usize = 4;
+
+/// Number of state parts applied and committed at once when setting the state of a shard.
+const STATE_PARTS_BATCH_SIZE: u64 = 64;
+
 /// Defines Nightshade state transition, validator rotation and block weight for fork choice rule.
 /// TODO: this possibly should be merged with the runtime cargo or at least reconsiled on the interfaces.
 pub struct NightshadeRuntime {
@@ -43,6 +55,8 @@ pub struct NightshadeRuntime {
     trie_viewer: TrieViewer,
     runtime: Runtime,
     validator_manager: RwLock<ValidatorManager>,
+    /// Splits of the state into parts for recently requested state roots.
+    state_parts_info: Mutex<SizedCache<MerkleHash, Arc<StatePartsInfo>>>,
 }
 
 impl NightshadeRuntime {
@@ -79,7 +93,59 @@ impl NightshadeRuntime {
             )
             .expect("Failed to start Validator Manager"),
         );
-        NightshadeRuntime { genesis_config, store, trie, runtime, trie_viewer, validator_manager }
+        NightshadeRuntime {
+            genesis_config,
+            store,
+            trie,
+            runtime,
+            trie_viewer,
+            validator_manager,
+            state_parts_info: Mutex::new(SizedCache::with_size(STATE_PARTS_CACHE_SIZE)),
+        }
+    }
+
+    /// Adds items of the given state parts to the state with the given root or, if `remove` is set,
+    /// removes them. Parts are loaded and committed in batches of `STATE_PARTS_BATCH_SIZE`, so the
+    /// whole state is never held in memory. Returns the new state root.
+    fn update_state_parts(
+        &self,
+        mut root: MerkleHash,
+        part_ids: Range<u64>,
+        get_part: &dyn Fn(u64) -> Result<StatePart, Box<dyn std::error::Error>>,
+        remove: bool,
+    ) -> Result<MerkleHash, Box<dyn std::error::Error>> {
+        let part_ids: Vec<u64> = part_ids.collect();
+        for batch in part_ids.chunks(STATE_PARTS_BATCH_SIZE as usize) {
+            let mut state_update = TrieUpdate::new(self.trie.clone(), root);
+            for part_id in batch {
+                for (key, value) in get_part(*part_id)?.items {
+                    if remove {
+                        state_update.remove(&key);
+                    } else {
+                        state_update.set(key, DBValue::from_vec(value));
+                    }
+                }
+            }
+            let (store_update, new_root) = state_update.finalize()?.into(self.trie.clone())?;
+            store_update.commit()?;
+            root = new_root;
+        }
+        Ok(root)
+    }
+
+    /// Splits the state into parts or returns cached split for recently requested state root.
+    fn get_cached_state_parts_info(
+        &self,
+        state_root: MerkleHash,
+    ) -> Result<Arc<StatePartsInfo>, Box<dyn std::error::Error>> {
+        if let Some(info) =
+            self.state_parts_info.lock().expect(POISONED_LOCK_ERR).cache_get(&state_root)
+        {
+            return Ok(info.clone());
+        }
+        let info = Arc::new(self.trie.get_state_parts_info(&state_root, STATE_PART_MAX_SIZE)?);
+        self.state_parts_info.lock().expect(POISONED_LOCK_ERR).cache_set(state_root, info.clone());
+        Ok(info)
     }
 }
 
@@ -116,6 +182,10 @@ impl RuntimeAdapter for NightshadeRuntime {
         (store_update, state_roots)
     }
 
+    fn get_trie(&self) -> Arc<Trie> {
+        self.trie.clone()
+    }
+
     fn compute_block_weight(
         &self,
         prev_header: &BlockHeader,
@@ -152,6 +222,19 @@ impl RuntimeAdapter for NightshadeRuntime {
         Ok(result)
     }
 
+    fn get_epoch_block_producers(
+        &self,
+        epoch_hash: &CryptoHash,
+    ) -> Result<EpochBlockProducers, Box<dyn std::error::Error>> {
+        let mut vm = self.validator_manager.write().expect(POISONED_LOCK_ERR);
+        let validator_assignment = vm.get_validators(*epoch_hash)?;
+        Ok(EpochBlockProducers {
+            epoch_hash: *epoch_hash,
+            validators: validator_assignment.validators.clone(),
+            seats: validator_assignment.block_producers.clone(),
+        })
+    }
+
     fn get_block_proposer(
         &self,
         epoch_hash: &CryptoHash,
@@ -212,19 +295,39 @@ impl RuntimeAdapter for NightshadeRuntime {
             % (self.genesis_config.block_producers_per_shard.len() as ShardId)
     }
 
+    fn transaction_validity_period(&self) -> BlockIndex {
+        self.genesis_config.transaction_validity_period
+    }
+
     fn validate_tx(
         &self,
         _shard_id: ShardId,
         state_root: MerkleHash,
+        block_index: BlockIndex,
+        reference_block_index: BlockIndex,
         transaction: SignedTransaction,
     ) -> Result<ValidTransaction, String> {
+        if block_index > reference_block_index + self.transaction_validity_period() {
+            debug!(target: "runtime", "Tx {:?} expired at {}", transaction, block_index);
+            return Err(format!(
+                "Transaction expired: referenced block at height {} is older than {} blocks",
+                reference_block_index,
+                self.transaction_validity_period()
+            ));
+        }
         let state_update = TrieUpdate::new(self.trie.clone(), state_root);
         let verifier = TransactionVerifier::new(&state_update);
-        if let Err(err) = verifier.verify_transaction(&transaction) {
-            debug!(target: "runtime", "Tx {:?} validation failed: {:?}", transaction, err);
-            return Err(err);
+        match verifier.verify_transaction(&transaction) {
+            Ok(data) => Ok(ValidTransaction {
+                transaction,
+                public_key: data.public_key,
+                reference_block_index,
+            }),
+            Err(err) => {
+                debug!(target: "runtime", "Tx {:?} validation failed: {:?}", transaction, err);
+                Err(err)
+            }
         }
-        Ok(ValidTransaction { transaction })
     }
 
     fn add_validator_proposals(
@@ -343,51 +446,70 @@ impl RuntimeAdapter for NightshadeRuntime {
         query_client(self, state_root, height, path, data)
     }
 
-    fn dump_state(
+    fn query_proof(
+        &self,
+        state_root: MerkleHash,
+        path: &str,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+        query_proof(&self.trie, state_root, path)
+    }
+
+    fn get_state_parts_info(
+        &self,
+        _shard_id: ShardId,
+        state_root: MerkleHash,
+    ) -> Result<StatePartsInfo, Box<dyn std::error::Error>> {
+        Ok(self.get_cached_state_parts_info(state_root)?.as_ref().clone())
+    }
+
+    fn obtain_state_part(
         &self,
         shard_id: ShardId,
+        part_id: u64,
         state_root: MerkleHash,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    ) -> Result<StatePart, Box<dyn std::error::Error>> {
         // TODO(1052): make sure state_root is present in the trie.
-        // create snapshot.
-        let mut result = vec![];
-        let mut cursor = Cursor::new(&mut result);
-        for item in self.trie.iter(&state_root)? {
-            let (key, value) = item?;
-            cursor.write_u32::<LittleEndian>(key.len() as u32)?;
-            cursor.write_all(&key)?;
-            cursor.write_u32::<LittleEndian>(value.len() as u32)?;
-            cursor.write_all(value.as_ref())?;
-        }
-        // TODO(1048): Save on disk an snapshot, split into chunks and compressed. Send chunks instead of single blob.
-        info!(target: "runtime", "Dumped state for shard #{} @ {}, size = {}", shard_id, state_root, result.len());
-        Ok(result)
+        let info = self.get_cached_state_parts_info(state_root)?;
+        let part = self.trie.get_state_part(&state_root, part_id, &info.boundaries)?;
+        debug!(target: "runtime", "Obtained state part #{} for shard #{} @ {}, size = {}", part_id, shard_id, state_root, part.size());
+        Ok(part)
     }
 
-    fn set_state(
+    fn validate_state_part(
+        &self,
+        _shard_id: ShardId,
+        state_root: MerkleHash,
+        part_boundaries: &[Vec<u8>],
+        part: &StatePart,
+    ) -> bool {
+        Trie::validate_state_part(&state_root, part_boundaries, part)
+    }
+
+    fn confirm_state(
         &self,
         shard_id: ShardId,
         state_root: MerkleHash,
-        payload: Vec<u8>,
+        num_parts: u64,
+        get_part: &dyn Fn(u64) -> Result<StatePart, Box<dyn std::error::Error>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        info!(target: "runtime", "Setting state for shard #{} @ {}, size = {}", shard_id, state_root, payload.len());
-        let mut state_update = TrieUpdate::new(self.trie.clone(), CryptoHash::default());
-        let payload_len = payload.len();
-        let mut cursor = Cursor::new(payload);
-        while cursor.position() < payload_len as u64 {
-            let key_len = cursor.read_u32::<LittleEndian>()? as usize;
-            let mut key = vec![0; key_len];
-            cursor.read_exact(&mut key)?;
-            let value_len = cursor.read_u32::<LittleEndian>()? as usize;
-            let mut value = vec![0; value_len];
-            cursor.read_exact(&mut value)?;
-            state_update.set(key, DBValue::from_slice(&value));
+        info!(target: "runtime", "Setting state for shard #{} @ {}, parts = {}", shard_id, state_root, num_parts);
+        let mut root = CryptoHash::default();
+        let mut applied_parts = 0;
+        while applied_parts < num_parts {
+            let batch_end = cmp::min(applied_parts + STATE_PARTS_BATCH_SIZE, num_parts);
+            match self.update_state_parts(root, applied_parts..batch_end, get_part, false) {
+                Ok(new_root) => root = new_root,
+                Err(err) => {
+                    self.update_state_parts(root, 0..applied_parts, get_part, true)?;
+                    return Err(err);
+                }
+            }
+            applied_parts = batch_end;
         }
-        let (store_update, root) = state_update.finalize()?.into(self.trie.clone())?;
         if root != state_root {
+            self.update_state_parts(root, 0..num_parts, get_part, true)?;
             return Err("Invalid state root".into());
         }
-        store_update.commit()?;
         Ok(())
     }
 }
@@ -472,7 +594,8 @@ mod test {
         TransactionBody,
     };
     use near_primitives::types::{Balance, BlockIndex, Nonce, ValidatorStake};
-    use near_store::create_store;
+    use near_primitives::utils::{key_for_account, prefix_for_access_key};
+    use near_store::{create_store, verify_prefix_proof, verify_proof};
     use node_runtime::adapter::ViewRuntimeAdapter;
 
     use crate::config::{TESTING_INIT_BALANCE, TESTING_INIT_STAKE};
@@ -483,6 +606,7 @@ mod test {
     fn stake(nonce: Nonce, sender: &BlockProducer, amount: Balance) -> SignedTransaction {
         TransactionBody::Stake(StakeTransaction {
             nonce,
+            block_hash: CryptoHash::default(),
             originator: sender.account_id.clone(),
             amount,
             public_key: sender.signer.public_key().to_base(),
@@ -581,13 +705,20 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 1,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE * 2,
                 stake: TESTING_INIT_STAKE * 2,
                 public_keys: vec![block_producers[0].signer.public_key()],
                 code_hash: account.code_hash,
             }
         );
+        let access_key = nightshade
+            .view_access_key(
+                state_root,
+                &block_producers[0].account_id,
+                &block_producers[0].signer.public_key(),
+            )
+            .unwrap();
+        assert_eq!(access_key.map(|access_key| access_key.nonce), Some(1));
 
         nightshade
             .add_validator_proposals(CryptoHash::default(), h0, 0, validator_stakes, vec![], vec![])
@@ -598,6 +729,7 @@ mod test {
             InMemorySigner::from_seed(&new_account, &new_account).into();
         let create_account_transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
             nonce: 2,
+            block_hash: CryptoHash::default(),
             originator: block_producers[0].account_id.clone(),
             new_account_id: new_account,
             amount: TESTING_INIT_STAKE * 3,
@@ -655,7 +787,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 2,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE * 5,
                 stake: TESTING_INIT_STAKE * 2,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -672,7 +803,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 0,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
                 stake: TESTING_INIT_STAKE,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -685,7 +815,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: new_validator.account_id.clone(),
-                nonce: 1,
                 amount: TESTING_INIT_STAKE,
                 stake: TESTING_INIT_STAKE * 2,
                 public_keys: vec![new_validator.signer.public_key()],
@@ -703,7 +832,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 0,
                 amount: TESTING_INIT_BALANCE,
                 stake: 0,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -722,7 +850,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 0,
                 amount: TESTING_INIT_BALANCE,
                 stake: 0,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -771,7 +898,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 1,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
                 stake: TESTING_INIT_STAKE,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -800,7 +926,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 1,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
                 stake: TESTING_INIT_STAKE,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -819,7 +944,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 1,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE + 1,
                 stake: TESTING_INIT_STAKE - 1,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -871,7 +995,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 2,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
                 stake: TESTING_INIT_STAKE,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -912,7 +1035,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE - 1,
                 stake: TESTING_INIT_STAKE + 1,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -925,7 +1047,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE - 1,
                 stake: TESTING_INIT_STAKE + 1,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -944,7 +1065,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE - 1,
                 stake: TESTING_INIT_STAKE + 1,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -957,7 +1077,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE - 1,
                 stake: TESTING_INIT_STAKE + 1,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -976,7 +1095,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[0].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE - 1,
                 stake: TESTING_INIT_STAKE + 1,
                 public_keys: vec![block_producers[0].signer.public_key()],
@@ -989,7 +1107,6 @@ mod test {
             account,
             AccountViewCallResult {
                 account_id: block_producers[1].account_id.clone(),
-                nonce: 3,
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE + 1,
                 stake: TESTING_INIT_STAKE - 1,
                 public_keys: vec![block_producers[1].signer.public_key()],
@@ -1043,4 +1160,44 @@ mod test {
             &signature
         ));
     }
+
+    #[test]
+    fn test_query_proof() {
+        let dir = TempDir::new("query_proof").unwrap();
+        let store = create_store(&get_store_path(dir.path()));
+        let validators = vec!["test1".to_string(), "test2".to_string()];
+        let genesis_config = GenesisConfig::test(validators.iter().map(|v| v.as_str()).collect());
+        let nightshade = NightshadeRuntime::new(dir.path(), store, genesis_config);
+        let (store_update, state_roots) = nightshade.genesis_state();
+        store_update.commit().unwrap();
+        let state_root = state_roots[0];
+
+        let proof = nightshade.query_proof(state_root, "account/test1").unwrap();
+        let key = key_for_account(&"test1".to_string());
+        assert_eq!(
+            verify_proof(&state_root, &key, &proof).unwrap(),
+            nightshade.trie.get(&state_root, &key)
+        );
+        assert!(verify_proof(&state_root, &key, &proof).unwrap().is_some());
+
+        let proof = nightshade.query_proof(state_root, "account/unknown").unwrap();
+        let key = key_for_account(&"unknown".to_string());
+        assert_eq!(verify_proof(&state_root, &key, &proof).unwrap(), None);
+
+        // Genesis accounts have a single full access key, proof shows that no other is omitted.
+        let proof = nightshade.query_proof(state_root, "access_key/test1").unwrap();
+        let prefix = prefix_for_access_key(&"test1".to_string());
+        let access_keys = verify_prefix_proof(&state_root, &prefix, &proof).unwrap();
+        assert_eq!(access_keys.len(), 1);
+        assert_eq!(
+            Some(access_keys[0].1.clone()),
+            nightshade.trie.get(&state_root, &access_keys[0].0)
+        );
+
+        let proof = nightshade.query_proof(state_root, "access_key/unknown").unwrap();
+        let prefix = prefix_for_access_key(&"unknown".to_string());
+        assert_eq!(verify_prefix_proof(&state_root, &prefix, &proof).unwrap(), vec![]);
+
+        assert!(nightshade.query_proof(state_root, "call/test1/method").is_err());
+    }
 }
@@ -22,11 +22,12 @@ fn runtime_hanldle_fork() {
     let runtime =
         Arc::new(NightshadeRuntime::new(tmp_dir.path(), store.clone(), genesis_config.clone()));
 
-    let mut chain = Chain::new(store, runtime, genesis_config.genesis_time).unwrap();
+    let mut chain = Chain::new(store, runtime, genesis_config.genesis_time, false).unwrap();
 
-    let tx1 = TransactionBody::send_money(1, "near.0", "near.1", 100).sign(&*signer);
-    let tx2 = TransactionBody::send_money(1, "near.0", "near.1", 500).sign(&*signer);
-    let tx3 = TransactionBody::send_money(2, "near.0", "near.1", 100).sign(&*signer);
+    let genesis_hash = chain.genesis().hash();
+    let tx1 = TransactionBody::send_money(1, "near.0", "near.1", 100, genesis_hash).sign(&*signer);
+    let tx2 = TransactionBody::send_money(1, "near.0", "near.1", 500, genesis_hash).sign(&*signer);
+    let tx3 = TransactionBody::send_money(2, "near.0", "near.1", 100, genesis_hash).sign(&*signer);
     let state_root = chain.get_post_state_root(&chain.genesis().hash()).unwrap().clone();
     let b1 = Block::produce(
         chain.genesis(),
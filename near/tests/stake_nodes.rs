@@ -9,14 +9,14 @@ use tempdir::TempDir;
 use lazy_static::lazy_static;
 use near::config::{TESTING_INIT_BALANCE, TESTING_INIT_STAKE};
 use near::{load_test_config, start_with_config, GenesisConfig, NearConfig};
-use near_client::{ClientActor, Query, Status, ViewClientActor};
+use near_client::{ClientActor, GetBlock, Query, Status, ViewClientActor};
 use near_network::test_utils::{convert_boot_nodes, open_port, WaitOrTimeout};
 use near_network::NetworkClientMessages;
 use near_primitives::rpc::{QueryResponse, ValidatorInfo};
 use near_primitives::serialize::BaseEncode;
 use near_primitives::test_utils::init_integration_logger;
 use near_primitives::transaction::{StakeTransaction, TransactionBody};
-use near_primitives::types::AccountId;
+use near_primitives::types::{AccountId, Balance};
 
 lazy_static! {
     static ref HEAVY_TESTS_LOCK: Mutex<()> = Mutex::new(());
@@ -70,6 +70,25 @@ fn init_test_staking(num_accounts: usize, num_nodes: usize, epoch_length: u64) -
         .collect()
 }
 
+/// Sends staking transaction from the account of `node` through `via` node.
+/// The transaction refers to the current head of `via` node.
+fn send_stake_transaction(via: &TestNode, node: &TestNode, amount: Balance) {
+    let client = via.client.clone();
+    let originator = node.account_id.clone();
+    let signer = node.config.block_producer.as_ref().unwrap().signer.clone();
+    actix::spawn(via.view_client.send(GetBlock::Best).then(move |res| {
+        let tx = TransactionBody::Stake(StakeTransaction {
+            nonce: 1,
+            block_hash: res.unwrap().unwrap().hash(),
+            originator,
+            amount,
+            public_key: signer.public_key().to_base(),
+        })
+        .sign(&*signer);
+        client.send(NetworkClientMessages::Transaction(tx)).map(|_| ()).map_err(|_| ())
+    }));
+}
+
 /// Runs one validator network, sends staking transaction for the second node and
 /// waits until it becomes a validator.
 #[test]
@@ -78,27 +97,7 @@ fn test_stake_nodes() {
         let system = System::new("NEAR");
         let test_nodes = init_test_staking(2, 1, 10);
 
-        let tx = TransactionBody::Stake(StakeTransaction {
-            nonce: 1,
-            originator: test_nodes[1].account_id.clone(),
-            amount: TESTING_INIT_STAKE,
-            public_key: test_nodes[1]
-                .config
-                .block_producer
-                .clone()
-                .unwrap()
-                .signer
-                .public_key()
-                .to_base(),
-        })
-        .sign(&*test_nodes[1].config.block_producer.clone().unwrap().signer);
-        actix::spawn(
-            test_nodes[0]
-                .client
-                .send(NetworkClientMessages::Transaction(tx))
-                .map(|_| ())
-                .map_err(|_| ()),
-        );
+        send_stake_transaction(&test_nodes[0], &test_nodes[1], TESTING_INIT_STAKE);
 
         WaitOrTimeout::new(
             Box::new(move |_ctx| {
@@ -133,33 +132,8 @@ fn test_validator_kickout() {
         let num_nodes = test_nodes.len();
         let mut rng = rand::thread_rng();
         let stakes = (0..num_nodes / 2).map(|_| rng.gen_range(1, 100));
-        let stake_transactions = stakes.enumerate().map(|(i, stake)| {
-            let test_node = &test_nodes[i];
-            TransactionBody::Stake(StakeTransaction {
-                nonce: 1,
-                originator: test_node.account_id.clone(),
-                amount: stake,
-                public_key: test_node
-                    .config
-                    .block_producer
-                    .as_ref()
-                    .unwrap()
-                    .signer
-                    .public_key()
-                    .to_base(),
-            })
-            .sign(&*test_node.config.block_producer.as_ref().unwrap().signer)
-        });
-
-        for (i, stake_transaction) in stake_transactions.enumerate() {
-            let test_node = &test_nodes[i];
-            actix::spawn(
-                test_node
-                    .client
-                    .send(NetworkClientMessages::Transaction(stake_transaction))
-                    .map(|_| ())
-                    .map_err(|_| ()),
-            );
+        for (i, stake) in stakes.enumerate() {
+            send_stake_transaction(&test_nodes[i], &test_nodes[i], stake);
         }
 
         let finalized_mark: Arc<Vec<_>> =
@@ -192,6 +166,8 @@ fn test_validator_kickout() {
                                             test_nodes[i].account_id.clone()
                                         ),
                                         data: vec![],
+                                        block_id: None,
+                                        prove: false,
                                     })
                                     .then(move |res| match res.unwrap().unwrap() {
                                         QueryResponse::ViewAccount(result) => {
@@ -218,6 +194,8 @@ fn test_validator_kickout() {
                                             test_nodes[i].account_id.clone()
                                         ),
                                         data: vec![],
+                                        block_id: None,
+                                        prove: false,
                                     })
                                     .then(move |res| match res.unwrap().unwrap() {
                                         QueryResponse::ViewAccount(result) => {
@@ -255,48 +233,8 @@ fn test_validator_join() {
     heavy_test(|| {
         let system = System::new("NEAR");
         let test_nodes = init_test_staking(4, 2, 16);
-        let unstake_transaction = TransactionBody::Stake(StakeTransaction {
-            nonce: 1,
-            originator: test_nodes[1].account_id.clone(),
-            amount: 0,
-            public_key: test_nodes[1]
-                .config
-                .block_producer
-                .as_ref()
-                .unwrap()
-                .signer
-                .public_key()
-                .to_base(),
-        })
-        .sign(&*test_nodes[1].config.block_producer.as_ref().unwrap().signer);
-        let stake_transaction = TransactionBody::Stake(StakeTransaction {
-            nonce: 1,
-            originator: test_nodes[2].account_id.clone(),
-            amount: TESTING_INIT_STAKE,
-            public_key: test_nodes[2]
-                .config
-                .block_producer
-                .as_ref()
-                .unwrap()
-                .signer
-                .public_key()
-                .to_base(),
-        })
-        .sign(&*test_nodes[2].config.block_producer.as_ref().unwrap().signer);
-        actix::spawn(
-            test_nodes[1]
-                .client
-                .send(NetworkClientMessages::Transaction(unstake_transaction))
-                .map(|_| ())
-                .map_err(|_| ()),
-        );
-        actix::spawn(
-            test_nodes[0]
-                .client
-                .send(NetworkClientMessages::Transaction(stake_transaction))
-                .map(|_| ())
-                .map_err(|_| ()),
-        );
+        send_stake_transaction(&test_nodes[1], &test_nodes[1], 0);
+        send_stake_transaction(&test_nodes[0], &test_nodes[2], TESTING_INIT_STAKE);
 
         let (done1, done2) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
         let (done1_copy1, done2_copy1) = (done1.clone(), done2.clone());
@@ -317,6 +255,8 @@ fn test_validator_join() {
                                 .send(Query {
                                     path: format!("account/{}", test_nodes[1].account_id.clone()),
                                     data: vec![],
+                                    block_id: None,
+                                    prove: false,
                                 })
                                 .then(move |res| match res.unwrap().unwrap() {
                                     QueryResponse::ViewAccount(result) => {
@@ -336,6 +276,8 @@ fn test_validator_join() {
                                 .send(Query {
                                     path: format!("account/{}", test_nodes[2].account_id.clone()),
                                     data: vec![],
+                                    block_id: None,
+                                    prove: false,
                                 })
                                 .then(move |res| match res.unwrap().unwrap() {
                                     QueryResponse::ViewAccount(result) => {
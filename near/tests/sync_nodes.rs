@@ -25,7 +25,7 @@ fn genesis_header(genesis_config: GenesisConfig) -> BlockHeader {
     let store = create_test_store();
     let genesis_time = genesis_config.genesis_time.clone();
     let runtime = Arc::new(NightshadeRuntime::new(dir.path(), store.clone(), genesis_config));
-    let chain = Chain::new(store, runtime, genesis_time).unwrap();
+    let chain = Chain::new(store, runtime, genesis_time, false).unwrap();
     chain.genesis().clone()
 }
 
@@ -176,6 +176,7 @@ fn sync_state_stake_change() {
 
     let unstake_transaction = TransactionBody::Stake(StakeTransaction {
         nonce: 1,
+        block_hash: genesis_header(genesis_config.clone()).hash(),
         originator: "test1".to_string(),
         amount: TESTING_INIT_STAKE / 2,
         public_key: near1.block_producer.as_ref().unwrap().signer.public_key().to_base(),
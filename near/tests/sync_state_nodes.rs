@@ -19,7 +19,7 @@ fn genesis_header(genesis_config: GenesisConfig) -> BlockHeader {
     let store = create_test_store();
     let genesis_time = genesis_config.genesis_time.clone();
     let runtime = Arc::new(NightshadeRuntime::new(dir.path(), store.clone(), genesis_config));
-    let chain = Chain::new(store, runtime, genesis_time).unwrap();
+    let chain = Chain::new(store, runtime, genesis_time, false).unwrap();
     chain.genesis().clone()
 }
 
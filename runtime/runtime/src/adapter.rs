@@ -5,6 +5,10 @@ use near_primitives::rpc::{
 };
 use near_primitives::serialize::BaseDecode;
 use near_primitives::types::{AccountId, BlockIndex, MerkleHash};
+use near_primitives::utils::{
+    key_for_access_key, key_for_account, prefix_for_access_key, prefix_for_data,
+};
+use near_store::Trie;
 
 /// Adapter for querying runtime.
 pub trait ViewRuntimeAdapter {
@@ -106,3 +110,28 @@ pub fn query_client(
         _ => Err(format!("Unknown path {}", path).into()),
     }
 }
+
+/// Returns encoded trie nodes proving the state that is read by the query at given <path>.
+/// Function calls execute contract code on the state, so they can't be proven this way.
+pub fn query_proof(
+    trie: &Trie,
+    state_root: MerkleHash,
+    path: &str,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let path_parts: Vec<&str> = path.split('/').collect();
+    match path_parts.as_slice() {
+        ["account", account_id] => {
+            trie.get_proof(&state_root, &key_for_account(&AccountId::from(*account_id)))
+        }
+        ["contract", account_id] => {
+            trie.get_prefix_proof(&state_root, &prefix_for_data(&AccountId::from(*account_id)))
+        }
+        ["access_key", account_id] => trie
+            .get_prefix_proof(&state_root, &prefix_for_access_key(&AccountId::from(*account_id))),
+        ["access_key", account_id, public_key] => trie.get_proof(
+            &state_root,
+            &key_for_access_key(&AccountId::from(*account_id), &PublicKey::from_base(public_key)?),
+        ),
+        _ => Err(format!("Proof is not supported for path {}", path).into()),
+    }
+}
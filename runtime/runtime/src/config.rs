@@ -1,5 +1,5 @@
 //! Settings of the parameters of the runtime.
-use near_primitives::transaction::TransactionBody;
+use near_primitives::transaction::{Action, TransactionBody};
 use near_primitives::types::{Balance, BlockIndex};
 use wasm::types::Config;
 
@@ -52,6 +52,19 @@ impl TransactionsCosts {
             AddKey(_) => self.add_key,
             DeleteKey(_) => self.delete_key,
             DeleteAccount(_) => self.delete_account,
+            Batch(t) => t.actions.iter().map(|action| self.action_cost(action)).sum(),
+        }
+    }
+
+    /// Get the cost of a single action of a batch transaction.
+    pub fn action_cost(&self, action: &Action) -> Balance {
+        match action {
+            Action::CreateAccount => self.create_account,
+            Action::DeployContract(_) => self.deploy_contract,
+            Action::Transfer(_) => self.send_money,
+            Action::Stake(_) => self.stake,
+            Action::AddKey(_) => self.add_key,
+            Action::DeleteKey(_) => self.delete_key,
         }
     }
 }
@@ -255,11 +255,12 @@ impl Runtime {
         validator_proposals: &mut Vec<ValidatorStake>,
         transaction_result: &mut TransactionResult,
     ) -> Result<Vec<ReceiptTransaction>, String> {
-        let VerificationData { originator_id, mut originator, public_key, .. } = {
+        let VerificationData { originator_id, mut originator, public_key, mut access_key } = {
             let verifier = TransactionVerifier::new(state_update);
             verifier.verify_transaction(transaction)?
         };
-        originator.nonce = transaction.body.get_nonce();
+        access_key.nonce = transaction.body.get_nonce();
+        set_access_key(state_update, &originator_id, &public_key, &access_key);
         let transaction_cost = self.config.transactions_costs.cost(&transaction.body);
         originator.checked_sub(transaction_cost)?;
         self.apply_rent(&originator_id, &mut originator, block_index);
@@ -278,7 +279,8 @@ impl Runtime {
             ),
             TransactionBody::Stake(ref t) => system::staking(
                 state_update,
-                &t,
+                t.amount,
+                &t.public_key,
                 &originator_id,
                 &mut originator,
                 validator_proposals,
@@ -314,13 +316,30 @@ impl Runtime {
                 public_key,
             ),
             TransactionBody::SwapKey(ref t) => system::swap_key(state_update, t, &mut originator),
-            TransactionBody::AddKey(ref t) => system::add_key(state_update, t, &mut originator),
-            TransactionBody::DeleteKey(ref t) => {
-                system::delete_key(state_update, t, &mut originator, transaction.get_hash())
-            }
+            TransactionBody::AddKey(ref t) => system::add_key(
+                state_update,
+                &t.originator,
+                &t.new_key,
+                &t.access_key,
+                &mut originator,
+            ),
+            TransactionBody::DeleteKey(ref t) => system::delete_key(
+                state_update,
+                &t.originator,
+                &t.cur_key,
+                &mut originator,
+                transaction.get_hash(),
+            ),
             TransactionBody::DeleteAccount(ref t) => {
                 system::delete_account(t, transaction.get_hash(), public_key)
             }
+            TransactionBody::Batch(ref t) => system::apply_actions(
+                state_update,
+                t,
+                transaction.get_hash(),
+                &mut originator,
+                validator_proposals,
+            ),
         };
         if !check_rent(&originator_id, &mut originator, &self.config, epoch_length) {
             return Err(format!("Failed to execute, because result will leave less then required rent on the account {}", originator_id).into());
@@ -943,7 +962,7 @@ mod tests {
     fn test_get_and_set_accounts() {
         let trie = create_trie();
         let mut state_update = TrieUpdate::new(trie, MerkleHash::default());
-        let test_account = Account::new(vec![], 10, hash(&[]));
+        let test_account = Account::new(10, hash(&[]));
         let account_id = bob_account();
         set_account(&mut state_update, &account_id, &test_account);
         let get_res = get_account(&state_update, &account_id).unwrap();
@@ -955,7 +974,7 @@ mod tests {
         let trie = create_trie();
         let root = MerkleHash::default();
         let mut state_update = TrieUpdate::new(trie.clone(), root);
-        let test_account = Account::new(vec![], 10, hash(&[]));
+        let test_account = Account::new(10, hash(&[]));
         let account_id = bob_account();
         set_account(&mut state_update, &account_id, &test_account);
         let (store_update, new_root) = state_update.finalize().unwrap().into(trie.clone()).unwrap();
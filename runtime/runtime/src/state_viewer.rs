@@ -3,13 +3,13 @@ use std::str;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use near_primitives::account::AccessKey;
+use near_primitives::account::{AccessKey, AccessKeyPermission};
 use near_primitives::crypto::signature::PublicKey;
 use near_primitives::hash::CryptoHash;
 use near_primitives::rpc::{AccountViewCallResult, ViewStateResult};
 use near_primitives::types::AccountId;
 use near_primitives::utils::{is_valid_account_id, prefix_for_data};
-use near_store::{get_access_key, get_account, TrieUpdate};
+use near_store::{get_access_key, get_access_keys, get_account, TrieUpdate};
 use wasm::executor;
 use wasm::types::{ReturnData, RuntimeContext};
 
@@ -39,10 +39,15 @@ impl TrieViewer {
         match get_account(state_update, &account_id) {
             Some(account) => Ok(AccountViewCallResult {
                 account_id: account_id.clone(),
-                nonce: account.nonce,
                 amount: account.amount,
                 stake: account.staked,
-                public_keys: account.public_keys,
+                public_keys: get_access_keys(state_update, account_id)
+                    .into_iter()
+                    .filter(|(_, access_key)| {
+                        access_key.permission == AccessKeyPermission::FullAccess
+                    })
+                    .map(|(public_key, _)| public_key)
+                    .collect(),
                 code_hash: account.code_hash,
             }),
             _ => Err(format!("account {} does not exist while viewing", account_id).into()),
@@ -21,18 +21,24 @@ pub enum StateRecord {
 }
 
 impl StateRecord {
-    pub fn account(account_id: &str, public_key: &str, amount: u128, staked: u128) -> Self {
-        StateRecord::Account {
-            account_id: account_id.to_string(),
-            account: Account {
-                public_keys: vec![PublicKey::try_from(public_key).unwrap()],
-                nonce: 0,
-                amount,
-                staked,
-                code_hash: Default::default(),
-                storage_usage: 0,
-                storage_paid_at: 0,
+    /// Records of the account with a single full access key.
+    pub fn account(account_id: &str, public_key: &str, amount: u128, staked: u128) -> Vec<Self> {
+        vec![
+            StateRecord::Account {
+                account_id: account_id.to_string(),
+                account: Account {
+                    amount,
+                    staked,
+                    code_hash: Default::default(),
+                    storage_usage: 0,
+                    storage_paid_at: 0,
+                },
             },
-        }
+            StateRecord::AccessKey {
+                account_id: account_id.to_string(),
+                public_key: PublicKey::try_from(public_key).unwrap().to_readable(),
+                access_key: AccessKey::full_access(),
+            },
+        ]
     }
 }
@@ -1,18 +1,20 @@
 use std::convert::TryFrom;
 
-use near_primitives::account::{AccessKey, Account};
+use near_primitives::account::{AccessKey, AccessKeyPermission, Account};
 use near_primitives::contract::ContractCode;
 use near_primitives::crypto::signature::PublicKey;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::transaction::{
-    AddKeyTransaction, AsyncCall, CallbackInfo, CallbackResult, CreateAccountTransaction,
-    DeleteAccountTransaction, DeleteKeyTransaction, ReceiptBody, ReceiptTransaction,
-    SendMoneyTransaction, StakeTransaction, SwapKeyTransaction,
+    Action, AsyncCall, BatchTransaction, CallbackInfo, CallbackResult, CreateAccountTransaction,
+    DeleteAccountTransaction, ReceiptBody, ReceiptTransaction, SendMoneyTransaction,
+    SwapKeyTransaction,
 };
 use near_primitives::types::{AccountId, Balance, BlockIndex, ValidatorStake};
-use near_primitives::utils::{create_nonce_with_nonce, is_valid_account_id, key_for_access_key};
+use near_primitives::utils::{
+    account_to_shard_id, create_nonce_with_nonce, is_valid_account_id, key_for_access_key,
+};
 use near_store::{
-    get_access_key, remove_account, set_access_key, set_account, set_code, TrieUpdate,
+    get_access_key, get_account, remove_account, set_access_key, set_account, set_code, TrieUpdate,
 };
 
 use crate::check_rent;
@@ -63,14 +65,15 @@ pub fn send_money(
 
 pub fn staking(
     state_update: &mut TrieUpdate,
-    body: &StakeTransaction,
+    amount: Balance,
+    public_key: &str,
     sender_account_id: &AccountId,
     sender: &mut Account,
     validator_proposals: &mut Vec<ValidatorStake>,
 ) -> Result<Vec<ReceiptTransaction>, String> {
-    let increment = if body.amount > sender.staked { body.amount - sender.staked } else { 0 };
+    let increment = if amount > sender.staked { amount - sender.staked } else { 0 };
     if sender.amount >= increment {
-        if sender.staked == 0 && body.amount == 0 {
+        if sender.staked == 0 && amount == 0 {
             // if the account hasn't staked, it cannot unstake
             return Err(format!(
                 "Account {} is not yet staked, but tries to unstake",
@@ -79,20 +82,19 @@ pub fn staking(
         }
         validator_proposals.push(ValidatorStake {
             account_id: sender_account_id.clone(),
-            public_key: PublicKey::try_from(body.public_key.as_str())
-                .map_err(|err| err.to_string())?,
-            amount: body.amount,
+            public_key: PublicKey::try_from(public_key).map_err(|err| err.to_string())?,
+            amount,
         });
-        if sender.staked < body.amount {
+        if sender.staked < amount {
             sender.amount -= increment;
-            sender.staked = body.amount;
+            sender.staked = amount;
             set_account(state_update, sender_account_id, &sender);
         }
         Ok(vec![])
     } else {
         let err_msg = format!(
             "Account {} tries to stake {}, but has staked {} and only has {}",
-            body.originator, body.amount, sender.staked, sender.amount,
+            sender_account_id, amount, sender.staked, sender.amount,
         );
         Err(err_msg)
     }
@@ -183,99 +185,90 @@ pub fn swap_key(
 ) -> Result<Vec<ReceiptTransaction>, String> {
     let cur_key = PublicKey::try_from(&body.cur_key as &[u8]).map_err(|e| format!("{}", e))?;
     let new_key = PublicKey::try_from(&body.new_key as &[u8]).map_err(|e| format!("{}", e))?;
-    let num_keys = account.public_keys.len();
-    account.public_keys.retain(|&x| x != cur_key);
-    if account.public_keys.len() == num_keys {
-        return Err(format!("Account {} does not have public key {}", body.originator, cur_key));
+    let access_key = get_access_key(state_update, &body.originator, &cur_key).ok_or_else(|| {
+        format!("Account {} does not have public key {}", body.originator, cur_key)
+    })?;
+    if get_access_key(state_update, &body.originator, &new_key).is_some() {
+        return Err("Cannot add a public key that already exists on the account".to_string());
     }
-    account.public_keys.push(new_key);
+    // New key keeps the nonce and the permission of the swapped one.
+    state_update.remove(&key_for_access_key(&body.originator, &cur_key));
+    set_access_key(state_update, &body.originator, &new_key, &access_key);
     set_account(state_update, &body.originator, &account);
     Ok(vec![])
 }
 
 pub fn add_key(
     state_update: &mut TrieUpdate,
-    body: &AddKeyTransaction,
+    account_id: &AccountId,
+    new_key: &[u8],
+    access_key: &AccessKey,
     account: &mut Account,
 ) -> Result<Vec<ReceiptTransaction>, String> {
-    let new_key = PublicKey::try_from(&body.new_key as &[u8]).map_err(|e| format!("{}", e))?;
-    let num_keys = account.public_keys.len();
-    account.public_keys.retain(|&x| x != new_key);
-    if account.public_keys.len() < num_keys {
+    let new_key = PublicKey::try_from(new_key).map_err(|e| format!("{}", e))?;
+    if get_access_key(state_update, account_id, &new_key).is_some() {
         return Err("Cannot add a public key that already exists on the account".to_string());
     }
-    if get_access_key(state_update, &body.originator, &new_key).is_some() {
-        return Err("Cannot add a public key that already used for an access key".to_string());
-    }
-    if let Some(access_key) = &body.access_key {
-        if account.amount >= access_key.amount {
-            if access_key.amount > 0 {
-                account.amount -= access_key.amount;
-                set_account(state_update, &body.originator, &account);
+    if let AccessKeyPermission::FunctionCall(ref permission) = access_key.permission {
+        if account.amount >= permission.allowance {
+            if permission.allowance > 0 {
+                account.amount -= permission.allowance;
+                set_account(state_update, account_id, &account);
             }
         } else {
             return Err(format!(
                 "Account {} tries to create new access key with {} amount, but only has {}",
-                body.originator, access_key.amount, account.amount
+                account_id, permission.allowance, account.amount
             ));
         }
-        if let Some(ref balance_owner) = access_key.balance_owner {
+        if let Some(ref balance_owner) = permission.balance_owner {
             if !is_valid_account_id(balance_owner) {
                 return Err("Invalid account ID for balance owner in the access key".to_string());
             }
         }
-        if let Some(ref contract_id) = access_key.contract_id {
+        if let Some(ref contract_id) = permission.contract_id {
             if !is_valid_account_id(contract_id) {
                 return Err("Invalid account ID for contract ID in the access key".to_string());
             }
         }
-        set_access_key(state_update, &body.originator, &new_key, access_key);
-    } else {
-        account.public_keys.push(new_key);
-        set_account(state_update, &body.originator, &account);
     }
+    set_access_key(state_update, account_id, &new_key, access_key);
     Ok(vec![])
 }
 
 pub fn delete_key(
     state_update: &mut TrieUpdate,
-    body: &DeleteKeyTransaction,
+    account_id: &AccountId,
+    cur_key: &[u8],
     account: &mut Account,
     nonce: CryptoHash,
 ) -> Result<Vec<ReceiptTransaction>, String> {
-    let cur_key = PublicKey::try_from(&body.cur_key as &[u8]).map_err(|e| format!("{}", e))?;
-    let num_keys = account.public_keys.len();
+    let cur_key = PublicKey::try_from(cur_key).map_err(|e| format!("{}", e))?;
     let mut new_receipts = vec![];
-    account.public_keys.retain(|&x| x != cur_key);
-    if account.public_keys.len() == num_keys {
-        let access_key: AccessKey = get_access_key(state_update, &body.originator, &cur_key)
-            .ok_or_else(|| {
-                format!(
-                    "Account {} tries to remove a public key that it does not own",
-                    body.originator
-                )
-            })?;
-        if access_key.amount > 0 {
+    let access_key: AccessKey =
+        get_access_key(state_update, account_id, &cur_key).ok_or_else(|| {
+            format!("Account {} tries to remove a public key that it does not own", account_id)
+        })?;
+    if let AccessKeyPermission::FunctionCall(permission) = access_key.permission {
+        if permission.allowance > 0 {
             let balance_owner_id: &AccountId =
-                access_key.balance_owner.as_ref().unwrap_or(&body.originator);
-            if balance_owner_id != &body.originator {
+                permission.balance_owner.as_ref().unwrap_or(account_id);
+            if balance_owner_id != account_id {
                 let new_receipt = ReceiptTransaction::new(
-                    body.originator.clone(),
+                    account_id.clone(),
                     balance_owner_id.clone(),
                     create_nonce_with_nonce(&nonce, 0),
-                    ReceiptBody::Refund(access_key.amount),
+                    ReceiptBody::Refund(permission.allowance),
                 );
                 new_receipts.push(new_receipt);
             } else {
-                account.amount += access_key.amount;
-                set_account(state_update, &body.originator, &account);
+                account.amount += permission.allowance;
             }
         }
-        // Remove access key
-        state_update.remove(&key_for_access_key(&body.originator, &cur_key));
-    } else {
-        set_account(state_update, &body.originator, &account);
     }
+    // Remove access key
+    state_update.remove(&key_for_access_key(account_id, &cur_key));
+    set_account(state_update, account_id, &account);
     Ok(new_receipts)
 }
 
@@ -301,6 +294,136 @@ pub fn delete_account(
     Ok(vec![receipt])
 }
 
+/// Returns the account that a batch is allowed to deploy code, stake or manage keys on: either the
+/// originator itself or the receiver created earlier in the same batch.
+fn batch_owned_account<'a>(
+    body: &BatchTransaction,
+    originator: &'a mut Account,
+    receiver: &'a mut Option<Account>,
+    receiver_created: bool,
+) -> Result<&'a mut Account, String> {
+    if body.originator == body.receiver_id {
+        Ok(originator)
+    } else if receiver_created {
+        receiver.as_mut().ok_or_else(|| format!("Account {} does not exist", body.receiver_id))
+    } else {
+        Err(format!(
+            "Account {} can only modify its own account or the account created in the same batch, but the receiver is {}",
+            body.originator, body.receiver_id
+        ))
+    }
+}
+
+/// Applies the actions of the batch transaction in order. Any failing action fails the whole batch
+/// and the caller rolls back the state changes done by the previous actions.
+pub fn apply_actions(
+    state_update: &mut TrieUpdate,
+    body: &BatchTransaction,
+    nonce: CryptoHash,
+    originator: &mut Account,
+    validator_proposals: &mut Vec<ValidatorStake>,
+) -> Result<Vec<ReceiptTransaction>, String> {
+    if body.actions.is_empty() {
+        return Err(format!(
+            "Account {} sends a batch transaction without actions",
+            body.originator
+        ));
+    }
+    if !is_valid_account_id(&body.receiver_id) {
+        return Err(format!("Account name {} {}", body.receiver_id, INVALID_ACCOUNT_ID));
+    }
+    let is_self = body.originator == body.receiver_id;
+    if account_to_shard_id(&body.originator) != account_to_shard_id(&body.receiver_id) {
+        return Err(format!(
+            "Batch transaction from {} to {} spans several shards, which is not supported",
+            body.originator, body.receiver_id
+        ));
+    }
+    // Receiver account, if it is not the originator itself.
+    let mut receiver = if is_self { None } else { get_account(state_update, &body.receiver_id) };
+    let mut receiver_created = false;
+    let mut receipts = vec![];
+    for action in body.actions.iter() {
+        match action {
+            Action::CreateAccount => {
+                if is_self || receiver.is_some() {
+                    return Err(format!("Account {} already exists", body.receiver_id));
+                }
+                receiver = Some(Account::new(0, hash(&[])));
+                receiver_created = true;
+            }
+            Action::Transfer(transfer) => {
+                if transfer.amount == 0 {
+                    return Err("Sending 0 tokens".to_string());
+                }
+                if originator.amount < transfer.amount {
+                    return Err(format!(
+                        "Account {} tries to send {}, but has staked {} and only has {}",
+                        body.originator, transfer.amount, originator.staked, originator.amount,
+                    ));
+                }
+                if !is_self {
+                    let receiver = receiver
+                        .as_mut()
+                        .ok_or_else(|| format!("Account {} does not exist", body.receiver_id))?;
+                    originator.amount -= transfer.amount;
+                    receiver.amount += transfer.amount;
+                }
+            }
+            Action::DeployContract(deploy_contract) => {
+                let account =
+                    batch_owned_account(body, originator, &mut receiver, receiver_created)?;
+                receipts.append(&mut deploy(
+                    state_update,
+                    &body.receiver_id,
+                    &deploy_contract.code,
+                    account,
+                )?);
+            }
+            Action::Stake(stake) => {
+                let account =
+                    batch_owned_account(body, originator, &mut receiver, receiver_created)?;
+                receipts.append(&mut staking(
+                    state_update,
+                    stake.amount,
+                    &stake.public_key,
+                    &body.receiver_id,
+                    account,
+                    validator_proposals,
+                )?);
+            }
+            Action::AddKey(add_key_action) => {
+                let account =
+                    batch_owned_account(body, originator, &mut receiver, receiver_created)?;
+                receipts.append(&mut add_key(
+                    state_update,
+                    &body.receiver_id,
+                    &add_key_action.public_key,
+                    &add_key_action.access_key,
+                    account,
+                )?);
+            }
+            Action::DeleteKey(delete_key_action) => {
+                let account =
+                    batch_owned_account(body, originator, &mut receiver, receiver_created)?;
+                let key_nonce = create_nonce_with_nonce(&nonce, receipts.len() as u64);
+                receipts.append(&mut delete_key(
+                    state_update,
+                    &body.receiver_id,
+                    &delete_key_action.public_key,
+                    account,
+                    key_nonce,
+                )?);
+            }
+        }
+    }
+    set_account(state_update, &body.originator, originator);
+    if let Some(receiver) = receiver {
+        set_account(state_update, &body.receiver_id, &receiver);
+    }
+    Ok(receipts)
+}
+
 /// System call to create an account.
 pub fn system_create_account(
     state_update: &mut TrieUpdate,
@@ -311,8 +434,9 @@ pub fn system_create_account(
         return Err(format!("Account name {} {}", account_id, INVALID_ACCOUNT_ID));
     }
     let public_key = PublicKey::try_from(&call.args as &[u8]).map_err(|e| format!("{}", e))?;
-    let new_account = Account::new(vec![public_key], call.amount, hash(&[]));
+    let new_account = Account::new(call.amount, hash(&[]));
     set_account(state_update, &account_id, &new_account);
+    set_access_key(state_update, &account_id, &public_key, &AccessKey::full_access());
     Ok(vec![])
 }
 
@@ -1,3 +1,4 @@
+use near_primitives::hash::CryptoHash;
 use near_primitives::transaction::{
     CreateAccountTransaction, DeployContractTransaction, TransactionBody,
 };
@@ -11,6 +12,7 @@ fn setup_test_contract(wasm_binary: &[u8]) -> RuntimeNode {
     let account_id = node.account_id().unwrap();
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(&account_id).unwrap_or_default() + 1,
+        block_hash: CryptoHash::default(),
         originator: account_id.clone(),
         new_account_id: "test_contract".to_string(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -22,6 +24,7 @@ fn setup_test_contract(wasm_binary: &[u8]) -> RuntimeNode {
 
     let transaction = TransactionBody::DeployContract(DeployContractTransaction {
         nonce: node.get_account_nonce(&account_id).unwrap_or_default() + 1,
+        block_hash: CryptoHash::default(),
         contract_id: "test_contract".to_string(),
         wasm_byte_array: wasm_binary.to_vec(),
     })
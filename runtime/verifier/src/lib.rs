@@ -1,7 +1,7 @@
-use near_primitives::account::{AccessKey, Account};
+use near_primitives::account::{AccessKey, AccessKeyPermission, Account};
 use near_primitives::crypto::signature::{verify, PublicKey};
 use near_primitives::logging;
-use near_primitives::transaction::{SignedTransaction, TransactionBody};
+use near_primitives::transaction::{Action, SignedTransaction, TransactionBody};
 use near_primitives::types::AccountId;
 use near_primitives::utils::is_valid_account_id;
 use near_store::{get_access_key, get_account, TrieUpdate};
@@ -10,7 +10,7 @@ pub struct VerificationData {
     pub originator_id: AccountId,
     pub originator: Account,
     pub public_key: PublicKey,
-    pub access_key: Option<AccessKey>,
+    pub access_key: AccessKey,
 }
 
 pub struct TransactionVerifier<'a> {
@@ -27,96 +27,87 @@ impl<'a> TransactionVerifier<'a> {
         transaction: &SignedTransaction,
     ) -> Result<VerificationData, String> {
         let originator_id = transaction.body.get_originator();
-        let originator = get_account(self.state_update, &originator_id);
-        match originator {
-            Some(originator) => {
-                if transaction.body.get_nonce() <= originator.nonce {
-                    return Err(format!(
-                        "Transaction nonce {} must be larger than originator's nonce {}",
-                        transaction.body.get_nonce(),
-                        originator.nonce,
-                    ));
-                }
+        let originator = get_account(self.state_update, &originator_id)
+            .ok_or_else(|| format!("Originator {:?} does not exist", originator_id))?;
 
-                let contract_id = transaction.body.get_contract_id();
-                if let Some(ref contract_id) = contract_id {
-                    if !is_valid_account_id(&contract_id) {
-                        return Err(format!(
-                            "Invalid contract_id / receiver {} according to requirements",
-                            contract_id
-                        ));
-                    }
-                }
+        let contract_id = transaction.body.get_contract_id();
+        if let Some(ref contract_id) = contract_id {
+            if !is_valid_account_id(&contract_id) {
+                return Err(format!(
+                    "Invalid contract_id / receiver {} according to requirements",
+                    contract_id
+                ));
+            }
+        }
 
-                let hash = transaction.get_hash();
-                let hash = hash.as_ref();
-                let public_key = originator
-                    .public_keys
-                    .iter()
-                    .find(|key| verify(&hash, &transaction.signature, &key))
-                    .cloned();
+        if let TransactionBody::Batch(ref batch) = transaction.body {
+            // Account created without an access key would be left with nothing to control it by.
+            let creates_account =
+                batch.actions.iter().any(|action| *action == Action::CreateAccount);
+            let adds_key = batch.actions.iter().any(|action| match action {
+                Action::AddKey(_) => true,
+                _ => false,
+            });
+            if creates_account && !adds_key {
+                return Err(format!(
+                    "Batch transaction creates account {} without adding an access key to it",
+                    batch.receiver_id
+                ));
+            }
+        }
 
-                if let Some(public_key) = public_key {
-                    if let Some(ref tx_public_key) = transaction.public_key {
-                        if &public_key != tx_public_key {
-                            return Err(
-                                "Transaction is signed with different public key than given"
-                                    .to_string(),
-                            );
-                        }
-                    }
-                    Ok(VerificationData { originator_id, originator, public_key, access_key: None })
-                } else {
-                    if let Some(ref public_key) = transaction.public_key {
-                        let access_key =
-                            get_access_key(self.state_update, &originator_id, &public_key);
-                        if let Some(access_key) = access_key {
-                            if let TransactionBody::FunctionCall(ref function_call) =
-                                transaction.body
-                            {
-                                let access_contract_id =
-                                    access_key.contract_id.as_ref().unwrap_or(&originator_id);
+        let hash = transaction.get_hash();
+        let hash = hash.as_ref();
+        // The key is required, so verifying the transaction takes a single signature check
+        // regardless of the number of access keys of the originator.
+        let public_key = transaction.public_key.ok_or_else(|| {
+            "Transaction doesn't specify the public key it's signed with".to_string()
+        })?;
+        let access_key = get_access_key(self.state_update, &originator_id, &public_key)
+            .filter(|_| verify(&hash, &transaction.signature, &public_key))
+            .ok_or_else(|| {
+                format!(
+                    "Transaction is not signed with a public key of the originator {:?}",
+                    originator_id,
+                )
+            })?;
 
-                                if &function_call.contract_id != access_contract_id {
-                                    return Err(format!(
-                                        "Access key contract ID {:?} doesn't match TX contract account ID {:?}",
-                                        access_contract_id,
-                                        function_call.contract_id,
-                                    ));
-                                }
-                                if let Some(ref access_method_name) = access_key.method_name {
-                                    if &function_call.method_name != access_method_name {
-                                        return Err(format!(
-                                            "Transaction method name {:?} doesn't match the access key method name {:?}",
-                                            logging::pretty_utf8(&function_call.method_name),
-                                            logging::pretty_utf8(access_method_name),
-                                        ));
-                                    }
-                                }
-                                Ok(VerificationData {
-                                    originator_id,
-                                    originator,
-                                    public_key: *public_key,
-                                    access_key: Some(access_key),
-                                })
-                            } else {
-                                Err("Access key can only be used with the FunctionCall transactions".to_string())
-                            }
-                        } else {
-                            Err(format!(
-                                "Transaction is not signed with a public key of the originator {:?}",
-                                originator_id,
-                            ))
-                        }
-                    } else {
-                        Err(format!(
-                            "Transaction is not signed with a public key of the originator {:?}",
-                            originator_id,
-                        ))
-                    }
+        if transaction.body.get_nonce() <= access_key.nonce {
+            return Err(format!(
+                "Transaction nonce {} must be larger than nonce of the used access key {}",
+                transaction.body.get_nonce(),
+                access_key.nonce,
+            ));
+        }
+
+        if let AccessKeyPermission::FunctionCall(ref permission) = access_key.permission {
+            if let TransactionBody::FunctionCall(ref function_call) = transaction.body {
+                let access_contract_id = permission.contract_id.as_ref().unwrap_or(&originator_id);
+                if &function_call.contract_id != access_contract_id {
+                    return Err(format!(
+                        "Access key contract ID {:?} doesn't match TX contract account ID {:?}",
+                        access_contract_id, function_call.contract_id,
+                    ));
+                }
+                if !permission.method_names.is_empty()
+                    && !permission.method_names.contains(&function_call.method_name)
+                {
+                    return Err(format!(
+                        "Transaction method name {:?} isn't allowed by the access key method names {:?}",
+                        logging::pretty_utf8(&function_call.method_name),
+                        permission
+                            .method_names
+                            .iter()
+                            .map(|method_name| logging::pretty_utf8(method_name))
+                            .collect::<Vec<_>>(),
+                    ));
                 }
+            } else {
+                return Err(
+                    "Access key can only be used with the FunctionCall transactions".to_string()
+                );
             }
-            _ => Err(format!("Originator {:?} does not exist", originator_id)),
         }
+        Ok(VerificationData { originator_id, originator, public_key, access_key })
     }
 }
@@ -9,6 +9,7 @@ use near_primitives::types::{Balance, StorageUsage, StorageUsageChange};
 
 use crate::cache;
 use crate::ext::External;
+use crate::metrics;
 use crate::runtime::{self, Runtime};
 use crate::types::{Config, Error, ReturnData, RuntimeContext};
 
@@ -65,10 +66,13 @@ pub fn execute(
 
     let method_name = std::str::from_utf8(method_name).map_err(|_| Error::BadUtf8)?;
 
-    match module
+    let timer = near_metrics::start_timer(&metrics::WASM_EXECUTION_TIME);
+    let result = module
         .instantiate(&import_object)
-        .and_then(|instance| instance.call(&method_name, &[]).map_err(|e| e.into()))
-    {
+        .and_then(|instance| instance.call(&method_name, &[]).map_err(|e| e.into()));
+    near_metrics::stop_timer(timer);
+
+    match result {
         Ok(_) => {
             let e = ExecutionOutcome {
                 storage_usage: (context.storage_usage as StorageUsageChange
@@ -13,6 +13,7 @@ extern crate wabt;
 pub mod cache;
 pub mod executor;
 pub mod ext;
+mod metrics;
 pub mod prepare;
 mod runtime;
 pub mod types;
@@ -0,0 +1,10 @@
+use lazy_static::lazy_static;
+use near_metrics::Histogram;
+
+lazy_static! {
+    pub static ref WASM_EXECUTION_TIME: near_metrics::Result<Histogram> =
+        near_metrics::try_create_histogram(
+            "near_wasm_execution_time",
+            "Time taken to instantiate the contract and run the method, in seconds"
+        );
+}
@@ -2,14 +2,19 @@
 extern crate serde_derive;
 
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 
+use near_primitives::crypto::key_derivation::{
+    generate_mnemonic, key_pair_from_mnemonic, DEFAULT_DERIVATION_PATH,
+};
 use near_primitives::crypto::signature::{sign, PublicKey, SecretKey};
 use near_primitives::crypto::signer::{
-    get_key_file, write_block_producer_key_file, InMemorySigner,
+    decrypt_key_file, encrypt_key_file, get_key_file, write_block_producer_key_file,
+    InMemorySigner, PassphraseSource,
 };
 use near_primitives::hash::hash;
 use near_primitives::serialize::{from_base, to_base, BaseEncode};
@@ -80,6 +85,33 @@ fn generate_tendermint_key(matches: &ArgMatches) {
     write_tendermint_key_file(&key_store_path.as_path(), signer.public_key, signer.secret_key);
 }
 
+fn derive_key(matches: &ArgMatches) {
+    let key_store_path = get_key_store_path(matches);
+    let phrase = match matches.value_of("mnemonic") {
+        Some(phrase) => phrase.to_string(),
+        None => {
+            let mut phrase = String::new();
+            if let Err(err) = io::stdin().read_line(&mut phrase) {
+                println!("Failed to read mnemonic: {}", err);
+                process::exit(7);
+            }
+            phrase.trim().to_string()
+        }
+    };
+    let passphrase = matches.value_of("mnemonic_passphrase").unwrap_or("");
+    let path = matches.value_of("derivation_path").unwrap();
+    match key_pair_from_mnemonic(&phrase, passphrase, path) {
+        Ok((public_key, secret_key)) => {
+            write_block_producer_key_file(&key_store_path.as_path(), public_key, secret_key);
+            print!("{}", public_key);
+        }
+        Err(err) => {
+            println!("Failed to derive key: {}", err);
+            process::exit(7);
+        }
+    }
+}
+
 fn get_public_key(matches: &ArgMatches) {
     let key_store_path = get_key_store_path(matches);
     let public_key = None;
@@ -87,6 +119,48 @@ fn get_public_key(matches: &ArgMatches) {
     print!("{}", key_file.public_key);
 }
 
+fn get_passphrase(matches: &ArgMatches, key_file_path: &Path, confirm: bool) -> String {
+    let source = matches
+        .value_of("passphrase_file")
+        .map(|path| PassphraseSource::File(PathBuf::from(path)))
+        .unwrap_or_else(PassphraseSource::from_env);
+    let read = || match source.read(key_file_path) {
+        Ok(passphrase) => passphrase,
+        Err(err) => {
+            println!("Failed to read passphrase: {}", err);
+            process::exit(5);
+        }
+    };
+    let passphrase = read();
+    if confirm {
+        if let PassphraseSource::Prompt = source {
+            if read() != passphrase {
+                println!("Passphrases do not match");
+                process::exit(5);
+            }
+        }
+    }
+    passphrase
+}
+
+fn encrypt(matches: &ArgMatches) {
+    let key_file_path = matches.value_of("key_file").map(PathBuf::from).unwrap();
+    let passphrase = get_passphrase(matches, &key_file_path, true);
+    if let Err(err) = encrypt_key_file(&key_file_path, &passphrase) {
+        println!("Failed to encrypt {:?}: {}", key_file_path, err);
+        process::exit(6);
+    }
+}
+
+fn decrypt(matches: &ArgMatches) {
+    let key_file_path = matches.value_of("key_file").map(PathBuf::from).unwrap();
+    let passphrase = get_passphrase(matches, &key_file_path, false);
+    if let Err(err) = decrypt_key_file(&key_file_path, &passphrase) {
+        println!("Failed to decrypt {:?}: {}", key_file_path, err);
+        process::exit(6);
+    }
+}
+
 fn main() {
     let key_store_path_arg = &Arg::with_name("key_store_path")
         .short("p")
@@ -96,6 +170,21 @@ fn main() {
         .default_value("keystore")
         .required(true)
         .takes_value(true);
+    let key_file_arg = &Arg::with_name("key_file")
+        .short("f")
+        .long("key-file")
+        .value_name("KEY_FILE")
+        .help("Key file to encrypt or decrypt in place")
+        .required(true)
+        .takes_value(true);
+    let passphrase_file_arg = &Arg::with_name("passphrase_file")
+        .long("passphrase-file")
+        .value_name("PASSPHRASE_FILE")
+        .help(
+            "File with the passphrase. NEAR_KEY_PASSPHRASE or NEAR_KEY_PASSPHRASE_FILE \
+             are used if omitted, otherwise the passphrase is prompted",
+        )
+        .takes_value(true);
     let matches = App::new("keystore")
         .subcommand(
             SubCommand::with_name("keygen")
@@ -138,6 +227,36 @@ fn main() {
                         .takes_value(true),
                 ),
         )
+        .subcommand(SubCommand::with_name("encrypt").arg(key_file_arg).arg(passphrase_file_arg))
+        .subcommand(SubCommand::with_name("decrypt").arg(key_file_arg).arg(passphrase_file_arg))
+        .subcommand(SubCommand::with_name("mnemonic"))
+        .subcommand(
+            SubCommand::with_name("derive")
+                .arg(key_store_path_arg)
+                .arg(
+                    Arg::with_name("mnemonic")
+                        .short("m")
+                        .long("mnemonic")
+                        .value_name("MNEMONIC")
+                        .help("Mnemonic to derive the key from, read from stdin if omitted")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mnemonic_passphrase")
+                        .long("mnemonic-passphrase")
+                        .value_name("MNEMONIC_PASSPHRASE")
+                        .help("Optional passphrase protecting the mnemonic")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("derivation_path")
+                        .long("path")
+                        .value_name("DERIVATION_PATH")
+                        .help("Derivation path of the key, the last index selects the key")
+                        .default_value(DEFAULT_DERIVATION_PATH)
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
     if let Some(sub) = matches.subcommand_matches("keygen") {
@@ -150,6 +269,14 @@ fn main() {
         sign_data(sub);
     } else if let Some(sub) = matches.subcommand_matches("get_public_key") {
         get_public_key(sub);
+    } else if let Some(sub) = matches.subcommand_matches("encrypt") {
+        encrypt(sub);
+    } else if let Some(sub) = matches.subcommand_matches("decrypt") {
+        decrypt(sub);
+    } else if matches.subcommand_matches("mnemonic").is_some() {
+        println!("{}", generate_mnemonic());
+    } else if let Some(sub) = matches.subcommand_matches("derive") {
+        derive_key(sub);
     } else {
         println!("Incorrect usage. See usage with: keystore --help");
         process::exit(1);
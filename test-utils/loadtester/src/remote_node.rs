@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::thread;
@@ -8,9 +9,11 @@ use protobuf::Message;
 use reqwest::r#async::Client as AsyncClient;
 use reqwest::Client as SyncClient;
 
+use near_primitives::account::AccessKey;
+use near_primitives::crypto::signature::PublicKey;
 use near_primitives::crypto::signer::InMemorySigner;
-use near_primitives::rpc::AccountViewCallResult;
-use near_primitives::serialize::from_base;
+use near_primitives::hash::CryptoHash;
+use near_primitives::serialize::{from_base, BaseEncode};
 use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::{AccountId, Nonce};
 
@@ -32,6 +35,8 @@ pub struct RemoteNode {
     pub addr: SocketAddr,
     pub signers: Vec<Arc<InMemorySigner>>,
     pub nonces: Vec<Nonce>,
+    /// Hash of the block the generated transactions refer to, fetched when the node is up.
+    pub block_hash: CryptoHash,
     pub url: String,
     async_client: Arc<AsyncClient>,
     sync_client: SyncClient,
@@ -115,20 +120,35 @@ impl RemoteNode {
             .connect_timeout(CONNECT_TIMEOUT)
             .build()
             .unwrap();
-        let mut result = Self { addr, signers, nonces, url, async_client, sync_client };
+        let mut result = Self {
+            addr,
+            signers,
+            nonces,
+            block_hash: CryptoHash::default(),
+            url,
+            async_client,
+            sync_client,
+        };
 
         // Wait for the node to be up.
         wait(|| result.health_ok());
+        result.block_hash = get_result(|| result.get_current_block_hash());
 
         // Collect nonces.
-        result.get_nonces(signers_accs);
+        result.get_nonces();
         Arc::new(RwLock::new(result))
     }
 
-    /// Get nonces for the given list of signers.
-    fn get_nonces(&mut self, signers: &[AccountId]) {
-        let nonces: Vec<Nonce> =
-            signers.iter().map(|s| get_result(|| self.view_account(s)).nonce).collect();
+    /// Get nonces of the access keys of the signers.
+    fn get_nonces(&mut self) {
+        let nonces: Vec<Nonce> = self
+            .signers
+            .iter()
+            .map(|s| {
+                get_result(|| self.view_access_key(&s.account_id, &s.public_key))
+                    .map_or(0, |access_key| access_key.nonce)
+            })
+            .collect();
         self.nonces = nonces;
     }
 
@@ -137,17 +157,15 @@ impl RemoteNode {
         Ok(self.sync_client.post(url.as_str()).send().map(|_| ())?)
     }
 
-    fn view_account(
+    fn view_access_key(
         &self,
         account_id: &AccountId,
-    ) -> Result<AccountViewCallResult, Box<dyn std::error::Error>> {
+        public_key: &PublicKey,
+    ) -> Result<Option<AccessKey>, Box<dyn std::error::Error>> {
         let url = format!("{}{}", self.url, "/abci_query");
-        let response: serde_json::Value = self
-            .sync_client
-            .post(url.as_str())
-            .form(&[("path", format!("\"account/{}\"", account_id))])
-            .send()?
-            .json()?;
+        let path = format!("\"access_key/{}/{}\"", account_id, public_key.to_base());
+        let response: serde_json::Value =
+            self.sync_client.post(url.as_str()).form(&[("path", path)]).send()?.json()?;
         let bytes =
             from_base(response["result"]["response"]["value"].as_str().ok_or(VALUE_NOT_STR_ERR)?)?;
         let s = std::str::from_utf8(&bytes)?;
@@ -211,6 +229,17 @@ impl RemoteNode {
             .parse()?)
     }
 
+    pub fn get_current_block_hash(&self) -> Result<CryptoHash, Box<dyn std::error::Error>> {
+        let url = format!("{}{}", self.url, "/status");
+        let response: serde_json::Value = self.sync_client.post(url.as_str()).send()?.json()?;
+        CryptoHash::try_from(
+            response["result"]["sync_info"]["latest_block_hash"]
+                .as_str()
+                .ok_or(VALUE_NOT_STR_ERR)?
+                .to_owned(),
+        )
+    }
+
     // This does not work because Tendermint RPC returns garbage: https://pastebin.com/RUbEdqt6
     pub fn block_result_codes(
         &self,
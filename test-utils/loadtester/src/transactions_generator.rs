@@ -24,10 +24,10 @@ impl Generator {
         signer_ind: usize,
         all_accounts: &Vec<String>,
     ) -> SignedTransaction {
-        let (signer_from, nonce) = {
+        let (signer_from, nonce, block_hash) = {
             let mut node = node.write().unwrap();
             node.nonces[signer_ind] += 1;
-            (node.signers[signer_ind].clone(), node.nonces[signer_ind])
+            (node.signers[signer_ind].clone(), node.nonces[signer_ind], node.block_hash)
         };
 
         let acc_from = signer_from.account_id.clone();
@@ -38,7 +38,7 @@ impl Generator {
             }
         };
 
-        TransactionBody::send_money(nonce, acc_from.as_str(), acc_to.as_str(), 1)
+        TransactionBody::send_money(nonce, acc_from.as_str(), acc_to.as_str(), 1, block_hash)
             .sign(&*signer_from)
     }
 
@@ -54,6 +54,7 @@ impl Generator {
             let contract_id = signer.account_id.clone();
             let t = DeployContractTransaction {
                 nonce,
+                block_hash: node.block_hash,
                 contract_id,
                 wasm_byte_array: wasm_binary.to_vec(),
             };
@@ -64,10 +65,10 @@ impl Generator {
 
     /// Create set key/value transaction.
     pub fn call_set(node: &Arc<RwLock<RemoteNode>>, signer_ind: usize) -> SignedTransaction {
-        let (signer_from, nonce) = {
+        let (signer_from, nonce, block_hash) = {
             let mut node = node.write().unwrap();
             node.nonces[signer_ind] += 1;
-            (node.signers[signer_ind].clone(), node.nonces[signer_ind])
+            (node.signers[signer_ind].clone(), node.nonces[signer_ind], node.block_hash)
         };
         let acc_from = signer_from.account_id.clone();
 
@@ -75,6 +76,7 @@ impl Generator {
         let value = rand::random::<usize>() % 1_000;
         let t = FunctionCallTransaction {
             nonce,
+            block_hash,
             originator: acc_from.clone(),
             contract_id: acc_from,
             method_name: b"setKeyValue".to_vec(),
@@ -89,15 +91,16 @@ impl Generator {
         node: &Arc<RwLock<RemoteNode>>,
         signer_ind: usize,
     ) -> SignedTransaction {
-        let (signer_from, nonce) = {
+        let (signer_from, nonce, block_hash) = {
             let mut node = node.write().unwrap();
             node.nonces[signer_ind] += 1;
-            (node.signers[signer_ind].clone(), node.nonces[signer_ind])
+            (node.signers[signer_ind].clone(), node.nonces[signer_ind], node.block_hash)
         };
         let acc_from = signer_from.account_id.clone();
 
         let t = FunctionCallTransaction {
             nonce,
+            block_hash,
             originator: acc_from.clone(),
             contract_id: acc_from,
             method_name: b"heavy_storage_blocks".to_vec(),
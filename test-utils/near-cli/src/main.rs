@@ -0,0 +1,329 @@
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use actix::System;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use protobuf::Message;
+
+use near_jsonrpc_client::{new_client, JsonRpcClient};
+use near_primitives::account::{AccessKey, AccessKeyPermission, FunctionCallPermission};
+use near_primitives::crypto::signature::PublicKey;
+use near_primitives::crypto::signer::{get_key_file, InMemorySigner};
+use near_primitives::hash::CryptoHash;
+use near_primitives::serialize::{to_base64, BaseEncode};
+use near_primitives::transaction::{
+    AddKeyTransaction, BatchTransaction, CreateAccountTransaction, DeleteAccountTransaction,
+    DeleteKeyTransaction, DeployContractTransaction, FinalTransactionResult,
+    FinalTransactionStatus, FunctionCallTransaction, StakeTransaction, SwapKeyTransaction,
+    TransactionBody,
+};
+use near_primitives::types::{Balance, Nonce};
+use near_protos::signed_transaction as transaction_proto;
+
+fn get_signer(matches: &ArgMatches) -> InMemorySigner {
+    let key_store_path = matches.value_of("key_store_path").map(PathBuf::from).unwrap();
+    let public_key = matches.value_of("public_key").map(String::from);
+    let key_file = get_key_file(&key_store_path, public_key);
+    InMemorySigner {
+        account_id: matches.value_of("account_id").unwrap().to_string(),
+        public_key: key_file.public_key,
+        secret_key: key_file.secret_key,
+    }
+}
+
+fn parse_public_key(value: &str) -> Result<Vec<u8>, String> {
+    PublicKey::try_from(value).map(|public_key| Vec::from(&public_key)).map_err(|e| e.to_string())
+}
+
+fn parse_balance(value: Option<&str>) -> Result<Balance, String> {
+    value.unwrap_or("0").parse().map_err(|e| format!("Invalid amount: {}", e))
+}
+
+/// Nonce for the next transaction signed by the given signer, every access key of the account
+/// has its own nonce.
+fn next_nonce(client: &mut JsonRpcClient, signer: &InMemorySigner) -> Result<Nonce, String> {
+    let path = format!("access_key/{}/{}", signer.account_id, signer.public_key.to_base());
+    let access_key: Option<AccessKey> =
+        System::new("actix").block_on(client.query(path, String::new()))?.try_into()?;
+    match access_key {
+        Some(access_key) => Ok(access_key.nonce + 1),
+        None => {
+            Err(format!("Key {} is not an access key of {}", signer.public_key, signer.account_id))
+        }
+    }
+}
+
+fn build_transaction(
+    name: &str,
+    matches: &ArgMatches,
+    signer: &InMemorySigner,
+    nonce: Nonce,
+    block_hash: CryptoHash,
+) -> Result<TransactionBody, String> {
+    let originator = signer.account_id.clone();
+    let amount = parse_balance(matches.value_of("amount"))?;
+    Ok(match name {
+        "send_money" => TransactionBody::send_money(
+            nonce,
+            &originator,
+            matches.value_of("receiver").unwrap(),
+            amount,
+            block_hash,
+        ),
+        "create_account" => TransactionBody::CreateAccount(CreateAccountTransaction {
+            nonce,
+            block_hash,
+            originator,
+            new_account_id: matches.value_of("new_account_id").unwrap().to_string(),
+            amount,
+            public_key: parse_public_key(matches.value_of("new_key").unwrap())?,
+        }),
+        "deploy" => {
+            let wasm_path = matches.value_of("wasm_file").unwrap();
+            TransactionBody::DeployContract(DeployContractTransaction {
+                nonce,
+                block_hash,
+                contract_id: originator,
+                wasm_byte_array: fs::read(wasm_path)
+                    .map_err(|e| format!("Failed to read {}: {}", wasm_path, e))?,
+            })
+        }
+        "call" => TransactionBody::FunctionCall(FunctionCallTransaction {
+            nonce,
+            block_hash,
+            originator,
+            contract_id: matches.value_of("contract_id").unwrap().to_string(),
+            method_name: matches.value_of("method_name").unwrap().as_bytes().to_vec(),
+            args: matches.value_of("args").unwrap().as_bytes().to_vec(),
+            amount,
+        }),
+        "stake" => TransactionBody::Stake(StakeTransaction {
+            nonce,
+            block_hash,
+            originator,
+            amount,
+            public_key: matches
+                .value_of("stake_key")
+                .map(String::from)
+                .unwrap_or_else(|| signer.public_key.to_base()),
+        }),
+        "swap_key" => TransactionBody::SwapKey(SwapKeyTransaction {
+            nonce,
+            block_hash,
+            originator,
+            cur_key: parse_public_key(matches.value_of("cur_key").unwrap())?,
+            new_key: parse_public_key(matches.value_of("new_key").unwrap())?,
+        }),
+        "add_key" => {
+            let permission = match matches.value_of("contract_id") {
+                Some(contract_id) => AccessKeyPermission::FunctionCall(FunctionCallPermission {
+                    allowance: amount,
+                    balance_owner: matches.value_of("balance_owner").map(String::from),
+                    contract_id: Some(contract_id.to_string()),
+                    method_names: matches
+                        .values_of("method_name")
+                        .map(|names| names.map(|name| name.as_bytes().to_vec()).collect())
+                        .unwrap_or_default(),
+                }),
+                None => AccessKeyPermission::FullAccess,
+            };
+            TransactionBody::AddKey(AddKeyTransaction {
+                nonce,
+                block_hash,
+                originator,
+                new_key: parse_public_key(matches.value_of("new_key").unwrap())?,
+                access_key: AccessKey { nonce: 0, permission },
+            })
+        }
+        "delete_key" => TransactionBody::DeleteKey(DeleteKeyTransaction {
+            nonce,
+            block_hash,
+            originator,
+            cur_key: parse_public_key(matches.value_of("cur_key").unwrap())?,
+        }),
+        "delete_account" => TransactionBody::DeleteAccount(DeleteAccountTransaction {
+            nonce,
+            block_hash,
+            originator_id: originator,
+            receiver_id: matches.value_of("beneficiary").unwrap().to_string(),
+        }),
+        "batch" => TransactionBody::Batch(BatchTransaction {
+            nonce,
+            block_hash,
+            originator,
+            receiver_id: matches.value_of("receiver").unwrap().to_string(),
+            actions: serde_json::from_str(matches.value_of("actions").unwrap())
+                .map_err(|e| format!("Invalid actions: {}", e))?,
+        }),
+        _ => return Err(format!("Unknown transaction {}", name)),
+    })
+}
+
+/// Signs the transaction with the next nonce on top of the latest block and waits until it and
+/// all its receipts are executed.
+fn submit_transaction(
+    matches: &ArgMatches,
+    name: &str,
+    sub: &ArgMatches,
+) -> Result<FinalTransactionResult, String> {
+    let signer = get_signer(matches);
+    let mut client = new_client(matches.value_of("rpc_addr").unwrap());
+    let nonce = next_nonce(&mut client, &signer)?;
+    let status = System::new("actix").block_on(client.status())?;
+    let transaction =
+        build_transaction(name, sub, &signer, nonce, status.sync_info.latest_block_hash)?
+            .sign(&signer);
+    let proto: transaction_proto::SignedTransaction = transaction.into();
+    let bytes = to_base64(&proto.write_to_bytes().map_err(|e| e.to_string())?);
+    System::new("actix").block_on(client.broadcast_tx_commit(bytes))
+}
+
+/// Prints the status of the transaction, followed by the logs, produced receipts and result of
+/// the transaction and of every receipt in the order they were executed.
+fn print_result(result: &FinalTransactionResult) {
+    println!("Status: {:?}", result.status);
+    for (i, logs) in result.logs.iter().enumerate() {
+        println!("{} {}", if i == 0 { "Transaction" } else { "Receipt" }, logs.hash);
+        for line in logs.lines.iter() {
+            println!("    log: {}", line);
+        }
+        for receipt in logs.receipts.iter() {
+            println!("    receipt: {}", receipt);
+        }
+        if let Some(result) = &logs.result {
+            println!("    result: {}", String::from_utf8_lossy(result));
+        }
+    }
+}
+
+fn value_arg<'a>(name: &'a str, long: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name).long(long).help(help).takes_value(true)
+}
+
+fn main() {
+    let amount_arg = value_arg("amount", "amount", "Amount of tokens").default_value("0");
+    let new_key_arg =
+        value_arg("new_key", "new-key", "Public key to add, in base58").required(true);
+    let cur_key_arg =
+        value_arg("cur_key", "cur-key", "Public key to remove, in base58").required(true);
+    let matches = App::new("near-cli")
+        .about("Signs and submits transactions through the RPC of a node")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            value_arg("rpc_addr", "rpc-addr", "Address of the node RPC")
+                .default_value("http://localhost:3030"),
+        )
+        .arg(
+            value_arg("account_id", "account-id", "Account that signs the transaction")
+                .short("a")
+                .required(true),
+        )
+        .arg(
+            value_arg("key_store_path", "keystore-path", "Sets a directory location for key store")
+                .short("p")
+                .default_value("keystore"),
+        )
+        .arg(
+            value_arg(
+                "public_key",
+                "public-key",
+                "Sets public key to sign with, can be omitted with 1 file in keystore",
+            )
+            .short("k"),
+        )
+        .subcommand(
+            SubCommand::with_name("send_money")
+                .arg(value_arg("receiver", "receiver", "Receiver account").required(true))
+                .arg(amount_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("create_account")
+                .arg(
+                    value_arg("new_account_id", "new-account-id", "Account to create")
+                        .required(true),
+                )
+                .arg(new_key_arg.clone())
+                .arg(amount_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("deploy").arg(
+                value_arg("wasm_file", "wasm-file", "Contract code to deploy to the account")
+                    .required(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("call")
+                .arg(value_arg("contract_id", "contract-id", "Contract to call").required(true))
+                .arg(value_arg("method_name", "method-name", "Method to call").required(true))
+                .arg(value_arg("args", "args", "Arguments of the call").default_value("{}"))
+                .arg(amount_arg.clone()),
+        )
+        .subcommand(SubCommand::with_name("stake").arg(amount_arg.clone()).arg(value_arg(
+            "stake_key",
+            "stake-key",
+            "Public key to validate with, the signing key if omitted",
+        )))
+        .subcommand(
+            SubCommand::with_name("swap_key").arg(cur_key_arg.clone()).arg(new_key_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("add_key")
+                .arg(new_key_arg.clone())
+                .arg(value_arg(
+                    "contract_id",
+                    "contract-id",
+                    "Restricts the key to calls of this contract, full access key if omitted",
+                ))
+                .arg(
+                    value_arg(
+                        "method_name",
+                        "method-name",
+                        "Restricts the key to these methods, any method of the contract if omitted",
+                    )
+                    .multiple(true)
+                    .number_of_values(1)
+                    .requires("contract_id"),
+                )
+                .arg(
+                    value_arg("balance_owner", "balance-owner", "Account that funds the key")
+                        .requires("contract_id"),
+                )
+                .arg(amount_arg.clone().help("Allowance of the restricted key")),
+        )
+        .subcommand(SubCommand::with_name("delete_key").arg(cur_key_arg.clone()))
+        .subcommand(
+            SubCommand::with_name("delete_account").arg(
+                value_arg("beneficiary", "beneficiary", "Account that receives the balance")
+                    .required(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .arg(value_arg("receiver", "receiver", "Receiver of the actions").required(true))
+                .arg(
+                    value_arg(
+                        "actions",
+                        "actions",
+                        "JSON list of actions, e.g. [{\"Transfer\":{\"amount\":\"10\"}}]",
+                    )
+                    .required(true),
+                ),
+        )
+        .get_matches();
+
+    let (name, sub) = matches.subcommand();
+    match submit_transaction(&matches, name, sub.unwrap()) {
+        Ok(result) => {
+            print_result(&result);
+            if result.status != FinalTransactionStatus::Completed {
+                process::exit(2);
+            }
+        }
+        Err(err) => {
+            println!("Failed to submit {}: {}", name, err);
+            process::exit(1);
+        }
+    }
+}
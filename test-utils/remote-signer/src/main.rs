@@ -0,0 +1,187 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use clap::{App, Arg};
+
+use near_primitives::crypto::remote_signer::{
+    new_challenge, read_auth_key, verify_challenge, DoubleSignGuard, SignRequest, SignResponse,
+};
+use near_primitives::crypto::signer::{
+    read_key_file, EDSigner, InMemorySigner, PassphraseSource, SignerError,
+};
+
+/// Signs the request, recording and persisting signed block headers and approvals first.
+fn handle_request(
+    request: SignRequest,
+    signer: &InMemorySigner,
+    guard: &Mutex<DoubleSignGuard>,
+    guard_path: &Path,
+) -> SignResponse {
+    let (height, hash) = match request {
+        SignRequest::Auth(_) => return SignResponse::Error("Already authenticated".to_string()),
+        SignRequest::PublicKey => return SignResponse::PublicKey(signer.public_key),
+        SignRequest::Data(data) => {
+            return match guard.lock().unwrap().check_data(&data) {
+                Ok(()) => SignResponse::Signature(signer.sign(&data)),
+                Err(err) => SignResponse::Error(err.to_string()),
+            };
+        }
+        SignRequest::AnnounceAccount { account_id, peer_id, epoch } => {
+            return match signer.sign_announce_account(&account_id, &peer_id, &epoch) {
+                Ok(signature) => SignResponse::Signature(signature),
+                Err(err) => SignResponse::Error(err.to_string()),
+            };
+        }
+        SignRequest::BlockHeader { height, hash } | SignRequest::Approval { height, hash } => {
+            (height, hash)
+        }
+    };
+    let mut guard = guard.lock().unwrap();
+    let checked = match request {
+        SignRequest::BlockHeader { .. } => guard.check_block_header(height, &hash),
+        _ => guard.check_approval(height, &hash),
+    };
+    match checked
+        .and_then(|_| guard.save(guard_path).map_err(|e| SignerError::Other(e.to_string())))
+    {
+        Ok(()) => SignResponse::Signature(signer.sign(hash.as_ref())),
+        Err(SignerError::DoubleSign(height)) => {
+            println!("Refused to sign {:?}: already signed a different one", request);
+            SignResponse::DoubleSign(height)
+        }
+        Err(SignerError::Other(err)) => SignResponse::Error(err),
+    }
+}
+
+fn write_response(writer: &mut TcpStream, response: &SignResponse) -> io::Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// Serves the requests of the connection once it answers the challenge with the auth key.
+fn serve_connection(
+    stream: TcpStream,
+    signer: &InMemorySigner,
+    guard: &Mutex<DoubleSignGuard>,
+    guard_path: &Path,
+    auth_key: &[u8],
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let challenge = new_challenge();
+    write_response(&mut writer, &SignResponse::Challenge(challenge.clone()))?;
+    let mut lines = BufReader::new(stream).lines();
+    let authenticated = match lines.next().transpose()?.map(|line| serde_json::from_str(&line)) {
+        Some(Ok(SignRequest::Auth(answer))) => verify_challenge(auth_key, &challenge, &answer),
+        _ => false,
+    };
+    if !authenticated {
+        write_response(&mut writer, &SignResponse::Error("Not authenticated".to_string()))?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Not authenticated"));
+    }
+    write_response(&mut writer, &SignResponse::Authenticated)?;
+    for line in lines {
+        let response = match serde_json::from_str(&line?) {
+            Ok(request) => handle_request(request, signer, guard, guard_path),
+            Err(err) => SignResponse::Error(format!("Invalid request: {}", err)),
+        };
+        write_response(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("remote-signer")
+        .about("Keeps the validator key and signs blocks and approvals for the node")
+        .arg(
+            Arg::with_name("key_file")
+                .short("k")
+                .long("key-file")
+                .value_name("KEY_FILE")
+                .help("Validator key file, may be encrypted")
+                .default_value("validator_key.json")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("addr")
+                .long("addr")
+                .value_name("ADDR")
+                .help("Address to listen on for the node")
+                .default_value("127.0.0.1:3031")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth_key_file")
+                .long("auth-key-file")
+                .value_name("AUTH_KEY_FILE")
+                .help("File with the secret key shared with the node, at least 16 bytes")
+                .default_value("signer_auth_key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state_file")
+                .long("state-file")
+                .value_name("STATE_FILE")
+                .help("File with the last signed block header and approval")
+                .default_value("signer_state.json")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let key_file_path = matches.value_of("key_file").map(PathBuf::from).unwrap();
+    let signer: InMemorySigner = match read_key_file(&key_file_path, &PassphraseSource::from_env())
+    {
+        Ok(signer) => signer,
+        Err(err) => {
+            println!("Failed to read key file {:?}: {}", key_file_path, err);
+            process::exit(1);
+        }
+    };
+    let auth_key_path = matches.value_of("auth_key_file").map(PathBuf::from).unwrap();
+    let auth_key = match read_auth_key(&auth_key_path) {
+        Ok(auth_key) => auth_key,
+        Err(err) => {
+            println!("Failed to read auth key file {:?}: {}", auth_key_path, err);
+            process::exit(1);
+        }
+    };
+    let guard_path = matches.value_of("state_file").map(PathBuf::from).unwrap();
+    let guard = match DoubleSignGuard::load(&guard_path) {
+        Ok(guard) => guard,
+        Err(err) => {
+            println!("Failed to read state file {:?}: {}", guard_path, err);
+            process::exit(1);
+        }
+    };
+    let addr = matches.value_of("addr").unwrap();
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Failed to listen on {}: {}", addr, err);
+            process::exit(1);
+        }
+    };
+    println!("Signing for {} with {} on {}", signer.account_id, signer.public_key, addr);
+
+    let state = Arc::new((signer, Mutex::new(guard), guard_path, auth_key));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Failed to accept connection: {}", err);
+                continue;
+            }
+        };
+        let state = state.clone();
+        thread::spawn(move || {
+            let (signer, guard, guard_path, auth_key) = &*state;
+            if let Err(err) = serve_connection(stream, signer, guard, guard_path, auth_key) {
+                println!("Connection closed: {}", err);
+            }
+        });
+    }
+}
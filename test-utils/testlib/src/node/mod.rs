@@ -7,6 +7,7 @@ use near::config::{
 };
 use near::NearConfig;
 use near_primitives::crypto::signer::{EDSigner, InMemorySigner};
+use near_primitives::hash::CryptoHash;
 use near_primitives::rpc::AccountViewCallResult;
 use near_primitives::serialize::to_base;
 use near_primitives::transaction::SignedTransaction;
@@ -66,6 +67,10 @@ pub trait Node: Send + Sync {
         self.user().get_account_nonce(account_id)
     }
 
+    fn get_best_block_hash(&self) -> Option<CryptoHash> {
+        self.user().get_best_block_hash()
+    }
+
     fn signer(&self) -> Arc<dyn EDSigner>;
 
     fn is_running(&self) -> bool;
@@ -1,16 +1,16 @@
 use near::config::{TESTING_INIT_BALANCE, TESTING_INIT_STAKE};
-use near_primitives::account::AccessKey;
+use near_primitives::account::{AccessKey, AccessKeyPermission, FunctionCallPermission};
 use near_primitives::crypto::signer::InMemorySigner;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::rpc::AccountViewCallResult;
 use near_primitives::serialize::Decode;
 use near_primitives::transaction::{
-    AddKeyTransaction, AsyncCall, Callback, CallbackInfo, CallbackResult, CreateAccountTransaction,
-    DeleteKeyTransaction, DeployContractTransaction, FinalTransactionStatus,
-    FunctionCallTransaction, ReceiptBody, ReceiptTransaction, SwapKeyTransaction, TransactionBody,
-    TransactionStatus,
+    Action, AddKeyAction, AddKeyTransaction, AsyncCall, BatchTransaction, Callback, CallbackInfo,
+    CallbackResult, CreateAccountTransaction, DeleteKeyTransaction, DeployContractAction,
+    DeployContractTransaction, FinalTransactionStatus, FunctionCallTransaction, ReceiptBody,
+    ReceiptTransaction, SwapKeyTransaction, TransactionBody, TransactionStatus, TransferAction,
 };
-use near_primitives::types::Balance;
+use near_primitives::types::{AccountId, Balance};
 use near_primitives::utils::key_for_callback;
 use near_store::set_callback;
 
@@ -24,6 +24,24 @@ use crate::user::User;
 /// The amount to send with function call.
 const FUNCTION_CALL_AMOUNT: Balance = 1_000_000_000_000;
 
+/// Access key that can only call functions of the given contract, the account itself if `None`.
+fn function_call_access_key(
+    allowance: Balance,
+    balance_owner: Option<AccountId>,
+    contract_id: Option<AccountId>,
+    method_names: Vec<Vec<u8>>,
+) -> AccessKey {
+    AccessKey {
+        nonce: 0,
+        permission: AccessKeyPermission::FunctionCall(FunctionCallPermission {
+            allowance,
+            balance_owner,
+            contract_id,
+            method_names,
+        }),
+    }
+}
+
 /// validate transaction result in the case that it is successful and generates given number of receipts
 /// recursively.
 pub fn validate_tx_result(
@@ -62,9 +80,10 @@ fn add_access_key(
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::AddKey(AddKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_key: signer2.public_key.0[..].to_vec(),
-        access_key: Some(access_key.clone()),
+        access_key: access_key.clone(),
     })
     .sign(&*node.signer());
     let tx_hash = transaction.get_hash();
@@ -107,6 +126,7 @@ pub fn test_smart_contract_self_call(node: impl Node) {
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: account_id.clone(),
         method_name: b"run_test".to_vec(),
@@ -127,6 +147,7 @@ pub fn test_smart_contract_bad_method_name(node: impl Node) {
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: b"_run_test".to_vec(),
@@ -152,6 +173,7 @@ pub fn test_smart_contract_empty_method_name_with_no_tokens(node: impl Node) {
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: vec![],
@@ -177,6 +199,7 @@ pub fn test_smart_contract_empty_method_name_with_tokens(node: impl Node) {
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: vec![],
@@ -197,6 +220,7 @@ pub fn test_smart_contract_with_args(node: impl Node) {
     let account_id = &node.account_id().unwrap();
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: b"run_test".to_vec(),
@@ -487,6 +511,7 @@ pub fn test_nonce_update_when_deploying_contract(node: impl Node) {
     let wasm_binary = include_bytes!("../../../runtime/wasm/runtest/res/wasm_with_mem.wasm");
     let transaction = TransactionBody::DeployContract(DeployContractTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         contract_id: account_id.clone(),
         wasm_byte_array: wasm_binary.to_vec(),
     })
@@ -512,6 +537,7 @@ pub fn test_nonce_updated_when_tx_failed(node: impl Node) {
         account_id,
         &bob_account(),
         TESTING_INIT_BALANCE + 1,
+        node.get_best_block_hash().unwrap_or_default(),
     )
     .sign(&*node.signer());
 
@@ -534,6 +560,7 @@ pub fn test_upload_contract(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: eve_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -549,6 +576,7 @@ pub fn test_upload_contract(node: impl Node) {
     let wasm_binary = include_bytes!("../../../runtime/wasm/runtest/res/wasm_with_mem.wasm");
     let transaction = TransactionBody::DeployContract(DeployContractTransaction {
         nonce: 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         contract_id: eve_account(),
         wasm_byte_array: wasm_binary.to_vec(),
     })
@@ -574,6 +602,7 @@ pub fn test_redeploy_contract(node: impl Node) {
     let test_binary = b"test_binary";
     let transaction = TransactionBody::DeployContract(DeployContractTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         contract_id: account_id.clone(),
         wasm_byte_array: test_binary.to_vec(),
     })
@@ -601,6 +630,7 @@ pub fn test_send_money(node: impl Node) {
         account_id,
         &bob_account(),
         money_used,
+        node.get_best_block_hash().unwrap_or_default(),
     )
     .sign(&*node.signer());
 
@@ -617,7 +647,6 @@ pub fn test_send_money(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - money_used - TESTING_INIT_STAKE,
@@ -625,12 +654,12 @@ pub fn test_send_money(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
     let result2 = node_user.view_account(&bob_account()).unwrap();
     let public_keys = result2.public_keys.clone();
     assert_eq!(
         result2,
         AccountViewCallResult {
-            nonce: 0,
             account_id: bob_account(),
             public_keys,
             amount: TESTING_INIT_BALANCE + money_used - TESTING_INIT_STAKE,
@@ -649,6 +678,7 @@ pub fn test_send_money_over_balance(node: impl Node) {
         account_id,
         &bob_account(),
         money_used,
+        node.get_best_block_hash().unwrap_or_default(),
     )
     .sign(&*node.signer());
 
@@ -666,7 +696,6 @@ pub fn test_send_money_over_balance(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -674,12 +703,12 @@ pub fn test_send_money_over_balance(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
     let result2 = node_user.view_account(&bob_account()).unwrap();
     let public_keys = result2.public_keys.clone();
     assert_eq!(
         result2,
         AccountViewCallResult {
-            nonce: 0,
             account_id: bob_account(),
             public_keys,
             amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -698,6 +727,7 @@ pub fn test_refund_on_send_money_to_non_existent_account(node: impl Node) {
         account_id,
         &eve_account(),
         money_used,
+        node.get_best_block_hash().unwrap_or_default(),
     )
     .sign(&*node.signer());
 
@@ -722,7 +752,6 @@ pub fn test_refund_on_send_money_to_non_existent_account(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -730,6 +759,7 @@ pub fn test_refund_on_send_money_to_non_existent_account(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
     let result2 = node_user.view_account(&eve_account());
     assert!(result2.is_err());
 }
@@ -741,6 +771,7 @@ pub fn test_create_account(node: impl Node) {
     let money_used = 10;
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: eve_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -764,7 +795,6 @@ pub fn test_create_account(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - money_used - TESTING_INIT_STAKE,
@@ -772,13 +802,13 @@ pub fn test_create_account(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
 
     let result2 = node_user.view_account(&eve_account()).unwrap();
     let public_keys = result2.public_keys.clone();
     assert_eq!(
         result2,
         AccountViewCallResult {
-            nonce: 0,
             account_id: eve_account(),
             public_keys,
             amount: money_used,
@@ -794,6 +824,7 @@ pub fn test_create_account_again(node: impl Node) {
     let money_used = 10;
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: eve_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -808,7 +839,6 @@ pub fn test_create_account_again(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - money_used - TESTING_INIT_STAKE,
@@ -816,13 +846,13 @@ pub fn test_create_account_again(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
 
     let result2 = node_user.view_account(&eve_account()).unwrap();
     let public_keys = result2.public_keys.clone();
     assert_eq!(
         result2,
         AccountViewCallResult {
-            nonce: 0,
             account_id: eve_account(),
             public_keys,
             amount: money_used,
@@ -833,6 +863,7 @@ pub fn test_create_account_again(node: impl Node) {
 
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: eve_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -861,7 +892,6 @@ pub fn test_create_account_again(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 2,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - money_used - TESTING_INIT_STAKE,
@@ -869,6 +899,7 @@ pub fn test_create_account_again(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 2);
 }
 
 pub fn test_create_account_failure_invalid_name(node: impl Node) {
@@ -888,6 +919,7 @@ pub fn test_create_account_failure_invalid_name(node: impl Node) {
     {
         let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
             nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+            block_hash: node.get_best_block_hash().unwrap_or_default(),
             originator: account_id.clone(),
             new_account_id: invalid_account_name.to_string(),
             public_key: node.signer().public_key().0[..].to_vec(),
@@ -907,7 +939,6 @@ pub fn test_create_account_failure_invalid_name(node: impl Node) {
         assert_eq!(
             account,
             AccountViewCallResult {
-                nonce: counter as u64 + 1,
                 account_id: account_id.clone(),
                 public_keys: vec![node.signer().public_key()],
                 amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -915,6 +946,7 @@ pub fn test_create_account_failure_invalid_name(node: impl Node) {
                 code_hash: default_code_hash(),
             }
         );
+        assert_eq!(node_user.get_account_nonce(account_id).unwrap(), counter as u64 + 1);
     }
 }
 
@@ -925,6 +957,7 @@ pub fn test_create_account_failure_already_exists(node: impl Node) {
     let money_used = 10;
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: bob_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -952,7 +985,6 @@ pub fn test_create_account_failure_already_exists(node: impl Node) {
     assert_eq!(
         result1.unwrap(),
         AccountViewCallResult {
-            nonce: 1,
             account_id: account_id.clone(),
             public_keys: vec![node.signer().public_key()],
             amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -960,13 +992,13 @@ pub fn test_create_account_failure_already_exists(node: impl Node) {
             code_hash: default_code_hash(),
         }
     );
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), 1);
 
     let result2 = node_user.view_account(&bob_account()).unwrap();
     let public_keys = result2.public_keys.clone();
     assert_eq!(
         result2,
         AccountViewCallResult {
-            nonce: 0,
             account_id: bob_account(),
             public_keys,
             amount: TESTING_INIT_BALANCE - TESTING_INIT_STAKE,
@@ -984,6 +1016,7 @@ pub fn test_swap_key(node: impl Node) {
     let money_used = 10;
     let transaction = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_account_id: eve_account(),
         public_key: node.signer().public_key().0[..].to_vec(),
@@ -998,6 +1031,7 @@ pub fn test_swap_key(node: impl Node) {
     assert_ne!(root, new_root);
     let transaction = TransactionBody::SwapKey(SwapKeyTransaction {
         nonce: node.get_account_nonce(&eve_account()).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: eve_account(),
         cur_key: node.signer().public_key().0[..].to_vec(),
         new_key: signer2.public_key.0[..].to_vec(),
@@ -1024,9 +1058,10 @@ pub fn test_add_key(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::AddKey(AddKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_key: signer2.public_key.0[..].to_vec(),
-        access_key: None,
+        access_key: AccessKey::full_access(),
     })
     .sign(&*node.signer());
     let tx_hash = transaction.get_hash();
@@ -1041,7 +1076,7 @@ pub fn test_add_key(node: impl Node) {
 
     let account = node_user.view_account(account_id).unwrap();
     assert_eq!(account.public_keys.len(), 2);
-    assert_eq!(account.public_keys[1], signer2.public_key);
+    assert!(account.public_keys.contains(&signer2.public_key));
 }
 
 pub fn test_add_existing_key(node: impl Node) {
@@ -1050,9 +1085,10 @@ pub fn test_add_existing_key(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::AddKey(AddKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_key: node.signer().public_key().0[..].to_vec(),
-        access_key: None,
+        access_key: AccessKey::full_access(),
     })
     .sign(&*node.signer());
     let tx_hash = transaction.get_hash();
@@ -1076,9 +1112,10 @@ pub fn test_delete_key(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::AddKey(AddKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         new_key: signer2.public_key.0[..].to_vec(),
-        access_key: None,
+        access_key: AccessKey::full_access(),
     })
     .sign(&*node.signer());
     let tx_hash = transaction.get_hash();
@@ -1093,6 +1130,7 @@ pub fn test_delete_key(node: impl Node) {
 
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: node.signer().public_key().0[..].to_vec(),
     })
@@ -1120,6 +1158,7 @@ pub fn test_delete_key_not_owned(node: impl Node) {
 
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: signer2.public_key.0[..].to_vec(),
     })
@@ -1145,6 +1184,7 @@ pub fn test_delete_key_last(node: impl Node) {
 
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: node.signer().public_key().0[..].to_vec(),
     })
@@ -1165,8 +1205,7 @@ pub fn test_delete_key_last(node: impl Node) {
 
 pub fn test_add_access_key(node: impl Node) {
     let node_user = node.user();
-    let access_key =
-        AccessKey { amount: 0, balance_owner: None, contract_id: None, method_name: None };
+    let access_key = function_call_access_key(0, None, None, vec![]);
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
@@ -1180,8 +1219,7 @@ pub fn test_add_access_key(node: impl Node) {
 
 pub fn test_delete_access_key(node: impl Node) {
     let node_user = node.user();
-    let access_key =
-        AccessKey { amount: 0, balance_owner: None, contract_id: None, method_name: None };
+    let access_key = function_call_access_key(0, None, None, vec![]);
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
@@ -1189,6 +1227,7 @@ pub fn test_delete_access_key(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: signer2.public_key.0[..].to_vec(),
     })
@@ -1212,8 +1251,7 @@ pub fn test_delete_access_key(node: impl Node) {
 }
 
 pub fn test_add_access_key_with_funding(node: impl Node) {
-    let access_key =
-        AccessKey { amount: 10, balance_owner: None, contract_id: None, method_name: None };
+    let access_key = function_call_access_key(10, None, None, vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
@@ -1230,8 +1268,7 @@ pub fn test_add_access_key_with_funding(node: impl Node) {
 }
 
 pub fn test_delete_access_key_with_owner_refund(node: impl Node) {
-    let access_key =
-        AccessKey { amount: 10, balance_owner: None, contract_id: None, method_name: None };
+    let access_key = function_call_access_key(10, None, None, vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
@@ -1242,6 +1279,7 @@ pub fn test_delete_access_key_with_owner_refund(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: signer2.public_key.0[..].to_vec(),
     })
@@ -1266,12 +1304,7 @@ pub fn test_delete_access_key_with_owner_refund(node: impl Node) {
 }
 
 pub fn test_delete_access_key_with_bob_refund(node: impl Node) {
-    let access_key = AccessKey {
-        amount: 10,
-        balance_owner: Some(bob_account()),
-        contract_id: None,
-        method_name: None,
-    };
+    let access_key = function_call_access_key(10, Some(bob_account()), None, vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
@@ -1284,6 +1317,7 @@ pub fn test_delete_access_key_with_bob_refund(node: impl Node) {
     let root = node_user.get_state_root();
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
         nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: signer2.public_key.0[..].to_vec(),
     })
@@ -1316,19 +1350,16 @@ pub fn test_delete_access_key_with_bob_refund(node: impl Node) {
 }
 
 pub fn test_access_key_smart_contract(node: impl Node) {
-    let access_key = AccessKey {
-        amount: FUNCTION_CALL_AMOUNT,
-        balance_owner: None,
-        contract_id: Some(bob_account()),
-        method_name: None,
-    };
+    let access_key =
+        function_call_access_key(FUNCTION_CALL_AMOUNT, None, Some(bob_account()), vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
 
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
-        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        nonce: node_user.get_access_key_nonce(account_id, &signer2.public_key).unwrap() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: b"run_test".to_vec(),
@@ -1345,19 +1376,16 @@ pub fn test_access_key_smart_contract(node: impl Node) {
 }
 
 pub fn test_access_key_smart_contract_reject_method_name(node: impl Node) {
-    let access_key = AccessKey {
-        amount: 0,
-        balance_owner: None,
-        contract_id: Some(bob_account()),
-        method_name: Some(b"log_something".to_vec()),
-    };
+    let access_key =
+        function_call_access_key(0, None, Some(bob_account()), vec![b"log_something".to_vec()]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
 
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
-        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        nonce: node_user.get_access_key_nonce(account_id, &signer2.public_key).unwrap() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: bob_account(),
         method_name: b"run_test".to_vec(),
@@ -1378,19 +1406,15 @@ pub fn test_access_key_smart_contract_reject_method_name(node: impl Node) {
 }
 
 pub fn test_access_key_smart_contract_reject_contract_id(node: impl Node) {
-    let access_key = AccessKey {
-        amount: 0,
-        balance_owner: None,
-        contract_id: Some(bob_account()),
-        method_name: None,
-    };
+    let access_key = function_call_access_key(0, None, Some(bob_account()), vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
 
     let transaction = TransactionBody::FunctionCall(FunctionCallTransaction {
-        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        nonce: node_user.get_access_key_nonce(account_id, &signer2.public_key).unwrap() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         contract_id: eve_account(),
         method_name: b"run_test".to_vec(),
@@ -1411,15 +1435,15 @@ pub fn test_access_key_smart_contract_reject_contract_id(node: impl Node) {
 }
 
 pub fn test_access_key_reject_non_function_call(node: impl Node) {
-    let access_key =
-        AccessKey { amount: 0, balance_owner: None, contract_id: None, method_name: None };
+    let access_key = function_call_access_key(0, None, None, vec![]);
     let node_user = node.user();
     let account_id = &node.account_id().unwrap();
     let signer2 = InMemorySigner::from_random();
     add_access_key(&node, &node_user, &access_key, &signer2);
 
     let transaction = TransactionBody::DeleteKey(DeleteKeyTransaction {
-        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        nonce: node_user.get_access_key_nonce(account_id, &signer2.public_key).unwrap() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: account_id.clone(),
         cur_key: node.signer().public_key().0[..].to_vec(),
     })
@@ -1436,6 +1460,50 @@ pub fn test_access_key_reject_non_function_call(node: impl Node) {
     assert_eq!(root, new_root);
 }
 
+/// Every access key has its own nonce, so transactions signed with different keys of the account
+/// don't collide.
+pub fn test_access_key_nonces_are_independent(node: impl Node) {
+    let access_key =
+        function_call_access_key(FUNCTION_CALL_AMOUNT, None, Some(bob_account()), vec![]);
+    let node_user = node.user();
+    let account_id = &node.account_id().unwrap();
+    let signer2 = InMemorySigner::from_random();
+    add_access_key(&node, &node_user, &access_key, &signer2);
+    let account_nonce = node_user.get_account_nonce(account_id).unwrap();
+    assert!(account_nonce >= 1);
+
+    let call = |args: &[u8]| {
+        TransactionBody::FunctionCall(FunctionCallTransaction {
+            nonce: 1,
+            block_hash: node.get_best_block_hash().unwrap_or_default(),
+            originator: account_id.clone(),
+            contract_id: bob_account(),
+            method_name: b"run_test".to_vec(),
+            args: args.to_vec(),
+            amount: FUNCTION_CALL_AMOUNT,
+        })
+        .sign(&signer2)
+    };
+    let transaction = call(b"");
+    let hash = transaction.get_hash();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &hash);
+    let transaction_result = node_user.get_transaction_result(&hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Completed);
+    assert_eq!(node_user.get_access_key_nonce(account_id, &signer2.public_key).unwrap(), 1);
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), account_nonce);
+
+    // The same nonce can't be used with the same key again.
+    let transaction = call(b"again");
+    let hash = transaction.get_hash();
+    let root = node_user.get_state_root();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &hash);
+    let transaction_result = node_user.get_transaction_result(&hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Failed);
+    assert_eq!(root, node_user.get_state_root());
+}
+
 pub fn test_increase_stake(node: impl Node) {
     let node_user = node.user();
     let root = node_user.get_state_root();
@@ -1547,3 +1615,131 @@ pub fn test_delete_account_while_staking(node: impl Node) {
     assert_eq!(result.status, FinalTransactionStatus::Failed);
     assert!(node.user().view_account(&eve_account()).is_ok());
 }
+
+pub fn test_batch_create_account_and_deploy(node: impl Node) {
+    let account_id = &node.account_id().unwrap();
+    let node_user = node.user();
+    let root = node_user.get_state_root();
+    let money_used = 10;
+    let wasm_binary = include_bytes!("../../../runtime/wasm/runtest/res/wasm_with_mem.wasm");
+    let transaction = TransactionBody::Batch(BatchTransaction {
+        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
+        originator: account_id.clone(),
+        receiver_id: eve_account(),
+        actions: vec![
+            Action::CreateAccount,
+            Action::Transfer(TransferAction { amount: money_used }),
+            Action::AddKey(AddKeyAction {
+                public_key: node.signer().public_key().0[..].to_vec(),
+                access_key: AccessKey::full_access(),
+            }),
+            Action::DeployContract(DeployContractAction { code: wasm_binary.to_vec() }),
+        ],
+    })
+    .sign(&*node.signer());
+    let tx_hash = transaction.get_hash();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &tx_hash);
+
+    let transaction_result = node_user.get_transaction_result(&tx_hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Completed);
+    assert!(transaction_result.receipts.is_empty());
+    let new_root = node_user.get_state_root();
+    assert_ne!(root, new_root);
+
+    let result1 = node_user.view_account(account_id).unwrap();
+    assert_eq!(result1.amount, TESTING_INIT_BALANCE - money_used - TESTING_INIT_STAKE);
+    let result2 = node_user.view_account(&eve_account()).unwrap();
+    assert_eq!(
+        result2,
+        AccountViewCallResult {
+            account_id: eve_account(),
+            public_keys: vec![node.signer().public_key()],
+            amount: money_used,
+            stake: 0,
+            code_hash: hash(wasm_binary),
+        }
+    );
+}
+
+pub fn test_batch_failure_reverts_all_actions(node: impl Node) {
+    let account_id = &node.account_id().unwrap();
+    let node_user = node.user();
+    let nonce = node.get_account_nonce(account_id).unwrap_or_default() + 1;
+    let transaction = TransactionBody::Batch(BatchTransaction {
+        nonce,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
+        originator: account_id.clone(),
+        receiver_id: eve_account(),
+        actions: vec![
+            Action::CreateAccount,
+            Action::AddKey(AddKeyAction {
+                public_key: node.signer().public_key().0[..].to_vec(),
+                access_key: AccessKey::full_access(),
+            }),
+            Action::Transfer(TransferAction { amount: TESTING_INIT_BALANCE + 1 }),
+        ],
+    })
+    .sign(&*node.signer());
+    let tx_hash = transaction.get_hash();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &tx_hash);
+
+    let transaction_result = node_user.get_transaction_result(&tx_hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Failed);
+    assert!(transaction_result.receipts.is_empty());
+    assert!(node_user.view_account(&eve_account()).is_err());
+    assert_eq!(node_user.get_account_nonce(account_id).unwrap(), nonce);
+    let result1 = node_user.view_account(account_id).unwrap();
+    assert_eq!(result1.amount, TESTING_INIT_BALANCE - TESTING_INIT_STAKE);
+}
+
+pub fn test_batch_deploy_to_existing_account_fails(node: impl Node) {
+    let account_id = &node.account_id().unwrap();
+    let node_user = node.user();
+    let transaction = TransactionBody::Batch(BatchTransaction {
+        nonce: node.get_account_nonce(account_id).unwrap_or_default() + 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
+        originator: account_id.clone(),
+        receiver_id: bob_account(),
+        actions: vec![
+            Action::Transfer(TransferAction { amount: 10 }),
+            Action::DeployContract(DeployContractAction { code: b"test_binary".to_vec() }),
+        ],
+    })
+    .sign(&*node.signer());
+    let tx_hash = transaction.get_hash();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &tx_hash);
+
+    let transaction_result = node_user.get_transaction_result(&tx_hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Failed);
+    let result2 = node_user.view_account(&bob_account()).unwrap();
+    assert_eq!(result2.amount, TESTING_INIT_BALANCE - TESTING_INIT_STAKE);
+    assert_eq!(result2.code_hash, default_code_hash());
+}
+
+pub fn test_batch_create_account_without_key_fails(node: impl Node) {
+    let account_id = &node.account_id().unwrap();
+    let node_user = node.user();
+    let nonce = node.get_account_nonce(account_id).unwrap_or_default() + 1;
+    let transaction = TransactionBody::Batch(BatchTransaction {
+        nonce,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
+        originator: account_id.clone(),
+        receiver_id: eve_account(),
+        actions: vec![Action::CreateAccount, Action::Transfer(TransferAction { amount: 10 })],
+    })
+    .sign(&*node.signer());
+    let tx_hash = transaction.get_hash();
+    node_user.add_transaction(transaction).unwrap();
+    wait_for_transaction(&node_user, &tx_hash);
+
+    // Account that nobody could sign for is not created.
+    let transaction_result = node_user.get_transaction_result(&tx_hash);
+    assert_eq!(transaction_result.status, TransactionStatus::Failed);
+    assert!(node_user.view_account(&eve_account()).is_err());
+    let result1 = node_user.view_account(account_id).unwrap();
+    assert_eq!(result1.amount, TESTING_INIT_BALANCE - TESTING_INIT_STAKE);
+}
@@ -42,10 +42,22 @@ pub trait User {
 
     fn add_receipt(&self, receipt: ReceiptTransaction) -> Result<(), String>;
 
-    fn get_account_nonce(&self, account_id: &AccountId) -> Option<u64>;
+    /// Nonce of the access key of the user's signer on the given account.
+    fn get_account_nonce(&self, account_id: &AccountId) -> Option<u64> {
+        self.get_access_key_nonce(account_id, &self.signer().public_key())
+    }
+
+    /// Nonce of the given access key, the next transaction signed with it must have a larger one.
+    fn get_access_key_nonce(&self, account_id: &AccountId, public_key: &PublicKey) -> Option<u64> {
+        self.get_access_key(account_id, public_key).ok()?.map(|access_key| access_key.nonce)
+    }
 
     fn get_best_block_index(&self) -> Option<u64>;
 
+    /// Hash of the latest block, that new transactions should refer to.
+    /// `None` if the user is not backed by a chain.
+    fn get_best_block_hash(&self) -> Option<CryptoHash>;
+
     fn get_block(&self, index: u64) -> Option<Block>;
 
     fn get_transaction_result(&self, hash: &CryptoHash) -> TransactionResult;
@@ -77,6 +89,7 @@ pub trait User {
     ) -> FinalTransactionResult {
         self.sign_and_commit_transaction(TransactionBody::SendMoney(SendMoneyTransaction {
             nonce: self.get_account_nonce(&originator_id).unwrap_or_default() + 1,
+            block_hash: self.get_best_block_hash().unwrap_or_default(),
             originator: originator_id,
             receiver: receiver_id,
             amount,
@@ -93,6 +106,7 @@ pub trait User {
     ) -> FinalTransactionResult {
         self.sign_and_commit_transaction(TransactionBody::FunctionCall(FunctionCallTransaction {
             nonce: self.get_account_nonce(&originator_id).unwrap_or_default() + 1,
+            block_hash: self.get_best_block_hash().unwrap_or_default(),
             originator: originator_id,
             contract_id,
             method_name: method_name.as_bytes().to_vec(),
@@ -110,6 +124,7 @@ pub trait User {
     ) -> FinalTransactionResult {
         self.sign_and_commit_transaction(TransactionBody::CreateAccount(CreateAccountTransaction {
             nonce: self.get_account_nonce(&originator_id).unwrap_or_default() + 1,
+            block_hash: self.get_best_block_hash().unwrap_or_default(),
             originator: originator_id,
             new_account_id,
             public_key: public_key.0[..].to_vec(),
@@ -124,6 +139,7 @@ pub trait User {
     ) -> FinalTransactionResult {
         self.sign_and_commit_transaction(TransactionBody::DeleteAccount(DeleteAccountTransaction {
             nonce: self.get_account_nonce(&originator_id).unwrap_or_default() + 1,
+            block_hash: self.get_best_block_hash().unwrap_or_default(),
             originator_id,
             receiver_id,
         }))
@@ -138,6 +154,7 @@ pub trait User {
         self.sign_and_commit_transaction(
             TransactionBody::Stake(StakeTransaction {
                 nonce: self.get_account_nonce(&originator_id).unwrap_or_default() + 1,
+                block_hash: self.get_best_block_hash().unwrap_or_default(),
                 originator: originator_id,
                 amount,
                 public_key: public_key.to_base(),
@@ -12,7 +12,7 @@ use near_primitives::crypto::signature::PublicKey;
 use near_primitives::crypto::signer::EDSigner;
 use near_primitives::hash::CryptoHash;
 use near_primitives::receipt::ReceiptInfo;
-use near_primitives::rpc::{AccountViewCallResult, QueryResponse, ViewStateResult};
+use near_primitives::rpc::{AccountViewCallResult, BlockId, QueryResponse, ViewStateResult};
 use near_primitives::serialize::{to_base, to_base64, BaseEncode};
 use near_primitives::transaction::{
     FinalTransactionResult, ReceiptTransaction, SignedTransaction, TransactionResult,
@@ -72,16 +72,18 @@ impl User for RpcUser {
         unimplemented!()
     }
 
-    fn get_account_nonce(&self, account_id: &String) -> Option<u64> {
-        self.view_account(account_id).ok().map(|acc| acc.nonce)
-    }
-
     fn get_best_block_index(&self) -> Option<u64> {
         self.get_status().map(|status| status.sync_info.latest_block_height)
     }
 
+    fn get_best_block_hash(&self) -> Option<CryptoHash> {
+        self.get_status().map(|status| status.sync_info.latest_block_hash)
+    }
+
     fn get_block(&self, index: u64) -> Option<Block> {
-        System::new("actix").block_on(self.client.write().unwrap().block(index)).ok()
+        System::new("actix")
+            .block_on(self.client.write().unwrap().block(BlockId::Height(index)))
+            .ok()
     }
 
     fn get_transaction_result(&self, hash: &CryptoHash) -> TransactionResult {
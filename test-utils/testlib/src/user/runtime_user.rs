@@ -189,14 +189,15 @@ impl User for RuntimeUser {
         Ok(())
     }
 
-    fn get_account_nonce(&self, account_id: &AccountId) -> Option<u64> {
-        self.view_account(account_id).ok().map(|account| account.nonce)
-    }
-
     fn get_best_block_index(&self) -> Option<u64> {
         unimplemented!("get_best_block_index should not be implemented for RuntimeUser");
     }
 
+    fn get_best_block_hash(&self) -> Option<CryptoHash> {
+        // Runtime is applied without a chain, so transactions don't refer to any block.
+        None
+    }
+
     fn get_block(&self, _index: u64) -> Option<Block> {
         unimplemented!("get_block should not be implemented for RuntimeUser");
     }
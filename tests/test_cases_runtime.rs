@@ -254,6 +254,12 @@ mod test {
         test_access_key_reject_non_function_call(node);
     }
 
+    #[test]
+    fn test_access_key_nonces_are_independent_runtime() {
+        let node = create_runtime_node();
+        test_access_key_nonces_are_independent(node);
+    }
+
     #[test]
     fn test_increase_stake_runtime() {
         let node = create_runtime_node();
@@ -307,4 +313,28 @@ mod test {
         let node = create_runtime_node();
         test_delete_account_while_staking(node);
     }
+
+    #[test]
+    fn test_batch_create_account_and_deploy_runtime() {
+        let node = create_runtime_node();
+        test_batch_create_account_and_deploy(node);
+    }
+
+    #[test]
+    fn test_batch_failure_reverts_all_actions_runtime() {
+        let node = create_runtime_node();
+        test_batch_failure_reverts_all_actions(node);
+    }
+
+    #[test]
+    fn test_batch_deploy_to_existing_account_fails_runtime() {
+        let node = create_runtime_node();
+        test_batch_deploy_to_existing_account_fails(node);
+    }
+
+    #[test]
+    fn test_batch_create_account_without_key_fails_runtime() {
+        let node = create_runtime_node();
+        test_batch_create_account_without_key_fails(node);
+    }
 }
@@ -201,4 +201,19 @@ mod test {
     fn test_access_key_smart_contract_testnet() {
         run_testnet_test!(test_access_key_smart_contract);
     }
+
+    #[test]
+    fn test_batch_create_account_and_deploy_testnet() {
+        run_testnet_test!(test_batch_create_account_and_deploy);
+    }
+
+    #[test]
+    fn test_batch_failure_reverts_all_actions_testnet() {
+        run_testnet_test!(test_batch_failure_reverts_all_actions);
+    }
+
+    #[test]
+    fn test_batch_deploy_to_existing_account_fails_testnet() {
+        run_testnet_test!(test_batch_deploy_to_existing_account_fails);
+    }
 }
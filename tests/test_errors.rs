@@ -23,6 +23,7 @@ fn test_check_tx_error_log() {
     let signer = InMemorySigner::from_seed("alice.near", "alice.near");
     let tx = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: "bob.near".to_string(),
         new_account_id: "test.near".to_string(),
         amount: 1_000,
@@ -43,6 +44,7 @@ fn test_deliver_tx_error_log() {
     let signer = InMemorySigner::from_seed("alice.near", "alice.near");
     let tx = TransactionBody::CreateAccount(CreateAccountTransaction {
         nonce: 1,
+        block_hash: node.get_best_block_hash().unwrap_or_default(),
         originator: "alice.near".to_string(),
         new_account_id: "test.near".to_string(),
         amount: TESTING_INIT_BALANCE + 1,
@@ -37,6 +37,7 @@ mod test {
                     account_names[from].as_str(),
                     account_names[to].as_str(),
                     1000,
+                    nodes[k].read().unwrap().get_best_block_hash().unwrap_or_default(),
                 )
                 .sign(&*nodes[from].read().unwrap().signer()),
             )
@@ -77,7 +78,14 @@ mod test {
         let mut nonces = vec![1; num_nodes];
         for i in 0..num_nodes {
             let account = nodes[0].read().unwrap().view_account(&account_names[i]).unwrap();
-            nonces[i] = account.nonce + 1;
+            let public_key = nodes[i].read().unwrap().signer().public_key();
+            nonces[i] = nodes[0]
+                .read()
+                .unwrap()
+                .user()
+                .get_access_key_nonce(&account_names[i], &public_key)
+                .unwrap()
+                + 1;
             expected_balances[i] = account.amount;
         }
         let trial_duration = 60000;
@@ -151,7 +159,14 @@ mod test {
         let mut nonces = vec![1; num_nodes];
         for i in 0..num_nodes {
             let account = nodes[0].read().unwrap().view_account(&account_names[i]).unwrap();
-            nonces[i] = account.nonce + 1;
+            let public_key = nodes[i].read().unwrap().signer().public_key();
+            nonces[i] = nodes[0]
+                .read()
+                .unwrap()
+                .user()
+                .get_access_key_nonce(&account_names[i], &public_key)
+                .unwrap()
+                + 1;
             expected_balances[i] = account.amount;
         }
 
@@ -0,0 +1,76 @@
+//! Runs the remote signer process and checks that it refuses to double sign.
+#[cfg(test)]
+#[cfg(feature = "expensive_tests")]
+mod test {
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::process::{Child, Command};
+    use std::thread;
+    use std::time::Duration;
+
+    use near_network::test_utils::open_port;
+    use near_primitives::crypto::remote_signer::RemoteSigner;
+    use near_primitives::crypto::signer::{EDSigner, InMemorySigner, SignerError};
+    use near_primitives::hash::hash;
+
+    const AUTH_KEY: &[u8] = b"remote signer test auth key";
+
+    /// Kills the signer process when the test ends, even if it fails.
+    struct SignerProcess(Child);
+
+    impl Drop for SignerProcess {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+        }
+    }
+
+    /// Connects to the signer, waiting for the process to start listening.
+    fn connect(addr: SocketAddr) -> RemoteSigner {
+        for _ in 0..60 {
+            if let Ok(signer) = RemoteSigner::connect(addr, AUTH_KEY.to_vec()) {
+                return signer;
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        panic!("Failed to connect to remote signer at {}", addr);
+    }
+
+    #[test]
+    fn test_remote_signer_refuses_double_sign() {
+        Command::new("cargo")
+            .args(&["build", "-p", "remote-signer"])
+            .spawn()
+            .expect("warmup failed")
+            .wait()
+            .unwrap();
+        let port = open_port();
+        let dir = std::env::temp_dir().join(format!("remote_signer_{}", port));
+        fs::create_dir_all(&dir).unwrap();
+        let signer = InMemorySigner::from_seed("test0", "test0");
+        signer.write_to_file(&dir.join("validator_key.json"));
+        fs::write(dir.join("signer_auth_key"), AUTH_KEY).unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        let _process = SignerProcess(
+            Command::new("cargo")
+                .args(&["run", "-p", "remote-signer", "--", "--addr", &addr.to_string()])
+                .args(&["--key-file", dir.join("validator_key.json").to_str().unwrap()])
+                .args(&["--state-file", dir.join("signer_state.json").to_str().unwrap()])
+                .args(&["--auth-key-file", dir.join("signer_auth_key").to_str().unwrap()])
+                .spawn()
+                .expect("Failed to start remote signer"),
+        );
+
+        let remote_signer = connect(addr);
+        assert_eq!(remote_signer.public_key(), signer.public_key);
+        let (hash1, hash2) = (hash(&[1]), hash(&[2]));
+        let signature = remote_signer.sign_block_header(5, &hash1).unwrap();
+        assert!(signer.verify(hash1.as_ref(), &signature));
+        assert_eq!(remote_signer.sign_block_header(5, &hash2), Err(SignerError::DoubleSign(5)));
+        // Conflicting header hash can't be signed as arbitrary data instead.
+        assert!(remote_signer.try_sign(hash2.as_ref()).is_err());
+        // Nodes without the auth key are not served.
+        assert!(RemoteSigner::connect(addr, b"wrong remote signer auth key".to_vec()).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
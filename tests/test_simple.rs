@@ -28,13 +28,20 @@ mod test {
             println!("TRIAL #{}", trial);
             let (i, j) = sample_two_nodes(num_nodes);
             let (k, r) = sample_two_nodes(num_nodes);
-            let account_i = nodes[k].read().unwrap().view_account(&account_names[i]).unwrap();
+            let public_key_i = nodes[i].read().unwrap().signer().public_key();
+            let nonce_i = nodes[k]
+                .read()
+                .unwrap()
+                .user()
+                .get_access_key_nonce(&account_names[i], &public_key_i)
+                .unwrap();
             let account_j = nodes[k].read().unwrap().view_account(&account_names[j]).unwrap();
             let transaction = TransactionBody::send_money(
-                account_i.nonce + 1,
+                nonce_i + 1,
                 account_names[i].as_str(),
                 account_names[j].as_str(),
                 amount_to_send,
+                nodes[k].read().unwrap().get_best_block_hash().unwrap_or_default(),
             )
             .sign(&*nodes[i].read().unwrap().signer());
             nodes[k].read().unwrap().add_transaction(transaction).unwrap();
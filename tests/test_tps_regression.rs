@@ -34,9 +34,16 @@ mod test {
 
         let sender_acc = nodes[money_sender].read().unwrap().account_id().unwrap();
         let receiver_acc = nodes[money_receiver].read().unwrap().account_id().unwrap();
-        let transaction =
-            TransactionBody::send_money(nonce, sender_acc.as_str(), receiver_acc.as_str(), 1)
-                .sign(&*nodes[money_sender].read().unwrap().signer());
+        let block_hash =
+            nodes[tx_receiver].read().unwrap().get_best_block_hash().unwrap_or_default();
+        let transaction = TransactionBody::send_money(
+            nonce,
+            sender_acc.as_str(),
+            receiver_acc.as_str(),
+            1,
+            block_hash,
+        )
+        .sign(&*nodes[money_sender].read().unwrap().signer());
         nodes[tx_receiver].read().unwrap().add_transaction(transaction).unwrap();
         submitted_transactions.write().unwrap().push((1, Instant::now()));
     }